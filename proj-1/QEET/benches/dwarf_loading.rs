@@ -0,0 +1,124 @@
+//! Measures how long deet takes to start up and set a breakpoint in a program with a lot of
+//! debugging information, compared to parsing all of it with `deet dump`.
+//!
+//! Run with `cargo bench`. The program is generated and compiled into a temporary directory, so
+//! gcc needs to be installed.
+
+use std::fmt::Write as _;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+use std::{env, fs};
+
+const FILES: usize = 200;
+const FUNCTIONS_PER_FILE: usize = 50;
+const RUNS: usize = 5;
+
+fn deet_path() -> PathBuf {
+    let mut path = env::current_exe().expect("Could not get current bench executable path");
+    path.pop();
+    path.pop();
+    path.push("deet");
+    path
+}
+
+/// Writes a C program whose functions all call into the next file, so the compiler keeps them.
+fn generate_program(dir: &Path) -> PathBuf {
+    let mut sources = Vec::new();
+    for file in 0..FILES {
+        let mut source = String::new();
+        writeln!(source, "int global_{};", file).unwrap();
+        if file + 1 < FILES {
+            writeln!(source, "int func_{}_0(int x);", file + 1).unwrap();
+        }
+        for func in 0..FUNCTIONS_PER_FILE {
+            writeln!(source, "int func_{}_{}(int x) {{", file, func).unwrap();
+            writeln!(source, "    int local = x * {} + global_{};", func, file).unwrap();
+            if func + 1 < FUNCTIONS_PER_FILE {
+                writeln!(source, "    return func_{}_{}(local);", file, func + 1).unwrap();
+            } else if file + 1 < FILES {
+                writeln!(source, "    return func_{}_0(local);", file + 1).unwrap();
+            } else {
+                writeln!(source, "    return local;").unwrap();
+            }
+            writeln!(source, "}}").unwrap();
+        }
+        let path = dir.join(format!("big_{}.c", file));
+        fs::write(&path, source).unwrap();
+        sources.push(path);
+    }
+    let main = dir.join("big.c");
+    fs::write(
+        &main,
+        "int func_0_0(int x);\nint main() {\n    return func_0_0(1) & 0xff;\n}\n",
+    )
+    .unwrap();
+    sources.push(main);
+
+    let binary = dir.join("big");
+    let status = Command::new("gcc")
+        .args(&["-O0", "-g", "-no-pie", "-fno-omit-frame-pointer", "-o"])
+        .arg(&binary)
+        .args(&sources)
+        .current_dir(dir)
+        .status()
+        .expect("Could not run gcc");
+    assert!(status.success(), "gcc failed");
+    binary
+}
+
+/// Runs deet with the given arguments and standard input, returning how long it took.
+fn time_deet(args: &[&Path], input: &str) -> Duration {
+    let start = Instant::now();
+    let mut child = Command::new(deet_path())
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Could not execute deet binary");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let status = child.wait().unwrap();
+    assert!(status.success(), "deet failed");
+    start.elapsed()
+}
+
+/// Prints the fastest of several runs.
+fn bench(name: &str, run: impl Fn() -> Duration) {
+    let best = (0..RUNS).map(|_| run()).min().unwrap();
+    println!("{:<40} {:>10.1} ms", name, best.as_secs_f64() * 1000.0);
+}
+
+fn main() {
+    let dir = env::temp_dir().join(format!("deet-bench-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    println!(
+        "Compiling a program with {} functions...",
+        FILES * FUNCTIONS_PER_FILE
+    );
+    let binary = generate_program(&dir);
+    let binary = binary.as_path();
+
+    let last_function = format!("func_{}_{}", FILES - 1, FUNCTIONS_PER_FILE - 1);
+    bench("startup and quit", || time_deet(&[binary], "quit\n"));
+    bench("break on a function in the last file", || {
+        time_deet(&[binary], &format!("break {}\nquit\n", last_function))
+    });
+    bench("info line for a function", || {
+        time_deet(&[binary], &format!("info line {}\nquit\n", last_function))
+    });
+    bench("break and run to it", || {
+        time_deet(&[binary], &format!("break {}\nrun\nquit\n", last_function))
+    });
+    bench("dump (parses every unit)", || {
+        time_deet(&[Path::new("dump"), binary], "")
+    });
+
+    fs::remove_dir_all(&dir).unwrap();
+}
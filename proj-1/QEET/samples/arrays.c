@@ -0,0 +1,9 @@
+#include <stdio.h>
+#include <string.h>
+
+int main() {
+    char buf[16] = "hello, world";
+    int grid[2][3] = {{1, 2, 3}, {4, 5, 6}};
+    printf("%s %zu %d\n", buf, strlen(buf), grid[1][2]);
+    return 0;
+}
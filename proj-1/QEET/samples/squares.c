@@ -0,0 +1,15 @@
+#include <stdio.h>
+
+int square(int x) {
+    int result = x * x;
+    return result;
+}
+
+int main() {
+    int total = 0;
+    for (int i = 1; i <= 3; i++) {
+        total += square(i);
+    }
+    printf("total = %d\n", total);
+    return 0;
+}
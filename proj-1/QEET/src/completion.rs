@@ -0,0 +1,145 @@
+//! Tab completion at the prompt. What an argument can be completed with comes from the command's
+//! entry in the command table (see `debugger_command`).
+
+use crate::debugger_command::{find_command, Arguments, Command, COMMANDS};
+use qeet::target::REGISTER_NAMES;
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+
+/// What the word under the cursor is.
+enum Completing {
+    /// A command or subcommand name from this table.
+    Command(&'static [Command]),
+    Argument(Arguments),
+    Nothing,
+}
+
+pub struct CommandHelper {
+    /// Names of the program's functions.
+    functions: Vec<String>,
+    /// Names of its global variables.
+    globals: Vec<String>,
+    /// Names of the program's source files, as they can be given in locations.
+    files: Vec<String>,
+    /// Variables visible where the program is stopped.
+    pub variables: Vec<String>,
+    paths: FilenameCompleter,
+}
+
+impl CommandHelper {
+    pub fn new(functions: Vec<String>, globals: Vec<String>, files: Vec<String>) -> CommandHelper {
+        CommandHelper {
+            functions,
+            globals,
+            files,
+            variables: Vec::new(),
+            paths: FilenameCompleter::new(),
+        }
+    }
+
+    /// Works out what is being completed from the words before it.
+    fn completing(tokens: &[&str]) -> Completing {
+        let mut table = COMMANDS;
+        let mut tokens = tokens;
+        loop {
+            let command = match tokens.split_first() {
+                None => return Completing::Command(table),
+                Some((word, rest)) => match find_command(table, word) {
+                    Ok(command) => {
+                        tokens = rest;
+                        command
+                    }
+                    Err(_) => return Completing::Nothing,
+                },
+            };
+            if command.subcommands.is_empty() {
+                return Completing::Argument(command.arguments);
+            }
+            table = command.subcommands;
+        }
+    }
+
+    fn candidates(&self, arguments: Arguments, word: &str) -> Vec<String> {
+        match arguments {
+            Arguments::None | Arguments::Path => Vec::new(),
+            Arguments::Location if word.contains(':') || word.starts_with('*') => Vec::new(),
+            Arguments::Location => {
+                let mut candidates = self.functions.clone();
+                candidates.extend(self.files.iter().map(|file| format!("{}:", file)));
+                candidates
+            }
+            Arguments::Function => self.functions.clone(),
+            Arguments::Expression if word.starts_with('$') => REGISTER_NAMES
+                .iter()
+                .map(|name| format!("${}", name))
+                .collect(),
+            Arguments::Expression => {
+                // Variables can have their address taken
+                let sigil = if word.starts_with('&') { "&" } else { "" };
+                self.variables
+                    .iter()
+                    .chain(self.globals.iter())
+                    .chain(self.functions.iter())
+                    .map(|name| format!("{}{}", sigil, name))
+                    .collect()
+            }
+            Arguments::Keywords(words) => words.iter().map(|word| (*word).to_string()).collect(),
+            Arguments::Command => COMMANDS
+                .iter()
+                .map(|command| command.name().to_string())
+                .collect(),
+        }
+    }
+}
+
+impl Completer for CommandHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let start = line[..pos]
+            .rfind(char::is_whitespace)
+            .map_or(0, |index| index + 1);
+        let tokens: Vec<&str> = line[..start].split_whitespace().collect();
+        let word = &line[start..pos];
+        let mut candidates = match CommandHelper::completing(&tokens) {
+            Completing::Command(table) => table
+                .iter()
+                .map(|command| command.name().to_string())
+                .collect(),
+            Completing::Argument(Arguments::Path) => return self.paths.complete(line, pos, ctx),
+            Completing::Argument(arguments) => self.candidates(arguments, word),
+            Completing::Nothing => Vec::new(),
+        };
+        candidates.retain(|candidate| candidate.starts_with(word));
+        candidates.sort();
+        candidates.dedup();
+        let pairs = candidates
+            .into_iter()
+            .map(|candidate| Pair {
+                display: candidate.clone(),
+                replacement: candidate,
+            })
+            .collect();
+        Ok((start, pairs))
+    }
+}
+
+impl Hinter for CommandHelper {
+    fn hint(&self, _line: &str, _pos: usize, _ctx: &Context<'_>) -> Option<String> {
+        None
+    }
+}
+
+impl Highlighter for CommandHelper {}
+
+impl Validator for CommandHelper {}
+
+impl Helper for CommandHelper {}
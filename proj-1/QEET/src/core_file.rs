@@ -0,0 +1,529 @@
+//! Reading and writing ELF core files, so a stopped inferior can be saved with `gcore` and
+//! examined later (along with cores produced by the kernel) without a live process.
+//!
+//! Only what we need is handled: x86-64 little-endian ELF64, PT_LOAD segments for memory and
+//! NT_PRSTATUS for registers.
+
+use crate::inferior::{Breakpoint, Inferior, Mapping};
+use crate::target::{regs_to_words, words_to_regs, Target, NUM_REGISTERS};
+use libc::user_regs_struct;
+use nix::errno::Errno;
+use nix::sys::ptrace;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::fs::FileExt;
+
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const ET_CORE: u16 = 4;
+const EM_X86_64: u16 = 62;
+const NT_PRSTATUS: u32 = 1;
+const NT_PRPSINFO: u32 = 3;
+const NT_FILE: u32 = 0x4649_4c45;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+const PAGE_SIZE: usize = 4096;
+
+// Layout of the x86-64 elf_prstatus and elf_prpsinfo structs (see linux/elfcore.h)
+const PRSTATUS_SIZE: usize = 336;
+const PRSTATUS_CURSIG: usize = 12;
+const PRSTATUS_PID: usize = 32;
+const PRSTATUS_REGS: usize = 112;
+const PRPSINFO_SIZE: usize = 136;
+const PRPSINFO_PID: usize = 24;
+const PRPSINFO_FNAME: usize = 40;
+const PRPSINFO_PSARGS: usize = 56;
+
+#[derive(Debug)]
+pub enum Error {
+    ErrorOpeningFile,
+    BadFormat(&'static str),
+}
+
+struct ProgramHeader {
+    p_type: u32,
+    p_flags: u32,
+    offset: usize,
+    vaddr: usize,
+    filesz: usize,
+    memsz: usize,
+}
+
+/// A range of the target's address space, and as much of its contents as we have.
+struct Segment {
+    vaddr: usize,
+    memsz: usize,
+    /// Bytes past the end of data (but within memsz) read as zero, like .bss.
+    data: Vec<u8>,
+}
+
+impl Segment {
+    fn read_word(&self, addr: usize) -> Option<u64> {
+        let end = self.vaddr.checked_add(self.memsz)?;
+        if addr < self.vaddr || addr.checked_add(8)? > end {
+            return None;
+        }
+        let mut word = [0u8; 8];
+        let start = addr - self.vaddr;
+        for (i, byte) in word.iter_mut().enumerate() {
+            *byte = *self.data.get(start + i).unwrap_or(&0);
+        }
+        Some(u64::from_le_bytes(word))
+    }
+}
+
+/// Returns `len` bytes of `buf` starting at `offset`, or None if they run past its end. Offsets and
+/// sizes come from the file, so the sum can't be trusted not to overflow.
+fn bytes_at(buf: &[u8], offset: usize, len: usize) -> Option<&[u8]> {
+    buf.get(offset..offset.checked_add(len)?)
+}
+
+fn u16_at(buf: &[u8], offset: usize) -> Result<u16, Error> {
+    bytes_at(buf, offset, 2)
+        .map(|b| u16::from_le_bytes(b.try_into().unwrap()))
+        .ok_or(Error::BadFormat("truncated file"))
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> Result<u32, Error> {
+    bytes_at(buf, offset, 4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+        .ok_or(Error::BadFormat("truncated file"))
+}
+
+fn u64_at(buf: &[u8], offset: usize) -> Result<u64, Error> {
+    bytes_at(buf, offset, 8)
+        .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+        .ok_or(Error::BadFormat("truncated file"))
+}
+
+/// Checks the ELF header and returns (e_type, program headers).
+fn parse_program_headers(buf: &[u8]) -> Result<(u16, Vec<ProgramHeader>), Error> {
+    if buf.len() < EHDR_SIZE || &buf[0..4] != b"\x7fELF" {
+        return Err(Error::BadFormat("not an ELF file"));
+    }
+    if buf[4] != 2 || buf[5] != 1 || u16_at(buf, 18)? != EM_X86_64 {
+        return Err(Error::BadFormat("only x86-64 ELF files are supported"));
+    }
+    let e_type = u16_at(buf, 16)?;
+    let phoff = u64_at(buf, 32)? as usize;
+    let phentsize = u16_at(buf, 54)? as usize;
+    let phnum = u16_at(buf, 56)? as usize;
+    if phentsize < PHDR_SIZE {
+        return Err(Error::BadFormat("program headers are too small"));
+    }
+    let table = phentsize
+        .checked_mul(phnum)
+        .and_then(|size| bytes_at(buf, phoff, size))
+        .ok_or(Error::BadFormat("truncated program headers"))?;
+    let mut headers = Vec::with_capacity(phnum);
+    for ph in table.chunks(phentsize) {
+        headers.push(ProgramHeader {
+            p_type: u32_at(ph, 0)?,
+            p_flags: u32_at(ph, 4)?,
+            offset: u64_at(ph, 8)? as usize,
+            vaddr: u64_at(ph, 16)? as usize,
+            filesz: u64_at(ph, 32)? as usize,
+            memsz: u64_at(ph, 40)? as usize,
+        });
+    }
+    Ok((e_type, headers))
+}
+
+fn load_segments(buf: &[u8], headers: &[ProgramHeader]) -> Vec<Segment> {
+    headers
+        .iter()
+        .filter(|ph| ph.p_type == PT_LOAD)
+        .map(|ph| Segment {
+            vaddr: ph.vaddr,
+            memsz: ph.memsz,
+            data: bytes_at(buf, ph.offset, ph.filesz).unwrap_or(&[]).to_vec(),
+        })
+        .collect()
+}
+
+pub struct CoreFile {
+    /// The signal that was being delivered when the core was made.
+    pub signal: i32,
+    pub pid: i32,
+    /// The program's name, if the core records it.
+    pub program: Option<String>,
+    regs: user_regs_struct,
+    segments: Vec<Segment>,
+    /// The executable's own segments. The kernel leaves unmodified file-backed memory (such as
+    /// program text) out of cores, so reads fall back to these.
+    exec_segments: Vec<Segment>,
+}
+
+impl CoreFile {
+    pub fn open(core_path: &str, executable_path: &str) -> Result<CoreFile, Error> {
+        let buf = fs::read(core_path).or(Err(Error::ErrorOpeningFile))?;
+        let (e_type, headers) = parse_program_headers(&buf)?;
+        if e_type != ET_CORE {
+            return Err(Error::BadFormat("not a core file"));
+        }
+
+        let mut signal = 0;
+        let mut pid = 0;
+        let mut program = None;
+        let mut regs = None;
+        for ph in headers.iter().filter(|ph| ph.p_type == PT_NOTE) {
+            let notes = bytes_at(&buf, ph.offset, ph.filesz)
+                .ok_or(Error::BadFormat("truncated note segment"))?;
+            let mut offset = 0;
+            while offset + 12 <= notes.len() {
+                let namesz = u32_at(notes, offset)? as usize;
+                let descsz = u32_at(notes, offset + 4)? as usize;
+                let n_type = u32_at(notes, offset + 8)?;
+                let desc_start = offset + 12 + align4(namesz);
+                let desc = bytes_at(notes, desc_start, descsz)
+                    .ok_or(Error::BadFormat("truncated note"))?;
+                match n_type {
+                    // Only the first NT_PRSTATUS; the rest belong to other threads
+                    NT_PRSTATUS if regs.is_none() => {
+                        signal = u16_at(desc, PRSTATUS_CURSIG)? as i32;
+                        pid = u32_at(desc, PRSTATUS_PID)? as i32;
+                        let mut words = [0u64; NUM_REGISTERS];
+                        for (i, word) in words.iter_mut().enumerate() {
+                            *word = u64_at(desc, PRSTATUS_REGS + 8 * i)?;
+                        }
+                        regs = Some(words_to_regs(&words));
+                    }
+                    NT_PRPSINFO => {
+                        let fname = desc
+                            .get(PRPSINFO_FNAME..PRPSINFO_FNAME + 16)
+                            .ok_or(Error::BadFormat("truncated prpsinfo"))?;
+                        let len = fname
+                            .iter()
+                            .position(|&b| b == 0)
+                            .unwrap_or_else(|| fname.len());
+                        program = Some(String::from_utf8_lossy(&fname[..len]).to_string());
+                    }
+                    _ => {}
+                }
+                offset = desc_start + align4(descsz);
+            }
+        }
+
+        let exec_buf = fs::read(executable_path).or(Err(Error::ErrorOpeningFile))?;
+        let (_, exec_headers) = parse_program_headers(&exec_buf)?;
+        Ok(CoreFile {
+            signal,
+            pid,
+            program,
+            regs: regs.ok_or(Error::BadFormat("core file has no NT_PRSTATUS note"))?,
+            segments: load_segments(&buf, &headers),
+            exec_segments: load_segments(&exec_buf, &exec_headers),
+        })
+    }
+}
+
+impl Target for CoreFile {
+    fn registers(&self) -> Result<user_regs_struct, nix::Error> {
+        Ok(self.regs)
+    }
+
+    fn read_word(&self, addr: usize) -> Result<u64, nix::Error> {
+        // Prefer segments whose contents were actually dumped
+        let dumped = self
+            .segments
+            .iter()
+            .filter(|seg| addr >= seg.vaddr && addr - seg.vaddr + 8 <= seg.data.len());
+        dumped
+            .chain(self.exec_segments.iter())
+            .find_map(|seg| seg.read_word(addr))
+            .ok_or(nix::Error::Sys(Errno::EFAULT))
+    }
+}
+
+fn align4(n: usize) -> usize {
+    (n + 3) & !3
+}
+
+fn align_to_page(n: usize) -> usize {
+    (n + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+fn push_note(notes: &mut Vec<u8>, n_type: u32, desc: &[u8]) {
+    let name = b"CORE\0";
+    notes.extend_from_slice(&(name.len() as u32).to_le_bytes());
+    notes.extend_from_slice(&(desc.len() as u32).to_le_bytes());
+    notes.extend_from_slice(&n_type.to_le_bytes());
+    notes.extend_from_slice(name);
+    notes.resize(align4(notes.len()), 0);
+    notes.extend_from_slice(desc);
+    notes.resize(align4(notes.len()), 0);
+}
+
+fn push_phdr(out: &mut Vec<u8>, ph: &ProgramHeader) {
+    let align = if ph.p_type == PT_LOAD { PAGE_SIZE } else { 4 };
+    out.extend_from_slice(&ph.p_type.to_le_bytes());
+    out.extend_from_slice(&ph.p_flags.to_le_bytes());
+    for value in &[ph.offset, ph.vaddr, 0, ph.filesz, ph.memsz, align] {
+        out.extend_from_slice(&(*value as u64).to_le_bytes());
+    }
+}
+
+fn read_proc_string(pid: nix::unistd::Pid, file: &str) -> String {
+    let contents = fs::read(format!("/proc/{}/{}", pid, file)).unwrap_or_default();
+    String::from_utf8_lossy(&contents)
+        .trim_end_matches(|c| c == '\n' || c == '\0')
+        .replace('\0', " ")
+}
+
+/// Builds the notes describing the process: registers, its name, and which files are mapped where.
+fn build_notes(inferior: &Inferior, mappings: &[Mapping]) -> Result<Vec<u8>, nix::Error> {
+    let pid = inferior.pid();
+    let mut notes = Vec::new();
+
+    let mut prstatus = vec![0u8; PRSTATUS_SIZE];
+    let signal = ptrace::getsiginfo(pid)
+        .map(|info| info.si_signo)
+        .unwrap_or(0);
+    prstatus[PRSTATUS_CURSIG..PRSTATUS_CURSIG + 2].copy_from_slice(&(signal as u16).to_le_bytes());
+    prstatus[PRSTATUS_PID..PRSTATUS_PID + 4].copy_from_slice(&pid.as_raw().to_le_bytes());
+    for (i, word) in regs_to_words(&inferior.registers()?).iter().enumerate() {
+        let offset = PRSTATUS_REGS + 8 * i;
+        prstatus[offset..offset + 8].copy_from_slice(&word.to_le_bytes());
+    }
+    push_note(&mut notes, NT_PRSTATUS, &prstatus);
+
+    let mut prpsinfo = vec![0u8; PRPSINFO_SIZE];
+    prpsinfo[PRPSINFO_PID..PRPSINFO_PID + 4].copy_from_slice(&pid.as_raw().to_le_bytes());
+    let fname = read_proc_string(pid, "comm");
+    let fname = &fname.as_bytes()[..fname.len().min(15)];
+    prpsinfo[PRPSINFO_FNAME..PRPSINFO_FNAME + fname.len()].copy_from_slice(fname);
+    let psargs = read_proc_string(pid, "cmdline");
+    let psargs = &psargs.as_bytes()[..psargs.len().min(79)];
+    prpsinfo[PRPSINFO_PSARGS..PRPSINFO_PSARGS + psargs.len()].copy_from_slice(psargs);
+    push_note(&mut notes, NT_PRPSINFO, &prpsinfo);
+
+    let files: Vec<&Mapping> = mappings.iter().filter(|m| m.is_file()).collect();
+    let mut nt_file = Vec::new();
+    for value in &[files.len(), PAGE_SIZE] {
+        nt_file.extend_from_slice(&(*value as u64).to_le_bytes());
+    }
+    for mapping in &files {
+        for value in &[mapping.start, mapping.end, mapping.offset / PAGE_SIZE] {
+            nt_file.extend_from_slice(&(*value as u64).to_le_bytes());
+        }
+    }
+    for mapping in &files {
+        nt_file.extend_from_slice(mapping.path.as_ref().unwrap().as_bytes());
+        nt_file.push(0);
+    }
+    push_note(&mut notes, NT_FILE, &nt_file);
+    Ok(notes)
+}
+
+/// Reads the contents of a mapping, with the instructions that `breakpoints` (and the heap
+/// tracker's breakpoints) have replaced with int3 put back. Returns nothing for regions that can't
+/// be read even by a tracer, such as [vvar].
+fn read_mapping(
+    inferior: &Inferior,
+    mem: &fs::File,
+    mapping: &Mapping,
+    breakpoints: &HashMap<usize, Breakpoint>,
+) -> Vec<u8> {
+    if !mapping.readable() {
+        return Vec::new();
+    }
+    let mut data = vec![0u8; mapping.end - mapping.start];
+    if mem.read_exact_at(&mut data, mapping.start as u64).is_err() {
+        return Vec::new();
+    }
+    let heap_breakpoints = inferior.heap().map(|heap| &heap.breakpoints);
+    let planted = breakpoints
+        .iter()
+        .map(|(addr, bp)| (*addr, bp.orig_byte))
+        .chain(
+            heap_breakpoints
+                .into_iter()
+                .flatten()
+                .map(|(addr, bp)| (*addr, bp.orig_byte)),
+        );
+    for (addr, orig_byte) in planted {
+        if addr >= mapping.start && addr < mapping.end && data[addr - mapping.start] == 0xcc {
+            data[addr - mapping.start] = orig_byte;
+        }
+    }
+    data
+}
+
+/// Writes a core file for the (stopped) inferior to `path`. Returns the number of memory regions
+/// that were saved.
+#[allow(clippy::implicit_hasher)]
+pub fn generate(
+    inferior: &Inferior,
+    breakpoints: &HashMap<usize, Breakpoint>,
+    path: &str,
+) -> io::Result<usize> {
+    let mappings = inferior.mappings()?;
+    let notes = build_notes(inferior, &mappings)
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+
+    // Read all of the memory up front, so that the layout is known before anything is written.
+    // Regions that can't be read are saved without contents.
+    let mem = fs::File::open(format!("/proc/{}/mem", inferior.pid()))?;
+    let contents: Vec<Vec<u8>> = mappings
+        .iter()
+        .map(|mapping| read_mapping(inferior, &mem, mapping, breakpoints))
+        .collect();
+
+    let phnum = mappings.len() + 1;
+    let notes_offset = EHDR_SIZE + PHDR_SIZE * phnum;
+    let mut data_offset = align_to_page(notes_offset + notes.len());
+
+    let mut out = Vec::new();
+    out.extend_from_slice(b"\x7fELF");
+    out.extend_from_slice(&[2, 1, 1, 0]); // ELFCLASS64, little-endian, version 1, SysV ABI
+    out.resize(16, 0);
+    out.extend_from_slice(&ET_CORE.to_le_bytes());
+    out.extend_from_slice(&EM_X86_64.to_le_bytes());
+    out.extend_from_slice(&1u32.to_le_bytes()); // e_version
+    out.extend_from_slice(&0u64.to_le_bytes()); // e_entry
+    out.extend_from_slice(&(EHDR_SIZE as u64).to_le_bytes()); // e_phoff
+    out.extend_from_slice(&0u64.to_le_bytes()); // e_shoff
+    out.extend_from_slice(&0u32.to_le_bytes()); // e_flags
+    for value in &[EHDR_SIZE, PHDR_SIZE, phnum, 0, 0, 0] {
+        out.extend_from_slice(&(*value as u16).to_le_bytes());
+    }
+
+    push_phdr(
+        &mut out,
+        &ProgramHeader {
+            p_type: PT_NOTE,
+            p_flags: 0,
+            offset: notes_offset,
+            vaddr: 0,
+            filesz: notes.len(),
+            memsz: 0,
+        },
+    );
+    for (mapping, data) in mappings.iter().zip(&contents) {
+        let mut flags = 0;
+        if mapping.readable() {
+            flags |= PF_R;
+        }
+        if mapping.perms.contains('w') {
+            flags |= PF_W;
+        }
+        if mapping.perms.contains('x') {
+            flags |= PF_X;
+        }
+        push_phdr(
+            &mut out,
+            &ProgramHeader {
+                p_type: PT_LOAD,
+                p_flags: flags,
+                offset: data_offset,
+                vaddr: mapping.start,
+                filesz: data.len(),
+                memsz: mapping.end - mapping.start,
+            },
+        );
+        data_offset += data.len();
+    }
+    out.extend_from_slice(&notes);
+    out.resize(align_to_page(out.len()), 0);
+
+    let mut file = fs::File::create(path)?;
+    file.write_all(&out)?;
+    for data in &contents {
+        file.write_all(data)?;
+    }
+    Ok(mappings.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inferior::Status;
+    use crate::testing;
+
+    #[test]
+    fn saved_core_matches_the_process() {
+        let debug_data = testing::debug_data("function_calls");
+        let mut breakpoints = testing::breakpoints_at(&debug_data, &["func2"]);
+        let bp_addr = *breakpoints.keys().next().unwrap();
+        let mut inferior = testing::spawn("function_calls", &mut breakpoints);
+        match inferior.continue_run(None, &breakpoints).unwrap() {
+            Status::Stopped(_, rip) => assert_eq!(rip, bp_addr),
+            _ => panic!("expected to stop at func2"),
+        }
+
+        let path = std::env::temp_dir().join(format!("qeet-test-core.{}", inferior.pid()));
+        let path = path.to_str().unwrap();
+        let regions = generate(&inferior, &breakpoints, path).unwrap();
+        assert!(regions > 0);
+        let core = CoreFile::open(path, &testing::sample("function_calls"));
+        fs::remove_file(path).unwrap();
+        let core = core.unwrap();
+
+        assert_eq!(core.pid, inferior.pid().as_raw());
+        assert_eq!(core.program.as_deref(), Some("function_calls"));
+        let regs = inferior.registers().unwrap();
+        assert_eq!(
+            regs_to_words(&core.registers().unwrap()),
+            regs_to_words(&regs)
+        );
+        let rsp = regs.rsp as usize;
+        for addr in (rsp..rsp + 64).step_by(8) {
+            assert_eq!(
+                core.read_word(addr).unwrap(),
+                inferior.read_word(addr).unwrap()
+            );
+        }
+
+        // The process has int3 at the breakpoint; the core has the instruction that was there
+        assert_eq!(inferior.read_word(bp_addr).unwrap() & 0xff, 0xcc);
+        let orig_byte = breakpoints[&bp_addr].orig_byte;
+        assert_ne!(orig_byte, 0xcc);
+        assert_eq!(core.read_word(bp_addr).unwrap() & 0xff, orig_byte as u64);
+        inferior.kill();
+    }
+
+    #[test]
+    fn corrupt_headers_are_rejected() {
+        let mut elf = vec![0u8; EHDR_SIZE];
+        elf[0..4].copy_from_slice(b"\x7fELF");
+        elf[4] = 2;
+        elf[5] = 1;
+        elf[16..18].copy_from_slice(&ET_CORE.to_le_bytes());
+        elf[18..20].copy_from_slice(&EM_X86_64.to_le_bytes());
+        elf[54..56].copy_from_slice(&(PHDR_SIZE as u16).to_le_bytes());
+        elf[56..58].copy_from_slice(&2u16.to_le_bytes());
+
+        // Program headers that would run past the end of the address space
+        elf[32..40].copy_from_slice(&(u64::MAX - 8).to_le_bytes());
+        assert!(parse_program_headers(&elf).is_err());
+        // ... or just past the end of the file
+        elf[32..40].copy_from_slice(&(EHDR_SIZE as u64).to_le_bytes());
+        assert!(parse_program_headers(&elf).is_err());
+
+        // A segment whose size overflows reads as having no contents
+        let mut ph = Vec::new();
+        push_phdr(
+            &mut ph,
+            &ProgramHeader {
+                p_type: PT_LOAD,
+                p_flags: PF_R,
+                offset: 16,
+                vaddr: 0x1000,
+                filesz: usize::MAX,
+                memsz: usize::MAX,
+            },
+        );
+        let headers = parse_program_headers(&[&elf[..], &ph, &ph].concat())
+            .unwrap()
+            .1;
+        let segments = load_segments(&elf, &headers);
+        assert!(segments[0].data.is_empty());
+        assert_eq!(segments[0].read_word(usize::MAX - 4), None);
+    }
+}
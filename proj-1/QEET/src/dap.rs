@@ -0,0 +1,453 @@
+//! A Debug Adapter Protocol server, so that editors (VS Code, nvim-dap, ...) can drive the
+//! debugger. Messages are exchanged over stdin/stdout, each one a JSON body preceded by a
+//! `Content-Length` header. See https://microsoft.github.io/debug-adapter-protocol/specification.
+//!
+//! Since stdout carries the protocol, the inferior's stdout/stderr are piped back to the editor
+//! as `output` events instead of being inherited.
+
+use qeet::dwarf_data::Variable;
+use qeet::error::Error;
+use qeet::launch::Word;
+use qeet::pretty::Printers;
+use qeet::target::Frame;
+use qeet::{Session, Step, Stop};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// DAP has a notion of threads; our inferiors are single-threaded, so this is the only one.
+const THREAD_ID: i64 = 1;
+/// The header that gives the size of a message's JSON body.
+const CONTENT_LENGTH: &str = "Content-Length:";
+
+/// Reads one message, returning Ok(None) once the client closes the stream.
+fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<Value>> {
+    let mut content_length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            if content_length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if header.starts_with(CONTENT_LENGTH) {
+            let len = &header[CONTENT_LENGTH.len()..];
+            content_length = len.trim().parse::<usize>().ok();
+        }
+    }
+    let mut body = vec![0; content_length.unwrap()];
+    reader.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Serializes outgoing messages. Shared with the threads forwarding the inferior's output.
+struct Writer {
+    seq: i64,
+    out: io::Stdout,
+}
+
+impl Writer {
+    fn send(&mut self, mut message: Value) {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        let body = message.to_string();
+        let mut out = self.out.lock();
+        // If the client has gone away there is nobody left to tell, so ignore write errors
+        let _ = write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body);
+        let _ = out.flush();
+    }
+}
+
+pub struct DapServer {
+    target: Option<String>,
+    /// Set up by the launch request.
+    session: Option<Session>,
+    /// Breakpoint addresses grouped by source path, since setBreakpoints replaces a whole file's.
+    source_breakpoints: HashMap<String, Vec<usize>>,
+    /// Frames from the last stackTrace request. Frame ids are indices into this.
+    frames: Vec<Frame>,
+    stop_on_entry: bool,
+    writer: Arc<Mutex<Writer>>,
+    output_threads: Vec<JoinHandle<()>>,
+}
+
+impl DapServer {
+    pub fn new(target: Option<&str>) -> DapServer {
+        DapServer {
+            target: target.map(|t| t.to_string()),
+            session: None,
+            source_breakpoints: HashMap::new(),
+            frames: Vec::new(),
+            stop_on_entry: false,
+            writer: Arc::new(Mutex::new(Writer {
+                seq: 0,
+                out: io::stdout(),
+            })),
+            output_threads: Vec::new(),
+        }
+    }
+
+    /// Serves requests from stdin until the client disconnects.
+    pub fn run(&mut self) {
+        let stdin = io::stdin();
+        let mut reader = stdin.lock();
+        while let Ok(Some(request)) = read_message(&mut reader) {
+            if request["type"] != "request" {
+                continue;
+            }
+            let command = request["command"].as_str().unwrap_or("").to_string();
+            let args = &request["arguments"];
+            let result = match command.as_str() {
+                "initialize" => Ok(json!({ "supportsConfigurationDoneRequest": true })),
+                "launch" => self.launch(args),
+                "setBreakpoints" => self.set_breakpoints(args),
+                "configurationDone" => Ok(json!({})),
+                "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })),
+                "continue" => Ok(json!({ "allThreadsContinued": true })),
+                "next" | "stepIn" => Ok(json!({})),
+                "stackTrace" => self.stack_trace(),
+                "scopes" => self.scopes(args),
+                "variables" => self.variables(args),
+                "disconnect" => Ok(json!({})),
+                _ => Err(format!("Unsupported request: {}", command)),
+            };
+            let succeeded = result.is_ok();
+            self.respond(&request, result);
+            if !succeeded {
+                continue;
+            }
+
+            // Some requests have effects that must only be reported after their response
+            match command.as_str() {
+                "launch" => self.send_event("initialized", json!({})),
+                "configurationDone" => {
+                    if self.stop_on_entry {
+                        self.send_stopped("entry");
+                    } else {
+                        self.resume(&command);
+                    }
+                }
+                "continue" | "next" | "stepIn" => self.resume(&command),
+                "disconnect" => break,
+                _ => {}
+            }
+        }
+        if let Some(session) = self.session.as_mut() {
+            session.kill();
+        }
+    }
+
+    fn respond(&self, request: &Value, result: Result<Value, String>) {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+        });
+        match result {
+            Ok(body) => {
+                response["success"] = json!(true);
+                response["body"] = body;
+            }
+            Err(message) => {
+                response["success"] = json!(false);
+                response["message"] = json!(message);
+            }
+        }
+        self.writer.lock().unwrap().send(response);
+    }
+
+    fn send_event(&self, event: &str, body: Value) {
+        self.writer.lock().unwrap().send(json!({
+            "type": "event",
+            "event": event,
+            "body": body,
+        }));
+    }
+
+    fn send_stopped(&self, reason: &str) {
+        self.send_event(
+            "stopped",
+            json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true }),
+        );
+    }
+
+    fn session(&self) -> Result<&Session, String> {
+        self.session
+            .as_ref()
+            .ok_or_else(|| "No program has been launched".to_string())
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        let target = match args["program"].as_str() {
+            Some(program) => program.to_string(),
+            None => self
+                .target
+                .clone()
+                .ok_or_else(|| "launch requires a \"program\"".to_string())?,
+        };
+        let mut session = Session::new(&target).map_err(|err| err.to_string())?;
+        // stdout carries the protocol, so errors in the config file go unreported
+        session.printers = Printers::with_config().0;
+        session.launch.args = args["args"]
+            .as_array()
+            .map(|a| {
+                a.iter()
+                    .filter_map(|v| v.as_str())
+                    .map(Word::quoted)
+                    .collect()
+            })
+            .unwrap_or_default();
+        session.launch.capture_output = true;
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+
+        let spawned = session.spawn();
+        for warning in session.take_warnings() {
+            self.send_console(&warning);
+        }
+        spawned.map_err(|err| err.to_string())?;
+        let (stdout, stderr) = session.take_output();
+        if let Some(stdout) = stdout {
+            self.forward_output(stdout, "stdout");
+        }
+        if let Some(stderr) = stderr {
+            self.forward_output(stderr, "stderr");
+        }
+
+        self.target = Some(target);
+        self.session = Some(session);
+        Ok(json!({}))
+    }
+
+    /// Relays everything the inferior writes to `stream` as output events.
+    fn forward_output<R: Read + Send + 'static>(&mut self, stream: R, category: &'static str) {
+        let writer = self.writer.clone();
+        self.output_threads.push(thread::spawn(move || {
+            let mut reader = BufReader::new(stream);
+            let mut line = String::new();
+            while let Ok(n) = reader.read_line(&mut line) {
+                if n == 0 {
+                    break;
+                }
+                writer.lock().unwrap().send(json!({
+                    "type": "event",
+                    "event": "output",
+                    "body": { "category": category, "output": line },
+                }));
+                line.clear();
+            }
+        }));
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let path = args["source"]["path"]
+            .as_str()
+            .ok_or_else(|| "setBreakpoints requires a source path".to_string())?
+            .to_string();
+
+        let session = self
+            .session
+            .as_mut()
+            .ok_or_else(|| "No program has been launched".to_string())?;
+
+        // Remove the file's old breakpoints before installing the new set
+        for addr in self.source_breakpoints.remove(&path).unwrap_or_default() {
+            let _ = session.remove_breakpoint(addr);
+        }
+
+        let lines: Vec<usize> = args["breakpoints"]
+            .as_array()
+            .map(|bps| {
+                bps.iter()
+                    .filter_map(|bp| bp["line"].as_u64())
+                    .map(|line| line as usize)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let mut results = Vec::new();
+        let mut addrs = Vec::new();
+        for line in lines {
+            let addr = session.debug_data().get_addr_for_line(Some(&path), line);
+            let installed = match addr {
+                Some(addr) => session.set_breakpoint(&format!("*{:#x}", addr)).is_ok(),
+                None => false,
+            };
+            if installed {
+                addrs.push(addr.unwrap());
+            }
+            let actual_line = addr
+                .and_then(|addr| session.debug_data().get_line_from_addr(addr))
+                .map_or(line, |l| l.number);
+            results.push(json!({ "verified": installed, "line": actual_line }));
+        }
+        self.source_breakpoints.insert(path, addrs);
+        Ok(json!({ "breakpoints": results }))
+    }
+
+    /// Carries out a continue/next/stepIn and reports where the inferior ended up.
+    fn resume(&mut self, command: &str) {
+        self.frames.clear();
+        let session = match self.session.as_mut() {
+            Some(session) if session.pid().is_some() => session,
+            _ => return,
+        };
+        let stop = match command {
+            "next" => session.step(Step::Over),
+            "stepIn" => session.step(Step::Into),
+            _ => session.resume(),
+        };
+        for warning in session.take_warnings() {
+            self.send_console(&warning);
+        }
+        match stop {
+            Ok(Stop::Breakpoint(_)) => self.send_stopped("breakpoint"),
+            Ok(Stop::Stepped(_)) => self.send_stopped("step"),
+            Ok(Stop::Signal(signal, _)) => self.send_event(
+                "stopped",
+                json!({
+                    "reason": "exception",
+                    "description": format!("Child stopped (signal {})", signal),
+                    "threadId": THREAD_ID,
+                    "allThreadsStopped": true,
+                }),
+            ),
+            // No functions are called over DAP
+            Ok(Stop::Returned(..)) => self.send_stopped("pause"),
+            // No catchpoints are set over DAP, but report one like any other pause
+            Ok(Stop::Event(event, _)) => self.send_event(
+                "stopped",
+                json!({
+                    "reason": "pause",
+                    "description": format!("{:?}", event),
+                    "threadId": THREAD_ID,
+                    "allThreadsStopped": true,
+                }),
+            ),
+            Ok(Stop::Exited(exit_code)) => {
+                self.flush_output();
+                self.send_event("exited", json!({ "exitCode": exit_code }));
+                self.send_event("terminated", json!({}));
+            }
+            Ok(Stop::Signaled(signal)) => {
+                self.flush_output();
+                self.send_console(&format!("Child exited due to signal {}", signal));
+                self.send_event("terminated", json!({}));
+            }
+            // The session has let go of the process
+            Err(Error::ProcessGone) => {
+                self.flush_output();
+                self.send_console(&format!("Error: {}", Error::ProcessGone));
+                self.send_event("terminated", json!({}));
+            }
+            // The program is still there, so it can be looked at and resumed again
+            Err(err) => self.send_event(
+                "stopped",
+                json!({
+                    "reason": "exception",
+                    "description": format!("Error: {}", err),
+                    "threadId": THREAD_ID,
+                    "allThreadsStopped": true,
+                }),
+            ),
+        }
+    }
+
+    fn send_console(&self, message: &str) {
+        self.send_event(
+            "output",
+            json!({ "category": "console", "output": format!("{}\n", message) }),
+        );
+    }
+
+    /// Waits until everything the (now dead) inferior printed has been relayed.
+    fn flush_output(&mut self) {
+        for handle in self.output_threads.drain(..) {
+            let _ = handle.join();
+        }
+    }
+
+    fn stack_trace(&mut self) -> Result<Value, String> {
+        self.frames = self.session()?.backtrace().map_err(|err| err.to_string())?;
+        let frames: Vec<Value> = self
+            .frames
+            .iter()
+            .enumerate()
+            .map(|(id, frame)| {
+                let mut value = json!({
+                    "id": id,
+                    "name": frame.function.clone().unwrap_or_else(|| "??".to_string()),
+                    "line": 0,
+                    "column": 0,
+                    "instructionPointerReference": format!("{:#x}", frame.rip),
+                });
+                if let Some(line) = &frame.line {
+                    value["line"] = json!(line.number);
+                    value["source"] = json!({
+                        "name": line.file.rsplit('/').next().unwrap_or(&line.file),
+                        "path": line.file,
+                    });
+                }
+                value
+            })
+            .collect();
+        Ok(json!({ "stackFrames": frames, "totalFrames": frames.len() }))
+    }
+
+    // Each frame has two scopes, numbered so that variablesReference identifies both the frame
+    // and the scope: 2 * id + 1 for locals and 2 * id + 2 for globals. 0 means "no children".
+    fn scopes(&self, args: &Value) -> Result<Value, String> {
+        let id = args["frameId"].as_u64().unwrap_or(0) as usize;
+        if id >= self.frames.len() {
+            return Err(format!("Unknown frame {}", id));
+        }
+        Ok(json!({
+            "scopes": [
+                { "name": "Locals", "variablesReference": 2 * id + 1, "expensive": false },
+                { "name": "Globals", "variablesReference": 2 * id + 2, "expensive": false },
+            ]
+        }))
+    }
+
+    fn variables(&self, args: &Value) -> Result<Value, String> {
+        let reference = args["variablesReference"].as_u64().unwrap_or(0) as usize;
+        if reference == 0 {
+            return Ok(json!({ "variables": [] }));
+        }
+        let frame = self
+            .frames
+            .get((reference - 1) / 2)
+            .ok_or_else(|| format!("Unknown variables reference {}", reference))?;
+        let session = self.session()?;
+        let debug_data = session.debug_data();
+        let variables: Vec<&Variable> = if reference % 2 == 1 {
+            frame.variables(debug_data)
+        } else {
+            // Only the globals of the frame's own unit: listing every unit's would parse all of
+            // them each time the program stops
+            debug_data.get_global_variables(frame.pc())
+        };
+        let variables: Vec<Value> = variables
+            .into_iter()
+            .map(|var| {
+                let value = session
+                    .read_variable(var, frame)
+                    .unwrap_or_else(|err| format!("<error: {}>", err));
+                json!({
+                    "name": var.name,
+                    "value": value,
+                    "type": var.entity_type.name,
+                    "variablesReference": 0,
+                })
+            })
+            .collect();
+        Ok(json!({ "variables": variables }))
+    }
+}
@@ -0,0 +1,390 @@
+//! Finds debugging information that lives outside the executable: separate debug files (found by
+//! build ID or `.gnu_debuglink`), supplementary files shared between several debug files (found by
+//! `.gnu_debugaltlink` or `.debug_sup`), and the `.dwo`/`.dwp` files written by `-gsplit-dwarf`.
+
+use object::Object;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Where distributions install separate debug files.
+const DEBUG_DIRECTORY: &str = "/usr/lib/debug";
+
+/// Returns the contents of the GNU build ID note, which identifies the build an executable or debug
+/// file came from.
+fn build_id(object: &object::File) -> Option<Vec<u8>> {
+    let note = object.section_data_by_name(".note.gnu.build-id")?;
+    let word = |offset: usize| -> Option<usize> {
+        let bytes = note.get(offset..offset + 4)?;
+        let bytes = bytes.try_into().ok()?;
+        Some(if object.is_little_endian() {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        } as usize)
+    };
+    // namesz, descsz and type, then the name ("GNU\0") padded to 4 bytes, then the ID itself
+    let (name_size, desc_size) = (word(0)?, word(4)?);
+    let desc_start = 12 + (name_size + 3) / 4 * 4;
+    Some(note.get(desc_start..desc_start + desc_size)?.to_vec())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Returns the path a debug file with this build ID would be installed at, e.g.
+/// `/usr/lib/debug/.build-id/ab/cdef1234.debug`.
+fn build_id_path(build_id: &[u8], suffix: &str) -> Option<PathBuf> {
+    let (first, rest) = build_id.split_first()?;
+    Some(
+        Path::new(DEBUG_DIRECTORY)
+            .join(".build-id")
+            .join(hex(&[*first]))
+            .join(format!("{}{}", hex(rest), suffix)),
+    )
+}
+
+/// Reads a section holding a NUL-terminated file name followed by some data, as used by
+/// `.gnu_debuglink` and `.gnu_debugaltlink`. The data starts at the next multiple of `align`.
+fn read_link(object: &object::File, section: &str, align: usize) -> Option<(String, Vec<u8>)> {
+    let data = object.section_data_by_name(section)?;
+    let name_len = data.iter().position(|&b| b == 0)?;
+    let name = String::from_utf8_lossy(&data[..name_len]).into_owned();
+    let rest = data.get((name_len + align) / align * align..)?;
+    Some((name, rest.to_vec()))
+}
+
+/// Finds the separate debug file for an executable whose DWARF has been stripped out, the way gdb
+/// does: first by build ID, then by the name in `.gnu_debuglink` in the executable's directory, its
+/// .debug subdirectory, and the same directory under /usr/lib/debug.
+pub fn find_debug_file(executable: &Path, object: &object::File) -> Option<PathBuf> {
+    if let Some(path) = build_id(object).and_then(|id| build_id_path(&id, ".debug")) {
+        if path.is_file() {
+            return Some(path);
+        }
+    }
+
+    let (name, crc) = read_link(object, ".gnu_debuglink", 4)?;
+    let crc = u32::from_le_bytes(crc.get(..4)?.try_into().ok()?);
+    let executable = executable.canonicalize().ok()?;
+    let dir = executable.parent()?;
+    let candidates = vec![
+        dir.join(&name),
+        dir.join(".debug").join(&name),
+        Path::new(DEBUG_DIRECTORY)
+            .join(dir.strip_prefix("/").ok()?)
+            .join(&name),
+    ];
+    candidates.into_iter().find(|path| {
+        // The link may name the executable itself; a CRC mismatch means it's for a different build
+        *path != executable && std::fs::read(path).map_or(false, |contents| crc32(&contents) == crc)
+    })
+}
+
+/// Finds the supplementary file holding DWARF shared between several debug files, as produced by
+/// `dwz -m`. GNU tools name it in `.gnu_debugaltlink` and DWARF 5 in `.debug_sup`.
+pub fn find_supplementary_file(debug_file: &Path, object: &object::File) -> Option<PathBuf> {
+    let name = match read_link(object, ".gnu_debugaltlink", 1) {
+        Some((name, build_id)) => {
+            let by_build_id = build_id_path(&build_id, ".debug");
+            if let Some(path) = by_build_id.filter(|path| path.is_file()) {
+                return Some(path);
+            }
+            name
+        }
+        None => {
+            // version (2 bytes), is_supplementary (1 byte), then the file name
+            let data = object.section_data_by_name(".debug_sup")?;
+            let rest = data.get(3..)?;
+            let name_len = rest.iter().position(|&b| b == 0)?;
+            String::from_utf8_lossy(&rest[..name_len]).into_owned()
+        }
+    };
+    let path = debug_file.parent()?.join(name);
+    if path.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// Finds the `.dwo` file a skeleton unit refers to. Relative names are relative to the
+/// compilation directory, but we also look next to the executable in case the build directory
+/// has moved.
+fn find_dwo(executable: &Path, comp_dir: Option<&str>, dwo_name: &str) -> Option<PathBuf> {
+    let mut candidates = Vec::new();
+    if let Some(comp_dir) = comp_dir {
+        candidates.push(Path::new(comp_dir).join(dwo_name));
+    }
+    candidates.push(PathBuf::from(dwo_name));
+    if let (Some(dir), Some(file_name)) = (executable.parent(), Path::new(dwo_name).file_name()) {
+        candidates.push(dir.join(file_name));
+    }
+    candidates.into_iter().find(|path| path.is_file())
+}
+
+/// Finds the `.dwp` package that `dwp` writes next to an executable, e.g. `prog.dwp` for `prog`.
+fn find_dwp(executable: &Path) -> Option<PathBuf> {
+    let mut name = executable.file_name()?.to_os_string();
+    name.push(".dwp");
+    let path = executable.with_file_name(name);
+    if path.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// The sections `-gsplit-dwarf` moves out of the executable.
+const SPLIT_SECTIONS: &[&str] = &[
+    ".debug_info.dwo",
+    ".debug_abbrev.dwo",
+    ".debug_line.dwo",
+    ".debug_loc.dwo",
+    ".debug_loclists.dwo",
+    ".debug_rnglists.dwo",
+    ".debug_str.dwo",
+    ".debug_str_offsets.dwo",
+];
+
+/// The split DWARF sections of a `.dwo` file, or one unit's share of them in a `.dwp` package.
+pub type SplitSections = HashMap<&'static str, Arc<[u8]>>;
+
+/// Reads the split DWARF sections out of a `.dwo` file.
+fn load_dwo(object: &object::File) -> SplitSections {
+    SPLIT_SECTIONS
+        .iter()
+        .filter_map(|&name| Some((name, Arc::from(&*object.section_data_by_name(name)?))))
+        .collect()
+}
+
+/// Where to find the split DWARF of an executable's skeleton units.
+pub struct SplitDwarf {
+    executable: PathBuf,
+    package: Option<DwarfPackage>,
+}
+
+impl SplitDwarf {
+    pub fn new(executable: &Path) -> SplitDwarf {
+        let package = find_dwp(executable).and_then(|path| {
+            let mmap = map_file(&path)?;
+            DwarfPackage::load(&object::File::parse(&*mmap).ok()?)
+        });
+        SplitDwarf {
+            executable: executable.to_path_buf(),
+            package,
+        }
+    }
+
+    /// Returns the sections of a split unit, from the program's .dwp package if it has one or else
+    /// from the named .dwo file.
+    pub fn sections(
+        &self,
+        comp_dir: Option<&str>,
+        dwo_name: &str,
+        dwo_id: Option<u64>,
+    ) -> Option<SplitSections> {
+        if let (Some(package), Some(dwo_id)) = (&self.package, dwo_id) {
+            if let Some(sections) = package.unit(dwo_id) {
+                return Some(sections);
+            }
+        }
+        let mmap = map_file(&find_dwo(&self.executable, comp_dir, dwo_name)?)?;
+        Some(load_dwo(&object::File::parse(&*mmap).ok()?))
+    }
+}
+
+fn map_file(path: &Path) -> Option<memmap::Mmap> {
+    let file = std::fs::File::open(path).ok()?;
+    unsafe { memmap::Mmap::map(&file).ok() }
+}
+
+/// dwo ID -> (section, offset, size) for each section the unit contributes to
+type UnitIndex = HashMap<u64, Vec<(&'static str, usize, usize)>>;
+
+/// A `.dwp` package: the `.dwo` files of a whole program, concatenated section by section, with
+/// an index saying where each unit's contribution to each section is.
+struct DwarfPackage {
+    sections: SplitSections,
+    units: UnitIndex,
+}
+
+impl DwarfPackage {
+    fn load(object: &object::File) -> Option<DwarfPackage> {
+        let index = object.section_data_by_name(".debug_cu_index")?;
+        let little_endian = object.is_little_endian();
+        let units = read_cu_index(&index, little_endian)?;
+        Some(DwarfPackage {
+            sections: load_dwo(object),
+            units,
+        })
+    }
+
+    /// Returns the sections of the unit with the given dwo ID. Sections shared by every unit, like
+    /// .debug_str.dwo, are returned whole.
+    fn unit(&self, dwo_id: u64) -> Option<SplitSections> {
+        let mut sections = self.sections.clone();
+        for &(name, offset, size) in self.units.get(&dwo_id)? {
+            let data = self.sections.get(name)?.get(offset..offset + size)?;
+            sections.insert(name, Arc::from(data));
+        }
+        Some(sections)
+    }
+}
+
+/// Parses `.debug_cu_index` (section 7.3.5 of the DWARF 5 standard, or the GNU version 2 format
+/// it was based on).
+fn read_cu_index(data: &[u8], little_endian: bool) -> Option<UnitIndex> {
+    let read_u32 = |offset: usize| -> Option<u32> {
+        let bytes = data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    };
+    let read_u64 = |offset: usize| -> Option<u64> {
+        let bytes = data.get(offset..offset + 8)?.try_into().ok()?;
+        Some(if little_endian {
+            u64::from_le_bytes(bytes)
+        } else {
+            u64::from_be_bytes(bytes)
+        })
+    };
+
+    // Version 5 has a 2-byte version followed by 2 bytes of padding, version 2 a 4-byte version
+    let version = if read_u32(0)? == 2 { 2 } else { 5 };
+    let column_count = read_u32(4)? as usize;
+    let unit_count = read_u32(8)? as usize;
+    let slot_count = read_u32(12)? as usize;
+    let section_name = |id: u32| -> Option<&'static str> {
+        Some(match (version, id) {
+            (_, 1) => ".debug_info.dwo",
+            (_, 3) => ".debug_abbrev.dwo",
+            (_, 4) => ".debug_line.dwo",
+            (2, 5) => ".debug_loc.dwo",
+            (5, 5) => ".debug_loclists.dwo",
+            (_, 6) => ".debug_str_offsets.dwo",
+            (5, 8) => ".debug_rnglists.dwo",
+            _ => return None,
+        })
+    };
+
+    let hashes = 16;
+    let indices = hashes + 8 * slot_count;
+    let columns = indices + 4 * slot_count;
+    let offsets = columns + 4 * column_count;
+    let sizes = offsets + 4 * column_count * unit_count;
+
+    let mut units = HashMap::new();
+    for slot in 0..slot_count {
+        let dwo_id = read_u64(hashes + 8 * slot)?;
+        // Rows are numbered from 1; 0 marks an empty slot
+        let row = read_u32(indices + 4 * slot)? as usize;
+        if row == 0 {
+            continue;
+        }
+        let mut contributions = Vec::new();
+        for column in 0..column_count {
+            let cell = 4 * ((row - 1) * column_count + column);
+            if let Some(name) = section_name(read_u32(columns + 4 * column)?) {
+                let offset = read_u32(offsets + cell)? as usize;
+                let size = read_u32(sizes + cell)? as usize;
+                contributions.push((name, offset, size));
+            }
+        }
+        units.insert(dwo_id, contributions);
+    }
+    Some(units)
+}
+
+/// The CRC-32 used by `.gnu_debuglink` (the same one zlib computes).
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    /// Checks that function_calls' functions and lines are found in a copy of it that keeps its
+    /// DWARF somewhere else.
+    fn assert_finds_functions_and_lines(name: &str) {
+        let mut debug_data = testing::debug_data(name);
+        assert!(debug_data.take_warnings().is_empty());
+        let func2 = debug_data.get_function("func2").unwrap();
+        assert_eq!(func2.line_number, 14);
+        assert_eq!(
+            debug_data.get_line_from_addr(func2.address).unwrap().number,
+            14
+        );
+        // printf("sum = %d\n", sum)
+        let addr = debug_data.get_addr_for_line(None, 17).unwrap();
+        assert_eq!(
+            debug_data.get_function_from_addr(addr).as_deref(),
+            Some("func2")
+        );
+        assert_eq!(
+            debug_data
+                .get_global_variable("global")
+                .unwrap()
+                .line_number,
+            3
+        );
+    }
+
+    #[test]
+    fn debug_file_is_found_by_debuglink() {
+        let executable = PathBuf::from(testing::sample("debuglink"));
+        let mmap = map_file(&executable).unwrap();
+        let object = object::File::parse(&*mmap).unwrap();
+        assert!(object.section_data_by_name(".debug_info").is_none());
+        assert_eq!(
+            find_debug_file(&executable, &object),
+            Some(executable.with_extension("debug").canonicalize().unwrap())
+        );
+        assert_finds_functions_and_lines("debuglink");
+    }
+
+    #[test]
+    fn build_id_names_the_debug_file() {
+        let mmap = map_file(Path::new(&testing::sample("debuglink"))).unwrap();
+        let object = object::File::parse(&*mmap).unwrap();
+        // GNU ld makes SHA-1 build IDs
+        assert_eq!(build_id(&object).unwrap().len(), 20);
+        assert_eq!(
+            build_id_path(&[0xab, 0xcd, 0x01], ".debug"),
+            Some(PathBuf::from("/usr/lib/debug/.build-id/ab/cd01.debug"))
+        );
+        assert_eq!(build_id_path(&[], ".debug"), None);
+    }
+
+    #[test]
+    fn split_dwarf_is_read_from_the_dwo_file() {
+        assert_finds_functions_and_lines("split_dwarf");
+    }
+
+    #[test]
+    fn split_dwarf_is_read_from_the_dwp_package() {
+        assert!(
+            SplitDwarf::new(Path::new(&testing::sample("split_dwarf_package")))
+                .package
+                .is_some()
+        );
+        assert_finds_functions_and_lines("split_dwarf_package");
+    }
+}
@@ -1,62 +1,141 @@
-use crate::debugger_command::DebuggerCommand;
-use crate::dwarf_data::{DwarfData, Error as DwarfError};
-use crate::inferior::Inferior;
-use crate::inferior::Status;
+use crate::completion::CommandHelper;
+use crate::debugger_command::{self, DebuggerCommand};
+use crate::tui::{Layout, Tui};
+use nix::sys::signal::Signal;
+use qeet::disassemble::disassemble;
+use qeet::dwarf_data::{DwarfData, Location, Variable};
+use qeet::error::Error;
+use qeet::heap::HeapError;
+use qeet::inferior::{self, Catchpoint, Event, WatchKind};
+use qeet::pretty::Printers;
+use qeet::record::ReplayStatus;
+use qeet::session::{self, parse_address, BreakpointEntry, Session, Step, Stop};
+use qeet::syscalls;
+use qeet::target::{regs_to_words, Frame, Target, REGISTER_NAMES};
+use regex::Regex;
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
-use std::collections::HashMap;
-
-fn parse_address(addr: &str) -> Option<usize> {
-    let addr_without_0x = if addr.to_lowercase().starts_with("0x") {
-        &addr[2..]
-    } else {
-        &addr
-    };
-    usize::from_str_radix(addr_without_0x, 16).ok()
-}
+use std::path::Path;
+use std::str::FromStr;
 
+/// The deet command line: reads commands, carries them out on a `Session`, and prints what
+/// happened.
 pub struct Debugger {
     target: String,
     history_path: String,
-    readline: Editor<()>,
-    inferior: Option<Inferior>,
-    debug_data: DwarfData,
-    breakpoints: HashMap<usize, u8>,
+    readline: Editor<CommandHelper>,
+    session: Session,
+    /// Expressions printed every time the inferior stops, by display number.
+    /// The panes drawn above the prompt in --tui mode.
+    tui: Option<Tui>,
+    prompt: String,
+    /// The last command that moved through the program, which an empty line repeats.
+    last_command: Option<String>,
 }
 
 impl Debugger {
     /// Initializes the debugger.
     pub fn new(target: &str) -> Debugger {
-        // #begin: Initializes DwarfData object
-        let debug_data = match DwarfData::from_file(target) {
-            Ok(val) => val,
-            Err(DwarfError::ErrorOpeningFile) => {
-                println!("Could not open file {}", target);
-                std::process::exit(1);
-            }
-            Err(DwarfError::DwarfFormatError(err)) => {
-                println!("Could not debugging symbols from {}: {:?}", target, err);
+        let mut session = match Session::new(target) {
+            Ok(session) => session,
+            Err(err) => {
+                println!("{}", err);
                 std::process::exit(1);
             }
         };
-        debug_data.print();
-        // #end
+        for warning in session.take_warnings() {
+            println!("{}", warning);
+        }
 
         let history_path = format!("{}/.deet_history", std::env::var("HOME").unwrap());
-        let mut readline = Editor::<()>::new();
+        let mut readline = Editor::<CommandHelper>::new();
         // Attempt to load history from ~/.deet_history if it exists
         let _ = readline.load_history(&history_path);
+        let debug_data = session.debug_data();
+        let symbol_names = |functions: bool| {
+            debug_data
+                .symbols()
+                .iter()
+                .filter(|symbol| symbol.is_function == functions && !symbol.name.is_empty())
+                .map(|symbol| symbol.name.clone())
+                .collect()
+        };
+        let files = debug_data
+            .unit_names()
+            .iter()
+            .filter_map(|name| Path::new(name).file_name()?.to_str())
+            .map(|name| name.to_string())
+            .collect();
+        readline.set_helper(Some(CommandHelper::new(
+            symbol_names(true),
+            symbol_names(false),
+            files,
+        )));
+
+        let (printers, result) = Printers::with_config();
+        if let Err(err) = result {
+            println!("Error loading pretty-printers: {}", err);
+        }
+        session.printers = printers;
 
-        // #begin: return Instantiated Object
         Debugger {
             target: target.to_string(),
             // target here is the X of "cargo run X",
             // like "samples/sleepy_print".
             history_path,
             readline,
-            inferior: None,
-            debug_data: debug_data,
-            breakpoints: HashMap::new(),
+            session,
+            tui: None,
+            prompt: "(qeet) ".to_string(),
+            last_command: None,
+        }
+    }
+
+    /// Switches to the --tui display, which shows the source above the prompt.
+    pub fn enable_tui(&mut self) {
+        self.tui = Some(Tui::new());
+        self.refresh_tui();
+    }
+
+    /// Makes `run` track the program's allocations, reporting bad frees as they happen and leaks
+    /// when the program exits.
+    pub fn enable_memory_checking(&mut self) {
+        self.session.check_memory = true;
+    }
+
+    fn debug_data(&self) -> &DwarfData {
+        self.session.debug_data()
+    }
+
+    fn refresh_tui(&self) {
+        if let Some(tui) = &self.tui {
+            tui.refresh(
+                self.debug_data(),
+                self.session.target(),
+                self.session.breakpoints(),
+            );
+        }
+    }
+
+    /// Loads a core file to examine instead of running the target.
+    pub fn load_core(&mut self, path: &str) {
+        let core = match self.session.load_core(path) {
+            Ok(core) => core,
+            Err(err) => {
+                println!("Could not load core file {}: {:?}", path, err);
+                return;
+            }
+        };
+        println!(
+            "Core was generated by `{}' (pid {}).",
+            core.program.as_deref().unwrap_or(&self.target),
+            core.pid
+        );
+        if core.signal != 0 {
+            println!("Program terminated with signal {}.", core.signal);
+        }
+        if let Ok(regs) = core.registers() {
+            self.print_stop_location(regs.rip as usize);
         }
     }
 
@@ -66,180 +145,1238 @@ impl Debugger {
                 // (deet) captured r 3, do a match, and let args = ["3"]
                 DebuggerCommand::Run(args) => {
                     // #begin: kill the inferior if it exists
-                    if self.inferior.is_some() {
-                        self.inferior.as_mut().unwrap().kill();
-                        self.inferior = None;
-                    }
+                    self.kill_inferior();
                     // #end
 
                     // #begin: try to instantiate a inferior
-                    if let Some(inferior) =
-                        Inferior::new(&self.target, &args, &mut self.breakpoints)
-                    {
-                        // Create the inferior
-                        self.inferior = Some(inferior);
-                        // #begin: run inferior until inferior's state occurs to change.
-                        // use self.inferior.as_mut().unwrap() to get a mutable reference to the Inferior object
-                        match self
-                            .inferior
-                            .as_mut()
-                            .unwrap()
-                            .continue_run(None, &mut self.breakpoints)
-                            .unwrap()
-                        {
-                            Status::Exited(exit_code) => {
-                                println!("Child exited (status {})", exit_code);
-                                self.inferior = None;
-                            }
-                            Status::Signaled(signal) => {
-                                println!("Child exited due to signal {}", signal);
-                                self.inferior = None;
-                            }
-                            Status::Stopped(signal, rip) => {
-                                println!("Child stopped (signal {})", signal);
-                                let _line = self.debug_data.get_line_from_addr(rip);
-                                let _func = self.debug_data.get_function_from_addr(rip);
-                                if _line.is_some() && _func.is_some() {
-                                    println!("Stopped at {} ({})", _func.unwrap(), _line.unwrap());
-                                }
-                            }
+                    if !args.is_empty() {
+                        self.session.launch.args = args;
+                    }
+                    match self.session.spawn() {
+                        Ok(()) => {
+                            // run inferior until inferior's state occurs to change.
+                            let stop = self.session.resume();
+                            self.report(stop);
+                        }
+                        Err(err) => {
+                            self.print_warnings();
+                            println!("{}", err);
                         }
-                        // #end
-                    } else {
-                        println!("Error starting subprocess");
                     }
                     // #end
                 }
 
                 DebuggerCommand::Continue => {
-                    if let None = &mut self.inferior {
-                        println!("Error: Inferior doesn't exist");
-                        continue;
-                    }
-                    // #begin: run inferior until inferior's state occurs to change.
-                    match self
-                        .inferior
-                        .as_mut()
-                        .unwrap()
-                        .continue_run(None, &mut self.breakpoints)
-                        .unwrap()
-                    {
-                        Status::Exited(exit_code) => {
-                            println!("Child exited (status {})", exit_code);
-                            self.inferior = None;
-                        }
-                        Status::Signaled(signal) => {
-                            println!("Child exited due to signal {}", signal);
-                            self.inferior = None;
-                        }
-                        Status::Stopped(signal, rip) => {
-                            println!("Child stopped (signal {})", signal);
-                            let _line = self.debug_data.get_line_from_addr(rip);
-                            let _func = self.debug_data.get_function_from_addr(rip);
-                            if _line.is_some() && _func.is_some() {
-                                println!("Stopped at {} ({})", _func.unwrap(), _line.unwrap());
-                            }
-                        }
+                    // run inferior until inferior's state occurs to change, passing on any signal
+                    // it stopped with
+                    let stop = self.session.resume();
+                    self.report(stop);
+                }
+
+                DebuggerCommand::Next => self.step(Step::Over),
+                DebuggerCommand::Step => self.step(Step::Into),
+                DebuggerCommand::StepInstruction => self.step(Step::Instruction),
+
+                DebuggerCommand::Record => match self.session.start_recording() {
+                    Ok(()) => println!("Recording started. Execution will be single-stepped."),
+                    Err(Error::NotRunning) => println!("Error: the program is not being run"),
+                    Err(err) => println!("Error: could not start recording: {}", err),
+                },
+
+                DebuggerCommand::RecordStop => {
+                    if self.session.stop_recording() {
+                        println!("Process record is stopped and all execution logs are deleted.");
+                    } else {
+                        println!("Error: process record is not started");
                     }
-                    // #end
                 }
 
+                DebuggerCommand::ReverseStepInstruction => {
+                    let status = self.session.reverse_step_instruction();
+                    self.report_replay(status);
+                }
+
+                DebuggerCommand::ReverseContinue => {
+                    let status = self.session.reverse_continue();
+                    self.report_replay(status);
+                }
+
+                DebuggerCommand::ReverseNext => {
+                    let status = self.session.reverse_next();
+                    self.report_replay(status);
+                }
+
+                DebuggerCommand::Help(topic) => match debugger_command::help(&topic) {
+                    Ok(text) => print!("{}", text),
+                    Err(message) => println!("{}", message),
+                },
+
                 DebuggerCommand::Quit => {
                     // #begin: kill the inferior if it exists
-                    if self.inferior.is_some() {
-                        self.inferior.as_mut().unwrap().kill();
-                        self.inferior = None;
-                    }
+                    self.kill_inferior();
                     // #end
                     return;
                 }
 
                 DebuggerCommand::Backtrace => {
-                    if self.inferior.is_none() {
-                        println!(
-                            "Error: you can not use backtrace when there is no process running"
-                        );
-                        continue;
+                    let target = match self.target() {
+                        Some(target) => target,
+                        None => {
+                            println!(
+                                "Error: you can not use backtrace when there is no process running"
+                            );
+                            continue;
+                        }
+                    };
+                    match target.backtrace(self.debug_data()) {
+                        Ok(frames) => {
+                            for frame in frames {
+                                println!("{}", frame);
+                            }
+                        }
+                        Err(error) => println!("{}", error),
                     }
+                }
+
+                DebuggerCommand::Print(expr) if expr.contains('(') => {
+                    self.call_function(&expr, true)
+                }
+                DebuggerCommand::Print(name) => self.print_variable(&name),
+                DebuggerCommand::Call(expr) => self.call_function(&expr, false),
 
-                    if let Err(error) = self
-                        .inferior
-                        .as_mut()
-                        .unwrap()
-                        .print_backtrace(&self.debug_data)
-                    {
-                        println!("{}", error);
+                DebuggerCommand::Examine(format, address) => self.examine(&format, &address),
+
+                DebuggerCommand::InfoRegisters => self.print_registers(),
+
+                DebuggerCommand::Display(Some(expr)) => {
+                    let number = self.session.add_display(&expr);
+                    if self.target().is_some() {
+                        self.do_displays(Some(number));
                     }
                 }
 
-                DebuggerCommand::Break(location) => {
-                    let breakpoint_addr;
-                    if location.starts_with("*") {
-                        if let Some(address) = parse_address(&location[1..]) {
-                            breakpoint_addr = address;
-                        } else {
-                            println!("Invalid address");
+                DebuggerCommand::Display(None) => self.do_displays(None),
+
+                DebuggerCommand::Undisplay(number) => {
+                    if let Err(err) = self.session.undisplay(number) {
+                        println!("{}", err);
+                    }
+                }
+
+                DebuggerCommand::InfoFunctions(regex) => self.info_functions(regex.as_deref()),
+                DebuggerCommand::InfoVariables(regex) => self.info_variables(regex.as_deref()),
+                DebuggerCommand::InfoLine(location) => self.info_line(&location),
+                DebuggerCommand::InfoSymbol(address) => self.info_symbol(&address),
+                DebuggerCommand::InfoScope(location) => self.info_scope(&location),
+                DebuggerCommand::InfoProcMappings => self.info_proc_mappings(),
+                DebuggerCommand::Watch(expr) => self.watch(&expr),
+                DebuggerCommand::Unwatch(number) => {
+                    let is_watchpoint = self.session.breakpoint_table().iter().any(|(n, entry)| {
+                        *n == number && matches!(entry, BreakpointEntry::Watch(_))
+                    });
+                    if is_watchpoint {
+                        self.delete(&[number]);
+                    } else {
+                        println!("No watchpoint number {}.", number);
+                    }
+                }
+                DebuggerCommand::Delete(numbers) => {
+                    self.delete(&numbers);
+                    self.refresh_tui();
+                }
+                DebuggerCommand::InfoBreakpoints => self.info_breakpoints(false),
+                DebuggerCommand::InfoWatchpoints => self.info_breakpoints(true),
+                DebuggerCommand::InfoHeap => self.info_heap(),
+                DebuggerCommand::Checkpoint => self.checkpoint(),
+                DebuggerCommand::Restart(number) => self.restart(number),
+
+                DebuggerCommand::InfoCheckpoints => {
+                    if self.session.checkpoints().is_empty() {
+                        println!("No checkpoints.");
+                    }
+                    for (number, checkpoint) in self.session.checkpoints() {
+                        let line = self.debug_data().get_line_from_addr(checkpoint.rip);
+                        let func = self.debug_data().get_function_from_addr(checkpoint.rip);
+                        match (func, line) {
+                            (Some(func), Some(line)) => println!(
+                                "{:<3} process {} at {:#x}, {} ({})",
+                                number, checkpoint.pid, checkpoint.rip, func, line
+                            ),
+                            _ => println!(
+                                "{:<3} process {} at {:#x}",
+                                number, checkpoint.pid, checkpoint.rip
+                            ),
+                        }
+                    }
+                }
+
+                DebuggerCommand::InfoPrettyPrinter => {
+                    for printer in self.session.printers.describe() {
+                        println!("  {}", printer);
+                    }
+                }
+
+                DebuggerCommand::InfoDisplay => {
+                    if self.session.displays().is_empty() {
+                        println!("There are no auto-display expressions now.");
+                    } else {
+                        println!("Auto-display expressions now in effect:");
+                        println!("Num Expression");
+                        for (number, expr) in self.session.displays() {
+                            println!("{:<3} {}", number, expr);
+                        }
+                    }
+                }
+
+                DebuggerCommand::Gcore(path) => {
+                    let pid = match self.session.pid() {
+                        Some(pid) => pid,
+                        None => {
+                            println!("Error: gcore needs a running process");
                             continue;
                         }
-                    } else if let Some(line) = usize::from_str_radix(&location, 10).ok() {
-                        //? str_radixq
-                        println!("{}", line);
-                        if let Some(address) = self
-                            .debug_data
-                            .get_addr_for_line(Some(&format!("{}.c", &self.target)), line)
-                        {
-                            breakpoint_addr = address;
+                    };
+                    let path = path.unwrap_or_else(|| format!("core.{}", pid));
+                    match self.session.generate_core(&path) {
+                        Ok(regions) => {
+                            println!("Saved corefile {} ({} memory regions)", path, regions)
+                        }
+                        Err(err) => println!("Error writing core file {}: {}", path, err),
+                    }
+                }
+
+                DebuggerCommand::Catch(kind, arg) => self.add_catchpoint(&kind, arg.as_deref()),
+
+                DebuggerCommand::Break(location) => {
+                    self.set_breakpoint(&location, false);
+                    self.refresh_tui();
+                }
+
+                DebuggerCommand::TemporaryBreak(location) => {
+                    self.set_breakpoint(&location, true);
+                    self.refresh_tui();
+                }
+
+                DebuggerCommand::Layout(name) => match (&self.tui, Layout::from_name(&name)) {
+                    (None, _) => println!("The layout can only be changed in --tui mode."),
+                    (Some(_), None) => println!("Unknown layout \"{}\" (src, asm or regs)", name),
+                    (Some(tui), Some(layout)) => {
+                        tui.set_layout(layout);
+                        self.refresh_tui();
+                    }
+                },
+
+                DebuggerCommand::Disassemble(location) => self.disassemble(location.as_deref()),
+                DebuggerCommand::Set(setting, value) => self.set(&setting, &value),
+                DebuggerCommand::Show(setting) => self.show(&setting),
+                DebuggerCommand::SetArgs(args) => self.session.launch.args = args,
+
+                DebuggerCommand::ShowEnvironment(None) => {
+                    for (name, value) in &self.session.launch.environment {
+                        println!("{}={}", name, value);
+                    }
+                }
+                DebuggerCommand::ShowEnvironment(Some(name)) => {
+                    match self.session.launch.environment.get(&name) {
+                        Some(value) => println!("{} = {}", name, value),
+                        None => println!("Environment variable \"{}\" not defined.", name),
+                    }
+                }
+                DebuggerCommand::UnsetEnvironment(None) => self.session.launch.environment.clear(),
+                DebuggerCommand::UnsetEnvironment(Some(name)) => {
+                    self.session.launch.environment.remove(&name);
+                }
+
+                DebuggerCommand::Until(location) => self.run_until(&location),
+            }
+        }
+    }
+
+    /// Resolves a location as accepted by `break`: `*address`, a line number, or a function name.
+    fn resolve_location(&self, location: &str) -> Result<usize, Error> {
+        self.session.resolve_location(location)
+    }
+
+    /// Implements `break` and `tbreak`. A function gets a breakpoint on its out-of-line copy and
+    /// on every copy of it inlined elsewhere.
+    fn set_breakpoint(&mut self, location: &str, temporary: bool) {
+        let addrs = match self.session.breakpoint_addresses(location) {
+            Ok(addrs) => addrs,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+        let kind = if temporary {
+            "temporary breakpoint"
+        } else {
+            "breakpoint"
+        };
+        for addr in addrs {
+            if let Some(number) = self.session.breakpoint_number(addr) {
+                println!("Breakpoint {} is already at {:#x}", number, addr);
+                continue;
+            }
+            match self.session.add_breakpoint(addr, temporary) {
+                Ok(number) => println!("Set {} {} at {:#x}", kind, number, addr),
+                Err(_) => println!("Invalid breakpoint address {:#x}", addr),
+            }
+        }
+    }
+
+    /// Implements `delete [numbers...]`, which without numbers deletes every breakpoint and
+    /// watchpoint.
+    fn delete(&mut self, numbers: &[usize]) {
+        let numbers: Vec<usize> = if numbers.is_empty() {
+            let table = self.session.breakpoint_table();
+            table.iter().map(|(number, _)| *number).collect()
+        } else {
+            numbers.to_vec()
+        };
+        for number in numbers {
+            match self.session.delete(number) {
+                Ok(()) => {}
+                Err(err @ Error::NoBreakpoint(_)) => println!("{}", err),
+                Err(err) => println!("Could not delete breakpoint {}: {}", number, err),
+            }
+        }
+    }
+
+    /// Implements `info breakpoints`, or `info watchpoints` with `watchpoints_only`.
+    fn info_breakpoints(&self, watchpoints_only: bool) {
+        let entries: Vec<&(usize, BreakpointEntry)> = self
+            .session
+            .breakpoint_table()
+            .iter()
+            .filter(|(_, entry)| !watchpoints_only || matches!(entry, BreakpointEntry::Watch(_)))
+            .collect();
+        if entries.is_empty() {
+            if watchpoints_only {
+                println!("No watchpoints.");
+            } else {
+                println!("No breakpoints, watchpoints or catchpoints.");
+            }
+            return;
+        }
+        println!("Num Type           Address            What");
+        for (number, entry) in entries {
+            let (kind, addr, what) = match entry {
+                BreakpointEntry::Code(addr) => {
+                    let breakpoint = self.session.breakpoints().get(addr);
+                    let temporary = breakpoint.map_or(false, |bp| bp.temporary);
+                    let kind = if temporary { "tbreakpoint" } else { "breakpoint" };
+                    let what = match self.debug_data().get_line_from_addr(*addr) {
+                        Some(line) => format!("{}", line),
+                        None => self.describe_address(*addr).unwrap_or_default(),
+                    };
+                    (kind, format!("{:#018x}", addr), what)
+                }
+                BreakpointEntry::Watch(watch) => {
+                    let kind = match self.session.watch_kind(*number) {
+                        Some(WatchKind::Hardware(_)) => "hw watchpoint",
+                        Some(WatchKind::Software) => "sw watchpoint",
+                        None => "watchpoint",
+                    };
+                    (kind, format!("{:#018x}", watch.addr), watch.expr.clone())
+                }
+                BreakpointEntry::Catch(catch) => ("catchpoint", String::new(), catch.to_string()),
+            };
+            println!("{:<3} {:<14} {:<18} {}", number, kind, addr, what);
+        }
+    }
+
+    /// Implements `until <location>` and `advance <location>`: runs until the location is reached
+    /// or the current function returns.
+    fn run_until(&mut self, location: &str) {
+        match self.session.run_until(location) {
+            Err(err @ Error::NotRunning) | Err(err @ Error::Location(_)) => println!("{}", err),
+            stop => self.report(stop),
+        }
+    }
+
+    /// Describes an address as symbol+offset, using DWARF functions and then ELF symbols.
+    fn describe_address(&self, addr: usize) -> Option<String> {
+        let (name, start) = match self.debug_data().get_function_containing(addr) {
+            Some(func) => (func.name.as_str(), func.address),
+            None => {
+                let symbol = self.debug_data().get_symbol_containing(addr)?;
+                (symbol.name.as_str(), symbol.address)
+            }
+        };
+        if addr == start {
+            Some(name.to_string())
+        } else {
+            Some(format!("{}+{}", name, addr - start))
+        }
+    }
+
+    /// Compiles the optional regex of `info functions`/`info variables`, reporting bad patterns.
+    fn compile_filter(regex: Option<&str>) -> Result<Option<Regex>, ()> {
+        match regex.map(Regex::new) {
+            None => Ok(None),
+            Some(Ok(regex)) => Ok(Some(regex)),
+            Some(Err(err)) => {
+                println!("Invalid regular expression: {}", err);
+                Err(())
+            }
+        }
+    }
+
+    fn info_functions(&self, regex: Option<&str>) {
+        let filter = match Debugger::compile_filter(regex) {
+            Ok(filter) => filter,
+            Err(()) => return,
+        };
+        let matches = |name: &str| filter.as_ref().map_or(true, |re| re.is_match(name));
+        match regex {
+            Some(regex) => println!("All functions matching regular expression \"{}\":", regex),
+            None => println!("All defined functions:"),
+        }
+        for (file, functions) in self.debug_data().functions_matching(&matches) {
+            println!("\nFile {}:", file.name);
+            for func in functions {
+                let params: Vec<&str> = func
+                    .parameters
+                    .iter()
+                    .map(|param| param.entity_type.name.as_str())
+                    .collect();
+                println!(
+                    "{}:\t{} {}({});",
+                    func.line_number,
+                    func.return_type
+                        .as_ref()
+                        .map_or("void", |ty| ty.name.as_str()),
+                    func.name,
+                    params.join(", ")
+                );
+            }
+        }
+        self.print_non_debugging_symbols(true, &matches);
+    }
+
+    fn info_variables(&self, regex: Option<&str>) {
+        let filter = match Debugger::compile_filter(regex) {
+            Ok(filter) => filter,
+            Err(()) => return,
+        };
+        let matches = |name: &str| filter.as_ref().map_or(true, |re| re.is_match(name));
+        match regex {
+            Some(regex) => println!("All variables matching regular expression \"{}\":", regex),
+            None => println!("All defined variables:"),
+        }
+        for (file, variables) in self.debug_data().global_variables_matching(&matches) {
+            println!("\nFile {}:", file.name);
+            for var in variables {
+                println!(
+                    "{}:\t{} {};",
+                    var.line_number, var.entity_type.name, var.name
+                );
+            }
+        }
+        self.print_non_debugging_symbols(false, &matches);
+    }
+
+    /// Lists the ELF symbols of the given kind that have no DWARF description.
+    fn print_non_debugging_symbols(&self, functions: bool, matches: &dyn Fn(&str) -> bool) {
+        let symbols = self.debug_data().undescribed_symbols(functions, matches);
+        if !symbols.is_empty() {
+            println!("\nNon-debugging symbols:");
+            for symbol in symbols {
+                println!("{:#018x}  {}", symbol.address, symbol.name);
+            }
+        }
+    }
+
+    /// Implements `info line <location>`, showing which addresses a line compiled to.
+    fn info_line(&self, location: &str) {
+        let addr = match self.resolve_location(location) {
+            Ok(addr) => addr,
+            Err(message) => {
+                println!("{}", message);
+                return;
+            }
+        };
+        let line = self.debug_data().get_line_from_addr(addr);
+        match (line, self.debug_data().get_line_range(addr)) {
+            (Some(line), Some((start, end))) => println!(
+                "Line {} of \"{}\" starts at address {:#x} <{}> and ends at {:#x} <{}>.",
+                line.number,
+                line.file,
+                start,
+                self.describe_address(start).unwrap_or_default(),
+                end,
+                self.describe_address(end).unwrap_or_default()
+            ),
+            _ => println!(
+                "No line number information available for address {:#x}",
+                addr
+            ),
+        }
+    }
+
+    /// Implements `info heap`, listing the allocations the program hasn't freed.
+    fn info_heap(&self) {
+        if self.session.pid().is_none() {
+            println!("The program is not being run.");
+            return;
+        }
+        let heap = match self.session.heap() {
+            Some(heap) => heap,
+            None => {
+                println!("The heap is only tracked with --check-memory.");
+                return;
+            }
+        };
+        let (mut blocks, mut bytes) = (0, 0);
+        for allocation in heap.live() {
+            println!("{:#x}: {} bytes", allocation.addr, allocation.size);
+            self.print_heap_stack(&allocation.stack);
+            blocks += 1;
+            bytes += allocation.size;
+        }
+        println!("{} bytes in {} blocks.", bytes, blocks);
+    }
+
+    /// With --check-memory, reports what the program didn't free before it exited, grouped by
+    /// where it was allocated. What libraries allocated for themselves (stdio buffers and the
+    /// like) is only counted, as still reachable.
+    fn report_leaks(&self) {
+        let heap = match self.session.heap() {
+            Some(heap) => heap,
+            None => return,
+        };
+        let leaks = heap.leaks(|addr| self.debug_data().get_function_from_addr(addr).is_some());
+        if leaks.leaked.is_empty() {
+            println!("No memory leaked.");
+        }
+        for leak in &leaks.leaked {
+            println!("{} bytes in {} blocks leaked, allocated", leak.bytes, leak.blocks);
+            self.print_heap_stack(&leak.stack);
+        }
+        if leaks.reachable.blocks > 0 {
+            println!(
+                "{} bytes in {} blocks allocated by libraries for themselves are still reachable.",
+                leaks.reachable.bytes, leaks.reachable.blocks
+            );
+        }
+    }
+
+    /// Prints the calls a heap function was called from, like `backtrace`, up to main.
+    fn print_heap_stack(&self, stack: &[usize]) {
+        for &addr in stack {
+            let func = self.debug_data().get_function_from_addr(addr);
+            match (&func, &self.debug_data().get_line_from_addr(addr)) {
+                (Some(func), Some(line)) => println!("    at {} ({})", func, line),
+                (Some(func), None) => println!("    at {}", func),
+                _ => println!("    at {:#x}", addr),
+            }
+            if func.as_deref() == Some("main") {
+                break;
+            }
+        }
+    }
+
+    /// Implements `watch <variable>`. The watchpoint uses a debug register if the variable fits
+    /// in one and one is free; otherwise it is a software watchpoint.
+    fn watch(&mut self, expr: &str) {
+        match self.session.watch(expr) {
+            Ok((number, WatchKind::Software)) => {
+                println!("Software watchpoint {}: {}", number, expr);
+                println!(
+                    "Warning: the program will be single-stepped while this watchpoint is set, \
+                     which is very slow."
+                );
+            }
+            Ok((number, _)) => println!("Hardware watchpoint {}: {}", number, expr),
+            Err(err @ Error::NotRunning) | Err(err @ Error::Evaluate(_)) => println!("{}", err),
+            Err(err) => println!("Cannot watch \"{}\": {}", expr, err),
+        }
+    }
+
+    /// Implements `checkpoint`.
+    fn checkpoint(&mut self) {
+        match self.session.checkpoint() {
+            Ok((number, pid)) => println!("Checkpoint {}: fork returned pid {}.", number, pid),
+            Err(err @ Error::NotRunning) => println!("{}", err),
+            Err(err) => println!("Could not make a checkpoint: {}", err),
+        }
+    }
+
+    /// Implements `restart <number>`. The program carries on from a copy of the checkpoint, so
+    /// the same checkpoint can be restarted any number of times.
+    fn restart(&mut self, number: usize) {
+        match self.session.restart(number) {
+            Ok(rip) => {
+                if let Some(pid) = self.session.pid() {
+                    println!("Switching to checkpoint {} (pid {})", number, pid);
+                }
+                self.print_warnings();
+                self.print_stop_location(rip);
+            }
+            Err(err @ Error::NoCheckpoint(_)) => println!("{}", err),
+            Err(err) => println!("Could not restart checkpoint {}: {}", number, err),
+        }
+    }
+
+    /// Implements `info proc mappings`. Anonymous memory right after a file's mappings is that
+    /// file's .bss, so it's shown as coming from the file.
+    fn info_proc_mappings(&self) {
+        let pid = match self.session.pid() {
+            Some(pid) => pid,
+            None => {
+                println!("The program is not being run.");
+                return;
+            }
+        };
+        let mappings = match self.session.mappings() {
+            Ok(mappings) => mappings,
+            Err(err) => {
+                println!("Could not read /proc/{}/maps: {}", pid, err);
+                return;
+            }
+        };
+        println!("process {}", pid);
+        println!("Mapped address spaces:\n");
+        println!(
+            "{:>18} {:>18} {:>10} {:>10} {:<5} objfile",
+            "Start Addr", "End Addr", "Size", "Offset", "Perms"
+        );
+        for (mapping, objfile) in mappings.iter().zip(inferior::objfile_names(&mappings)) {
+            println!(
+                "{:>#18x} {:>#18x} {:>#10x} {:>#10x} {:<5} {}",
+                mapping.start,
+                mapping.end,
+                mapping.end - mapping.start,
+                mapping.offset,
+                mapping.perms,
+                objfile
+            );
+        }
+    }
+
+    /// Implements `info symbol <address>`.
+    fn info_symbol(&self, address: &str) {
+        let addr = match self.session.evaluate_argument(address) {
+            Ok(addr) => addr as usize,
+            Err(message) => {
+                println!("{}", message);
+                return;
+            }
+        };
+        match self.debug_data().get_symbol_containing(addr) {
+            Some(symbol) if symbol.address == addr => {
+                println!("{} in section {}", symbol.name, symbol.section)
+            }
+            Some(symbol) => println!(
+                "{} + {} in section {}",
+                symbol.name,
+                addr - symbol.address,
+                symbol.section
+            ),
+            None => println!("No symbol matches {}.", address),
+        }
+    }
+
+    /// Implements `disassemble [location]`, marking the instruction the program is stopped at.
+    fn disassemble(&self, location: Option<&str>) {
+        let target = match self.target() {
+            Some(target) => target,
+            None => {
+                println!("The program is not being run.");
+                return;
+            }
+        };
+        let rip = target.registers().ok().map(|regs| regs.rip as usize);
+        let addr = match (location, rip) {
+            (Some(location), _) => match self.resolve_location(location) {
+                Ok(addr) => addr,
+                Err(message) => {
+                    println!("{}", message);
+                    return;
+                }
+            },
+            (None, Some(rip)) => rip,
+            (None, None) => {
+                println!("No frame selected.");
+                return;
+            }
+        };
+        let func = match self.debug_data().get_function_containing(addr) {
+            Some(func) => func,
+            None => {
+                println!("No function contains specified address.");
+                return;
+            }
+        };
+        match disassemble(
+            target,
+            func.address,
+            func.text_length,
+            self.session.breakpoints(),
+        ) {
+            Ok(instructions) => {
+                println!("Dump of assembler code for function {}:", func.name);
+                for (addr, text) in instructions {
+                    println!(
+                        "{} {:#018x} <+{}>:\t{}",
+                        if Some(addr) == rip { "=>" } else { "  " },
+                        addr,
+                        addr - func.address,
+                        text
+                    );
+                }
+                println!("End of assembler dump.");
+            }
+            Err(err) => println!(
+                "Cannot access memory at address {:#x}: {}",
+                func.address, err
+            ),
+        }
+    }
+
+    /// Implements `set <setting> <value>`.
+    fn set(&mut self, setting: &str, value: &str) {
+        match setting {
+            "print elements" => match value {
+                "unlimited" | "0" => self.session.printers.set_max_elements(None),
+                value => match value.parse() {
+                    Ok(max) => self.session.printers.set_max_elements(Some(max)),
+                    Err(_) => println!("Invalid number \"{}\".", value),
+                },
+            },
+            "prompt" => self.prompt = value.to_string(),
+            "environment" => {
+                // Either `NAME=value` or `NAME value`
+                let (name, value) = match value.find('=') {
+                    Some(index) => (&value[..index], &value[index + 1..]),
+                    None => match value.find(char::is_whitespace) {
+                        Some(index) => (&value[..index], &value[index..]),
+                        None => (value, ""),
+                    },
+                };
+                let (name, value) = (name.trim(), value.trim());
+                if value.is_empty() {
+                    println!("Setting environment variable \"{}\" to null value.", name);
+                }
+                self.session
+                    .launch
+                    .environment
+                    .insert(name.to_string(), value.to_string());
+            }
+            "disable-randomization" => match value {
+                "on" => self.session.launch.disable_randomization = true,
+                "off" => self.session.launch.disable_randomization = false,
+                _ => println!("\"on\" or \"off\" expected."),
+            },
+            "cwd" if value.is_empty() => self.session.launch.cwd = None,
+            "cwd" => self.session.launch.cwd = Some(value.to_string()),
+            "inferior-tty" if value.is_empty() => self.session.launch.tty = None,
+            "inferior-tty" => self.session.launch.tty = Some(value.to_string()),
+            _ => println!("Undefined set command: \"{}\".", setting),
+        }
+    }
+
+    /// Implements `show <setting>`.
+    fn show(&self, setting: &str) {
+        match setting {
+            "print elements" => match self.session.printers.max_elements() {
+                Some(max) => println!("Limit on array elements to print is {}.", max),
+                None => println!("Limit on array elements to print is unlimited."),
+            },
+            "prompt" => println!("Prompt is \"{}\".", self.prompt),
+            "args" => {
+                let args: Vec<String> = self
+                    .session
+                    .launch
+                    .args
+                    .iter()
+                    .map(|arg| {
+                        // Quote what was quoted, so that quoted redirections still look literal
+                        if arg.text.is_empty() || arg.unquoted < arg.text.len() {
+                            let (unquoted, quoted) = arg.text.split_at(arg.unquoted);
+                            format!("{}\"{}\"", unquoted, quoted)
                         } else {
-                            println!("Invalid line number");
-                            continue;
+                            arg.text.clone()
                         }
-                    } else if let Some(address) = self
-                        .debug_data
-                        .get_addr_for_function(Some(&format!("{}.c", &self.target)), &location)
-                    {
-                        breakpoint_addr = address;
-                    } else {
-                        println!("Usage: b|break|breakpoint *address|line|func");
-                        continue;
+                    })
+                    .collect();
+                println!(
+                    "Argument list to give program being debugged when it is started is \"{}\".",
+                    args.join(" ")
+                );
+            }
+            "disable-randomization" => println!(
+                "Disabling randomization of debuggee's virtual address space is {}.",
+                if self.session.launch.disable_randomization {
+                    "on"
+                } else {
+                    "off"
+                }
+            ),
+            "cwd" => match &self.session.launch.cwd {
+                Some(cwd) => println!("Current working directory for the program is \"{}\".", cwd),
+                None => println!("You have not set the program's current working directory."),
+            },
+            "inferior-tty" => match &self.session.launch.tty {
+                Some(tty) => println!(
+                    "Terminal for future runs of program being debugged is \"{}\".",
+                    tty
+                ),
+                None => println!("The program uses the debugger's terminal."),
+            },
+            _ => println!("Undefined show command: \"{}\".", setting),
+        }
+    }
+
+    /// Implements `info scope <location>`, listing the variables visible in a function and where
+    /// they are stored.
+    fn info_scope(&self, location: &str) {
+        let func = self
+            .resolve_location(location)
+            .ok()
+            .and_then(|addr| self.debug_data().get_function_containing(addr));
+        let func = match func {
+            Some(func) => func,
+            None => {
+                println!("No function contains specified address.");
+                return;
+            }
+        };
+        if func.variables.is_empty() {
+            println!("Scope for {}:\nSymbol table contains no locals.", location);
+            return;
+        }
+        println!("Scope for {}:", location);
+        for var in &func.variables {
+            let storage = match &var.location {
+                Location::Address(addr) => format!("static storage at address {:#x}", addr),
+                Location::FramePointerOffset(offset) => {
+                    format!("a variable at frame base offset {}", offset)
+                }
+                Location::Expression(expr) => format!("a variable computed by {}", expr),
+                Location::List(entries) => {
+                    format!("a variable with {} locations", entries.len())
+                }
+                Location::OptimizedOut => "optimized out".to_string(),
+            };
+            println!(
+                "Symbol {} is {}, length {}.",
+                var.name, storage, var.entity_type.size
+            );
+        }
+    }
+
+    /// Implements `catch syscall [name|number]`, `catch signal [signal]`, `catch fork` and
+    /// `catch exec`.
+    fn add_catchpoint(&mut self, kind: &str, arg: Option<&str>) {
+        let catchpoint = match (kind, arg) {
+            ("syscall", None) => Catchpoint::Syscall(None),
+            ("syscall", Some(arg)) => {
+                match arg.parse::<u64>().ok().or_else(|| syscalls::number(arg)) {
+                    Some(number) => Catchpoint::Syscall(Some(number)),
+                    None => {
+                        println!("Unknown syscall name '{}'.", arg);
+                        return;
                     }
+                }
+            }
+            ("signal", None) | ("signal", Some("all")) => Catchpoint::Signal(None),
+            ("signal", Some(arg)) => {
+                let name = if arg.starts_with("SIG") {
+                    arg.to_string()
+                } else {
+                    format!("SIG{}", arg)
+                };
+                match Signal::from_str(&name.to_uppercase()) {
+                    Ok(signal) => Catchpoint::Signal(Some(signal)),
+                    Err(_) => {
+                        println!("Unknown signal name '{}'.", arg);
+                        return;
+                    }
+                }
+            }
+            ("fork", None) => Catchpoint::Fork,
+            ("exec", None) => Catchpoint::Exec,
+            _ => {
+                println!("Usage: catch syscall [name|number] | signal [signal] | fork | exec");
+                return;
+            }
+        };
+        let what = catchpoint.to_string();
+        let number = self.session.add_catchpoint(catchpoint);
+        println!("Catchpoint {} ({})", number, what);
+    }
 
-                    if self.inferior.is_some() {
-                        if let Some(instruction) = self
-                            .inferior
-                            .as_mut()
-                            .unwrap()
-                            .write_byte(breakpoint_addr, 0xcc)
-                            .ok()
-                        {
-                            println!(
-                                "Set breakpoint {} at {:#x}",
-                                self.breakpoints.len(),
-                                breakpoint_addr
-                            );
-                            self.breakpoints.insert(breakpoint_addr, instruction);
+    /// Returns the number of the first catchpoint that stops for the given event.
+    fn catchpoint_number(&self, event: &Event) -> usize {
+        self.session
+            .catchpoints()
+            .find(|(_, catch)| catch.matches(event))
+            .map_or(0, |(number, _)| number)
+    }
+
+    /// Returns the number of the first catchpoint for the given signal, if any.
+    fn signal_catchpoint_number(&self, signal: Signal) -> Option<usize> {
+        self.session
+            .catchpoints()
+            .find(|(_, catch)| catch.catches_signal(signal))
+            .map(|(number, _)| number)
+    }
+
+    /// Returns what inspection commands should read from: the live inferior if there is one,
+    /// otherwise the loaded core file.
+    fn target(&self) -> Option<&dyn Target> {
+        self.session.target()
+    }
+
+    /// Finds a variable by name, looking in the frame's function before globals.
+    fn lookup_variable(&self, frame: &Frame, name: &str) -> Option<&Variable> {
+        session::lookup_variable(self.debug_data(), frame, name)
+    }
+
+    /// Returns the names of the variables visible where the program is stopped, for completion.
+    fn scope_variable_names(&self) -> Vec<String> {
+        let frame = match self.session.backtrace() {
+            Ok(mut frames) if !frames.is_empty() => frames.remove(0),
+            _ => return Vec::new(),
+        };
+        frame
+            .variables(self.debug_data())
+            .into_iter()
+            .map(|var| var.name.clone())
+            .collect()
+    }
+
+    /// Evaluates a variable in the innermost frame, returning its formatted value or a message
+    /// saying why it couldn't be read.
+    fn evaluate(&self, name: &str) -> Result<String, String> {
+        self.session.evaluate(name).map_err(|err| match err {
+            Error::NotRunning => {
+                "Error: no process or core file to read variables from".to_string()
+            }
+            Error::Evaluate(message) => message,
+            err => format!("Error: {}", err),
+        })
+    }
+
+    fn print_variable(&self, name: &str) {
+        match self.evaluate(name) {
+            Ok(value) => println!("{} = {}", name, value),
+            Err(message) => println!("{}", message),
+        }
+    }
+
+    /// Implements `call func(args)` and `print func(args)`: calls a function in the inferior and
+    /// prints what it returned.
+    fn call_function(&mut self, expr: &str, print_void: bool) {
+        let result = self.session.call(expr);
+        match result {
+            Ok(Stop::Returned(expr, value, _)) => {
+                self.print_warnings();
+                match value {
+                    Some(value) => println!("{} = {}", expr, value),
+                    None if print_void => println!("{} = void", expr),
+                    None => {}
+                }
+            }
+            Ok(stop) => {
+                self.report(Ok(stop));
+                if self.session.pid().is_some() {
+                    println!(
+                        "The program stopped in a function called from the debugger. When {} \
+                         returns, the state from before the call will be restored.",
+                        expr
+                    );
+                }
+            }
+            Err(Error::NotRunning) => println!("You can't do that without a process to debug."),
+            Err(Error::Evaluate(message)) => println!("{}", message),
+            Err(Error::ProcessGone) => self.report(Err(Error::ProcessGone)),
+            Err(err) => {
+                self.print_warnings();
+                println!("Error calling {}: {}", expr, err);
+            }
+        }
+    }
+
+    /// Prints the display expressions (or just the given one) that can be evaluated here.
+    fn do_displays(&self, only: Option<usize>) {
+        for (number, expr, value) in self.session.display_values(only) {
+            println!("{}: {} = {}", number, expr, value);
+        }
+    }
+
+    /// Implements `x/<count><format> <address>`, dumping 8-byte words in hex (x) or decimal (d).
+    /// The address can be a number, a $register, or &variable.
+    fn examine(&self, format: &str, address: &str) {
+        let target = match self.target() {
+            Some(target) => target,
+            None => {
+                println!("Error: no process or core file to read memory from");
+                return;
+            }
+        };
+        let digits: String = format.chars().take_while(|c| c.is_ascii_digit()).collect();
+        let count = digits.parse::<usize>().unwrap_or(1);
+        let radix = format[digits.len()..].chars().next().unwrap_or('x');
+        if radix != 'x' && radix != 'd' {
+            println!("Unsupported format '{}', use x or d", radix);
+            return;
+        }
+
+        let addr = if address.starts_with('$') {
+            let register = &address[1..];
+            let index = REGISTER_NAMES.iter().position(|name| *name == register);
+            match (index, target.registers()) {
+                (Some(index), Ok(regs)) => Some(regs_to_words(&regs)[index] as usize),
+                _ => None,
+            }
+        } else if address.starts_with('&') {
+            let name = &address[1..];
+            target.backtrace(self.debug_data()).ok().and_then(|frames| {
+                let var = self.lookup_variable(&frames[0], name)?;
+                target.variable_address(var, &frames[0])
+            })
+        } else {
+            parse_address(address)
+        };
+        let addr = match addr {
+            Some(addr) => addr,
+            None => {
+                println!("Invalid address {}", address);
+                return;
+            }
+        };
+
+        for row in 0..(count + 1) / 2 {
+            let row_addr = addr + 16 * row;
+            let mut line = format!("{:#x}:", row_addr);
+            for i in 0..2usize.min(count - 2 * row) {
+                match target.read_memory(row_addr + 8 * i, 8) {
+                    Ok(bytes) => {
+                        let mut word = [0u8; 8];
+                        word.copy_from_slice(&bytes);
+                        let word = u64::from_le_bytes(word);
+                        if radix == 'x' {
+                            line.push_str(&format!("\t{:#018x}", word));
                         } else {
-                            println!("Invalid breakpoint address {:#x}", breakpoint_addr);
+                            line.push_str(&format!("\t{}", word as i64));
                         }
-                    } else {
-                        // when the inferior is initiated, these breakpoints will be installed
+                    }
+                    Err(err) => {
+                        println!("{}", line);
+                        println!(
+                            "Cannot access memory at address {:#x}: {}",
+                            row_addr + 8 * i,
+                            err
+                        );
+                        return;
+                    }
+                }
+            }
+            println!("{}", line);
+        }
+    }
+
+    fn print_registers(&self) {
+        let regs = match self.target().map(|target| target.registers()) {
+            Some(Ok(regs)) => regs,
+            Some(Err(err)) => {
+                println!("Error: {}", err);
+                return;
+            }
+            None => {
+                println!("The program has no registers now.");
+                return;
+            }
+        };
+        for (name, value) in REGISTER_NAMES.iter().zip(regs_to_words(&regs).iter()) {
+            println!(
+                "{:<15}{:<19}{}",
+                name,
+                format!("{:#x}", value),
+                *value as i64
+            );
+        }
+    }
+
+    /// Kills the inferior if there is one running.
+    fn kill_inferior(&mut self) {
+        if let Some(pid) = self.session.pid() {
+            println!("Killing running inferior (pid {})", pid);
+            self.session.kill();
+        }
+    }
+
+    /// Reports what resuming the inferior led to, whether it stopped or the debugger failed to
+    /// follow it.
+    fn report(&mut self, result: Result<Stop, Error>) {
+        self.print_warnings();
+        match result {
+            Ok(stop) => self.report_stop(stop),
+            Err(err) => self.report_error(err),
+        }
+    }
+
+    /// Prints an error from the inferior. The program stays where it was and can still be
+    /// examined, unless it has gone, in which case the session has let go of it.
+    fn report_error(&mut self, err: Error) {
+        println!("Error: {}", err);
+        if let Error::ProcessGone = err {
+            self.tui_stopped_at(None);
+        }
+    }
+
+    /// Prints where the inferior stopped, or how it ended.
+    fn report_stop(&mut self, stop: Stop) {
+        match stop {
+            Stop::Exited(exit_code) => {
+                println!("Child exited (status {})", exit_code);
+                self.report_leaks();
+                self.tui_stopped_at(None);
+            }
+            Stop::Signaled(signal) => {
+                println!("Child exited due to signal {}", signal);
+                self.tui_stopped_at(None);
+            }
+            Stop::Returned(expr, value, rip) => {
+                println!("Call to {} returned.", expr);
+                if let Some(value) = value {
+                    println!("{} = {}", expr, value);
+                }
+                self.print_stop_location(rip);
+            }
+            // The end of a step is reported like any other SIGTRAP
+            Stop::Breakpoint(rip) | Stop::Stepped(rip) => self.report_signal(Signal::SIGTRAP, rip),
+            Stop::Signal(signal, rip) => self.report_signal(signal, rip),
+            Stop::Event(event, rip) => {
+                let number = self.catchpoint_number(&event);
+                match event {
+                    Event::SyscallEntry(syscall, args) => {
+                        let call = self.target().map_or_else(String::new, |target| {
+                            syscalls::format_call(syscall, &args, target)
+                        });
                         println!(
-                            "Set breakpoint {} at {:#x}",
-                            self.breakpoints.len(),
-                            breakpoint_addr
+                            "Catchpoint {} (call to syscall {}), {}",
+                            number,
+                            syscalls::display_name(syscall),
+                            call
                         );
-                        self.breakpoints.insert(breakpoint_addr, 0);
+                    }
+                    Event::SyscallExit(syscall, value) => println!(
+                        "Catchpoint {} (returned from syscall {}) = {}",
+                        number,
+                        syscalls::display_name(syscall),
+                        syscalls::format_return(value)
+                    ),
+                    Event::Fork(child) => {
+                        println!("Catchpoint {} (forked process {})", number, child)
+                    }
+                    Event::Exec(path) => println!("Catchpoint {} (exec'd {})", number, path),
+                    Event::Watchpoint(number, old, new) => {
+                        let table = self.session.breakpoint_table();
+                        let watch = table.iter().find_map(|(n, entry)| match entry {
+                            BreakpointEntry::Watch(watch) if *n == number => Some(watch),
+                            _ => None,
+                        });
+                        if let Some(watch) = watch {
+                            let read = |addr, len| self.target()?.read_memory(addr, len).ok();
+                            let kind = match self.session.watch_kind(number) {
+                                Some(WatchKind::Software) => "Software",
+                                _ => "Hardware",
+                            };
+                            let printers = &self.session.printers;
+                            println!("\n{} watchpoint {}: {}\n", kind, number, watch.expr);
+                            println!(
+                                "Old value = {}",
+                                printers.format_value(&watch.ty, &old, &read)
+                            );
+                            println!(
+                                "New value = {}",
+                                printers.format_value(&watch.ty, &new, &read)
+                            );
+                        }
+                    }
+                    Event::HeapError(HeapError::DoubleFree(ptr, stack, freed_stack)) => {
+                        println!("\nDouble free of {:#x}", ptr);
+                        self.print_heap_stack(&stack);
+                        println!("It was already freed");
+                        self.print_heap_stack(&freed_stack);
+                    }
+                    Event::HeapError(HeapError::InvalidFree(ptr, stack)) => {
+                        println!("\nFree of {:#x}, which was never allocated", ptr);
+                        self.print_heap_stack(&stack);
                     }
                 }
+                self.print_stop_location(rip);
+            }
+        }
+    }
+
+    /// Says which signal the inferior stopped with, and where.
+    fn report_signal(&self, signal: Signal, rip: usize) {
+        match self.signal_catchpoint_number(signal) {
+            Some(number) if signal != Signal::SIGTRAP => {
+                println!("Catchpoint {} (signal {})", number, signal)
+            }
+            _ => println!("Child stopped (signal {})", signal),
+        }
+        self.print_stop_location(rip);
+    }
+
+    fn print_warnings(&mut self) {
+        for warning in self.session.take_warnings() {
+            println!("{}", warning);
+        }
+    }
+
+    fn report_replay(&mut self, status: Result<ReplayStatus, Error>) {
+        match status {
+            Ok(ReplayStatus::Stopped(rip)) => self.print_stop_location(rip),
+            Ok(ReplayStatus::NoMoreHistory(rip)) => {
+                println!("No more reverse-execution history.");
+                self.print_stop_location(rip);
+            }
+            Err(err) => {
+                self.print_warnings();
+                self.report_error(err);
             }
         }
     }
 
+    /// Moves the --tui display to where the inferior stopped (None once it has exited).
+    fn tui_stopped_at(&self, rip: Option<usize>) {
+        if let Some(tui) = &self.tui {
+            tui.stopped_at(rip);
+            self.refresh_tui();
+        }
+    }
+
+    fn print_stop_location(&self, rip: usize) {
+        self.tui_stopped_at(Some(rip));
+        let _line = self.debug_data().get_line_from_addr(rip);
+        let _func = self.debug_data().get_function_from_addr(rip);
+        if _line.is_some() && _func.is_some() {
+            println!("Stopped at {} ({})", _func.unwrap(), _line.unwrap());
+        }
+        self.do_displays(None);
+    }
+
+    fn step(&mut self, kind: Step) {
+        let stop = self.session.step(kind);
+        self.report(stop);
+    }
+
     /// This function prompts the user to enter a command, and continues re-prompting until the user
     /// enters a valid command. It uses DebuggerCommand::from_tokens to do the command parsing.
     fn get_next_command(&mut self) -> DebuggerCommand {
         loop {
+            let variables = self.scope_variable_names();
+            if let Some(helper) = self.readline.helper_mut() {
+                helper.variables = variables;
+            }
             // Print prompt and get next line of user input
-            match self.readline.readline("(qeet) ") {
+            match self.readline.readline(&self.prompt) {
                 Err(ReadlineError::Interrupted) => {
                     // User pressed ctrl+c. We're going to ignore it
                     println!("Type \"quit\" to exit");
@@ -249,11 +1386,18 @@ impl Debugger {
                     return DebuggerCommand::Quit;
                 }
                 Err(err) => {
-                    panic!("Unexpected I/O error: {:?}", err);
+                    // The prompt can't be read any more, so quit as for ctrl+d
+                    println!("Error reading input: {}", err);
+                    return DebuggerCommand::Quit;
                 }
                 Ok(line) => {
-                    if line.trim().len() == 0 {
-                        continue;
+                    if line.trim().is_empty() {
+                        // Like gdb, Enter on its own runs the last stepping command again
+                        let last = self.last_command.clone();
+                        match last.map(|line| self.parse_command(&line)) {
+                            Some(Some(cmd)) => return cmd,
+                            _ => continue,
+                        }
                     }
                     self.readline.add_history_entry(line.as_str());
                     if let Err(err) = self.readline.save_history(&self.history_path) {
@@ -262,16 +1406,30 @@ impl Debugger {
                             self.history_path, err
                         );
                     }
-                    let tokens: Vec<&str> = line.split_whitespace().collect();
-                    if let Some(cmd) = DebuggerCommand::from_tokens(&tokens) {
+                    if let Some(cmd) = self.parse_command(&line) {
+                        self.last_command = if cmd.repeats() { Some(line) } else { None };
                         return cmd;
-                        // cmd here is DebuggerCommand, it will deliver the instruction's value after parse the type of instruction
-                        // Example code shown in Millstone1 "r 3" corresponds to cmd = DebuggerCommand::Run(Vec<String>{3})
-                    } else {
-                        println!("Unrecognized command.");
                     }
                 }
             }
         }
     }
+
+    /// Parses a command line, printing why if it isn't a valid command.
+    fn parse_command(&self, line: &str) -> Option<DebuggerCommand> {
+        let tokens = match debugger_command::tokenize(line) {
+            Ok(tokens) => tokens,
+            Err(message) => {
+                println!("{}", message);
+                return None;
+            }
+        };
+        match DebuggerCommand::from_tokens(&tokens) {
+            Ok(cmd) => Some(cmd),
+            Err(message) => {
+                println!("{}", message);
+                None
+            }
+        }
+    }
 }
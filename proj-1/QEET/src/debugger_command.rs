@@ -1,26 +1,1019 @@
+use qeet::launch::Word;
+
 pub enum DebuggerCommand {
     Quit,
-    Run(Vec<String>),
+    Run(Vec<Word>),
     Continue,
+    Next,
+    Step,
+    StepInstruction,
     Backtrace,
     Break(String),
+    /// tbreak <location>: a breakpoint that is removed once hit
+    TemporaryBreak(String),
+    /// until|advance <location>
+    Until(String),
+    Print(String),
+    /// call <function>(<args>)
+    Call(String),
+    /// x/<format> <address>
+    Examine(String, String),
+    InfoRegisters,
+    /// display [expression]
+    Display(Option<String>),
+    Undisplay(usize),
+    InfoDisplay,
+    /// info functions [regex]
+    InfoFunctions(Option<String>),
+    /// info variables [regex]
+    InfoVariables(Option<String>),
+    InfoLine(String),
+    InfoSymbol(String),
+    InfoScope(String),
+    InfoPrettyPrinter,
+    InfoProcMappings,
+    InfoCheckpoints,
+    /// watch <variable>
+    Watch(String),
+    /// unwatch <watchpoint number>
+    Unwatch(usize),
+    /// delete [breakpoint numbers...]
+    Delete(Vec<usize>),
+    InfoBreakpoints,
+    InfoWatchpoints,
+    InfoHeap,
+    Checkpoint,
+    /// restart <checkpoint number>
+    Restart(usize),
+    /// layout src|asm|regs (--tui mode)
+    Layout(String),
+    /// disassemble [function|*address]
+    Disassemble(Option<String>),
+    /// set <setting> <value>, where the setting is named by its words ("print elements")
+    Set(String, String),
+    /// show <setting>
+    Show(String),
+    /// set args [arguments...]
+    SetArgs(Vec<Word>),
+    /// show environment [variable]
+    ShowEnvironment(Option<String>),
+    /// unset environment [variable]
+    UnsetEnvironment(Option<String>),
+    Gcore(Option<String>),
+    /// catch <event> [argument]
+    Catch(String, Option<String>),
+    Record,
+    RecordStop,
+    ReverseStepInstruction,
+    ReverseContinue,
+    ReverseNext,
+    /// help [command]
+    Help(Vec<String>),
+}
+
+/// What a command's arguments are, for tab completion.
+#[derive(Clone, Copy)]
+pub enum Arguments {
+    None,
+    /// A function, [file:]line or *address.
+    Location,
+    /// A function name.
+    Function,
+    /// A variable or $register.
+    Expression,
+    /// A path on disk.
+    Path,
+    /// One of a fixed set of words.
+    Keywords(&'static [&'static str]),
+    /// A command name.
+    Command,
+}
+
+/// An entry in the command table, which parsing, completion and `help` all work from.
+pub struct Command {
+    /// The command's name, followed by its aliases.
+    pub names: &'static [&'static str],
+    pub usage: &'static str,
+    pub help: &'static str,
+    pub arguments: Arguments,
+    /// Commands that take a subcommand (`info`) list them here instead of having a parser.
+    pub subcommands: &'static [Command],
+    /// Parses the words of the command line, starting with the command's own name. Returns None
+    /// if the arguments are wrong.
+    parse: fn(&[Word]) -> Option<DebuggerCommand>,
+}
+
+impl Command {
+    pub fn name(&self) -> &'static str {
+        self.names[0]
+    }
+}
+
+fn rest(tokens: &[Word]) -> Option<String> {
+    if tokens.len() > 1 {
+        let words: Vec<&str> = tokens[1..].iter().map(|word| word.text.as_str()).collect();
+        Some(words.join(" "))
+    } else {
+        None
+    }
+}
+
+fn arg(tokens: &[Word], i: usize) -> Option<String> {
+    tokens.get(i).map(|token| token.text.clone())
+}
+
+pub const COMMANDS: &[Command] = &[
+    Command {
+        names: &["run", "r"],
+        usage: "run [arguments...]",
+        help: "Start the program, killing it first if it is running. Without arguments, the last \
+               ones are used. They can redirect the program's input and output with <, >, >>, \
+               2> and 2>&1, unless quoted.",
+        arguments: Arguments::Path,
+        subcommands: &[],
+        parse: |tokens| Some(DebuggerCommand::Run(tokens[1..].to_vec())),
+    },
+    Command {
+        names: &["continue", "c", "cont"],
+        usage: "continue",
+        help: "Continue running the program until it stops again.",
+        arguments: Arguments::None,
+        subcommands: &[],
+        parse: |_| Some(DebuggerCommand::Continue),
+    },
+    Command {
+        names: &["next", "n"],
+        usage: "next",
+        help: "Run to the next line, stepping over function calls.",
+        arguments: Arguments::None,
+        subcommands: &[],
+        parse: |_| Some(DebuggerCommand::Next),
+    },
+    Command {
+        names: &["step", "s"],
+        usage: "step",
+        help: "Run to the next line, stepping into function calls.",
+        arguments: Arguments::None,
+        subcommands: &[],
+        parse: |_| Some(DebuggerCommand::Step),
+    },
+    Command {
+        names: &["stepi", "si"],
+        usage: "stepi",
+        help: "Run one machine instruction.",
+        arguments: Arguments::None,
+        subcommands: &[],
+        parse: |_| Some(DebuggerCommand::StepInstruction),
+    },
+    Command {
+        names: &["until", "u", "advance"],
+        usage: "until <location>",
+        help: "Run until the program reaches a location or the current function returns.",
+        arguments: Arguments::Location,
+        subcommands: &[],
+        parse: |tokens| Some(DebuggerCommand::Until(arg(tokens, 1)?)),
+    },
+    Command {
+        names: &["break", "b"],
+        usage: "break <location>",
+        help: "Set a breakpoint at a function, a [file:]line or an *address.",
+        arguments: Arguments::Location,
+        subcommands: &[],
+        parse: |tokens| Some(DebuggerCommand::Break(arg(tokens, 1)?)),
+    },
+    Command {
+        names: &["tbreak", "tb"],
+        usage: "tbreak <location>",
+        help: "Set a breakpoint that is removed the first time it is hit.",
+        arguments: Arguments::Location,
+        subcommands: &[],
+        parse: |tokens| Some(DebuggerCommand::TemporaryBreak(arg(tokens, 1)?)),
+    },
+    Command {
+        names: &["watch"],
+        usage: "watch <variable>",
+        help: "Stop when a variable's value changes. Variables that don't fit in a debug \
+               register are watched by single-stepping, which is very slow.",
+        arguments: Arguments::Expression,
+        subcommands: &[],
+        parse: |tokens| Some(DebuggerCommand::Watch(arg(tokens, 1)?)),
+    },
+    Command {
+        names: &["unwatch"],
+        usage: "unwatch <number>",
+        help: "Delete a watchpoint.",
+        arguments: Arguments::None,
+        subcommands: &[],
+        parse: |tokens| Some(DebuggerCommand::Unwatch(tokens.get(1)?.text.parse().ok()?)),
+    },
+    Command {
+        names: &["delete", "d"],
+        usage: "delete [number...]",
+        help: "Delete breakpoints, watchpoints and catchpoints by number, or all of them.",
+        arguments: Arguments::None,
+        subcommands: &[],
+        parse: |tokens| {
+            let numbers: Result<Vec<usize>, _> =
+                tokens[1..].iter().map(|word| word.text.parse()).collect();
+            Some(DebuggerCommand::Delete(numbers.ok()?))
+        },
+    },
+    Command {
+        names: &["catch"],
+        usage: "catch syscall [name|number] | signal [signal] | fork | exec",
+        help: "Stop when the program makes a system call, receives a signal, forks or execs. \
+               Signals such as SIGCHLD only stop the program when caught.",
+        arguments: Arguments::Keywords(&["syscall", "signal", "fork", "exec"]),
+        subcommands: &[],
+        parse: |tokens| Some(DebuggerCommand::Catch(arg(tokens, 1)?, arg(tokens, 2))),
+    },
+    Command {
+        names: &["backtrace", "bt", "back"],
+        usage: "backtrace",
+        help: "Show the functions that were called to get to the current one.",
+        arguments: Arguments::None,
+        subcommands: &[],
+        parse: |_| Some(DebuggerCommand::Backtrace),
+    },
+    Command {
+        names: &["print", "p"],
+        usage: "print <variable> | <function>(<arguments>)",
+        help: "Show the value of a variable, or call a function and show what it returns.",
+        arguments: Arguments::Expression,
+        subcommands: &[],
+        parse: |tokens| Some(DebuggerCommand::Print(rest(tokens)?)),
+    },
+    Command {
+        names: &["call"],
+        usage: "call <function>(<arguments>)",
+        help: "Call a function in the program, showing what it returns unless that's void.",
+        arguments: Arguments::Function,
+        subcommands: &[],
+        parse: |tokens| Some(DebuggerCommand::Call(rest(tokens)?)),
+    },
+    Command {
+        names: &["x"],
+        usage: "x[/<count><format>] <address>",
+        help: "Show words of memory at an address, in hex (x, the default) or decimal (d).",
+        arguments: Arguments::Expression,
+        subcommands: &[],
+        parse: |tokens| {
+            let format = if tokens[0].text.starts_with("x/") {
+                &tokens[0].text[2..]
+            } else {
+                ""
+            };
+            Some(DebuggerCommand::Examine(
+                format.to_string(),
+                arg(tokens, 1)?,
+            ))
+        },
+    },
+    Command {
+        names: &["display"],
+        usage: "display [expression]",
+        help: "Show the value of an expression every time the program stops, or show them all.",
+        arguments: Arguments::Expression,
+        subcommands: &[],
+        parse: |tokens| Some(DebuggerCommand::Display(rest(tokens))),
+    },
+    Command {
+        names: &["undisplay"],
+        usage: "undisplay <number>",
+        help: "Stop showing an expression added with display.",
+        arguments: Arguments::None,
+        subcommands: &[],
+        parse: |tokens| {
+            Some(DebuggerCommand::Undisplay(
+                tokens.get(1)?.text.parse().ok()?,
+            ))
+        },
+    },
+    Command {
+        names: &["info", "i"],
+        usage: "info <subcommand>",
+        help: "Show information about the program being debugged.",
+        arguments: Arguments::None,
+        subcommands: INFO_COMMANDS,
+        parse: |_| None,
+    },
+    Command {
+        names: &["record", "rec"],
+        usage: "record [stop]",
+        help: "Start (or stop) recording execution, so that it can be run in reverse.",
+        arguments: Arguments::Keywords(&["stop"]),
+        subcommands: &[],
+        parse: |tokens| match tokens.get(1).map(|word| word.text.as_str()) {
+            None => Some(DebuggerCommand::Record),
+            Some("stop") => Some(DebuggerCommand::RecordStop),
+            _ => None,
+        },
+    },
+    Command {
+        names: &["reverse-stepi", "rsi"],
+        usage: "reverse-stepi",
+        help: "Go back one machine instruction in the recording.",
+        arguments: Arguments::None,
+        subcommands: &[],
+        parse: |_| Some(DebuggerCommand::ReverseStepInstruction),
+    },
+    Command {
+        names: &["reverse-next", "rn"],
+        usage: "reverse-next",
+        help: "Go back to the previous line in the recording, stepping over function calls.",
+        arguments: Arguments::None,
+        subcommands: &[],
+        parse: |_| Some(DebuggerCommand::ReverseNext),
+    },
+    Command {
+        names: &["reverse-continue", "rc"],
+        usage: "reverse-continue",
+        help: "Run backwards through the recording until a breakpoint or its start.",
+        arguments: Arguments::None,
+        subcommands: &[],
+        parse: |_| Some(DebuggerCommand::ReverseContinue),
+    },
+    Command {
+        names: &["disassemble"],
+        usage: "disassemble [function|*address]",
+        help: "Show the machine instructions of a function, by default the current one.",
+        arguments: Arguments::Location,
+        subcommands: &[],
+        parse: |tokens| Some(DebuggerCommand::Disassemble(arg(tokens, 1))),
+    },
+    Command {
+        names: &["set"],
+        usage: "set <setting> <value>",
+        help: "Change a setting.",
+        arguments: Arguments::None,
+        subcommands: SET_COMMANDS,
+        parse: |_| None,
+    },
+    Command {
+        names: &["show"],
+        usage: "show <setting>",
+        help: "Show the value of a setting.",
+        arguments: Arguments::None,
+        subcommands: SHOW_COMMANDS,
+        parse: |_| None,
+    },
+    Command {
+        names: &["unset"],
+        usage: "unset environment [variable]",
+        help: "Remove a variable from the program's environment.",
+        arguments: Arguments::None,
+        subcommands: UNSET_COMMANDS,
+        parse: |_| None,
+    },
+    Command {
+        names: &["tty"],
+        usage: "tty <terminal>",
+        help: "Use a terminal such as /dev/pts/1 for the program's input and output.",
+        arguments: Arguments::Path,
+        subcommands: &[],
+        parse: |tokens| {
+            Some(DebuggerCommand::Set(
+                "inferior-tty".to_string(),
+                arg(tokens, 1)?,
+            ))
+        },
+    },
+    Command {
+        names: &["checkpoint"],
+        usage: "checkpoint",
+        help: "Save a copy of the program as it is now, which restart can go back to.",
+        arguments: Arguments::None,
+        subcommands: &[],
+        parse: |_| Some(DebuggerCommand::Checkpoint),
+    },
+    Command {
+        names: &["restart"],
+        usage: "restart <number>",
+        help: "Go back to a checkpoint, discarding the program's current state.",
+        arguments: Arguments::None,
+        subcommands: &[],
+        parse: |tokens| Some(DebuggerCommand::Restart(tokens.get(1)?.text.parse().ok()?)),
+    },
+    Command {
+        names: &["generate-core-file", "gcore"],
+        usage: "generate-core-file [file]",
+        help: "Save the program's memory and registers to a core file (core.<pid> by default).",
+        arguments: Arguments::Path,
+        subcommands: &[],
+        parse: |tokens| Some(DebuggerCommand::Gcore(arg(tokens, 1))),
+    },
+    Command {
+        names: &["layout"],
+        usage: "layout src|asm|regs",
+        help: "Choose what is shown below the source in --tui mode.",
+        arguments: Arguments::Keywords(&["src", "asm", "regs"]),
+        subcommands: &[],
+        parse: |tokens| Some(DebuggerCommand::Layout(arg(tokens, 1)?)),
+    },
+    Command {
+        names: &["help", "h"],
+        usage: "help [command]",
+        help: "List the commands, or describe one.",
+        arguments: Arguments::Command,
+        subcommands: &[],
+        parse: |tokens| {
+            Some(DebuggerCommand::Help(
+                tokens[1..].iter().map(|word| word.text.clone()).collect(),
+            ))
+        },
+    },
+    Command {
+        names: &["quit", "q"],
+        usage: "quit",
+        help: "Kill the program if it is running, and exit.",
+        arguments: Arguments::None,
+        subcommands: &[],
+        parse: |_| Some(DebuggerCommand::Quit),
+    },
+];
+
+pub const INFO_COMMANDS: &[Command] = &[
+    Command {
+        names: &["registers", "r", "reg"],
+        usage: "info registers",
+        help: "Show the values of the registers.",
+        arguments: Arguments::None,
+        subcommands: &[],
+        parse: |_| Some(DebuggerCommand::InfoRegisters),
+    },
+    Command {
+        names: &["functions"],
+        usage: "info functions [regex]",
+        help: "List the functions whose names match a regular expression, or all of them.",
+        arguments: Arguments::None,
+        subcommands: &[],
+        parse: |tokens| Some(DebuggerCommand::InfoFunctions(arg(tokens, 1))),
+    },
+    Command {
+        names: &["variables"],
+        usage: "info variables [regex]",
+        help: "List the global variables whose names match a regular expression, or all of them.",
+        arguments: Arguments::None,
+        subcommands: &[],
+        parse: |tokens| Some(DebuggerCommand::InfoVariables(arg(tokens, 1))),
+    },
+    Command {
+        names: &["line"],
+        usage: "info line <location>",
+        help: "Show the addresses of the code for a line.",
+        arguments: Arguments::Location,
+        subcommands: &[],
+        parse: |tokens| Some(DebuggerCommand::InfoLine(arg(tokens, 1)?)),
+    },
+    Command {
+        names: &["symbol"],
+        usage: "info symbol <address>",
+        help: "Show which symbol an address is in.",
+        arguments: Arguments::None,
+        subcommands: &[],
+        parse: |tokens| Some(DebuggerCommand::InfoSymbol(arg(tokens, 1)?)),
+    },
+    Command {
+        names: &["scope"],
+        usage: "info scope <location>",
+        help: "List the variables visible at a location, and where they are stored.",
+        arguments: Arguments::Location,
+        subcommands: &[],
+        parse: |tokens| Some(DebuggerCommand::InfoScope(arg(tokens, 1)?)),
+    },
+    Command {
+        names: &["display"],
+        usage: "info display",
+        help: "List the expressions added with display.",
+        arguments: Arguments::None,
+        subcommands: &[],
+        parse: |_| Some(DebuggerCommand::InfoDisplay),
+    },
+    Command {
+        names: &["proc"],
+        usage: "info proc <subcommand>",
+        help: "Show information about the program's process.",
+        arguments: Arguments::None,
+        subcommands: INFO_PROC_COMMANDS,
+        parse: |_| None,
+    },
+    Command {
+        names: &["breakpoints", "b", "break"],
+        usage: "info breakpoints",
+        help: "List the breakpoints and watchpoints.",
+        arguments: Arguments::None,
+        subcommands: &[],
+        parse: |_| Some(DebuggerCommand::InfoBreakpoints),
+    },
+    Command {
+        names: &["watchpoints"],
+        usage: "info watchpoints",
+        help: "List the watchpoints, and whether they use debug registers.",
+        arguments: Arguments::None,
+        subcommands: &[],
+        parse: |_| Some(DebuggerCommand::InfoWatchpoints),
+    },
+    Command {
+        names: &["checkpoints"],
+        usage: "info checkpoints",
+        help: "List the checkpoints.",
+        arguments: Arguments::None,
+        subcommands: &[],
+        parse: |_| Some(DebuggerCommand::InfoCheckpoints),
+    },
+    Command {
+        names: &["heap"],
+        usage: "info heap",
+        help: "List the memory the program has allocated and not freed (with --check-memory).",
+        arguments: Arguments::None,
+        subcommands: &[],
+        parse: |_| Some(DebuggerCommand::InfoHeap),
+    },
+    Command {
+        names: &["pretty-printer"],
+        usage: "info pretty-printer",
+        help: "List the pretty-printers, in the order they are tried.",
+        arguments: Arguments::None,
+        subcommands: &[],
+        parse: |_| Some(DebuggerCommand::InfoPrettyPrinter),
+    },
+];
+
+const INFO_PROC_COMMANDS: &[Command] = &[Command {
+    names: &["mappings"],
+    usage: "info proc mappings",
+    help: "List the program's memory mappings and the files they come from.",
+    arguments: Arguments::None,
+    subcommands: &[],
+    parse: |_| Some(DebuggerCommand::InfoProcMappings),
+}];
+
+pub const SET_COMMANDS: &[Command] = &[
+    Command {
+        names: &["disable-randomization"],
+        usage: "set disable-randomization on|off",
+        help: "Whether to run the program with address space randomization turned off.",
+        arguments: Arguments::Keywords(&["on", "off"]),
+        subcommands: &[],
+        parse: |tokens| {
+            Some(DebuggerCommand::Set(
+                "disable-randomization".to_string(),
+                arg(tokens, 1)?,
+            ))
+        },
+    },
+    Command {
+        names: &["args"],
+        usage: "set args [arguments...]",
+        help: "Set the arguments (and redirections) the next run starts the program with.",
+        arguments: Arguments::Path,
+        subcommands: &[],
+        parse: |tokens| Some(DebuggerCommand::SetArgs(tokens[1..].to_vec())),
+    },
+    Command {
+        names: &["environment", "env"],
+        usage: "set environment <variable>[=<value>]",
+        help: "Set a variable in the environment the program starts with.",
+        arguments: Arguments::None,
+        subcommands: &[],
+        parse: |tokens| {
+            Some(DebuggerCommand::Set(
+                "environment".to_string(),
+                rest(tokens)?,
+            ))
+        },
+    },
+    Command {
+        names: &["cwd"],
+        usage: "set cwd [directory]",
+        help: "Set the directory the program starts in, or go back to the debugger's.",
+        arguments: Arguments::Path,
+        subcommands: &[],
+        parse: |tokens| {
+            Some(DebuggerCommand::Set(
+                "cwd".to_string(),
+                rest(tokens).unwrap_or_default(),
+            ))
+        },
+    },
+    Command {
+        names: &["inferior-tty"],
+        usage: "set inferior-tty [terminal]",
+        help: "Use a terminal for the program's input and output, or go back to the debugger's.",
+        arguments: Arguments::Path,
+        subcommands: &[],
+        parse: |tokens| {
+            Some(DebuggerCommand::Set(
+                "inferior-tty".to_string(),
+                rest(tokens).unwrap_or_default(),
+            ))
+        },
+    },
+    Command {
+        names: &["print", "p"],
+        usage: "set print <setting> <value>",
+        help: "Change how values are printed.",
+        arguments: Arguments::None,
+        subcommands: SET_PRINT_COMMANDS,
+        parse: |_| None,
+    },
+    Command {
+        names: &["prompt"],
+        usage: "set prompt <text>",
+        help: "Change the prompt. Quote it to keep a trailing space: set prompt \"(gdb) \"",
+        arguments: Arguments::None,
+        subcommands: &[],
+        parse: |tokens| Some(DebuggerCommand::Set("prompt".to_string(), rest(tokens)?)),
+    },
+];
+
+const SET_PRINT_COMMANDS: &[Command] = &[Command {
+    names: &["elements"],
+    usage: "set print elements <number>|unlimited",
+    help: "Limit how many elements of an array, Vec or slice are printed.",
+    arguments: Arguments::Keywords(&["unlimited"]),
+    subcommands: &[],
+    parse: |tokens| {
+        Some(DebuggerCommand::Set(
+            "print elements".to_string(),
+            arg(tokens, 1)?,
+        ))
+    },
+}];
+
+pub const SHOW_COMMANDS: &[Command] = &[
+    Command {
+        names: &["disable-randomization"],
+        usage: "show disable-randomization",
+        help: "Show whether the program runs with address space randomization turned off.",
+        arguments: Arguments::None,
+        subcommands: &[],
+        parse: |_| Some(DebuggerCommand::Show("disable-randomization".to_string())),
+    },
+    Command {
+        names: &["args"],
+        usage: "show args",
+        help: "Show the arguments the next run starts the program with.",
+        arguments: Arguments::None,
+        subcommands: &[],
+        parse: |_| Some(DebuggerCommand::Show("args".to_string())),
+    },
+    Command {
+        names: &["environment", "env"],
+        usage: "show environment [variable]",
+        help: "Show the program's environment, or one variable in it.",
+        arguments: Arguments::None,
+        subcommands: &[],
+        parse: |tokens| Some(DebuggerCommand::ShowEnvironment(arg(tokens, 1))),
+    },
+    Command {
+        names: &["cwd"],
+        usage: "show cwd",
+        help: "Show the directory the program starts in.",
+        arguments: Arguments::None,
+        subcommands: &[],
+        parse: |_| Some(DebuggerCommand::Show("cwd".to_string())),
+    },
+    Command {
+        names: &["inferior-tty"],
+        usage: "show inferior-tty",
+        help: "Show the terminal the program uses for its input and output.",
+        arguments: Arguments::None,
+        subcommands: &[],
+        parse: |_| Some(DebuggerCommand::Show("inferior-tty".to_string())),
+    },
+    Command {
+        names: &["print", "p"],
+        usage: "show print <setting>",
+        help: "Show how values are printed.",
+        arguments: Arguments::None,
+        subcommands: SHOW_PRINT_COMMANDS,
+        parse: |_| None,
+    },
+    Command {
+        names: &["prompt"],
+        usage: "show prompt",
+        help: "Show the prompt.",
+        arguments: Arguments::None,
+        subcommands: &[],
+        parse: |_| Some(DebuggerCommand::Show("prompt".to_string())),
+    },
+];
+
+const SHOW_PRINT_COMMANDS: &[Command] = &[Command {
+    names: &["elements"],
+    usage: "show print elements",
+    help: "Show how many elements of an array, Vec or slice are printed.",
+    arguments: Arguments::None,
+    subcommands: &[],
+    parse: |_| Some(DebuggerCommand::Show("print elements".to_string())),
+}];
+
+pub const UNSET_COMMANDS: &[Command] = &[Command {
+    names: &["environment", "env"],
+    usage: "unset environment [variable]",
+    help: "Remove a variable from the program's environment, or all of them.",
+    arguments: Arguments::None,
+    subcommands: &[],
+    parse: |tokens| Some(DebuggerCommand::UnsetEnvironment(arg(tokens, 1))),
+}];
+
+/// Finds a command by its name, an alias or a prefix of its name that no other command in the
+/// table starts with. `x/4x` is `x` with a format. If there is no such command, returns the
+/// names of the commands the word is ambiguous between, which is empty if it matches none.
+pub fn find_command(
+    table: &'static [Command],
+    word: &str,
+) -> Result<&'static Command, Vec<&'static str>> {
+    let word = if word.starts_with("x/") { "x" } else { word };
+    if let Some(command) = table.iter().find(|command| command.names.contains(&word)) {
+        return Ok(command);
+    }
+    let matches: Vec<&Command> = table
+        .iter()
+        .filter(|command| command.names.iter().any(|name| name.starts_with(word)))
+        .collect();
+    match matches.as_slice() {
+        [command] if !word.is_empty() => Ok(command),
+        _ => Err(matches.iter().map(|command| command.name()).collect()),
+    }
+}
+
+/// Splits a command line into words. Quotes group words, so `set prompt "(dbg) "` has three,
+/// and a backslash takes the next character literally. Each word records how much of its start
+/// was unquoted, which is what `run` can take as a redirection.
+pub fn tokenize(line: &str) -> Result<Vec<Word>, String> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    // Whether we're in a word, which an empty pair of quotes also starts
+    let mut in_token = false;
+    // How much of the word came before anything quoted or escaped
+    let mut unquoted = None;
+    let mut quote = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                unquoted = unquoted.or_else(|| Some(token.len()));
+                token.push(chars.next().ok_or("Trailing backslash")?);
+                in_token = true;
+            }
+            c if Some(c) == quote => quote = None,
+            c if quote.is_some() => token.push(c),
+            '"' | '\'' => {
+                unquoted = unquoted.or_else(|| Some(token.len()));
+                quote = Some(c);
+                in_token = true;
+            }
+            c if c.is_whitespace() => {
+                if in_token {
+                    let unquoted = unquoted.take().unwrap_or_else(|| token.len());
+                    tokens.push(Word {
+                        text: std::mem::take(&mut token),
+                        unquoted,
+                    });
+                    in_token = false;
+                }
+            }
+            c => {
+                token.push(c);
+                in_token = true;
+            }
+        }
+    }
+    if let Some(quote) = quote {
+        return Err(format!("Unterminated {} quote.", quote));
+    }
+    if in_token {
+        let unquoted = unquoted.unwrap_or_else(|| token.len());
+        tokens.push(Word {
+            text: token,
+            unquoted,
+        });
+    }
+    Ok(tokens)
+}
+
+/// Parses a command line against a command table, returning the message to show if it isn't a
+/// valid command. `prefix` is the command the table belongs to, if it's a table of subcommands.
+fn parse(
+    table: &'static [Command],
+    tokens: &[Word],
+    prefix: &str,
+) -> Result<DebuggerCommand, String> {
+    let word = tokens.first().map_or("", |word| word.text.as_str());
+    let command = match find_command(table, word) {
+        Ok(command) => command,
+        Err(candidates) if !candidates.is_empty() => {
+            let kind = if prefix.is_empty() {
+                "command".to_string()
+            } else {
+                format!("{} command", prefix)
+            };
+            return Err(format!(
+                "Ambiguous {} \"{}\": {}.",
+                kind,
+                word,
+                candidates.join(", ")
+            ));
+        }
+        Err(_) if prefix.is_empty() => {
+            return Err(format!("Undefined command: \"{}\". Try \"help\".", word))
+        }
+        Err(_) => {
+            return Err(format!(
+                "Undefined {} command: \"{}\". Try \"help {}\".",
+                prefix, word, prefix
+            ))
+        }
+    };
+    if !command.subcommands.is_empty() {
+        let path = format!("{} {}", prefix, command.name());
+        let path = path.trim_start();
+        return match tokens.get(1) {
+            Some(_) => parse(command.subcommands, &tokens[1..], path),
+            None => Err(format!("Usage: {}. Try \"help {}\".", command.usage, path)),
+        };
+    }
+    (command.parse)(tokens).ok_or_else(|| format!("Usage: {}", command.usage))
+}
+
+/// Returns the text for `help` or `help <command> [subcommand]`.
+pub fn help(topic: &[String]) -> Result<String, String> {
+    let mut table = COMMANDS;
+    let mut command = None;
+    for word in topic {
+        let found = find_command(table, word)
+            .map_err(|_| format!("Undefined command: \"{}\". Try \"help\".", topic.join(" ")))?;
+        table = found.subcommands;
+        command = Some(found);
+    }
+    let mut text = String::new();
+    if let Some(command) = command {
+        text.push_str(&format!("Usage: {}\n", command.usage));
+        if command.names.len() > 1 {
+            text.push_str(&format!("Aliases: {}\n", command.names[1..].join(", ")));
+        }
+        text.push_str(command.help);
+        text.push('\n');
+        if table.is_empty() {
+            return Ok(text);
+        }
+        text.push_str("\nSubcommands:\n");
+    } else {
+        text.push_str("Commands:\n");
+    }
+    for command in table {
+        text.push_str(&format!("  {:<24}{}\n", command.name(), command.help));
+    }
+    if command.is_none() {
+        text.push_str("\nType \"help <command>\" for more about a command.\n");
+    }
+    Ok(text)
 }
 
 impl DebuggerCommand {
-    pub fn from_tokens(tokens: &Vec<&str>) -> Option<DebuggerCommand> {
-        match tokens[0] {
-            "q" | "quit" => Some(DebuggerCommand::Quit),
-            "r" | "run" => {
-                let args = tokens[1..].to_vec();
-                Some(DebuggerCommand::Run(
-                    args.iter().map(|s| s.to_string()).collect(),
-                ))
+    /// Parses a command line, returning the message to show if it isn't a valid command.
+    pub fn from_tokens(tokens: &[Word]) -> Result<DebuggerCommand, String> {
+        parse(COMMANDS, tokens, "")
+    }
+
+    /// Whether pressing Enter on an empty line should run this command again, as it does for
+    /// commands that move through the program.
+    pub fn repeats(&self) -> bool {
+        match self {
+            DebuggerCommand::Continue
+            | DebuggerCommand::Next
+            | DebuggerCommand::Step
+            | DebuggerCommand::StepInstruction
+            | DebuggerCommand::ReverseStepInstruction
+            | DebuggerCommand::ReverseContinue
+            | DebuggerCommand::ReverseNext => true,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(line: &str) -> Vec<String> {
+        let words = tokenize(line).unwrap();
+        words.into_iter().map(|word| word.text).collect()
+    }
+
+    fn parse_line(line: &str) -> Result<DebuggerCommand, String> {
+        DebuggerCommand::from_tokens(&tokenize(line)?)
+    }
+
+    #[test]
+    fn quotes_and_backslashes_group_words() {
+        assert_eq!(texts("  break  main "), vec!["break", "main"]);
+        assert_eq!(
+            texts("set prompt \"(dbg) \""),
+            vec!["set", "prompt", "(dbg) "]
+        );
+        assert_eq!(texts("run 'a b'\"c\" d\\ e"), vec!["run", "a bc", "d e"]);
+        assert_eq!(texts("run \"\" ''"), vec!["run", "", ""]);
+        assert_eq!(texts("print '\"'"), vec!["print", "\""]);
+        assert_eq!(tokenize("run \"a").unwrap_err(), "Unterminated \" quote.");
+        assert_eq!(tokenize("run a\\").unwrap_err(), "Trailing backslash");
+    }
+
+    #[test]
+    fn words_record_how_much_was_unquoted() {
+        let words = tokenize(r#"run ">" 2">"x >"out" \<in a'b'"#).unwrap();
+        let unquoted: Vec<usize> = words.iter().map(|word| word.unquoted).collect();
+        assert_eq!(unquoted, vec![3, 0, 1, 1, 0, 1]);
+        assert_eq!(words[2].text, "2>x");
+        assert_eq!(words[3].text, ">out");
+    }
+
+    #[test]
+    fn commands_match_names_aliases_and_unique_prefixes() {
+        match parse_line("cont").unwrap() {
+            DebuggerCommand::Continue => {}
+            _ => panic!("cont should be continue"),
+        }
+        match parse_line("bt").unwrap() {
+            DebuggerCommand::Backtrace => {}
+            _ => panic!("bt should be backtrace"),
+        }
+        match parse_line("disas").unwrap() {
+            DebuggerCommand::Disassemble(None) => {}
+            _ => panic!("disas should be disassemble"),
+        }
+        // An alias wins over a longer command it is also a prefix of
+        match parse_line("s").unwrap() {
+            DebuggerCommand::Step => {}
+            _ => panic!("s should be step"),
+        }
+        match parse_line("i r").unwrap() {
+            DebuggerCommand::InfoRegisters => {}
+            _ => panic!("i r should be info registers"),
+        }
+        match parse_line("set print elements 5").unwrap() {
+            DebuggerCommand::Set(setting, value) => {
+                assert_eq!(setting, "print elements");
+                assert_eq!(value, "5");
             }
-            "c" | "cont" | "continue" => Some(DebuggerCommand::Continue),
-            "bt" | "back" | "backtrace" => Some(DebuggerCommand::Backtrace),
-            "b" | "break" => Some(DebuggerCommand::Break(tokens[1].to_string())),
-            // Default case:
-            _ => None,
+            _ => panic!("expected a setting"),
+        }
+        match parse_line("x/4d &n").unwrap() {
+            DebuggerCommand::Examine(format, address) => {
+                assert_eq!(format, "4d");
+                assert_eq!(address, "&n");
+            }
+            _ => panic!("x/4d should examine memory"),
+        }
+        match parse_line("run 'a b' c").unwrap() {
+            DebuggerCommand::Run(args) => {
+                assert_eq!(args, vec![Word::quoted("a b"), Word::plain("c")])
+            }
+            _ => panic!("expected run"),
         }
+        match parse_line("unw 2").unwrap() {
+            DebuggerCommand::Unwatch(2) => {}
+            _ => panic!("unw should be unwatch"),
+        }
+    }
+
+    #[test]
+    fn bad_commands_say_what_is_wrong() {
+        assert_eq!(
+            parse_line("di").err().unwrap(),
+            "Ambiguous command \"di\": display, disassemble."
+        );
+        assert_eq!(
+            parse_line("frobnicate").err().unwrap(),
+            "Undefined command: \"frobnicate\". Try \"help\"."
+        );
+        assert_eq!(
+            parse_line("info frobnicate").err().unwrap(),
+            "Undefined info command: \"frobnicate\". Try \"help info\"."
+        );
+        assert_eq!(
+            parse_line("set print").err().unwrap(),
+            "Usage: set print <setting> <value>. Try \"help set print\"."
+        );
+        assert_eq!(
+            parse_line("break").err().unwrap(),
+            "Usage: break <location>"
+        );
+    }
+
+    #[test]
+    fn help_describes_commands_and_subcommands() {
+        let text = help(&["c".to_string()]).unwrap();
+        assert!(text.starts_with("Usage: continue\nAliases: c, cont\n"));
+        let text = help(&["info".to_string()]).unwrap();
+        assert!(text.contains("\nSubcommands:\n"));
+        assert!(text.contains("  registers "));
+        assert!(help(&[]).unwrap().starts_with("Commands:\n"));
+        assert_eq!(
+            help(&["info".to_string(), "nope".to_string()]).unwrap_err(),
+            "Undefined command: \"info nope\". Try \"help\"."
+        );
     }
 }
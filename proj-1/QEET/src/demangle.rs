@@ -0,0 +1,131 @@
+//! Symbol names as people write them. Rust and C++ compilers mangle the names they put in symbol
+//! tables and DW_AT_linkage_name; we show them demangled, and let `break` and friends take a
+//! function's qualified name (`alloc::vec::Vec<T>::push`), a suffix of it (`Vec::push`), or just
+//! its last component (`push`).
+
+/// Demangles a Rust (legacy or v0) or Itanium C++ symbol. Other names are returned unchanged.
+pub fn demangle(name: &str) -> String {
+    if let Ok(demangled) = rustc_demangle::try_demangle(name) {
+        // The alternate format leaves out the hash at the end of legacy symbols
+        return format!("{:#}", demangled);
+    }
+    if name.starts_with("_Z") {
+        if let Ok(symbol) = cpp_demangle::Symbol::new(name) {
+            if let Ok(demangled) = symbol.demangle(&Default::default()) {
+                return demangled;
+            }
+        }
+    }
+    name.to_string()
+}
+
+/// Removes a C++ parameter list from the end of a demangled name.
+fn strip_parameters(name: &str) -> &str {
+    let mut depth = 0;
+    for (i, c) in name.char_indices() {
+        match c {
+            '<' => depth += 1,
+            '>' => depth -= 1,
+            '(' if depth == 0 && i > 0 => return &name[..i],
+            _ => {}
+        }
+    }
+    name
+}
+
+/// Drops generic arguments, so that `<alloc::vec::Vec<i32>>::push` and `Vec<T, A>::push` both
+/// become plain paths (`alloc::vec::Vec::push`, `Vec::push`).
+fn strip_generics(name: &str) -> String {
+    let mut stripped = String::with_capacity(name.len());
+    let mut depth = 0;
+    let mut prev = ' ';
+    for c in name.chars() {
+        match c {
+            // Arguments follow a name; other angle brackets (`<T as Trait>::f`) just group
+            '<' if depth > 0 || prev.is_alphanumeric() || prev == '_' => depth += 1,
+            '>' if depth > 0 => depth -= 1,
+            '<' | '>' => {}
+            _ if depth == 0 => stripped.push(c),
+            _ => {}
+        }
+        prev = c;
+    }
+    stripped
+}
+
+/// Returns the last component of a (demangled) name, which is what DWARF records as a function's
+/// DW_AT_name and so what the name index is keyed on.
+pub fn base_name(name: &str) -> String {
+    let path = strip_generics(strip_parameters(name));
+    match path.rfind("::") {
+        Some(index) => path[index + 2..].to_string(),
+        None => path,
+    }
+}
+
+/// Returns true if `query`, as typed by the user, refers to the function named `name`.
+pub fn name_matches(name: &str, query: &str) -> bool {
+    if name == query {
+        return true;
+    }
+    let name = strip_generics(strip_parameters(name));
+    let query = strip_generics(strip_parameters(query));
+    name == query || name.ends_with(&format!("::{}", query))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn demangles_rust_and_cpp_symbols() {
+        assert_eq!(
+            demangle("_ZN4core3fmt5write17h0123456789abcdefE"),
+            "core::fmt::write"
+        );
+        assert_eq!(
+            demangle("_RNvNtCs1234_7mycrate3foo3bar"),
+            "mycrate::foo::bar"
+        );
+        assert_eq!(demangle("_ZN3foo3barEv"), "foo::bar()");
+        assert_eq!(
+            demangle("_ZN8geometry5Point6lengthEi"),
+            "geometry::Point::length(int)"
+        );
+    }
+
+    #[test]
+    fn leaves_other_names_alone() {
+        assert_eq!(demangle("main"), "main");
+        assert_eq!(demangle("_Znot_mangled"), "_Znot_mangled");
+        assert_eq!(demangle(""), "");
+    }
+
+    #[test]
+    fn base_names_drop_paths_generics_and_parameters() {
+        assert_eq!(base_name("main"), "main");
+        assert_eq!(base_name("alloc::vec::Vec<T, A>::push"), "push");
+        assert_eq!(base_name("<alloc::vec::Vec<i32>>::push"), "push");
+        assert_eq!(base_name("<T as core::ops::Drop>::drop"), "drop");
+        assert_eq!(base_name("geometry::Point::length(int)"), "length");
+        assert_eq!(
+            base_name("std::map<int, std::pair<int, int> >::find(int const&)"),
+            "find"
+        );
+    }
+
+    #[test]
+    fn queries_match_a_suffix_of_the_path() {
+        let name = "alloc::vec::Vec<T, A>::push";
+        assert!(name_matches(name, name));
+        assert!(name_matches(name, "Vec::push"));
+        assert!(name_matches(name, "Vec<i32>::push"));
+        assert!(name_matches(name, "push"));
+        assert!(!name_matches(name, "ec::push"));
+        assert!(!name_matches(name, "Vec::pop"));
+        assert!(name_matches(
+            "geometry::Point::length(int)",
+            "Point::length"
+        ));
+    }
+}
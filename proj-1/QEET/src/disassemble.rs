@@ -0,0 +1,37 @@
+//! Decoding the program's machine code, for `disassemble` and the --tui assembly pane.
+
+use crate::inferior::Breakpoint;
+use crate::target::Target;
+use iced_x86::{Decoder, DecoderOptions, Formatter, GasFormatter, Instruction};
+use std::collections::HashMap;
+
+/// Decodes the instructions in `len` bytes of memory at `start`, returning each one's address and
+/// AT&T-syntax text. Breakpoints are looked through, so the instructions under them show as they
+/// were.
+#[allow(clippy::implicit_hasher)]
+pub fn disassemble(
+    target: &dyn Target,
+    start: usize,
+    len: usize,
+    breakpoints: &HashMap<usize, Breakpoint>,
+) -> Result<Vec<(usize, String)>, nix::Error> {
+    let mut code = target.read_memory(start, len)?;
+    for (&addr, breakpoint) in breakpoints {
+        if addr >= start && addr < start + code.len() && code[addr - start] == 0xcc {
+            code[addr - start] = breakpoint.orig_byte;
+        }
+    }
+
+    let mut decoder = Decoder::new(64, &code, DecoderOptions::NONE);
+    decoder.set_ip(start as u64);
+    let mut formatter = GasFormatter::new();
+    let mut instruction = Instruction::default();
+    let mut instructions = Vec::new();
+    while decoder.can_decode() {
+        decoder.decode_out(&mut instruction);
+        let mut text = String::new();
+        formatter.format(&instruction, &mut text);
+        instructions.push((instruction.ip() as usize, text));
+    }
+    Ok(instructions)
+}
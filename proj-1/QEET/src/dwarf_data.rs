@@ -1,7 +1,16 @@
-use crate::gimli_wrapper;
+use crate::debug_files::{self, SplitDwarf};
+use crate::demangle;
+use crate::dwarf_index::DwarfIndex;
+use crate::gimli_wrapper::{self, SectionReader};
 use addr2line::Context;
-use object::Object;
+use object::{Object, ObjectSection};
+use once_cell::unsync::OnceCell;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
 use std::convert::TryInto;
+use std::fmt::Write;
+use std::path::Path;
+use std::rc::Rc;
 use std::{fmt, fs};
 
 #[derive(Debug)]
@@ -10,14 +19,33 @@ pub enum Error {
     DwarfFormatError(gimli_wrapper::Error),
 }
 
+/// Debugging information for an executable. Compilation units are only parsed when a lookup
+/// needs them: `index` says which unit describes a given address or name, so that setting a
+/// breakpoint in a large program doesn't mean parsing every unit in it.
 pub struct DwarfData {
-    files: Vec<File>,
-    addr2line: Context<addr2line::gimli::EndianRcSlice<addr2line::gimli::RunTimeEndian>>,
+    dwarf: gimli::Dwarf<SectionReader>,
+    index: DwarfIndex,
+    /// The parsed units, in the same order as `index.units`. A unit that fails to parse is None.
+    files: Vec<OnceCell<Option<File>>>,
+    split_dwarf: SplitDwarf,
+    /// Function and data symbols from the ELF symbol table, sorted by address.
+    symbols: Vec<Symbol>,
+    /// None if addr2line can't read this program's DWARF (it doesn't understand split DWARF 5),
+    /// in which case lookups fall back to our own line tables.
+    addr2line: Option<Context<addr2line::gimli::EndianRcSlice<addr2line::gimli::RunTimeEndian>>>,
+    /// Problems found while loading that the user should know about, for the front end to show.
+    warnings: Vec<String>,
 }
 
 impl fmt::Debug for DwarfData {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "DwarfData {{files: {:?}}}", self.files)
+        let units: Vec<&str> = self
+            .index
+            .units
+            .iter()
+            .map(|unit| unit.name.as_str())
+            .collect();
+        write!(f, "DwarfData {{units: {:?}}}", units)
     }
 }
 
@@ -28,34 +56,222 @@ impl From<gimli_wrapper::Error> for Error {
 }
 
 impl DwarfData {
+    /// Loads the debugging information for an executable, from the executable itself or from a
+    /// separate debug file if it has been stripped.
     pub fn from_file(path: &str) -> Result<DwarfData, Error> {
-        let file = fs::File::open(path).or(Err(Error::ErrorOpeningFile))?;
-        let mmap = unsafe { memmap::Mmap::map(&file).or(Err(Error::ErrorOpeningFile))? };
-        let object = object::File::parse(&*mmap)
-            .or_else(|e| Err(gimli_wrapper::Error::ObjectError(e.to_string())))?;
+        let executable = Path::new(path);
+        let mmap = map_file(executable)?;
+        let object = parse_object(&mmap)?;
         let endian = if object.is_little_endian() {
             gimli::RunTimeEndian::Little
         } else {
             gimli::RunTimeEndian::Big
         };
+
+        let debug_path = if object.section_data_by_name(".debug_info").is_some() {
+            executable.to_path_buf()
+        } else {
+            debug_files::find_debug_file(executable, &object)
+                .unwrap_or_else(|| executable.to_path_buf())
+        };
+        let debug_mmap = map_file(&debug_path)?;
+        let debug_object = parse_object(&debug_mmap)?;
+        let supplementary_mmap =
+            match debug_files::find_supplementary_file(&debug_path, &debug_object) {
+                Some(path) => Some(map_file(&path)?),
+                None => None,
+            };
+        let supplementary_object = match &supplementary_mmap {
+            Some(mmap) => Some(parse_object(mmap)?),
+            None => None,
+        };
+
+        let dwarf =
+            gimli_wrapper::load_dwarf(&debug_object, supplementary_object.as_ref(), endian)?;
+        let split_dwarf = SplitDwarf::new(executable);
+        let mut index = DwarfIndex::build(&debug_object, endian, &dwarf, &split_dwarf)?;
+        // Stripped executables keep their dynamic symbols, but the full symbol table moves to the
+        // debug file
+        let mut symbols = load_symbols(&object);
+        if symbols.is_empty() {
+            symbols = load_symbols(&debug_object);
+        }
         Ok(DwarfData {
-            files: gimli_wrapper::load_file(&object, endian)?,
-            addr2line: Context::new(&object).or_else(|e| Err(gimli_wrapper::Error::from(e)))?,
+            files: index.units.iter().map(|_| OnceCell::new()).collect(),
+            warnings: std::mem::take(&mut index.warnings),
+            dwarf,
+            index,
+            split_dwarf,
+            symbols,
+            addr2line: Context::new(&debug_object).ok(),
         })
     }
 
+    /// Takes the warnings gathered while loading, e.g. split DWARF files that couldn't be found.
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Returns the numbered unit, parsing it if this is the first time it's needed.
+    fn file(&self, unit: usize) -> Option<&File> {
+        self.files
+            .get(unit)?
+            .get_or_init(|| {
+                let offset = self.index.units[unit].offset;
+                gimli_wrapper::load_unit(&self.dwarf, offset, &self.split_dwarf).ok()
+            })
+            .as_ref()
+    }
+
+    /// Returns every compilation unit. This parses all of them, so lookups should go through the
+    /// indexes instead where they can.
+    pub fn files(&self) -> Vec<&File> {
+        (0..self.files.len())
+            .filter_map(|unit| self.file(unit))
+            .collect()
+    }
+
+    /// Returns the units that may define a function or global variable with this name.
+    fn files_defining(&self, name: &str) -> Vec<&File> {
+        let units = self.index.units_for_name(name);
+        if units.is_empty() && !self.index.names_complete {
+            return self.files();
+        }
+        units.iter().filter_map(|&unit| self.file(unit)).collect()
+    }
+
+    /// Returns the unit whose code contains addr.
+    fn file_containing(&self, addr: usize) -> Option<&File> {
+        self.file(self.index.unit_for_address(addr)?)
+    }
+
+    /// Returns the paths of the program's source files (the names of its compilation units),
+    /// without parsing them.
+    pub fn unit_names(&self) -> Vec<&str> {
+        self.index
+            .units
+            .iter()
+            .map(|unit| unit.name.as_str())
+            .collect()
+    }
+
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    /// Returns the defined functions whose names match, by file and sorted by name, for `info
+    /// functions`. Files without any are left out.
+    pub fn functions_matching(
+        &self,
+        matches: &dyn Fn(&str) -> bool,
+    ) -> Vec<(&File, Vec<&Function>)> {
+        self.files()
+            .into_iter()
+            .filter_map(|file| {
+                let mut functions: Vec<_> = file
+                    .functions
+                    .iter()
+                    .filter(|func| func.address != 0 && matches(&func.name))
+                    .collect();
+                functions.sort_by(|a, b| a.name.cmp(&b.name));
+                if functions.is_empty() {
+                    None
+                } else {
+                    Some((file, functions))
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the global variables whose names match, by file and sorted by name, for `info
+    /// variables`. Files without any are left out.
+    pub fn global_variables_matching(
+        &self,
+        matches: &dyn Fn(&str) -> bool,
+    ) -> Vec<(&File, Vec<&Variable>)> {
+        self.files()
+            .into_iter()
+            .filter_map(|file| {
+                let mut variables: Vec<_> = file
+                    .global_variables
+                    .iter()
+                    .filter(|var| matches(&var.name))
+                    .collect();
+                variables.sort_by(|a, b| a.name.cmp(&b.name));
+                if variables.is_empty() {
+                    None
+                } else {
+                    Some((file, variables))
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the ELF symbols for functions (or, without `functions`, data objects) whose names
+    /// match and that have no DWARF description.
+    pub fn undescribed_symbols(
+        &self,
+        functions: bool,
+        matches: &dyn Fn(&str) -> bool,
+    ) -> Vec<&Symbol> {
+        let files = self.files();
+        let described = |name: &str| {
+            files.iter().any(|file| {
+                if functions {
+                    file.functions.iter().any(|func| func.name == name)
+                } else {
+                    file.global_variables.iter().any(|var| var.name == name)
+                }
+            })
+        };
+        self.symbols
+            .iter()
+            .filter(|sym| {
+                sym.is_function == functions && matches(&sym.name) && !described(&sym.name)
+            })
+            .collect()
+    }
+
+    /// Returns the ELF symbol whose extent contains addr.
+    pub fn get_symbol_containing(&self, addr: usize) -> Option<&Symbol> {
+        let index = match self.symbols.binary_search_by_key(&addr, |sym| sym.address) {
+            Ok(index) => index,
+            Err(0) => return None,
+            Err(index) => index - 1,
+        };
+        let symbol = &self.symbols[index];
+        if addr < symbol.address + symbol.size.max(1) {
+            Some(symbol)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the range of addresses [start, end) generated for the line containing addr.
+    pub fn get_line_range(&self, addr: usize) -> Option<(usize, usize)> {
+        let line_table = &self.file_containing(addr)?.line_table;
+        let (&start, _) = line_table.range(..=addr).next_back()?;
+        let (&end, _) = line_table.range(start + 1..).next()?;
+        Some((start, end))
+    }
+
     #[allow(dead_code)]
     fn get_target_file(&self, file: &str) -> Option<&File> {
-        self.files.iter().find(|f| {
-            f.name == file || (!file.contains("/") && f.name.ends_with(&format!("/{}", file)))
-        })
+        // Editors refer to files by absolute path, while compilation units are usually named
+        // relative to the directory the compiler was run from, so match on trailing components too.
+        let unit = self.index.units.iter().position(|unit| {
+            unit.name == file
+                || (!file.contains('/') && unit.name.ends_with(&format!("/{}", file)))
+                || Path::new(file).ends_with(&unit.name)
+        })?;
+        self.file(unit)
     }
 
     #[allow(dead_code)]
     pub fn get_addr_for_line(&self, file: Option<&str>, line_number: usize) -> Option<usize> {
         let target_file = match file {
             Some(filename) => self.get_target_file(filename)?,
-            None => self.files.get(0)?,
+            None => self.file(0)?,
         };
         Some(
             target_file
@@ -69,28 +285,96 @@ impl DwarfData {
     #[allow(dead_code)]
     pub fn get_addr_for_function(&self, file: Option<&str>, func_name: &str) -> Option<usize> {
         match file {
-            Some(filename) => Some(
-                self.get_target_file(filename)?
-                    .functions
-                    .iter()
-                    .find(|func| func.name == func_name)?
-                    .address,
-            ),
-            None => {
-                for file in &self.files {
-                    if let Some(func) = file.functions.iter().find(|func| func.name == func_name) {
-                        return Some(func.address);
-                    }
-                }
-                None
+            Some(filename) => {
+                let functions: Vec<&Function> =
+                    self.get_target_file(filename)?.functions.iter().collect();
+                Some(find_function(&functions, func_name)?.address)
             }
+            None => Some(self.get_function(func_name)?.address),
         }
     }
 
+    /// Returns the definition of the function with the given name.
+    #[allow(dead_code)]
+    pub fn get_function(&self, func_name: &str) -> Option<&Function> {
+        let functions: Vec<&Function> = self
+            .files_defining(func_name)
+            .into_iter()
+            .flat_map(|file| file.functions.iter())
+            .collect();
+        find_function(&functions, func_name)
+    }
+
+    #[allow(dead_code)]
+    pub fn get_function_containing(&self, addr: usize) -> Option<&Function> {
+        self.file_containing(addr)?
+            .functions
+            .iter()
+            .find(|func| func.address <= addr && addr < func.address + func.text_length)
+    }
+
+    /// Returns the inlined calls that `addr` is inside of, outermost first.
+    pub fn get_inlined_calls(&self, addr: usize) -> Vec<&InlinedCall> {
+        let mut calls: Vec<&InlinedCall> = match self.get_function_containing(addr) {
+            Some(func) => func
+                .inlined_calls
+                .iter()
+                .filter(|call| call.contains(addr))
+                .collect(),
+            None => return Vec::new(),
+        };
+        calls.sort_by_key(|call| call.depth);
+        calls
+    }
+
+    /// Returns the entry point of every copy of the named function that was inlined somewhere.
+    pub fn get_inlined_entries(&self, func_name: &str) -> Vec<usize> {
+        self.files_defining(func_name)
+            .into_iter()
+            .flat_map(|file| file.functions.iter())
+            .flat_map(|func| func.inlined_calls.iter())
+            .filter(|call| demangle::name_matches(&call.name, func_name))
+            .map(|call| call.entry)
+            .collect()
+    }
+
+    /// Returns the global variables of the unit whose code contains addr, which is the only unit
+    /// this parses.
+    pub fn get_global_variables(&self, addr: usize) -> Vec<&Variable> {
+        self.file_containing(addr)
+            .map_or_else(Vec::new, |file| file.global_variables.iter().collect())
+    }
+
+    /// Returns the global variable with the given name.
+    pub fn get_global_variable(&self, name: &str) -> Option<&Variable> {
+        self.files_defining(name)
+            .into_iter()
+            .flat_map(|file| file.global_variables.iter())
+            .find(|var| var.name == name)
+    }
+
+    /// Returns true if addr is the first instruction generated for some line of source.
+    #[allow(dead_code)]
+    pub fn is_line_boundary(&self, addr: usize) -> bool {
+        self.file_containing(addr)
+            .map_or(false, |file| file.line_table.contains_key(&addr))
+    }
+
     #[allow(dead_code)]
     pub fn get_line_from_addr(&self, curr_addr: usize) -> Option<Line> {
-        let location = self
-            .addr2line
+        let addr2line = match &self.addr2line {
+            Some(addr2line) => addr2line,
+            None => {
+                let file = self.file_containing(curr_addr)?;
+                let (_, &number) = file.line_table.range(..=curr_addr).next_back()?;
+                return Some(Line {
+                    file: file.name.clone(),
+                    number,
+                    address: curr_addr,
+                });
+            }
+        };
+        let location = addr2line
             .find_location(curr_addr.try_into().unwrap())
             .ok()??;
         Some(Line {
@@ -100,51 +384,208 @@ impl DwarfData {
         })
     }
 
+    /// Returns the name of the innermost function at an address, which may have been inlined
+    /// into the function whose code it is.
     #[allow(dead_code)]
     pub fn get_function_from_addr(&self, curr_addr: usize) -> Option<String> {
-        let frame = self
-            .addr2line
-            .find_frames(curr_addr.try_into().unwrap())
-            .ok()?
-            .next()
-            .ok()??;
-        Some(frame.function?.raw_name().ok()?.to_string())
+        if let Some(call) = self.get_inlined_calls(curr_addr).last() {
+            return Some(call.name.clone());
+        }
+        let frame = self.addr2line.as_ref().and_then(|addr2line| {
+            addr2line
+                .find_frames(curr_addr.try_into().unwrap())
+                .ok()?
+                .next()
+                .ok()?
+        });
+        match frame.and_then(|frame| frame.function) {
+            Some(function) => Some(demangle::demangle(&function.raw_name().ok()?)),
+            // addr2line doesn't know about functions in split DWARF
+            None => Some(self.get_function_containing(curr_addr)?.name.clone()),
+        }
     }
 
-    #[allow(dead_code)]
-    pub fn print(&self) {
-        for file in &self.files {
-            println!("------");
-            println!("{}", file.name);
-            println!("------");
+    /// Describes the debugging information for `deet dump`: each file's variables, functions,
+    /// types and line numbers, then the symbol table.
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        // Writing to a String can't fail
+        self.write_text(&mut out).unwrap();
+        out
+    }
+
+    fn write_text(&self, out: &mut String) -> fmt::Result {
+        for file in self.files() {
+            writeln!(out, "------")?;
+            writeln!(out, "{}", file.name)?;
+            writeln!(out, "------")?;
 
-            println!("Global variables:");
+            writeln!(out, "Global variables:")?;
             for var in &file.global_variables {
-                println!(
+                writeln!(
+                    out,
                     "  * {} ({}, located at {}, declared at line {})",
                     var.name, var.entity_type.name, var.location, var.line_number
-                );
+                )?;
             }
 
-            println!("Functions:");
+            writeln!(out, "Functions:")?;
             for func in &file.functions {
-                println!(
-                    "  * {} (declared on line {}, located at {:#x}, {} bytes long)",
-                    func.name, func.line_number, func.address, func.text_length
-                );
+                writeln!(
+                    out,
+                    "  * {} (declared on line {}, located at {:#x}, {} bytes long, returns {})",
+                    func.name,
+                    func.line_number,
+                    func.address,
+                    func.text_length,
+                    func.return_type
+                        .as_ref()
+                        .map_or("void", |ty| ty.name.as_str())
+                )?;
+                for param in &func.parameters {
+                    writeln!(
+                        out,
+                        "    * Parameter: {} ({})",
+                        param.name, param.entity_type.name
+                    )?;
+                }
                 for var in &func.variables {
-                    println!(
+                    writeln!(
+                        out,
                         "    * Variable: {} ({}, located at {}, declared at line {})",
                         var.name, var.entity_type.name, var.location, var.line_number
-                    );
+                    )?;
                 }
+                for call in &func.inlined_calls {
+                    writeln!(
+                        out,
+                        "    * Inlined call: {} (called from {}:{}, entry at {:#x})",
+                        call.name, call.call_file, call.call_line, call.entry
+                    )?;
+                }
+            }
+
+            writeln!(out, "Types:")?;
+            for ty in file.types() {
+                ty.write_tree(out, 2)?;
             }
 
-            println!("Line numbers:");
+            writeln!(out, "Line numbers:")?;
             for line in &file.lines {
-                println!("  * {} (at {:#x})", line.number, line.address);
+                writeln!(out, "  * {} (at {:#x})", line.number, line.address)?;
             }
         }
+
+        writeln!(out, "------")?;
+        writeln!(out, "Symbol table")?;
+        writeln!(out, "------")?;
+        for symbol in &self.symbols {
+            writeln!(
+                out,
+                "  * {} ({}, located at {:#x} in {}, {} bytes long)",
+                symbol.name,
+                if symbol.is_function {
+                    "function"
+                } else {
+                    "object"
+                },
+                symbol.address,
+                symbol.section,
+                symbol.size
+            )?;
+        }
+        Ok(())
+    }
+
+    /// The same information as `to_text`, as JSON.
+    pub fn to_json(&self) -> Value {
+        let files: Vec<Value> = self.files().into_iter().map(File::to_json).collect();
+        let symbols: Vec<Value> = self
+            .symbols
+            .iter()
+            .map(|symbol| {
+                json!({
+                    "name": symbol.name,
+                    "address": symbol.address,
+                    "size": symbol.size,
+                    "kind": if symbol.is_function { "function" } else { "object" },
+                    "section": symbol.section,
+                })
+            })
+            .collect();
+        json!({ "compile_units": files, "symbols": symbols })
+    }
+}
+
+fn map_file(path: &Path) -> Result<memmap::Mmap, Error> {
+    let file = fs::File::open(path).or(Err(Error::ErrorOpeningFile))?;
+    unsafe { memmap::Mmap::map(&file).or(Err(Error::ErrorOpeningFile)) }
+}
+
+fn parse_object(mmap: &memmap::Mmap) -> Result<object::File, Error> {
+    Ok(object::File::parse(&**mmap)
+        .or_else(|e| Err(gimli_wrapper::Error::ObjectError(e.to_string())))?)
+}
+
+/// Reads the function and data symbols out of the ELF symbol table, which also covers code that
+/// has no debugging information.
+fn load_symbols(object: &object::File) -> Vec<Symbol> {
+    let mut symbols: Vec<Symbol> = object
+        .symbols()
+        .filter_map(|(_, symbol)| {
+            let is_function = match symbol.kind() {
+                object::SymbolKind::Text => true,
+                object::SymbolKind::Data => false,
+                _ => return None,
+            };
+            let name = symbol.name()?;
+            if name.is_empty() || symbol.address() == 0 {
+                return None;
+            }
+            let section = symbol
+                .section_index()
+                .and_then(|index| object.section_by_index(index))
+                .and_then(|section| section.name().map(|name| name.to_string()))
+                .unwrap_or_default();
+            Some(Symbol {
+                name: demangle::demangle(name),
+                address: symbol.address() as usize,
+                size: symbol.size() as usize,
+                is_function,
+                section,
+            })
+        })
+        .collect();
+    symbols.sort_by_key(|symbol| symbol.address);
+    symbols
+}
+
+/// An entry of the ELF symbol table.
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub address: usize,
+    pub size: usize,
+    /// True for functions, false for data objects.
+    pub is_function: bool,
+    /// e.g. ".text"
+    pub section: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TypeKind {
+    Base,
+    Pointer,
+    Struct,
+    Union,
+    Enum,
+    /// A fixed number of elements stored one after the other.
+    Array,
+}
+
+impl Default for TypeKind {
+    fn default() -> Self {
+        TypeKind::Base
     }
 }
 
@@ -152,13 +593,142 @@ impl DwarfData {
 pub struct Type {
     pub name: String,
     pub size: usize,
+    pub kind: TypeKind,
+    /// For pointers, the type pointed to, if known. For arrays, the type of their elements.
+    pub pointee: Option<Rc<Type>>,
+    /// For structures and unions, their fields.
+    pub members: Vec<Member>,
+    /// For C-style enumerations, the name of each value.
+    pub enumerators: Vec<(String, i64)>,
+    /// For Rust enums, which field says what the value is, and what each variant holds.
+    pub variants: Option<Rc<Variants>>,
+    /// Defined by Rust code, so values are shown the way `{:?}` would show them.
+    pub rust: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub name: String,
+    /// Offset in bytes from the start of the containing value.
+    pub offset: usize,
+    pub entity_type: Rc<Type>,
+}
+
+/// A DW_TAG_variant_part.
+#[derive(Debug, Clone)]
+pub struct Variants {
+    /// The field holding the discriminant. Enums with only one variant don't need one.
+    pub discriminant: Option<Member>,
+    pub variants: Vec<Variant>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Variant {
+    /// The discriminant value that selects this variant. None for the variant that applies
+    /// when no other one matches, which is how niche-optimized enums store their data variant.
+    pub discr_value: Option<u64>,
+    /// The variant's name and contents (a structure).
+    pub member: Member,
 }
 
 impl Type {
     pub fn new(name: String, size: usize) -> Self {
         Type {
-            name: name,
-            size: size,
+            name,
+            size,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the field with the given name.
+    pub fn member(&self, name: &str) -> Option<&Member> {
+        self.members.iter().find(|member| member.name == name)
+    }
+
+    /// Writes the type and, indented below it, the types it refers to.
+    fn write_tree(&self, out: &mut String, indent: usize) -> fmt::Result {
+        writeln!(
+            out,
+            "{:indent$}* {} ({} bytes)",
+            "",
+            self.name,
+            self.size,
+            indent = indent
+        )?;
+        if let Some(pointee) = &self.pointee {
+            pointee.write_tree(out, indent + 2)?;
+        }
+        for member in &self.members {
+            writeln!(
+                out,
+                "{:indent$}- {} at offset {}:",
+                "",
+                member.name,
+                member.offset,
+                indent = indent + 2
+            )?;
+            member.entity_type.write_tree(out, indent + 4)?;
+        }
+        Ok(())
+    }
+
+    fn to_json(&self) -> Value {
+        let members: Vec<Value> = self
+            .members
+            .iter()
+            .map(|member| {
+                json!({
+                    "name": member.name,
+                    "offset": member.offset,
+                    "type": member.entity_type.name,
+                })
+            })
+            .collect();
+        json!({
+            "name": self.name,
+            "size": self.size,
+            "pointee": self.pointee.as_ref().map(|pointee| pointee.to_json()),
+            "members": members,
+        })
+    }
+
+    /// Renders a value of this type from the little-endian bytes read out of the inferior.
+    pub fn format_value(&self, bytes: &[u8]) -> String {
+        if bytes.is_empty() || bytes.len() > 8 {
+            return format!("<{} bytes>", bytes.len());
+        }
+        let mut buf = [0u8; 8];
+        buf[..bytes.len()].copy_from_slice(bytes);
+        let raw = u64::from_le_bytes(buf);
+        let shift = 64 - 8 * bytes.len() as u32;
+        let signed = ((raw << shift) as i64) >> shift;
+        if self.kind == TypeKind::Pointer {
+            return format!("{:#x}", raw);
+        }
+        if self.kind == TypeKind::Enum {
+            if let Some((name, _)) = self.enumerators.iter().find(|(_, value)| *value == signed) {
+                return name.clone();
+            }
+        }
+        match self.name.as_str() {
+            // Rust chars are Unicode scalar values
+            "char" if self.size == 4 => match std::char::from_u32(raw as u32) {
+                Some(c) => format!("{:?}", c),
+                None => raw.to_string(),
+            },
+            "f32" if self.size == 4 => f32::from_bits(raw as u32).to_string(),
+            "f64" if self.size == 8 => f64::from_bits(raw).to_string(),
+            "u8" | "u16" | "u32" | "u64" | "usize" => raw.to_string(),
+            "char" | "signed char" => {
+                format!("{} '{}'", signed, (raw as u8 as char).escape_default())
+            }
+            "unsigned char" => format!("{} '{}'", raw, (raw as u8 as char).escape_default()),
+            "_Bool" | "bool" => (raw != 0).to_string(),
+            "float" if self.size == 4 => f32::from_bits(raw as u32).to_string(),
+            "double" if self.size == 8 => f64::from_bits(raw).to_string(),
+            name if name.ends_with('*') => format!("{:#x}", raw),
+            name if name.contains("unsigned") => raw.to_string(),
+            _ => signed.to_string(),
         }
     }
 }
@@ -167,13 +737,33 @@ impl Type {
 pub enum Location {
     Address(usize),
     FramePointerOffset(isize),
+    /// Any other location description, such as a register or a value assembled from pieces.
+    /// These are evaluated against the program's state by `dwarf_expr`.
+    Expression(Expression),
+    /// A location list: the variable is described by a different expression over each
+    /// (start, end) address range, and is not available anywhere else.
+    List(Vec<(usize, usize, Expression)>),
+    /// The variable exists in the source, but the compiler didn't keep it anywhere.
+    OptimizedOut,
 }
 
 impl fmt::Display for Location {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match *self {
+        match self {
             Location::Address(addr) => write!(f, "Address({:#x})", addr),
             Location::FramePointerOffset(offset) => write!(f, "FramePointerOffset({})", offset),
+            Location::Expression(expr) => write!(f, "Expression({})", expr),
+            Location::List(entries) => {
+                write!(f, "LocationList(")?;
+                for (i, (start, end, expr)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "[{:#x}, {:#x}): {}", start, end, expr)?;
+                }
+                write!(f, ")")
+            }
+            Location::OptimizedOut => write!(f, "OptimizedOut"),
         }
     }
 }
@@ -184,6 +774,33 @@ impl fmt::Debug for Location {
     }
 }
 
+/// The bytecode of a DWARF expression, kept around so it can be evaluated whenever the variable
+/// is printed.
+#[derive(Clone)]
+pub struct Expression {
+    pub bytes: Vec<u8>,
+    pub encoding: gimli::Encoding,
+    /// Addresses the expression refers to by their index in .debug_addr (DW_OP_addrx and
+    /// DW_OP_constx), resolved when the unit was parsed.
+    pub addresses: Vec<(u64, u64)>,
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            gimli_wrapper::format_expression(&self.bytes, self.encoding)
+        )
+    }
+}
+
+impl fmt::Debug for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
 // For variables and formal parameters
 #[derive(Debug, Clone)]
 pub struct Variable {
@@ -193,6 +810,36 @@ pub struct Variable {
     pub line_number: usize, // Line number in source file
 }
 
+impl Variable {
+    fn to_json(&self) -> Value {
+        let location = match &self.location {
+            Location::Address(addr) => json!({ "kind": "address", "address": addr }),
+            Location::FramePointerOffset(offset) => {
+                json!({ "kind": "frame_offset", "offset": offset })
+            }
+            Location::Expression(expr) => {
+                json!({ "kind": "expression", "expression": expr.to_string() })
+            }
+            Location::List(entries) => {
+                let entries: Vec<Value> = entries
+                    .iter()
+                    .map(|(start, end, expr)| {
+                        json!({ "start": start, "end": end, "expression": expr.to_string() })
+                    })
+                    .collect();
+                json!({ "kind": "list", "entries": entries })
+            }
+            Location::OptimizedOut => json!({ "kind": "optimized_out" }),
+        };
+        json!({
+            "name": self.name,
+            "type": self.entity_type.to_json(),
+            "location": location,
+            "line_number": self.line_number,
+        })
+    }
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct Function {
     pub name: String,
@@ -200,6 +847,95 @@ pub struct Function {
     pub text_length: usize,
     pub line_number: usize, // Line number in source file
     pub variables: Vec<Variable>,
+    /// The formal parameters, in order. These are also included in `variables`.
+    pub parameters: Vec<Variable>,
+    /// None for functions returning void.
+    pub return_type: Option<Type>,
+    /// Calls this function makes, for recovering the arguments it passed once the callee has
+    /// overwritten the registers they arrived in.
+    pub call_sites: Vec<CallSite>,
+    /// Copies of other functions that the compiler inlined into this one.
+    pub inlined_calls: Vec<InlinedCall>,
+}
+
+/// A DW_TAG_inlined_subroutine entry: a call whose callee was inlined into the caller's code.
+#[derive(Debug, Clone)]
+pub struct InlinedCall {
+    /// Name of the inlined function.
+    pub name: String,
+    /// Address of the first instruction of the inlined function, where a breakpoint goes.
+    pub entry: usize,
+    /// The (start, end) address ranges of the inlined code, which need not be contiguous.
+    pub ranges: Vec<(usize, usize)>,
+    /// Where the call is in the source.
+    pub call_file: String,
+    pub call_line: usize,
+    /// How many other inlined calls this one is nested in.
+    pub depth: usize,
+    /// Variables and parameters of the inlined copy.
+    pub variables: Vec<Variable>,
+}
+
+impl InlinedCall {
+    pub fn contains(&self, addr: usize) -> bool {
+        self.ranges
+            .iter()
+            .any(|&(start, end)| start <= addr && addr < end)
+    }
+
+    fn to_json(&self) -> Value {
+        let ranges: Vec<Value> = self
+            .ranges
+            .iter()
+            .map(|(start, end)| json!({ "start": start, "end": end }))
+            .collect();
+        json!({
+            "name": self.name,
+            "entry": self.entry,
+            "ranges": ranges,
+            "call_file": self.call_file,
+            "call_line": self.call_line,
+            "depth": self.depth,
+            "variables": to_json_list(&self.variables, Variable::to_json),
+        })
+    }
+}
+
+/// A DW_TAG_call_site entry.
+#[derive(Debug, Clone)]
+pub struct CallSite {
+    /// Address of the instruction after the call, i.e. the callee's return address.
+    pub return_address: usize,
+    /// The DWARF number of each register an argument was passed in, and an expression for the
+    /// argument's value, evaluated in the caller's frame.
+    pub parameters: Vec<(u16, Expression)>,
+}
+
+impl CallSite {
+    fn to_json(&self) -> Value {
+        let parameters: Vec<Value> = self
+            .parameters
+            .iter()
+            .map(|(register, value)| json!({ "register": register, "value": value.to_string() }))
+            .collect();
+        json!({ "return_address": self.return_address, "parameters": parameters })
+    }
+}
+
+impl Function {
+    fn to_json(&self) -> Value {
+        json!({
+            "name": self.name,
+            "address": self.address,
+            "text_length": self.text_length,
+            "line_number": self.line_number,
+            "return_type": self.return_type.as_ref().map(Type::to_json),
+            "parameters": to_json_list(&self.parameters, Variable::to_json),
+            "variables": to_json_list(&self.variables, Variable::to_json),
+            "call_sites": to_json_list(&self.call_sites, CallSite::to_json),
+            "inlined_calls": to_json_list(&self.inlined_calls, InlinedCall::to_json),
+        })
+    }
 }
 
 #[derive(Debug, Default, Clone)]
@@ -208,6 +944,61 @@ pub struct File {
     pub global_variables: Vec<Variable>,
     pub functions: Vec<Function>,
     pub lines: Vec<Line>,
+    /// Line numbers keyed by the address of their first instruction, for lookups by address.
+    pub line_table: BTreeMap<usize, usize>,
+}
+
+/// Finds the defined (not just declared) function that `query` names, preferring an exact match
+/// to a partly qualified one.
+fn find_function<'a>(functions: &[&'a Function], query: &str) -> Option<&'a Function> {
+    let defined = || functions.iter().copied().filter(|func| func.address != 0);
+    defined()
+        .find(|func| func.name == query)
+        .or_else(|| defined().find(|func| demangle::name_matches(&func.name, query)))
+}
+
+/// Converts each item to JSON.
+fn to_json_list<T>(items: &[T], to_json: fn(&T) -> Value) -> Vec<Value> {
+    items.iter().map(to_json).collect()
+}
+
+impl File {
+    fn to_json(&self) -> Value {
+        let lines: Vec<Value> = self
+            .lines
+            .iter()
+            .map(|line| json!({ "number": line.number, "address": line.address }))
+            .collect();
+        let types: Vec<Value> = self.types().iter().map(|ty| ty.to_json()).collect();
+        json!({
+            "name": self.name,
+            "global_variables": to_json_list(&self.global_variables, Variable::to_json),
+            "functions": to_json_list(&self.functions, Function::to_json),
+            "types": types,
+            "lines": lines,
+        })
+    }
+
+    /// Returns each distinct type used by the file's variables and functions.
+    fn types(&self) -> Vec<&Type> {
+        let mut types: Vec<&Type> = Vec::new();
+        let variables = self
+            .global_variables
+            .iter()
+            .chain(self.functions.iter().flat_map(|func| func.variables.iter()));
+        let used = variables.map(|var| &var.entity_type).chain(
+            self.functions
+                .iter()
+                .filter_map(|func| func.return_type.as_ref()),
+        );
+        for ty in used {
+            if !types.iter().any(|known| known.name == ty.name) {
+                types.push(ty);
+            }
+        }
+        types.sort_by(|a, b| a.name.cmp(&b.name));
+        types
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -222,3 +1013,171 @@ impl fmt::Display for Line {
         write!(f, "{}:{}", self.file, self.number)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pretty::Printers;
+    use crate::testing;
+    use regex::Regex;
+
+    #[test]
+    fn units_are_parsed_on_demand() {
+        let debug_data = testing::debug_data("function_calls");
+        assert!(debug_data.file(0).is_some());
+        assert!(debug_data.file(debug_data.files.len()).is_none());
+        assert_eq!(
+            debug_data.get_addr_for_function(None, "func2"),
+            debug_data.get_function("func2").map(|func| func.address)
+        );
+    }
+
+    #[test]
+    fn arrays_have_element_types_and_sizes() {
+        let debug_data = testing::debug_data("arrays");
+        let main = debug_data.get_function("main").unwrap();
+        let variable = |name| {
+            &main
+                .variables
+                .iter()
+                .find(|var| var.name == name)
+                .unwrap()
+                .entity_type
+        };
+
+        let buf = variable("buf");
+        assert_eq!(buf.kind, TypeKind::Array);
+        assert_eq!(buf.name, "char [16]");
+        assert_eq!(buf.size, 16);
+        assert_eq!(buf.pointee.as_ref().unwrap().name, "char");
+
+        // Both dimensions count towards the size
+        let grid = variable("grid");
+        assert_eq!(grid.kind, TypeKind::Array);
+        assert_eq!(grid.size, 24);
+        assert_eq!(grid.pointee.as_ref().unwrap().name, "int");
+
+        let printers = Printers::new();
+        let read = |_, _| None;
+        let mut bytes = b"hello".to_vec();
+        bytes.resize(16, 0);
+        assert_eq!(printers.format_value(buf, &bytes, &read), "\"hello\"");
+        let bytes: Vec<u8> = (1..=6i32).flat_map(|n| n.to_le_bytes().to_vec()).collect();
+        assert_eq!(
+            printers.format_value(grid, &bytes, &read),
+            "{1, 2, 3, 4, 5, 6}"
+        );
+    }
+    #[test]
+    fn json_dump_has_units_functions_types_and_lines() {
+        let debug_data = testing::debug_data("function_calls");
+        let json = debug_data.to_json();
+        let units = json["compile_units"].as_array().unwrap();
+        let unit = units
+            .iter()
+            .find(|unit| unit["name"].as_str().unwrap().ends_with("function_calls.c"))
+            .unwrap();
+
+        let func2 = unit["functions"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|func| func["name"] == "func2")
+            .unwrap();
+        assert_eq!(
+            func2["address"],
+            debug_data.get_addr_for_function(None, "func2").unwrap()
+        );
+        let parameters: Vec<&Value> = func2["parameters"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|param| &param["name"])
+            .collect();
+        assert_eq!(parameters, ["a", "b"]);
+        assert_eq!(unit["global_variables"][0]["name"], "global");
+
+        let types = unit["types"].as_array().unwrap();
+        assert!(types.iter().any(|ty| ty["name"] == "int"));
+
+        let lines = unit["lines"].as_array().unwrap();
+        let addr = func2["address"].as_u64().unwrap() as usize;
+        let line = debug_data.get_line_from_addr(addr).unwrap().number;
+        assert!(lines.iter().any(|entry| entry["number"] == line));
+        assert!(json["symbols"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|sym| sym["name"] == "main" && sym["kind"] == "function"));
+    }
+
+    #[test]
+    fn symbol_containing_an_address_is_found_by_its_extent() {
+        let debug_data = testing::debug_data("function_calls");
+        let func2 = debug_data.get_function("func2").unwrap();
+        let symbol = debug_data.get_symbol_containing(func2.address).unwrap();
+        assert_eq!(symbol.name, "func2");
+        assert!(symbol.is_function);
+        let last = func2.address + symbol.size - 1;
+        assert_eq!(
+            debug_data.get_symbol_containing(last).unwrap().name,
+            "func2"
+        );
+        assert_ne!(
+            debug_data
+                .get_symbol_containing(func2.address + symbol.size)
+                .map(|sym| sym.name.as_str()),
+            Some("func2")
+        );
+        assert!(debug_data.get_symbol_containing(0).is_none());
+
+        let global = debug_data.get_global_variable("global").unwrap();
+        let addr = debug_data
+            .symbols()
+            .iter()
+            .find(|sym| sym.name == "global")
+            .unwrap()
+            .address;
+        let symbol = debug_data.get_symbol_containing(addr + 3).unwrap();
+        assert_eq!(symbol.name, global.name);
+        assert!(!symbol.is_function);
+    }
+
+    #[test]
+    fn info_functions_and_variables_filter_by_regex() {
+        let debug_data = testing::debug_data("function_calls");
+        let regex = Regex::new("^func[12]$").unwrap();
+        let matches = |name: &str| regex.is_match(name);
+        let functions = debug_data.functions_matching(&matches);
+        assert_eq!(functions.len(), 1);
+        assert!(functions[0].0.name.ends_with("function_calls.c"));
+        let names: Vec<&str> = functions[0]
+            .1
+            .iter()
+            .map(|func| func.name.as_str())
+            .collect();
+        assert_eq!(names, ["func1", "func2"]);
+        assert!(debug_data.undescribed_symbols(true, &matches).is_empty());
+
+        // Symbols without debugging information are listed separately
+        let regex = Regex::new("_st.rt").unwrap();
+        let matches = |name: &str| regex.is_match(name);
+        assert!(debug_data.functions_matching(&matches).is_empty());
+        let symbols: Vec<&str> = debug_data
+            .undescribed_symbols(true, &matches)
+            .iter()
+            .map(|sym| sym.name.as_str())
+            .collect();
+        assert!(symbols.contains(&"_start"));
+
+        let regex = Regex::new("^glob.l$").unwrap();
+        let matches = |name: &str| regex.is_match(name);
+        let variables = debug_data.global_variables_matching(&matches);
+        assert_eq!(variables.len(), 1);
+        assert_eq!(variables[0].1[0].name, "global");
+        assert!(debug_data.undescribed_symbols(false, &matches).is_empty());
+        assert!(debug_data
+            .global_variables_matching(&|name: &str| name == "func1")
+            .is_empty());
+    }
+}
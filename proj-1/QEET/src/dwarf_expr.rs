@@ -0,0 +1,404 @@
+//! Evaluating DWARF location descriptions against a stopped program. Unoptimized code keeps every
+//! variable at a fixed offset from the frame base, but optimized code moves them into registers,
+//! splits them into pieces, computes them from other values, or drops them altogether for parts
+//! of the function. gimli does the stack machine; this module answers its questions about the
+//! program's registers and memory.
+//!
+//! Only x86-64 is handled. Outer frames are unwound with the frame pointer, so the only registers
+//! known there are %rbp and %rip; anything else counts as optimized out.
+
+use crate::dwarf_data::{Expression, Location};
+use crate::target::{Frame, Target};
+use gimli::{EndianSlice, EvaluationResult, LittleEndian, Value};
+use std::convert::TryInto;
+use std::fmt;
+
+/// Where a variable's value is in a particular frame.
+#[derive(Debug)]
+pub enum Place {
+    Memory(usize),
+    /// A DWARF register number.
+    Register(u16),
+    /// The value itself, computed by the expression (DW_OP_stack_value, DW_OP_implicit_value).
+    Value(Vec<u8>),
+    /// A value assembled from DW_OP_piece parts, each with its size in bytes.
+    Pieces(Vec<(Place, usize)>),
+    OptimizedOut,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// Reading the program's memory failed.
+    Memory(nix::Error),
+    /// The expression needs something we can't provide.
+    Unsupported(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Memory(err) => write!(f, "{}", err),
+            Error::Unsupported(what) => write!(f, "Unhandled DWARF expression: {}", what),
+        }
+    }
+}
+
+impl From<nix::Error> for Error {
+    fn from(err: nix::Error) -> Self {
+        Error::Memory(err)
+    }
+}
+
+impl From<gimli::Error> for Error {
+    fn from(err: gimli::Error) -> Self {
+        Error::Unsupported(err.to_string())
+    }
+}
+
+/// `user_regs_struct` indices (see `target::REGISTER_NAMES`) of the DWARF registers 0 to 16:
+/// rax, rdx, rcx, rbx, rsi, rdi, rbp, rsp, r8 to r15, and the return address (rip).
+const REGISTER_INDICES: [usize; 17] = [10, 12, 11, 5, 13, 14, 4, 19, 9, 8, 7, 6, 3, 2, 1, 0, 16];
+
+/// Returns the value of a register in the given frame, or None if it wasn't saved anywhere we
+/// know of.
+fn register_value(frame: &Frame, register: u16) -> Result<Option<u64>, Error> {
+    match &frame.registers {
+        Some(registers) => match REGISTER_INDICES.get(register as usize) {
+            Some(&index) => Ok(Some(registers[index])),
+            None => Err(Error::Unsupported(format!("register {}", register))),
+        },
+        None => Ok(match register {
+            6 => Some(frame.rbp as u64),
+            16 => Some(frame.rip as u64),
+            _ => None,
+        }),
+    }
+}
+
+/// The canonical frame address, which is what GCC uses as DW_AT_frame_base. It sits just above
+/// the return address and saved %rbp.
+fn frame_base(frame: &Frame) -> u64 {
+    frame.rbp as u64 + 16
+}
+
+fn to_u64(bytes: &[u8]) -> u64 {
+    let mut word = [0; 8];
+    let len = bytes.len().min(8);
+    word[..len].copy_from_slice(&bytes[..len]);
+    u64::from_le_bytes(word)
+}
+
+/// Works out where a variable is in the given frame.
+pub fn evaluate<T: Target + ?Sized>(
+    location: &Location,
+    frame: &Frame,
+    target: &T,
+) -> Result<Place, Error> {
+    match location {
+        Location::Address(addr) => Ok(Place::Memory(*addr)),
+        Location::FramePointerOffset(offset) => Ok(Place::Memory(
+            (frame_base(frame) as isize + offset) as usize,
+        )),
+        Location::Expression(expr) => evaluate_expression(expr, frame, target),
+        Location::List(entries) => {
+            let pc = frame.pc();
+            match entries
+                .iter()
+                .find(|(start, end, _)| (*start..*end).contains(&pc))
+            {
+                Some((_, _, expr)) => evaluate_expression(expr, frame, target),
+                None => Ok(Place::OptimizedOut),
+            }
+        }
+        Location::OptimizedOut => Ok(Place::OptimizedOut),
+    }
+}
+
+/// Runs a single location description.
+pub fn evaluate_expression<T: Target + ?Sized>(
+    expr: &Expression,
+    frame: &Frame,
+    target: &T,
+) -> Result<Place, Error> {
+    let bytecode = EndianSlice::new(&expr.bytes, LittleEndian);
+    let mut evaluation = gimli::Evaluation::new(bytecode, expr.encoding);
+    let mut result = evaluation.evaluate()?;
+    loop {
+        result = match result {
+            EvaluationResult::Complete => break,
+            EvaluationResult::RequiresMemory { address, size, .. } => {
+                let bytes = target.read_memory(address as usize, size as usize)?;
+                evaluation.resume_with_memory(Value::Generic(to_u64(&bytes)))?
+            }
+            EvaluationResult::RequiresRegister { register, .. } => {
+                match register_value(frame, register.0)? {
+                    Some(value) => evaluation.resume_with_register(Value::Generic(value))?,
+                    None => return Ok(Place::OptimizedOut),
+                }
+            }
+            EvaluationResult::RequiresFrameBase => {
+                evaluation.resume_with_frame_base(frame_base(frame))?
+            }
+            EvaluationResult::RequiresCallFrameCfa => {
+                evaluation.resume_with_call_frame_cfa(frame_base(frame))?
+            }
+            EvaluationResult::RequiresEntryValue(entry) => {
+                match entry_value(entry.0, expr.encoding, frame) {
+                    Some(value) => evaluation.resume_with_entry_value(Value::Generic(value))?,
+                    None => return Ok(Place::OptimizedOut),
+                }
+            }
+            // We only debug executables that are loaded where they were linked
+            EvaluationResult::RequiresRelocatedAddress(address) => {
+                evaluation.resume_with_relocated_address(address)?
+            }
+            EvaluationResult::RequiresIndexedAddress { index, .. } => {
+                match expr.addresses.iter().find(|(i, _)| *i == index.0 as u64) {
+                    Some(&(_, address)) => evaluation.resume_with_indexed_address(address)?,
+                    None => return Err(Error::Unsupported("unresolved address index".to_string())),
+                }
+            }
+            EvaluationResult::RequiresTls(_) => {
+                return Err(Error::Unsupported("thread-local storage".to_string()))
+            }
+            EvaluationResult::RequiresAtLocation(_) => {
+                return Err(Error::Unsupported("DW_OP_call".to_string()))
+            }
+            EvaluationResult::RequiresParameterRef(_) => {
+                return Err(Error::Unsupported("DW_OP_GNU_parameter_ref".to_string()))
+            }
+            EvaluationResult::RequiresBaseType(_) => {
+                return Err(Error::Unsupported("typed stack values".to_string()))
+            }
+        };
+    }
+    let mut pieces = evaluation.result();
+    if pieces.len() == 1 && pieces[0].size_in_bits.is_none() {
+        return to_place(pieces.remove(0).location);
+    }
+    let mut places = Vec::new();
+    for piece in pieces {
+        let size_in_bits = piece.size_in_bits.unwrap_or(0);
+        if size_in_bits % 8 != 0 || piece.bit_offset.is_some() {
+            return Err(Error::Unsupported("DW_OP_bit_piece".to_string()));
+        }
+        places.push((to_place(piece.location)?, (size_in_bits / 8) as usize));
+    }
+    Ok(Place::Pieces(places))
+}
+
+fn to_place(location: gimli::Location<EndianSlice<LittleEndian>>) -> Result<Place, Error> {
+    Ok(match location {
+        gimli::Location::Empty => Place::OptimizedOut,
+        gimli::Location::Register { register } => Place::Register(register.0),
+        gimli::Location::Address { address } => Place::Memory(address.try_into().unwrap()),
+        gimli::Location::Value { value } => Place::Value(value.to_u64(!0)?.to_le_bytes().to_vec()),
+        gimli::Location::Bytes { value } => Place::Value(value.slice().to_vec()),
+        gimli::Location::ImplicitPointer { .. } => {
+            return Err(Error::Unsupported("DW_OP_implicit_pointer".to_string()))
+        }
+    })
+}
+
+/// DW_OP_entry_value asks for the value a register had when the function was called. We know it
+/// if the caller described the argument it passed there (see `Target::backtrace`).
+fn entry_value(
+    bytecode: EndianSlice<LittleEndian>,
+    encoding: gimli::Encoding,
+    frame: &Frame,
+) -> Option<u64> {
+    let mut pc = bytecode;
+    match gimli::Operation::parse(&mut pc, encoding).ok()? {
+        gimli::Operation::Register { register } if pc.len() == 0 => {
+            frame.entry_values.get(&register.0).copied()
+        }
+        _ => None,
+    }
+}
+
+/// Reads `size` bytes of a value from where `evaluate` found it. Returns None if (any part of) it
+/// was optimized out.
+pub fn read<T: Target + ?Sized>(
+    place: &Place,
+    size: usize,
+    frame: &Frame,
+    target: &T,
+) -> Result<Option<Vec<u8>>, Error> {
+    Ok(match place {
+        Place::Memory(addr) => Some(target.read_memory(*addr, size)?),
+        Place::Register(register) => match register_value(frame, *register)? {
+            Some(value) if size <= 8 => Some(value.to_le_bytes()[..size].to_vec()),
+            Some(_) => {
+                return Err(Error::Unsupported(format!(
+                    "{}-byte value in register {}",
+                    size, register
+                )))
+            }
+            None => None,
+        },
+        Place::Value(bytes) => {
+            let mut bytes = bytes.clone();
+            bytes.resize(size, 0);
+            Some(bytes)
+        }
+        Place::Pieces(pieces) => {
+            let mut bytes = Vec::with_capacity(size);
+            for (piece, piece_size) in pieces {
+                match read(piece, *piece_size, frame, target)? {
+                    Some(piece) => bytes.extend(piece),
+                    None => return Ok(None),
+                }
+            }
+            bytes.resize(size, 0);
+            Some(bytes)
+        }
+        Place::OptimizedOut => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::target::NUM_REGISTERS;
+    use libc::user_regs_struct;
+    use nix::errno::Errno;
+    use std::collections::HashMap;
+
+    const RBP: usize = 0x7ff0_0000;
+    const RSP: usize = 0x7fef_ff00;
+
+    /// A program that only has the memory it's given.
+    struct Memory(HashMap<usize, u64>);
+
+    impl Target for Memory {
+        fn registers(&self) -> Result<user_regs_struct, nix::Error> {
+            Err(nix::Error::Sys(Errno::ESRCH))
+        }
+
+        fn read_word(&self, addr: usize) -> Result<u64, nix::Error> {
+            self.0
+                .get(&addr)
+                .copied()
+                .ok_or(nix::Error::Sys(Errno::EIO))
+        }
+    }
+
+    fn expression(bytes: &[u8]) -> Expression {
+        Expression {
+            bytes: bytes.to_vec(),
+            encoding: gimli::Encoding {
+                format: gimli::Format::Dwarf32,
+                version: 4,
+                address_size: 8,
+            },
+            addresses: Vec::new(),
+        }
+    }
+
+    /// The innermost frame, stopped at `rip`, with rax = 1, rbx = 2, and so on for the other
+    /// registers in `REGISTER_INDICES`.
+    fn innermost(rip: usize) -> Frame {
+        let mut registers = [0u64; NUM_REGISTERS];
+        for (number, &index) in REGISTER_INDICES.iter().enumerate() {
+            registers[index] = number as u64 + 1;
+        }
+        registers[REGISTER_INDICES[6]] = RBP as u64;
+        registers[REGISTER_INDICES[7]] = RSP as u64;
+        registers[REGISTER_INDICES[16]] = rip as u64;
+        Frame {
+            function: None,
+            line: None,
+            rip,
+            rbp: RBP,
+            registers: Some(registers),
+            entry_values: HashMap::new(),
+            inlined: false,
+        }
+    }
+
+    /// A caller's frame, which only knows its return address and frame pointer.
+    fn outer(rip: usize) -> Frame {
+        Frame {
+            registers: None,
+            ..innermost(rip)
+        }
+    }
+
+    fn read_u64(location: &Location, frame: &Frame, target: &Memory) -> Option<u64> {
+        let place = evaluate(location, frame, target).unwrap();
+        read(&place, 8, frame, target)
+            .unwrap()
+            .map(|bytes| to_u64(&bytes))
+    }
+
+    #[test]
+    fn frame_base_and_register_locations() {
+        let memory = Memory(vec![(RBP + 16 - 24, 42)].into_iter().collect());
+        let frame = innermost(0x1000);
+
+        // DW_OP_fbreg -24
+        let local = Location::Expression(expression(&[0x91, 0x68]));
+        match evaluate(&local, &frame, &memory).unwrap() {
+            Place::Memory(addr) => assert_eq!(addr, RBP + 16 - 24),
+            place => panic!("expected memory, got {:?}", place),
+        }
+        assert_eq!(read_u64(&local, &frame, &memory), Some(42));
+
+        // DW_OP_reg3 (rbx)
+        let in_register = Location::Expression(expression(&[0x53]));
+        assert_eq!(read_u64(&in_register, &frame, &memory), Some(4));
+        // Callers' frames don't know what was in rbx
+        assert_eq!(read_u64(&in_register, &outer(0x1000), &memory), None);
+    }
+
+    #[test]
+    fn computed_values_and_pieces() {
+        let memory = Memory(vec![(RSP + 8, 0x1234)].into_iter().collect());
+        let frame = innermost(0x1000);
+
+        // DW_OP_breg7 (rsp) 8, DW_OP_deref, DW_OP_lit5, DW_OP_plus, DW_OP_stack_value
+        let computed = Location::Expression(expression(&[0x77, 0x08, 0x06, 0x35, 0x22, 0x9f]));
+        assert_eq!(read_u64(&computed, &frame, &memory), Some(0x1239));
+
+        // DW_OP_reg0 (rax), DW_OP_piece 4, DW_OP_lit7, DW_OP_stack_value, DW_OP_piece 4
+        let pieces = Location::Expression(expression(&[0x50, 0x93, 0x04, 0x37, 0x9f, 0x93, 0x04]));
+        assert_eq!(read_u64(&pieces, &frame, &memory), Some(7 << 32 | 1));
+
+        // A piece that was optimized out makes the whole value unavailable
+        let missing = Location::Expression(expression(&[0x93, 0x04, 0x50, 0x93, 0x04]));
+        assert_eq!(read_u64(&missing, &frame, &memory), None);
+
+        // Reading memory that isn't there is an error, not an optimized-out value
+        let bad = Location::Expression(expression(&[0x77, 0x10, 0x06, 0x9f]));
+        assert!(evaluate(&bad, &frame, &memory).is_err());
+    }
+
+    #[test]
+    fn entry_values_come_from_the_caller() {
+        let memory = Memory(HashMap::new());
+        let mut frame = innermost(0x1000);
+        // DW_OP_GNU_entry_value(DW_OP_reg5 (rdi)), DW_OP_stack_value
+        let location = Location::Expression(expression(&[0xf3, 0x01, 0x55, 0x9f]));
+        assert_eq!(read_u64(&location, &frame, &memory), None);
+        frame.entry_values.insert(5, 99);
+        assert_eq!(read_u64(&location, &frame, &memory), Some(99));
+    }
+
+    #[test]
+    fn location_lists_pick_the_range_containing_the_pc() {
+        let memory = Memory(vec![(RBP + 16 - 8, 5)].into_iter().collect());
+        let list = Location::List(vec![
+            // DW_OP_reg0 (rax)
+            (0x1000, 0x1010, expression(&[0x50])),
+            // DW_OP_fbreg -8
+            (0x1010, 0x1020, expression(&[0x91, 0x78])),
+        ]);
+        assert_eq!(read_u64(&list, &innermost(0x1008), &memory), Some(1));
+        assert_eq!(read_u64(&list, &innermost(0x1010), &memory), Some(5));
+        assert_eq!(read_u64(&list, &innermost(0x1020), &memory), None);
+        assert_eq!(read_u64(&list, &innermost(0xfff), &memory), None);
+        // A caller is looked up at its call instruction, just before the return address
+        assert_eq!(read_u64(&list, &outer(0x1020), &memory), Some(5));
+        assert_eq!(read_u64(&list, &outer(0x1010), &memory), None);
+    }
+}
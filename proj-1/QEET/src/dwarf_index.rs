@@ -0,0 +1,430 @@
+//! Indexes that map addresses and names to the compilation units describing them, so that
+//! `DwarfData` only parses the units a lookup actually needs. They are read from the accelerator
+//! tables (.debug_aranges, and .debug_names or .debug_pubnames) when the compiler emitted them,
+//! and otherwise from a shallow pass over each unit's top-level entries and namespaces.
+
+use crate::debug_files::SplitDwarf;
+use crate::demangle;
+use crate::gimli_wrapper::{self, Error, OpenUnit, SectionReader};
+use gimli::{EndianSlice, Reader, RunTimeEndian};
+use object::Object;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::TryInto;
+
+/// A compilation unit we know about but may not have parsed yet.
+pub struct UnitEntry {
+    pub offset: gimli::DebugInfoOffset,
+    /// DW_AT_name of the unit, usually the path of its source file.
+    pub name: String,
+}
+
+pub struct DwarfIndex {
+    pub units: Vec<UnitEntry>,
+    /// (start, end, unit number) address ranges, sorted by start address.
+    ranges: Vec<(usize, usize, usize)>,
+    /// Names of functions and global variables, mapped to the units that define them.
+    names: HashMap<String, Vec<usize>>,
+    /// False if the name index may be missing some names (.debug_pubnames leaves out statics),
+    /// in which case lookups that miss have to fall back to parsing everything.
+    pub names_complete: bool,
+    /// Problems that didn't stop the index from being built, such as missing split DWARF files.
+    pub warnings: Vec<String>,
+}
+
+impl DwarfIndex {
+    pub fn build(
+        object: &object::File,
+        endian: RunTimeEndian,
+        dwarf: &gimli::Dwarf<SectionReader>,
+        split_dwarf: &SplitDwarf,
+    ) -> Result<DwarfIndex, Error> {
+        let section = |name: &str| object.section_data_by_name(name).unwrap_or_default();
+
+        let mut units = Vec::new();
+        let mut unit_numbers = HashMap::new();
+        let mut root_ranges = Vec::new();
+        // Split units are kept open, since we'll need them again to index names
+        let mut split_units = Vec::new();
+        let mut warnings = Vec::new();
+        for offset in gimli_wrapper::unit_offsets(dwarf)? {
+            let opened = match gimli_wrapper::open_unit(dwarf, offset, split_dwarf) {
+                Ok(opened) => opened,
+                Err(Error::MissingSplitDwarf(dwo_name)) => {
+                    warnings.push(format!(
+                        "Warning: could not find split DWARF file {}",
+                        dwo_name
+                    ));
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            let range = match opened.unit.entries().next_dfs()? {
+                Some((_, root)) => root_range(dwarf, &opened.unit, root),
+                None => None,
+            };
+            let (entries_dwarf, entries_unit) = opened.entries(dwarf);
+            let name = match entries_unit.entries().next_dfs()? {
+                Some((_, root)) => unit_name(entries_dwarf, entries_unit, root),
+                None => String::new(),
+            };
+            unit_numbers.insert(offset.0, units.len());
+            if let Some((start, end)) = range {
+                root_ranges.push((start, end, units.len()));
+            }
+            units.push(UnitEntry { offset, name });
+            split_units.push(if opened.split.is_some() {
+                Some(opened)
+            } else {
+                None
+            });
+        }
+
+        let mut ranges = Vec::new();
+        let aranges = section(".debug_aranges");
+        let mut covered = vec![false; units.len()];
+        let mut items = gimli::DebugAranges::new(&aranges, endian).items();
+        while let Some(arange) = items.next()? {
+            if let Some(&unit) = unit_numbers.get(&arange.debug_info_offset().0) {
+                let start = arange.address() as usize;
+                ranges.push((start, start + arange.length() as usize, unit));
+                covered[unit] = true;
+            }
+        }
+        // Units the compiler didn't list in .debug_aranges are indexed by their root DIE's range
+        ranges.extend(root_ranges.into_iter().filter(|range| !covered[range.2]));
+        ranges.sort();
+
+        let debug_names = section(".debug_names");
+        let pubnames = section(".debug_pubnames");
+        let (names, names_complete) = if !debug_names.is_empty() {
+            let debug_str = section(".debug_str");
+            let names = read_debug_names(&debug_names, &debug_str, endian, &unit_numbers)
+                .unwrap_or_else(|_| scan_names(dwarf, &units, &split_units));
+            (names, true)
+        } else if !pubnames.is_empty() {
+            let mut names: HashMap<String, Vec<usize>> = HashMap::new();
+            let mut items = gimli::DebugPubNames::new(&pubnames, endian).items();
+            while let Some(item) = items.next()? {
+                if let Some(&unit) = unit_numbers.get(&item.unit_header_offset().0) {
+                    let name = item.name().to_string_lossy().into_owned();
+                    names.entry(name).or_default().push(unit);
+                }
+            }
+            (names, false)
+        } else {
+            (scan_names(dwarf, &units, &split_units), true)
+        };
+
+        Ok(DwarfIndex {
+            units,
+            ranges,
+            names,
+            names_complete,
+            warnings,
+        })
+    }
+
+    /// Returns the number of the unit whose code contains addr.
+    pub fn unit_for_address(&self, addr: usize) -> Option<usize> {
+        // The ranges starting at or before addr come first
+        let end = self
+            .ranges
+            .binary_search_by(|range| {
+                if range.0 <= addr {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                }
+            })
+            .unwrap_or_else(|end| end);
+        // Ranges may nest or overlap, so check every range starting before addr
+        self.ranges[..end]
+            .iter()
+            .rev()
+            .find(|range| addr < range.1)
+            .map(|range| range.2)
+    }
+
+    /// Returns the numbers of the units defining a function or global variable with this name.
+    pub fn units_for_name(&self, name: &str) -> &[usize] {
+        // Functions are indexed by their DW_AT_name, the last component of a qualified name
+        self.names
+            .get(name)
+            .or_else(|| self.names.get(&demangle::base_name(name)))
+            .map_or(&[], |units| units.as_slice())
+    }
+}
+
+fn unit_name(
+    dwarf: &gimli::Dwarf<SectionReader>,
+    unit: &gimli::Unit<SectionReader>,
+    root: &gimli::DebuggingInformationEntry<SectionReader>,
+) -> String {
+    root.attr_value(gimli::DW_AT_name)
+        .ok()
+        .flatten()
+        .and_then(|name| dwarf.attr_string(unit, name).ok())
+        .map_or_else(
+            || "<unknown>".to_string(),
+            |name| {
+                name.to_string_lossy()
+                    .map(|name| name.into_owned())
+                    .unwrap_or_default()
+            },
+        )
+}
+
+/// Reads the [low_pc, high_pc) range of a unit's root DIE.
+fn root_range(
+    dwarf: &gimli::Dwarf<SectionReader>,
+    unit: &gimli::Unit<SectionReader>,
+    root: &gimli::DebuggingInformationEntry<SectionReader>,
+) -> Option<(usize, usize)> {
+    let address = |value: gimli::AttributeValue<SectionReader>| match value {
+        gimli::AttributeValue::Addr(addr) => Some(addr),
+        // Skeleton units refer to addresses by their index in .debug_addr
+        gimli::AttributeValue::DebugAddrIndex(index) => dwarf.address(unit, index).ok(),
+        _ => None,
+    };
+    let low = address(root.attr_value(gimli::DW_AT_low_pc).ok()??)?;
+    let high = root.attr_value(gimli::DW_AT_high_pc).ok()??;
+    // DWARF 4 and later encode high_pc as an offset from low_pc
+    let high = match high.udata_value() {
+        Some(offset) => low + offset,
+        None => address(high)?,
+    };
+    Some((low as usize, high as usize))
+}
+
+/// Builds the name index by reading the names of each unit's top-level functions and variables.
+/// Nested entries are skipped without being parsed.
+fn scan_names(
+    dwarf: &gimli::Dwarf<SectionReader>,
+    units: &[UnitEntry],
+    split_units: &[Option<OpenUnit>],
+) -> HashMap<String, Vec<usize>> {
+    let mut names: HashMap<String, Vec<usize>> = HashMap::new();
+    for (number, entry) in units.iter().enumerate() {
+        // A unit we can't scan can still be found by address, so errors are ignored
+        let _ = (|| -> Result<(), gimli::Error> {
+            let opened;
+            let (dwarf, unit) = match &split_units[number] {
+                Some(split_unit) => split_unit.entries(dwarf),
+                None => {
+                    opened = dwarf.unit(gimli_wrapper::unit_header(dwarf, entry.offset)?)?;
+                    (dwarf, &opened)
+                }
+            };
+            let mut tree = unit.entries_tree(None)?;
+            scan_children(dwarf, unit, tree.root()?, &mut |name| {
+                let units = names.entry(name).or_default();
+                if units.last() != Some(&number) {
+                    units.push(number);
+                }
+            })
+        })();
+    }
+    names
+}
+
+/// Reports the names of the functions and variables among a DIE's children. Rust and C++ put
+/// most of theirs inside namespaces, and C++ methods defined outside their class only name
+/// themselves through DW_AT_specification.
+fn scan_children(
+    dwarf: &gimli::Dwarf<SectionReader>,
+    unit: &gimli::Unit<SectionReader>,
+    node: gimli::EntriesTreeNode<SectionReader>,
+    add: &mut dyn FnMut(String),
+) -> Result<(), gimli::Error> {
+    let mut children = node.children();
+    while let Some(child) = children.next()? {
+        let die = child.entry();
+        match die.tag() {
+            gimli::DW_TAG_namespace => {
+                scan_children(dwarf, unit, child, add)?;
+                continue;
+            }
+            gimli::DW_TAG_subprogram | gimli::DW_TAG_variable => {}
+            _ => continue,
+        }
+        let name = match die.attr_value(gimli::DW_AT_name)? {
+            Some(name) => Some(name),
+            None => match die.attr_value(gimli::DW_AT_specification)? {
+                Some(gimli::AttributeValue::UnitRef(offset)) => {
+                    unit.entry(offset)?.attr_value(gimli::DW_AT_name)?
+                }
+                _ => None,
+            },
+        };
+        if let Some(name) = name {
+            add(dwarf
+                .attr_string(unit, name)?
+                .to_string_lossy()?
+                .into_owned());
+        }
+    }
+    Ok(())
+}
+
+/// Reads the name tables in a DWARF 5 .debug_names section (section 6.1.1 of the standard). Each
+/// table covers one or more units; every name points at entries whose DW_IDX_compile_unit
+/// attribute says which unit defines it.
+fn read_debug_names(
+    data: &[u8],
+    debug_str: &[u8],
+    endian: RunTimeEndian,
+    unit_numbers: &HashMap<usize, usize>,
+) -> Result<HashMap<String, Vec<usize>>, gimli::Error> {
+    let mut names: HashMap<String, Vec<usize>> = HashMap::new();
+    let mut input = EndianSlice::new(data, endian);
+    while !input.is_empty() {
+        let unit_length = input.read_u32()? as usize;
+        if unit_length >= 0xffff_fff0 {
+            // 64-bit DWARF isn't supported
+            return Err(gimli::Error::UnknownReservedLength);
+        }
+        let mut table = input.split(unit_length)?;
+        let _version = table.read_u16()?;
+        let _padding = table.read_u16()?;
+        let comp_unit_count = table.read_u32()? as usize;
+        let local_type_unit_count = table.read_u32()? as usize;
+        let foreign_type_unit_count = table.read_u32()? as usize;
+        let bucket_count = table.read_u32()? as usize;
+        let name_count = table.read_u32()? as usize;
+        let abbrev_table_size = table.read_u32()? as usize;
+        let augmentation_size = table.read_u32()? as usize;
+        table.skip(augmentation_size)?;
+
+        let mut comp_units = Vec::with_capacity(comp_unit_count);
+        for _ in 0..comp_unit_count {
+            comp_units.push(unit_numbers.get(&(table.read_u32()? as usize)).copied());
+        }
+        table.skip(4 * local_type_unit_count + 8 * foreign_type_unit_count)?;
+        table.skip(4 * bucket_count)?;
+        if bucket_count > 0 {
+            table.skip(4 * name_count)?;
+        }
+        let mut string_offsets = Vec::with_capacity(name_count);
+        for _ in 0..name_count {
+            string_offsets.push(table.read_u32()? as usize);
+        }
+        let mut entry_offsets = Vec::with_capacity(name_count);
+        for _ in 0..name_count {
+            entry_offsets.push(table.read_u32()? as usize);
+        }
+
+        // code -> (tag, [(index attribute, form)])
+        let mut abbrevs = HashMap::new();
+        let mut abbrev_table = table.split(abbrev_table_size)?;
+        loop {
+            let code = abbrev_table.read_uleb128()?;
+            if code == 0 {
+                break;
+            }
+            let tag = abbrev_table.read_uleb128()?;
+            let mut attributes = Vec::new();
+            loop {
+                let index = abbrev_table.read_uleb128()?;
+                let form = abbrev_table.read_uleb128()?;
+                if index == 0 && form == 0 {
+                    break;
+                }
+                attributes.push((index, form));
+            }
+            abbrevs.insert(code, (tag, attributes));
+        }
+
+        // What's left is the entry pool
+        for (string_offset, entry_offset) in string_offsets.into_iter().zip(entry_offsets) {
+            let name = match debug_str.get(string_offset..) {
+                Some(rest) => {
+                    let end = rest
+                        .iter()
+                        .position(|&b| b == 0)
+                        .unwrap_or_else(|| rest.len());
+                    String::from_utf8_lossy(&rest[..end]).into_owned()
+                }
+                None => continue,
+            };
+            let mut entries = table;
+            entries.skip(entry_offset)?;
+            loop {
+                let code = entries.read_uleb128()?;
+                let (tag, attributes) = match abbrevs.get(&code) {
+                    Some(abbrev) => abbrev,
+                    None => break,
+                };
+                let mut comp_unit = if comp_units.len() == 1 { Some(0) } else { None };
+                for &(index, form) in attributes {
+                    let value = read_index_value(&mut entries, form)?;
+                    if index == gimli::DW_IDX_compile_unit.0 as u64 {
+                        comp_unit = Some(value as usize);
+                    }
+                }
+                let is_named_object = *tag == gimli::DW_TAG_subprogram.0 as u64
+                    || *tag == gimli::DW_TAG_inlined_subroutine.0 as u64
+                    || *tag == gimli::DW_TAG_variable.0 as u64;
+                let unit = comp_unit.and_then(|cu| comp_units.get(cu).copied().flatten());
+                if let (true, Some(unit)) = (is_named_object, unit) {
+                    let units = names.entry(name.clone()).or_default();
+                    if !units.contains(&unit) {
+                        units.push(unit);
+                    }
+                }
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// Reads the value of a .debug_names index attribute. Only the forms the standard allows for
+/// index attributes are supported.
+fn read_index_value(
+    input: &mut EndianSlice<RunTimeEndian>,
+    form: u64,
+) -> Result<u64, gimli::Error> {
+    let form = gimli::DwForm(form.try_into().map_err(|_| gimli::Error::UnknownForm)?);
+    Ok(match form {
+        gimli::DW_FORM_flag_present => 1,
+        gimli::DW_FORM_data1 | gimli::DW_FORM_ref1 | gimli::DW_FORM_flag => input.read_u8()? as u64,
+        gimli::DW_FORM_data2 | gimli::DW_FORM_ref2 => input.read_u16()? as u64,
+        gimli::DW_FORM_data4 | gimli::DW_FORM_ref4 => input.read_u32()? as u64,
+        gimli::DW_FORM_data8 | gimli::DW_FORM_ref8 | gimli::DW_FORM_ref_sig8 => input.read_u64()?,
+        gimli::DW_FORM_udata | gimli::DW_FORM_ref_udata => input.read_uleb128()?,
+        gimli::DW_FORM_sdata => input.read_sleb128()? as u64,
+        _ => return Err(gimli::Error::UnknownForm),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index_of(ranges: Vec<(usize, usize, usize)>) -> DwarfIndex {
+        DwarfIndex {
+            units: Vec::new(),
+            ranges,
+            names: HashMap::new(),
+            names_complete: true,
+            warnings: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn address_lookup_handles_nested_ranges() {
+        let index = index_of(vec![
+            (0x100, 0x400, 0),
+            (0x200, 0x300, 1),
+            (0x300, 0x300, 2),
+        ]);
+        assert_eq!(index.unit_for_address(0xff), None);
+        assert_eq!(index.unit_for_address(0x100), Some(0));
+        assert_eq!(index.unit_for_address(0x200), Some(1));
+        assert_eq!(index.unit_for_address(0x2ff), Some(1));
+        // Past the end of the inner ranges, but still in the outer one
+        assert_eq!(index.unit_for_address(0x300), Some(0));
+        assert_eq!(index.unit_for_address(0x400), None);
+        assert_eq!(index_of(Vec::new()).unit_for_address(0x100), None);
+    }
+}
@@ -0,0 +1,107 @@
+//! What can go wrong in a `Session`.
+
+use nix::errno::Errno;
+use nix::sys::wait::WaitStatus;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum Error {
+    /// There is no process to act on: none was spawned or attached to, or it has ended.
+    NotRunning,
+    /// The process went away while the debugger was working on it, e.g. it was killed from
+    /// outside while stopped.
+    ProcessGone,
+    /// The kernel won't let the debugger attach to the process, usually because of
+    /// /proc/sys/kernel/yama/ptrace_scope.
+    PermissionDenied,
+    /// Memory at this address can't be read or written.
+    BadAddress(usize),
+    /// The program's entry point isn't in /proc/<pid>/auxv. Heap checking and calling functions
+    /// need it.
+    NoEntryPoint,
+    /// waitpid reported a change of state the debugger doesn't know what to do with.
+    UnsupportedStop(WaitStatus),
+    /// The program's debugging information couldn't be read.
+    DebugInfo(String),
+    /// The program couldn't be started.
+    Spawn(String),
+    /// A breakpoint location that doesn't resolve to any address.
+    Location(String),
+    /// A variable that can't be evaluated where the program is stopped.
+    Evaluate(String),
+    /// There is no breakpoint, watchpoint or catchpoint with this number.
+    NoBreakpoint(usize),
+    /// There is no display expression with this number.
+    NoDisplay(usize),
+    /// There is no checkpoint with this number.
+    NoCheckpoint(usize),
+    /// Running backwards needs the program's execution to have been recorded.
+    NotRecording,
+    /// A file couldn't be read or written, e.g. /proc/<pid>/maps or a core file.
+    Io(io::Error),
+    /// Any other failure of a ptrace request or system call. Failures the caller can say more
+    /// about are turned into the variants above where they happen, e.g. by `Error::memory`.
+    Ptrace(nix::Error),
+}
+
+impl Error {
+    /// Classifies the failure of a read or write of the process's memory at `addr`.
+    pub fn memory(err: nix::Error, addr: usize) -> Error {
+        match err {
+            nix::Error::Sys(Errno::EIO) | nix::Error::Sys(Errno::EFAULT) => Error::BadAddress(addr),
+            err => Error::from(err),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotRunning => write!(f, "The program is not being run."),
+            Error::ProcessGone => write!(f, "The program is no longer running."),
+            Error::PermissionDenied => write!(
+                f,
+                "Could not attach to the process: Operation not permitted. Check \
+                 /proc/sys/kernel/yama/ptrace_scope."
+            ),
+            Error::BadAddress(addr) => write!(f, "Cannot access memory at address {:#x}", addr),
+            Error::NoEntryPoint => write!(f, "Could not find the program's entry point."),
+            Error::UnsupportedStop(status) => {
+                write!(f, "The program stopped in an unexpected way: {:?}", status)
+            }
+            Error::DebugInfo(message)
+            | Error::Spawn(message)
+            | Error::Location(message)
+            | Error::Evaluate(message) => write!(f, "{}", message),
+            Error::NoBreakpoint(number) => write!(f, "No breakpoint number {}.", number),
+            Error::NoDisplay(number) => write!(f, "No display number {}.", number),
+            Error::NoCheckpoint(number) => write!(f, "Invalid checkpoint number {}.", number),
+            Error::NotRecording => write!(
+                f,
+                "Reverse execution needs a recording; use \"record\" first."
+            ),
+            Error::Io(err) => write!(f, "{}", err),
+            Error::Ptrace(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<nix::Error> for Error {
+    fn from(err: nix::Error) -> Self {
+        match err {
+            // ptrace fails with ESRCH once the tracee is dead, and waitpid with ECHILD once it
+            // has been reaped
+            nix::Error::Sys(Errno::ESRCH) | nix::Error::Sys(Errno::ECHILD) => Error::ProcessGone,
+            err => Error::Ptrace(err),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
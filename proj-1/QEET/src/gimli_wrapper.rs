@@ -5,61 +5,266 @@
 //!
 //! This code is a huge mess. Please don't read it unless you're trying to do an extension :)
 
-use gimli;
-use gimli::{UnitOffset, UnitSectionOffset};
+use gimli::Reader as _;
+use gimli::{Section, UnitOffset, UnitSectionOffset};
 use object::Object;
 use std::borrow;
 //use std::io::{BufWriter, Write};
-use crate::dwarf_data::{File, Function, Line, Location, Type, Variable};
+use crate::debug_files::SplitDwarf;
+use crate::demangle;
+use crate::dwarf_data::{
+    CallSite, Expression, File, Function, InlinedCall, Line, Location, Member, Type, TypeKind,
+    Variable, Variant, Variants,
+};
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fmt::Write;
+use std::rc::Rc;
+use std::sync::Arc;
 use std::{io, path};
 
-pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<Vec<File>, Error> {
-    // Load a section and return as `Cow<[u8]>`.
-    let load_section = |id: gimli::SectionId| -> Result<borrow::Cow<[u8]>, gimli::Error> {
-        Ok(object
-            .section_data_by_name(id.name())
-            .unwrap_or(borrow::Cow::Borrowed(&[][..])))
+/// Section data shared by `DwarfData` and the units it parses later on.
+pub type SectionReader = gimli::EndianArcSlice<gimli::RunTimeEndian>;
+
+/// Copies the DWARF sections out of the object file, so that units can be parsed on demand after
+/// the file has been unmapped.
+pub fn load_dwarf(
+    object: &object::File,
+    supplementary: Option<&object::File>,
+    endian: gimli::RunTimeEndian,
+) -> Result<gimli::Dwarf<SectionReader>, Error> {
+    // Load a section and return as an `EndianArcSlice`.
+    let load_section = |id| load_section_from(Some(object), id, endian);
+    // Load a section of the supplementary object file (see `debug_files`), which holds strings
+    // and entries shared by several programs' debug files. Most programs don't have one.
+    let load_section_sup = |id| load_section_from(supplementary, id, endian);
+
+    Ok(gimli::Dwarf::load(&load_section, &load_section_sup)?)
+}
+
+fn load_section_from(
+    object: Option<&object::File>,
+    id: gimli::SectionId,
+    endian: gimli::RunTimeEndian,
+) -> Result<SectionReader, gimli::Error> {
+    let data = object
+        .and_then(|object| object.section_data_by_name(id.name()))
+        .unwrap_or(borrow::Cow::Borrowed(&[][..]));
+    Ok(gimli::EndianArcSlice::new(Arc::from(&*data), endian))
+}
+
+/// Returns the offsets of the compilation units in .debug_info.
+pub fn unit_offsets(
+    dwarf: &gimli::Dwarf<SectionReader>,
+) -> Result<Vec<gimli::DebugInfoOffset>, Error> {
+    let mut offsets = Vec::new();
+    let mut offset = 0;
+    while offset < dwarf.debug_info.reader().len() {
+        let header = unit_header(dwarf, gimli::DebugInfoOffset(offset))?;
+        offsets.push(gimli::DebugInfoOffset(offset));
+        offset += header.length_including_self();
+    }
+    Ok(offsets)
+}
+
+/// Parses the header of the unit at `offset` in .debug_info.
+pub fn unit_header(
+    dwarf: &gimli::Dwarf<SectionReader>,
+    offset: gimli::DebugInfoOffset,
+) -> Result<gimli::CompilationUnitHeader<SectionReader>, gimli::Error> {
+    Ok(read_unit_header(dwarf.debug_info.reader(), offset)?.0)
+}
+
+/// Parses the header of the unit at `offset`, returning its dwo ID if it has one. gimli only
+/// understands DWARF 5 headers of ordinary compilation units, so this also handles the skeleton
+/// and split units of DWARF 5 split DWARF, whose headers are followed by the dwo ID.
+fn read_unit_header(
+    debug_info: &SectionReader,
+    offset: gimli::DebugInfoOffset,
+) -> Result<(gimli::CompilationUnitHeader<SectionReader>, Option<u64>), gimli::Error> {
+    let mut input = debug_info.clone();
+    input.skip(offset.0)?;
+    let (unit_length, format) = input.read_initial_length()?;
+    let mut rest = input.split(unit_length)?;
+    let version = rest.read_u16()?;
+    let mut dwo_id = None;
+    let (address_size, abbrev_offset) = match version {
+        2..=4 => {
+            let abbrev_offset = rest.read_offset(format)?;
+            (rest.read_u8()?, abbrev_offset)
+        }
+        5 => {
+            let unit_type = gimli::DwUt(rest.read_u8()?);
+            let address_size = rest.read_u8()?;
+            let abbrev_offset = rest.read_offset(format)?;
+            match unit_type {
+                gimli::DW_UT_compile | gimli::DW_UT_partial => {}
+                gimli::DW_UT_skeleton | gimli::DW_UT_split_compile => {
+                    dwo_id = Some(rest.read_u64()?)
+                }
+                _ => return Err(gimli::Error::UnsupportedUnitType),
+            }
+            (address_size, abbrev_offset)
+        }
+        _ => return Err(gimli::Error::UnknownVersion(version.into())),
     };
-    // Load a supplementary section. We don't have a supplementary object file,
-    // so always return an empty slice.
-    let load_section_sup = |_| Ok(borrow::Cow::Borrowed(&[][..]));
+    let encoding = gimli::Encoding {
+        format,
+        version,
+        address_size,
+    };
+    let header = gimli::UnitHeader::new(
+        encoding,
+        unit_length,
+        gimli::DebugAbbrevOffset(abbrev_offset),
+        rest,
+    );
+    Ok((gimli::CompilationUnitHeader::new(header, offset), dwo_id))
+}
 
-    // Load all of the sections.
-    let dwarf_cow = gimli::Dwarf::load(&load_section, &load_section_sup)?;
+/// A compilation unit in .debug_info. With split DWARF (`-gsplit-dwarf`) this is just a skeleton
+/// holding the line table, and the entries are in a split unit in a .dwo or .dwp file.
+pub struct OpenUnit {
+    pub unit: gimli::Unit<SectionReader>,
+    /// The split unit, and the DWARF it has to be read with.
+    pub split: Option<(gimli::Dwarf<SectionReader>, gimli::Unit<SectionReader>)>,
+}
 
-    // Borrow a `Cow<[u8]>` to create an `EndianSlice`.
-    let borrow_section: &dyn for<'a> Fn(
-        &'a borrow::Cow<[u8]>,
-    ) -> gimli::EndianSlice<'a, gimli::RunTimeEndian> =
-        &|section| gimli::EndianSlice::new(&*section, endian);
+impl OpenUnit {
+    /// Returns the unit holding the entries, along with the DWARF to read them with.
+    pub fn entries<'a>(
+        &'a self,
+        dwarf: &'a gimli::Dwarf<SectionReader>,
+    ) -> (&'a gimli::Dwarf<SectionReader>, &'a gimli::Unit<SectionReader>) {
+        match &self.split {
+            Some((split_dwarf, split_unit)) => (split_dwarf, split_unit),
+            None => (dwarf, &self.unit),
+        }
+    }
+}
 
-    // Create `EndianSlice`s for all of the sections.
-    let dwarf = dwarf_cow.borrow(&borrow_section);
+/// Opens the unit at `offset` in .debug_info, and the split unit it refers to if it's a skeleton.
+pub fn open_unit(
+    dwarf: &gimli::Dwarf<SectionReader>,
+    offset: gimli::DebugInfoOffset,
+    split_dwarf: &SplitDwarf,
+) -> Result<OpenUnit, Error> {
+    let (header, mut dwo_id) = read_unit_header(dwarf.debug_info.reader(), offset)?;
+    let unit = dwarf.unit(header)?;
 
-    // Define a mapping from type offsets to type structs
-    let mut offset_to_type: HashMap<usize, Type> = HashMap::new();
+    let mut dwo_name = None;
+    let mut addr_base = unit.addr_base;
+    {
+        let mut entries = unit.entries();
+        let (_, root) = entries.next_dfs()?.ok_or(gimli::Error::MissingUnitDie)?;
+        let mut attrs = root.attrs();
+        while let Some(attr) = attrs.next()? {
+            match (attr.name(), attr.value()) {
+                (gimli::DW_AT_dwo_name, value) | (gimli::DW_AT_GNU_dwo_name, value) => {
+                    let name = dwarf.attr_string(&unit, value)?;
+                    dwo_name = Some(name.to_string_lossy()?.into_owned());
+                }
+                (gimli::DW_AT_GNU_dwo_id, value) => dwo_id = value.udata_value(),
+                (gimli::DW_AT_GNU_addr_base, gimli::AttributeValue::SecOffset(base)) => {
+                    addr_base = gimli::DebugAddrBase(base);
+                }
+                _ => {}
+            }
+        }
+    }
+    let dwo_name = match dwo_name {
+        Some(name) => name,
+        None => return Ok(OpenUnit { unit, split: None }),
+    };
 
-    let mut compilation_units: Vec<File> = Vec::new();
+    let comp_dir = match &unit.comp_dir {
+        Some(comp_dir) => Some(comp_dir.to_string_lossy()?.into_owned()),
+        None => None,
+    };
+    let sections = split_dwarf
+        .sections(comp_dir.as_deref(), &dwo_name, dwo_id)
+        .ok_or_else(|| Error::MissingSplitDwarf(dwo_name.clone()))?;
+    let endian = dwarf.debug_info.reader().endian();
+    let load_section = |id: gimli::SectionId| -> Result<SectionReader, gimli::Error> {
+        // Addresses are still kept in the executable, since they need relocating
+        if id == gimli::SectionId::DebugAddr {
+            return Ok(dwarf.debug_addr.reader().clone());
+        }
+        let data = sections
+            .get(format!("{}.dwo", id.name()).as_str())
+            .cloned()
+            .unwrap_or_else(|| Arc::from(&[][..]));
+        Ok(gimli::EndianArcSlice::new(data, endian))
+    };
+    let load_section_sup = |_| Ok(gimli::EndianArcSlice::new(Arc::from(&[][..]), endian));
+    let dwo_dwarf = gimli::Dwarf::load(&load_section, &load_section_sup)?;
 
-    // Iterate over the compilation units.
-    let mut iter = dwarf.units();
-    while let Some(header) = iter.next()? {
-        let unit = dwarf.unit(header)?;
+    let header = unit_header(&dwo_dwarf, gimli::DebugInfoOffset(0))?;
+    let mut split_unit = dwo_dwarf.unit(header)?;
+    split_unit.addr_base = addr_base;
+    // In DWARF 5, each unit's string offsets start after the section header; GNU split DWARF
+    // has no header
+    if split_unit.encoding().version >= 5 {
+        let header_size = split_unit.encoding().format.initial_length_size() as usize + 4;
+        split_unit.str_offsets_base = gimli::DebugStrOffsetsBase(header_size);
+    }
+    Ok(OpenUnit {
+        unit,
+        split: Some((dwo_dwarf, split_unit)),
+    })
+}
+
+/// Parses the compilation unit whose header is at `offset` in .debug_info.
+pub fn load_unit(
+    dwarf: &gimli::Dwarf<SectionReader>,
+    offset: gimli::DebugInfoOffset,
+    split_dwarf: &SplitDwarf,
+) -> Result<File, Error> {
+    let opened = open_unit(dwarf, offset, split_dwarf)?;
+    let (entries_dwarf, entries_unit) = opened.entries(dwarf);
+    let mut file = read_entries(entries_dwarf, entries_unit)?;
+    read_lines(dwarf, &opened.unit, &mut file)?;
+    file.line_table = file
+        .lines
+        .iter()
+        .map(|line| (line.address, line.number))
+        .collect();
+    Ok(file)
+}
 
+/// Reads the variables, functions and types of a unit.
+#[allow(clippy::cognitive_complexity)]
+fn read_entries(
+    dwarf: &gimli::Dwarf<SectionReader>,
+    unit: &gimli::Unit<SectionReader>,
+) -> Result<File, Error> {
+    let mut types = TypeTable::read(dwarf, unit)?;
+
+    let mut compilation_units: Vec<File> = Vec::new();
+
+    {
         // Iterate over the Debugging Information Entries (DIEs) in the unit.
         let mut depth = 0;
+        // The DFS depth of each DW_TAG_inlined_subroutine we're inside, and its index in the
+        // function's inlined_calls
+        let mut inline_stack: Vec<(isize, usize)> = Vec::new();
+        // The DFS depth of the DW_TAG_subprogram we're inside, if any. (In Rust and C++, things
+        // outside of functions can still be nested in namespaces.)
+        let mut function_depth: Option<isize> = None;
         let mut entries = unit.entries();
         while let Some((delta_depth, entry)) = entries.next_dfs()? {
             depth += delta_depth;
-            // Update the offset_to_type mapping for types
+            while inline_stack.last().map_or(false, |&(call_depth, _)| call_depth >= depth) {
+                inline_stack.pop();
+            }
+            if function_depth.map_or(false, |function_depth| function_depth >= depth) {
+                function_depth = None;
+            }
             // Update the variable list for formal params/variables
             match entry.tag() {
                 gimli::DW_TAG_compile_unit => {
                     let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
-                        if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, &dwarf) {
+                        if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, unit, dwarf) {
                             name
                         } else {
                             "<unknown>".to_string()
@@ -72,40 +277,16 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                         global_variables: Vec::new(),
                         functions: Vec::new(),
                         lines: Vec::new(),
+                        line_table: Default::default(),
                     });
                 }
-                gimli::DW_TAG_base_type => {
-                    let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
-                        if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, &dwarf) {
-                            name
-                        } else {
-                            "<unknown>".to_string()
-                        }
-                    } else {
-                        "<unknown>".to_string()
-                    };
-                    let byte_size = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_byte_size) {
-                        if let Ok(DebugValue::Uint(byte_size)) =
-                            get_attr_value(&attr, &unit, &dwarf)
-                        {
-                            byte_size
-                        } else {
-                            // TODO: report error?
-                            0
-                        }
-                    } else {
-                        // TODO: report error?
-                        0
-                    };
-                    let type_offset = entry.offset().0;
-                    offset_to_type
-                        .insert(type_offset, Type::new(name, byte_size.try_into().unwrap()));
-                }
                 gimli::DW_TAG_subprogram => {
+                    function_depth = Some(depth);
                     let mut func: Function = Default::default();
+                    let mut linkage_name = None;
                     let mut attrs = entry.attrs();
                     while let Some(attr) = attrs.next()? {
-                        let val = get_attr_value(&attr, &unit, &dwarf);
+                        let val = get_attr_value(&attr, unit, dwarf);
                         //println!("   {}: {:?}", attr.name(), val);
                         match attr.name() {
                             gimli::DW_AT_name => {
@@ -129,9 +310,33 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                                     func.line_number = line_number.try_into().unwrap();
                                 }
                             }
+                            gimli::DW_AT_type => {
+                                if let Ok(DebugValue::Size(offset)) = val {
+                                    func.return_type = types.get(offset);
+                                }
+                            }
+                            // Rust and C++ qualify names through the mangled linkage name
+                            gimli::DW_AT_linkage_name | gimli::DW_AT_MIPS_linkage_name => {
+                                if let Ok(DebugValue::Str(name)) = val {
+                                    linkage_name = Some(demangle::demangle(&name));
+                                }
+                            }
+                            // Out-of-line copies of inline functions only refer to the abstract
+                            // instance, which has the name, and C++ methods defined outside
+                            // their class refer to the declaration
+                            gimli::DW_AT_abstract_origin | gimli::DW_AT_specification => {
+                                if let Some(origin) = get_origin(&attr, unit) {
+                                    if let Some(name) = get_function_name(&origin, unit, dwarf) {
+                                        func.name = name;
+                                    }
+                                }
+                            }
                             _ => {}
                         }
                     }
+                    if let Some(linkage_name) = linkage_name {
+                        func.name = linkage_name;
+                    }
                     compilation_units.last_mut().unwrap().functions.push(func);
                 }
                 gimli::DW_TAG_formal_parameter | gimli::DW_TAG_variable => {
@@ -139,9 +344,10 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                     let mut entity_type: Option<Type> = None;
                     let mut location: Option<Location> = None;
                     let mut line_number = 0;
+                    let mut declaration = false;
                     let mut attrs = entry.attrs();
                     while let Some(attr) = attrs.next()? {
-                        let val = get_attr_value(&attr, &unit, &dwarf);
+                        let val = get_attr_value(&attr, unit, dwarf);
                         //println!("   {}: {:?}", attr.name(), val);
                         match attr.name() {
                             gimli::DW_AT_name => {
@@ -151,13 +357,13 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                             }
                             gimli::DW_AT_type => {
                                 if let Ok(DebugValue::Size(offset)) = val {
-                                    if let Some(dtype) = offset_to_type.get(&offset).clone() {
-                                        entity_type = Some(dtype.clone());
+                                    if let Some(dtype) = types.get(offset) {
+                                        entity_type = Some(dtype);
                                     }
                                 }
                             }
                             gimli::DW_AT_location => {
-                                if let Some(loc) = get_location(&attr, &unit) {
+                                if let Some(loc) = get_location(&attr, unit, dwarf) {
                                     location = Some(loc);
                                 }
                             }
@@ -166,33 +372,170 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                                     line_number = num;
                                 }
                             }
+                            gimli::DW_AT_declaration => declaration = true,
+                            // Variables of inlined and out-of-line copies of inline functions
+                            // take their name and type from the abstract instance
+                            gimli::DW_AT_abstract_origin => {
+                                if let Some(origin) = get_origin(&attr, unit) {
+                                    if let Some(origin_name) = get_name(&origin, unit, dwarf) {
+                                        name = origin_name;
+                                    }
+                                    if let Ok(Some(attr)) = origin.attr(gimli::DW_AT_type) {
+                                        if let Ok(DebugValue::Size(offset)) =
+                                            get_attr_value(&attr, unit, dwarf)
+                                        {
+                                            entity_type = types.get(offset);
+                                        }
+                                    }
+                                }
+                            }
                             _ => {}
                         }
                     }
-                    if entity_type.is_some() && location.is_some() {
+                    // Optimized code drops the location of locals it doesn't need to keep.
+                    // (Unnamed parameters belong to prototypes, which have no locations at all.)
+                    let in_function = function_depth.is_some();
+                    if location.is_none() && in_function && !declaration && !name.is_empty() {
+                        location = Some(Location::OptimizedOut);
+                    }
+                    if let (Some(entity_type), Some(location)) = (entity_type, location) {
                         let var = Variable {
                             name,
-                            entity_type: entity_type.unwrap(),
-                            location: location.unwrap(),
+                            entity_type,
+                            location,
                             line_number: line_number.try_into().unwrap(),
                         };
-                        if depth == 1 {
+                        if !in_function {
                             compilation_units
                                 .last_mut()
                                 .unwrap()
                                 .global_variables
                                 .push(var);
-                        } else if depth > 1 {
-                            compilation_units
+                        } else {
+                            let func = compilation_units
                                 .last_mut()
                                 .unwrap()
                                 .functions
                                 .last_mut()
-                                .unwrap()
-                                .variables
-                                .push(var);
+                                .unwrap();
+                            if let Some(&(_, index)) = inline_stack.last() {
+                                func.inlined_calls[index].variables.push(var);
+                            } else {
+                                if Some(depth - 1) == function_depth
+                                    && entry.tag() == gimli::DW_TAG_formal_parameter
+                                {
+                                    func.parameters.push(var.clone());
+                                }
+                                func.variables.push(var);
+                            }
+                        }
+                    }
+                }
+                gimli::DW_TAG_inlined_subroutine => {
+                    let file = compilation_units.last_mut().unwrap();
+                    let mut call = InlinedCall {
+                        name: String::new(),
+                        entry: 0,
+                        ranges: Vec::new(),
+                        call_file: file.name.clone(),
+                        call_line: 0,
+                        depth: inline_stack.len(),
+                        variables: Vec::new(),
+                    };
+                    let mut entry_pc = None;
+                    let mut attrs = entry.attrs();
+                    while let Some(attr) = attrs.next()? {
+                        match (attr.name(), attr.value()) {
+                            (gimli::DW_AT_abstract_origin, _) => {
+                                if let Some(origin) = get_origin(&attr, unit) {
+                                    call.name =
+                                        get_function_name(&origin, unit, dwarf).unwrap_or_default();
+                                }
+                            }
+                            // (DWARF 5 also allows an offset from low_pc, which GCC doesn't use)
+                            (gimli::DW_AT_entry_pc, gimli::AttributeValue::Addr(_))
+                            | (gimli::DW_AT_entry_pc, gimli::AttributeValue::DebugAddrIndex(_)) => {
+                                if let Ok(DebugValue::Uint(addr)) =
+                                    get_attr_value(&attr, unit, dwarf)
+                                {
+                                    entry_pc = Some(addr.try_into().unwrap());
+                                }
+                            }
+                            (gimli::DW_AT_call_file, gimli::AttributeValue::FileIndex(index)) => {
+                                if let Some(name) = get_file_name(index, unit, dwarf) {
+                                    call.call_file = name;
+                                }
+                            }
+                            (gimli::DW_AT_call_line, gimli::AttributeValue::Udata(line)) => {
+                                call.call_line = line.try_into().unwrap();
+                            }
+                            _ => {}
+                        }
+                    }
+                    let mut ranges = dwarf.die_ranges(unit, entry)?;
+                    while let Some(range) = ranges.next()? {
+                        call.ranges
+                            .push((range.begin.try_into().unwrap(), range.end.try_into().unwrap()));
+                    }
+                    call.entry = match entry_pc {
+                        Some(entry_pc) => entry_pc,
+                        None => call.ranges.iter().map(|&(start, _)| start).min().unwrap_or(0),
+                    };
+                    if let Some(func) = file.functions.last_mut() {
+                        inline_stack.push((depth, func.inlined_calls.len()));
+                        func.inlined_calls.push(call);
+                    }
+                }
+                gimli::DW_TAG_call_site | gimli::DW_TAG_GNU_call_site => {
+                    let mut return_address = None;
+                    let mut attrs = entry.attrs();
+                    while let Some(attr) = attrs.next()? {
+                        match attr.name() {
+                            // GCC's pre-DWARF 5 extension puts the return address in low_pc
+                            gimli::DW_AT_call_return_pc | gimli::DW_AT_low_pc => {
+                                if let Ok(DebugValue::Uint(addr)) =
+                                    get_attr_value(&attr, unit, dwarf)
+                                {
+                                    return_address = Some(addr.try_into().unwrap());
+                                }
+                            }
+                            _ => {}
                         }
                     }
+                    let func = compilation_units.last_mut().unwrap().functions.last_mut();
+                    if let (Some(func), Some(return_address)) = (func, return_address) {
+                        func.call_sites.push(CallSite {
+                            return_address,
+                            parameters: Vec::new(),
+                        });
+                    }
+                }
+                gimli::DW_TAG_call_site_parameter | gimli::DW_TAG_GNU_call_site_parameter => {
+                    let mut register = None;
+                    let mut value = None;
+                    let mut attrs = entry.attrs();
+                    while let Some(attr) = attrs.next()? {
+                        match attr.name() {
+                            gimli::DW_AT_location => register = get_register(&attr, unit),
+                            gimli::DW_AT_call_value | gimli::DW_AT_GNU_call_site_value => {
+                                if let gimli::AttributeValue::Exprloc(ref data) = attr.value() {
+                                    value = to_expression(data, unit, dwarf);
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                    let call_site = compilation_units
+                        .last_mut()
+                        .unwrap()
+                        .functions
+                        .last_mut()
+                        .and_then(|func| func.call_sites.last_mut());
+                    if let (Some(call_site), Some(register), Some(value)) =
+                        (call_site, register, value)
+                    {
+                        call_site.parameters.push((register, value));
+                    }
                 }
                 // NOTE: :You may consider supporting other types by extending this
                 // match statement
@@ -200,47 +543,443 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
             }
         }
 
-        // Get line numbers
-        if let Some(program) = unit.line_program.clone() {
-            // Iterate over the line program rows.
-            let mut rows = program.rows();
-            while let Some((header, row)) = rows.next_row()? {
-                if !row.end_sequence() {
-                    // Determine the path. Real applications should cache this for performance.
-                    let mut path = path::PathBuf::new();
-                    if let Some(file) = row.file(header) {
-                        if let Some(dir) = file.directory(header) {
-                            path.push(dwarf.attr_string(&unit, dir)?.to_string_lossy().as_ref());
+    }
+    Ok(compilation_units
+        .pop()
+        .ok_or(Error::GimliError(gimli::Error::MissingUnitDie))?)
+}
+
+/// How many pointers deep `TypeTable` follows what pointers point to. Linked structures point back
+/// to themselves, so this has to stop somewhere.
+const POINTER_DEPTH: usize = 3;
+
+/// A type entry as it appears in the unit, before the types it refers to are looked up.
+struct TypeEntry {
+    tag: gimli::DwTag,
+    name: Option<String>,
+    size: Option<usize>,
+    /// What a pointer points to, what an array holds, or what a typedef or qualifier applies to.
+    target: Option<usize>,
+    /// For arrays, the number of elements (of all dimensions together), if it is fixed.
+    count: Option<usize>,
+    members: Vec<MemberEntry>,
+    enumerators: Vec<(String, i64)>,
+    /// Set for Rust enums, which are structures holding a DW_TAG_variant_part.
+    variant_part: Option<VariantPartEntry>,
+}
+
+impl TypeEntry {
+    fn new(tag: gimli::DwTag, name: Option<String>, size: Option<usize>) -> Self {
+        TypeEntry {
+            tag,
+            name,
+            size,
+            target: None,
+            count: None,
+            members: Vec::new(),
+            enumerators: Vec::new(),
+            variant_part: None,
+        }
+    }
+}
+
+struct MemberEntry {
+    name: String,
+    offset: usize,
+    type_offset: Option<usize>,
+}
+
+#[derive(Default)]
+struct VariantPartEntry {
+    discriminant: Option<MemberEntry>,
+    /// The discriminant value and contents of each DW_TAG_variant.
+    variants: Vec<(Option<u64>, Option<MemberEntry>)>,
+}
+
+/// The types declared in a unit. Types refer to each other in any order (and sometimes in
+/// cycles), so they are all read first and then turned into `Type`s as variables need them.
+struct TypeTable {
+    entries: HashMap<usize, TypeEntry>,
+    /// Types already built, by offset and the pointer depth they were built with.
+    resolved: HashMap<(usize, usize), Rc<Type>>,
+    rust: bool,
+}
+
+impl TypeTable {
+    fn read(
+        dwarf: &gimli::Dwarf<SectionReader>,
+        unit: &gimli::Unit<SectionReader>,
+    ) -> Result<TypeTable, Error> {
+        let mut table = TypeTable {
+            entries: HashMap::new(),
+            resolved: HashMap::new(),
+            rust: false,
+        };
+        // C names structures "struct foo"; Rust and C++ qualify them with their namespaces
+        let mut c = false;
+        // The tag, offset and name of each entry enclosing the current one
+        let mut path: Vec<(gimli::DwTag, usize, Option<String>)> = Vec::new();
+        let mut depth: isize = 0;
+        let mut entries = unit.entries();
+        while let Some((delta_depth, entry)) = entries.next_dfs()? {
+            depth += delta_depth;
+            path.truncate(depth.try_into().unwrap_or(0));
+            let offset = section_offset(entry.offset(), unit);
+            let name = get_name(entry, unit, dwarf);
+            let parent = path.last().map(|&(tag, offset, _)| (tag, offset));
+            let attr_type = match entry.attr(gimli::DW_AT_type) {
+                Ok(Some(attr)) => match get_attr_value(&attr, unit, dwarf) {
+                    Ok(DebugValue::Size(offset)) => Some(offset),
+                    _ => None,
+                },
+                _ => None,
+            };
+            let attr_udata = |attr_name| match entry.attr(attr_name) {
+                Ok(Some(attr)) => attr.udata_value(),
+                _ => None,
+            };
+            let size = attr_udata(gimli::DW_AT_byte_size).map(|size| size as usize);
+            match entry.tag() {
+                gimli::DW_TAG_compile_unit | gimli::DW_TAG_partial_unit => {
+                    if let Ok(Some(gimli::AttributeValue::Language(language))) =
+                        entry.attr_value(gimli::DW_AT_language)
+                    {
+                        table.rust = language == gimli::DW_LANG_Rust;
+                        c = match language {
+                            gimli::DW_LANG_C89
+                            | gimli::DW_LANG_C
+                            | gimli::DW_LANG_C99
+                            | gimli::DW_LANG_C11 => true,
+                            _ => false,
+                        };
+                    }
+                }
+                gimli::DW_TAG_base_type
+                | gimli::DW_TAG_pointer_type
+                | gimli::DW_TAG_reference_type
+                | gimli::DW_TAG_rvalue_reference_type
+                | gimli::DW_TAG_typedef
+                | gimli::DW_TAG_const_type
+                | gimli::DW_TAG_volatile_type => {
+                    let mut type_entry = TypeEntry::new(entry.tag(), name.clone(), size);
+                    type_entry.target = attr_type;
+                    table.entries.insert(offset, type_entry);
+                }
+                gimli::DW_TAG_structure_type
+                | gimli::DW_TAG_class_type
+                | gimli::DW_TAG_union_type
+                | gimli::DW_TAG_enumeration_type => {
+                    let qualified_name = name.as_ref().map(|name| {
+                        if c {
+                            let keyword = match entry.tag() {
+                                gimli::DW_TAG_union_type => "union",
+                                gimli::DW_TAG_enumeration_type => "enum",
+                                _ => "struct",
+                            };
+                            format!("{} {}", keyword, name)
+                        } else {
+                            let mut scopes: Vec<&str> = path
+                                .iter()
+                                .filter(|(tag, _, _)| match *tag {
+                                    gimli::DW_TAG_namespace
+                                    | gimli::DW_TAG_structure_type
+                                    | gimli::DW_TAG_class_type
+                                    | gimli::DW_TAG_union_type
+                                    | gimli::DW_TAG_enumeration_type => true,
+                                    _ => false,
+                                })
+                                .filter_map(|(_, _, name)| name.as_deref())
+                                .collect();
+                            scopes.push(name);
+                            scopes.join("::")
+                        }
+                    });
+                    table
+                        .entries
+                        .insert(offset, TypeEntry::new(entry.tag(), qualified_name, size));
+                }
+                gimli::DW_TAG_array_type => {
+                    let mut type_entry = TypeEntry::new(entry.tag(), name.clone(), size);
+                    type_entry.target = attr_type;
+                    type_entry.count = Some(1);
+                    table.entries.insert(offset, type_entry);
+                }
+                gimli::DW_TAG_subrange_type => {
+                    // One for each dimension of the array. C gives the upper bound, Rust the count;
+                    // neither is there for arrays whose size isn't known.
+                    let count = attr_udata(gimli::DW_AT_count)
+                        .or_else(|| attr_udata(gimli::DW_AT_upper_bound).map(|bound| bound + 1));
+                    if let Some((gimli::DW_TAG_array_type, parent_offset)) = parent {
+                        if let Some(array) = table.entries.get_mut(&parent_offset) {
+                            array.count = match (array.count, count) {
+                                (Some(total), Some(count)) => Some(total * count as usize),
+                                _ => None,
+                            };
+                        }
+                    }
+                }
+                gimli::DW_TAG_member => {
+                    let member = MemberEntry {
+                        name: name.clone().unwrap_or_default(),
+                        offset: attr_udata(gimli::DW_AT_data_member_location).unwrap_or(0)
+                            as usize,
+                        type_offset: attr_type,
+                    };
+                    // Members of a variant part are its discriminant, and members of a variant
+                    // are what it holds. Both belong to the enclosing structure.
+                    let grandparent = path.len().checked_sub(2).map(|i| path[i].1);
+                    let great_grandparent = path.len().checked_sub(3).map(|i| path[i].1);
+                    match parent {
+                        Some((gimli::DW_TAG_variant_part, _)) => {
+                            let container = grandparent.and_then(|o| table.entries.get_mut(&o));
+                            if let Some(container) = container {
+                                if let Some(part) = &mut container.variant_part {
+                                    part.discriminant = Some(member);
+                                }
+                            }
+                        }
+                        Some((gimli::DW_TAG_variant, _)) => {
+                            let container =
+                                great_grandparent.and_then(|o| table.entries.get_mut(&o));
+                            if let Some(container) = container {
+                                if let Some(part) = &mut container.variant_part {
+                                    if let Some(variant) = part.variants.last_mut() {
+                                        variant.1 = Some(member);
+                                    }
+                                }
+                            }
+                        }
+                        Some((_, parent_offset)) => {
+                            if let Some(container) = table.entries.get_mut(&parent_offset) {
+                                container.members.push(member);
+                            }
+                        }
+                        None => {}
+                    }
+                }
+                gimli::DW_TAG_variant_part => {
+                    if let Some((_, parent_offset)) = parent {
+                        if let Some(container) = table.entries.get_mut(&parent_offset) {
+                            container.variant_part = Some(Default::default());
+                        }
+                    }
+                }
+                gimli::DW_TAG_variant => {
+                    let discr_value = match entry.attr(gimli::DW_AT_discr_value) {
+                        Ok(Some(attr)) => attr
+                            .udata_value()
+                            .or_else(|| attr.sdata_value().map(|value| value as u64)),
+                        _ => None,
+                    };
+                    let grandparent = path.len().checked_sub(2).map(|i| path[i].1);
+                    if let Some(container) = grandparent.and_then(|o| table.entries.get_mut(&o)) {
+                        if let Some(part) = &mut container.variant_part {
+                            part.variants.push((discr_value, None));
+                        }
+                    }
+                }
+                gimli::DW_TAG_enumerator => {
+                    let value = match entry.attr(gimli::DW_AT_const_value) {
+                        Ok(Some(attr)) => attr.sdata_value().unwrap_or(0),
+                        _ => 0,
+                    };
+                    if let Some((_, parent_offset)) = parent {
+                        if let Some(container) = table.entries.get_mut(&parent_offset) {
+                            container.enumerators.push((name.clone().unwrap_or_default(), value));
                         }
-                        path.push(
-                            dwarf
-                                .attr_string(&unit, file.path_name())?
-                                .to_string_lossy()
-                                .as_ref(),
-                        );
                     }
+                }
+                _ => {}
+            }
+            path.push((entry.tag(), offset, name));
+        }
+        Ok(table)
+    }
 
-                    // Get the File
-                    let file = compilation_units
-                        .iter_mut()
-                        .find(|f| f.name == path.as_os_str().to_str().unwrap());
+    /// Returns the type at the given offset in .debug_info, if the unit declares it.
+    fn get(&mut self, offset: usize) -> Option<Type> {
+        resolve(&self.entries, &mut self.resolved, self.rust, offset, POINTER_DEPTH)
+            .map(|ty| (*ty).clone())
+    }
+}
 
-                    // Determine line/column. DWARF line/column is never 0, so we use that
-                    // but other applications may want to display this differently.
-                    let line = row.line().unwrap_or(0);
+/// Builds the `Type` for a type entry. Pointers only say what they point to while `depth` lasts.
+fn resolve(
+    entries: &HashMap<usize, TypeEntry>,
+    resolved: &mut HashMap<(usize, usize), Rc<Type>>,
+    rust: bool,
+    offset: usize,
+    depth: usize,
+) -> Option<Rc<Type>> {
+    if let Some(ty) = resolved.get(&(offset, depth)) {
+        return Some(ty.clone());
+    }
+    let entry = entries.get(&offset)?;
+    let resolve_member = |resolved: &mut HashMap<(usize, usize), Rc<Type>>,
+                          member: &MemberEntry| {
+        Some(Member {
+            name: member.name.clone(),
+            offset: member.offset,
+            entity_type: resolve(entries, resolved, rust, member.type_offset?, depth)?,
+        })
+    };
+    let ty = match entry.tag {
+        // Qualifiers and typedefs don't change how values look
+        gimli::DW_TAG_typedef | gimli::DW_TAG_const_type | gimli::DW_TAG_volatile_type => {
+            return resolve(entries, resolved, rust, entry.target?, depth);
+        }
+        gimli::DW_TAG_pointer_type
+        | gimli::DW_TAG_reference_type
+        | gimli::DW_TAG_rvalue_reference_type => {
+            let name = match &entry.name {
+                // Rust names its pointers ("&str", "*const u8")
+                Some(name) => name.clone(),
+                None => {
+                    let sigil = if entry.tag == gimli::DW_TAG_pointer_type { "*" } else { "&" };
+                    match entry.target {
+                        Some(target) => format!("{} {}", type_name(entries, target), sigil),
+                        None => format!("void {}", sigil),
+                    }
+                }
+            };
+            let mut pointer = Type::new(name, entry.size.unwrap_or(8));
+            pointer.kind = TypeKind::Pointer;
+            if depth > 0 {
+                pointer.pointee = entry
+                    .target
+                    .and_then(|target| resolve(entries, resolved, rust, target, depth - 1));
+            }
+            pointer
+        }
+        gimli::DW_TAG_base_type => Type::new(
+            entry.name.clone().unwrap_or_else(|| "<unknown>".to_string()),
+            entry.size.unwrap_or(0),
+        ),
+        gimli::DW_TAG_array_type => {
+            // The elements are part of the array, so they don't use up any pointer depth
+            let element = resolve(entries, resolved, rust, entry.target?, depth)?;
+            let size = entry.size.unwrap_or_else(|| element.size * entry.count.unwrap_or(0));
+            let mut array = Type::new(type_name(entries, offset), size);
+            array.kind = TypeKind::Array;
+            array.pointee = Some(element);
+            array
+        }
+        tag => {
+            let mut ty = Type::new(type_name(entries, offset), entry.size.unwrap_or(0));
+            ty.kind = match tag {
+                gimli::DW_TAG_union_type => TypeKind::Union,
+                gimli::DW_TAG_enumeration_type => TypeKind::Enum,
+                _ => TypeKind::Struct,
+            };
+            ty.members = entry
+                .members
+                .iter()
+                .filter_map(|member| resolve_member(resolved, member))
+                .collect();
+            ty.enumerators = entry.enumerators.clone();
+            if let Some(part) = &entry.variant_part {
+                let discriminant = part
+                    .discriminant
+                    .as_ref()
+                    .and_then(|member| resolve_member(resolved, member));
+                let variants = part
+                    .variants
+                    .iter()
+                    .filter_map(|(discr_value, member)| {
+                        Some(Variant {
+                            discr_value: *discr_value,
+                            member: resolve_member(resolved, member.as_ref()?)?,
+                        })
+                    })
+                    .collect();
+                ty.variants = Some(Rc::new(Variants {
+                    discriminant,
+                    variants,
+                }));
+            }
+            ty
+        }
+    };
+    let ty = Rc::new(Type { rust, ..ty });
+    resolved.insert((offset, depth), ty.clone());
+    Some(ty)
+}
 
-                    if let Some(file) = file {
-                        file.lines.push(Line {
-                            file: file.name.clone(),
-                            number: line.try_into().unwrap(),
-                            address: row.address().try_into().unwrap(),
-                        });
+/// Names a type without building it.
+fn type_name(entries: &HashMap<usize, TypeEntry>, offset: usize) -> String {
+    let entry = match entries.get(&offset) {
+        Some(entry) => entry,
+        None => return "<unknown>".to_string(),
+    };
+    if let Some(name) = &entry.name {
+        return name.clone();
+    }
+    match (entry.tag, entry.target) {
+        (gimli::DW_TAG_pointer_type, Some(target)) => format!("{} *", type_name(entries, target)),
+        (gimli::DW_TAG_pointer_type, None) => "void *".to_string(),
+        (gimli::DW_TAG_array_type, Some(target)) => {
+            let count = entry.count.map(|count| count.to_string()).unwrap_or_default();
+            format!("{} [{}]", type_name(entries, target), count)
+        }
+        (gimli::DW_TAG_const_type, Some(target)) | (gimli::DW_TAG_volatile_type, Some(target)) => {
+            type_name(entries, target)
+        }
+        (gimli::DW_TAG_union_type, _) => "<anonymous union>".to_string(),
+        (gimli::DW_TAG_enumeration_type, _) => "<anonymous enum>".to_string(),
+        _ => "<anonymous struct>".to_string(),
+    }
+}
+
+/// Reads the line table of a unit into `file`.
+fn read_lines(
+    dwarf: &gimli::Dwarf<SectionReader>,
+    unit: &gimli::Unit<SectionReader>,
+    file: &mut File,
+) -> Result<(), Error> {
+    // Get line numbers
+    if let Some(program) = unit.line_program.clone() {
+        // Iterate over the line program rows.
+        let mut rows = program.rows();
+        while let Some((header, row)) = rows.next_row()? {
+            if !row.end_sequence() {
+                // Determine the path. Real applications should cache this for performance.
+                let mut path = path::PathBuf::new();
+                if let Some(file) = row.file(header) {
+                    if let Some(dir) = file.directory(header) {
+                        path.push(dwarf.attr_string(unit, dir)?.to_string_lossy()?.as_ref());
                     }
+                    path.push(
+                        dwarf
+                            .attr_string(unit, file.path_name())?
+                            .to_string_lossy()?
+                            .as_ref(),
+                    );
+                }
+
+                // Determine line/column. DWARF line/column is never 0, so we use that
+                // but other applications may want to display this differently.
+                let line = row.line().unwrap_or(0);
+
+                if file.name == path.as_os_str().to_str().unwrap() {
+                    file.lines.push(Line {
+                        file: file.name.clone(),
+                        number: line.try_into().unwrap(),
+                        address: row.address().try_into().unwrap(),
+                    });
                 }
             }
         }
     }
-    Ok(compilation_units)
+    Ok(())
+}
+
+/// Converts an offset within a unit into an offset within .debug_info, which is how references
+/// to types are resolved by `get_attr_value`.
+fn section_offset<R: Reader>(offset: UnitOffset, unit: &gimli::Unit<R>) -> usize {
+    match offset.to_unit_section_offset(unit) {
+        UnitSectionOffset::DebugInfoOffset(goff) => goff.0,
+        UnitSectionOffset::DebugTypesOffset(goff) => goff.0,
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -258,6 +997,8 @@ pub enum Error {
     Addr2lineError(addr2line::gimli::Error),
     ObjectError(String),
     IoError,
+    /// The .dwo file holding a skeleton unit's entries couldn't be found.
+    MissingSplitDwarf(String),
 }
 
 impl From<gimli::Error> for Error {
@@ -289,14 +1030,24 @@ impl<'input, Endian> Reader for gimli::EndianSlice<'input, Endian> where
 {
 }
 
+impl<Endian> Reader for gimli::EndianArcSlice<Endian> where
+    Endian: gimli::Endianity + Send + Sync
+{
+}
+
 trait Reader: gimli::Reader<Offset = usize> + Send + Sync {}
 
-fn get_location<R: Reader>(attr: &gimli::Attribute<R>, unit: &gimli::Unit<R>) -> Option<Location> {
+fn get_location<R: Reader>(
+    attr: &gimli::Attribute<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<Location> {
     if let gimli::AttributeValue::Exprloc(ref data) = attr.value() {
         let encoding = unit.encoding();
         let mut pc = data.0.clone();
-        if pc.len() > 0 {
-            if let Ok(op) = gimli::Operation::parse(&mut pc, encoding) {
+        // Unoptimized code describes almost every variable with a single operation
+        if let Ok(op) = gimli::Operation::parse(&mut pc, encoding) {
+            if pc.len() == 0 {
                 match op {
                     gimli::Operation::FrameOffset { offset } => {
                         return Some(Location::FramePointerOffset(offset.try_into().unwrap()));
@@ -304,10 +1055,149 @@ fn get_location<R: Reader>(attr: &gimli::Attribute<R>, unit: &gimli::Unit<R>) ->
                     gimli::Operation::Address { address } => {
                         return Some(Location::Address(address.try_into().unwrap()));
                     }
+                    // Split DWARF refers to addresses by their index in .debug_addr
+                    gimli::Operation::AddressIndex { index } => {
+                        let address = dwarf.address(unit, index).ok()?;
+                        return Some(Location::Address(address.try_into().unwrap()));
+                    }
                     _ => {}
                 }
             }
         }
+        return Some(Location::Expression(to_expression(data, unit, dwarf)?));
+    }
+    // Otherwise the attribute refers to a location list
+    let offset = dwarf.attr_locations_offset(unit, attr.value()).ok()??;
+    let mut locations = dwarf.locations(unit, offset).ok()?;
+    let mut entries = Vec::new();
+    while let Ok(Some(entry)) = locations.next() {
+        entries.push((
+            entry.range.begin.try_into().unwrap(),
+            entry.range.end.try_into().unwrap(),
+            to_expression(&entry.data, unit, dwarf)?,
+        ));
+    }
+    Some(Location::List(entries))
+}
+
+/// Copies an expression out of the unit so that it can be evaluated later.
+fn to_expression<R: Reader>(
+    data: &gimli::Expression<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<Expression> {
+    let encoding = unit.encoding();
+    let mut addresses = Vec::new();
+    let mut pc = data.0.clone();
+    while pc.len() != 0 {
+        match gimli::Operation::parse(&mut pc, encoding).ok()? {
+            gimli::Operation::AddressIndex { index }
+            | gimli::Operation::ConstantIndex { index } => {
+                addresses.push((index.0 as u64, dwarf.address(unit, index).ok()?));
+            }
+            _ => {}
+        }
+    }
+    Some(Expression {
+        bytes: data.0.to_slice().ok()?.into_owned(),
+        encoding,
+        addresses,
+    })
+}
+
+/// Formats an expression the way `dump` prints DW_AT_location attributes.
+pub fn format_expression(bytes: &[u8], encoding: gimli::Encoding) -> String {
+    let data = gimli::Expression(gimli::EndianSlice::new(bytes, gimli::LittleEndian));
+    let mut buf = String::new();
+    if dump_exprloc(&mut buf, encoding, &data).is_err() {
+        buf.push_str("<invalid expression>");
+    }
+    buf
+}
+
+/// Returns the entry that a DW_AT_abstract_origin attribute refers to.
+fn get_origin<'unit, R: Reader>(
+    attr: &gimli::Attribute<R>,
+    unit: &'unit gimli::Unit<R>,
+) -> Option<gimli::DebuggingInformationEntry<'unit, 'unit, R>> {
+    match attr.value() {
+        gimli::AttributeValue::UnitRef(offset) => unit.entry(offset).ok(),
+        _ => None,
+    }
+}
+
+fn get_name<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<String> {
+    match get_attr_value(&entry.attr(gimli::DW_AT_name).ok()??, unit, dwarf) {
+        Ok(DebugValue::Str(name)) => Some(name),
+        _ => None,
+    }
+}
+
+/// Names a function the way `read_entries` does: by its demangled linkage name, which includes
+/// namespaces and types, or else its plain name. Abstract instances of C++ methods only refer to
+/// the declaration in the class, so follow that.
+fn get_function_name<'unit, R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<'unit, 'unit, R>,
+    unit: &'unit gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<String> {
+    let mut entry = entry.clone();
+    for _ in 0..4 {
+        for &attr_name in &[gimli::DW_AT_linkage_name, gimli::DW_AT_MIPS_linkage_name] {
+            if let Ok(Some(attr)) = entry.attr(attr_name) {
+                if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, unit, dwarf) {
+                    return Some(demangle::demangle(&name));
+                }
+            }
+        }
+        if let Some(name) = get_name(&entry, unit, dwarf) {
+            return Some(name);
+        }
+        let attr = match entry.attr(gimli::DW_AT_abstract_origin) {
+            Ok(Some(attr)) => attr,
+            _ => entry.attr(gimli::DW_AT_specification).ok()??,
+        };
+        entry = get_origin(&attr, unit)?;
+    }
+    None
+}
+
+/// Returns the path of a file in the unit's line program, in the same form as `read_lines`.
+fn get_file_name<R: Reader>(
+    index: u64,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<String> {
+    let header = unit.line_program.as_ref()?.header();
+    let file = header.file(index)?;
+    let mut path = path::PathBuf::new();
+    if let Some(dir) = file.directory(header) {
+        path.push(dwarf.attr_string(unit, dir).ok()?.to_string_lossy().ok()?.as_ref());
+    }
+    path.push(
+        dwarf
+            .attr_string(unit, file.path_name())
+            .ok()?
+            .to_string_lossy()
+            .ok()?
+            .as_ref(),
+    );
+    Some(path.to_str()?.to_string())
+}
+
+/// Returns the register a DW_TAG_call_site_parameter's argument was passed in.
+fn get_register<R: Reader>(attr: &gimli::Attribute<R>, unit: &gimli::Unit<R>) -> Option<u16> {
+    if let gimli::AttributeValue::Exprloc(ref data) = attr.value() {
+        let mut pc = data.0.clone();
+        if let Ok(gimli::Operation::Register { register }) =
+            gimli::Operation::parse(&mut pc, unit.encoding())
+        {
+            return Some(register.0);
+        }
     }
     None
 }
@@ -325,7 +1215,7 @@ fn get_attr_value<R: Reader>(
     match value {
         gimli::AttributeValue::Exprloc(ref data) => {
             dump_exprloc(w, unit.encoding(), data)?;
-            Ok(DebugValue::Str(w.to_string()))
+            Ok(DebugValue::Str(buf))
         }
         gimli::AttributeValue::UnitRef(offset) => {
             match offset.to_unit_section_offset(unit) {
@@ -344,6 +1234,22 @@ fn get_attr_value<R: Reader>(
                 Ok(DebugValue::Str(format!("<.debug_str+0x{:08x}>", offset.0)))
             }
         }
+        gimli::AttributeValue::DebugLineStrRef(offset) => {
+            if let Ok(s) = dwarf.debug_line_str.get_str(offset) {
+                Ok(DebugValue::Str(format!("{}", s.to_string_lossy()?)))
+            } else {
+                Ok(DebugValue::Str(format!("<.debug_line_str+0x{:08x}>", offset.0)))
+            }
+        }
+        // Strings in split DWARF and supplementary object files
+        gimli::AttributeValue::DebugStrOffsetsIndex(_)
+        | gimli::AttributeValue::DebugStrRefSup(_) => {
+            let s = dwarf.attr_string(unit, attr.value())?;
+            Ok(DebugValue::Str(format!("{}", s.to_string_lossy()?)))
+        }
+        gimli::AttributeValue::DebugAddrIndex(index) => {
+            Ok(DebugValue::Uint(dwarf.address(unit, index)?))
+        }
         gimli::AttributeValue::Sdata(data) => Ok(DebugValue::Int(data)),
         gimli::AttributeValue::Addr(data) => Ok(DebugValue::Uint(data)),
         gimli::AttributeValue::Udata(data) => Ok(DebugValue::Uint(data)),
@@ -354,7 +1260,7 @@ fn get_attr_value<R: Reader>(
         gimli::AttributeValue::FileIndex(value) => {
             write!(w, "0x{:08x}", value)?;
             dump_file_index(w, value, unit, dwarf)?;
-            Ok(DebugValue::Str(w.to_string()))
+            Ok(DebugValue::Str(buf))
         }
         _ => {
             Ok(DebugValue::NoVal)
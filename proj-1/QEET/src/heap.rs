@@ -0,0 +1,387 @@
+//! `--check-memory`: keeps track of the program's heap from breakpoints on malloc, calloc, realloc
+//! and free in libc. Every live allocation is recorded with the stack it was made from, so that
+//! frees of pointers that were never allocated (or were already freed) can be caught, and what is
+//! still allocated when the program exits can be reported as leaked. Memory that libc allocated
+//! for itself, such as stdio buffers, is still in use then, so it is only counted.
+//!
+//! libc isn't loaded when the program starts, so the functions are found once it reaches its
+//! entry point. A call that allocates is seen twice: on entry, for its arguments, and when it
+//! returns to its caller, for the pointer.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+
+/// How many return addresses of the stack to keep for each allocation.
+pub const MAX_STACK_DEPTH: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeapFunction {
+    Malloc,
+    Calloc,
+    Realloc,
+    Free,
+}
+
+impl HeapFunction {
+    pub const ALL: [HeapFunction; 4] = [
+        HeapFunction::Malloc,
+        HeapFunction::Calloc,
+        HeapFunction::Realloc,
+        HeapFunction::Free,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HeapFunction::Malloc => "malloc",
+            HeapFunction::Calloc => "calloc",
+            HeapFunction::Realloc => "realloc",
+            HeapFunction::Free => "free",
+        }
+    }
+}
+
+/// Why the tracker has a breakpoint somewhere.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stop {
+    /// The program's entry point, by which time libc has been loaded.
+    Entry,
+    Call(HeapFunction),
+    /// Where an allocating call returns to.
+    Return,
+}
+
+#[derive(Clone)]
+pub struct HeapBreakpoint {
+    pub orig_byte: u8,
+    pub stop: Stop,
+}
+
+#[derive(Debug, Clone)]
+pub struct Allocation {
+    pub addr: usize,
+    pub size: usize,
+    /// Return addresses of the calls that led to the allocation, innermost first.
+    pub stack: Vec<usize>,
+}
+
+/// Allocations made from the same stack that were never freed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Leak {
+    pub stack: Vec<usize>,
+    pub blocks: usize,
+    pub bytes: usize,
+}
+
+/// What was still allocated when the program exited.
+#[derive(Debug, Default, PartialEq)]
+pub struct Leaks {
+    /// Memory the program allocated and lost, largest first.
+    pub leaked: Vec<Leak>,
+    /// Memory that libraries allocated for themselves, such as stdio buffers. They keep using it
+    /// until the program exits, so it is still reachable rather than leaked.
+    pub reachable: Leak,
+}
+
+/// A free (or realloc) the program shouldn't have made.
+#[derive(Debug, Clone)]
+pub enum HeapError {
+    /// A pointer that was already freed. Contains the pointer, the stack of this free and the
+    /// stack of the one before.
+    DoubleFree(usize, Vec<usize>, Vec<usize>),
+    /// A pointer that was never returned by an allocation. Contains the pointer and the stack.
+    InvalidFree(usize, Vec<usize>),
+}
+
+/// An allocating call waiting to return.
+#[derive(Clone)]
+struct PendingCall {
+    function: HeapFunction,
+    size: usize,
+    /// The pointer passed to realloc.
+    old_ptr: usize,
+    /// The stack pointer once the call has returned.
+    sp: usize,
+    stack: Vec<usize>,
+}
+
+#[derive(Clone, Default)]
+pub struct HeapTracker {
+    /// The tracker's own breakpoints, kept apart from the user's.
+    pub breakpoints: HashMap<usize, HeapBreakpoint>,
+    live: BTreeMap<usize, Allocation>,
+    /// Freed pointers that haven't been handed out again, with the stack that freed them.
+    freed: HashMap<usize, Vec<usize>>,
+    pending: Vec<PendingCall>,
+    /// The breakpoint handled at the current stop, so that it isn't handled again when the
+    /// program is resumed from there.
+    pub handled: Option<usize>,
+}
+
+impl HeapTracker {
+    pub fn new() -> HeapTracker {
+        HeapTracker::default()
+    }
+
+    /// The allocations that haven't been freed, by address.
+    pub fn live(&self) -> impl Iterator<Item = &Allocation> {
+        self.live.values()
+    }
+
+    /// Sorts out what is still allocated into leaks and memory that is still reachable.
+    /// `in_program` tells whether an address is in the program's own code: an allocation counts
+    /// as the program's if it called the allocating function itself.
+    pub fn leaks(&self, in_program: impl Fn(usize) -> bool) -> Leaks {
+        let mut leaks = Leaks::default();
+        for allocation in self.live.values() {
+            let leak = match allocation.stack.first() {
+                Some(&caller) if in_program(caller) => {
+                    let existing = leaks
+                        .leaked
+                        .iter()
+                        .position(|leak| leak.stack == allocation.stack);
+                    match existing {
+                        Some(index) => &mut leaks.leaked[index],
+                        None => {
+                            leaks.leaked.push(Leak {
+                                stack: allocation.stack.clone(),
+                                blocks: 0,
+                                bytes: 0,
+                            });
+                            leaks.leaked.last_mut().unwrap()
+                        }
+                    }
+                }
+                _ => &mut leaks.reachable,
+            };
+            leak.blocks += 1;
+            leak.bytes += allocation.size;
+        }
+        leaks.leaked.sort_by_key(|leak| Reverse(leak.bytes));
+        leaks
+    }
+
+    /// Records a call to one of the heap functions, given its arguments and the stack it was
+    /// called from. For calls that allocate, returns where the call returns to, for the caller to
+    /// put a `Stop::Return` breakpoint there.
+    pub fn call(
+        &mut self,
+        function: HeapFunction,
+        args: [usize; 2],
+        sp: usize,
+        stack: Vec<usize>,
+    ) -> Result<Option<usize>, HeapError> {
+        let (size, old_ptr) = match function {
+            HeapFunction::Free => {
+                self.free(args[0], &stack)?;
+                return Ok(None);
+            }
+            HeapFunction::Malloc => (args[0], 0),
+            HeapFunction::Calloc => (args[0].saturating_mul(args[1]), 0),
+            HeapFunction::Realloc => {
+                // A bad pointer is as wrong here as it is in free
+                if args[0] != 0 && !self.live.contains_key(&args[0]) {
+                    return Err(self.bad_free(args[0], &stack));
+                }
+                (args[1], args[0])
+            }
+        };
+        let return_addr = stack.first().copied();
+        self.pending.push(PendingCall {
+            function,
+            size,
+            old_ptr,
+            // `ret` pops the return address
+            sp: sp + 8,
+            stack,
+        });
+        Ok(return_addr)
+    }
+
+    /// Records an allocating call returning `result` with the stack pointer at `sp`. Returns
+    /// false if no call returned here, which happens when a recursive caller passes through the
+    /// same return address.
+    pub fn returned(&mut self, sp: usize, result: usize) -> bool {
+        let call = match self.pending.iter().position(|call| call.sp == sp) {
+            Some(index) => self.pending.remove(index),
+            None => return false,
+        };
+        if call.function == HeapFunction::Realloc && call.old_ptr != 0 {
+            // realloc(p, 0) frees p and returns NULL; otherwise NULL means p was left alone
+            if result != 0 || call.size == 0 {
+                self.live.remove(&call.old_ptr);
+                self.freed.insert(call.old_ptr, call.stack.clone());
+            }
+        }
+        if result != 0 {
+            self.freed.remove(&result);
+            self.live.insert(
+                result,
+                Allocation {
+                    addr: result,
+                    size: call.size,
+                    stack: call.stack,
+                },
+            );
+        }
+        true
+    }
+
+    /// Whether some call still has to return to `addr`.
+    pub fn returns_to(&self, addr: usize) -> bool {
+        self.pending
+            .iter()
+            .any(|call| call.stack.first() == Some(&addr))
+    }
+
+    fn free(&mut self, ptr: usize, stack: &[usize]) -> Result<(), HeapError> {
+        if ptr == 0 {
+            return Ok(());
+        }
+        if self.live.remove(&ptr).is_none() {
+            return Err(self.bad_free(ptr, stack));
+        }
+        self.freed.insert(ptr, stack.to_vec());
+        Ok(())
+    }
+
+    fn bad_free(&self, ptr: usize, stack: &[usize]) -> HeapError {
+        match self.freed.get(&ptr) {
+            Some(freed) => HeapError::DoubleFree(ptr, stack.to_vec(), freed.clone()),
+            None => HeapError::InvalidFree(ptr, stack.to_vec()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SP: usize = 0x7fff_0000;
+    const MAIN: usize = 0x0040_1000;
+    const LIBC: usize = 0x7fff_f000_0000;
+
+    /// Has the tracker see a whole allocating call from main return `result`.
+    fn allocate(heap: &mut HeapTracker, function: HeapFunction, args: [usize; 2], result: usize) {
+        allocate_from(heap, function, args, result, vec![MAIN + 0x10, MAIN + 0x80]);
+    }
+
+    fn allocate_from(
+        heap: &mut HeapTracker,
+        function: HeapFunction,
+        args: [usize; 2],
+        result: usize,
+        stack: Vec<usize>,
+    ) {
+        let return_addr = stack[0];
+        assert_eq!(
+            heap.call(function, args, SP, stack).ok(),
+            Some(Some(return_addr))
+        );
+        assert!(heap.returns_to(return_addr));
+        assert!(heap.returned(SP + 8, result));
+        assert!(!heap.returns_to(return_addr));
+    }
+
+    fn free(heap: &mut HeapTracker, ptr: usize, caller: usize) -> Result<(), HeapError> {
+        heap.call(HeapFunction::Free, [ptr, 0], SP, vec![caller])
+            .map(|_| ())
+    }
+
+    #[test]
+    fn double_free_reports_both_frees() {
+        let mut heap = HeapTracker::new();
+        allocate(&mut heap, HeapFunction::Malloc, [16, 0], 0x1000);
+        assert!(free(&mut heap, 0x1000, MAIN + 0x20).is_ok());
+        match free(&mut heap, 0x1000, MAIN + 0x30) {
+            Err(HeapError::DoubleFree(0x1000, stack, freed_stack)) => {
+                assert_eq!(stack, vec![MAIN + 0x30]);
+                assert_eq!(freed_stack, vec![MAIN + 0x20]);
+            }
+            _ => panic!("expected a double free"),
+        }
+
+        // Once the address is handed out again, freeing it is fine
+        allocate(&mut heap, HeapFunction::Calloc, [4, 8], 0x1000);
+        assert_eq!(heap.live().next().map(|a| a.size), Some(32));
+        assert!(free(&mut heap, 0x1000, MAIN + 0x20).is_ok());
+    }
+
+    #[test]
+    fn freeing_what_was_never_allocated_is_invalid() {
+        let mut heap = HeapTracker::new();
+        assert!(free(&mut heap, 0, MAIN).is_ok(), "free(NULL) does nothing");
+        match free(&mut heap, 0x2000, MAIN) {
+            Err(HeapError::InvalidFree(0x2000, stack)) => assert_eq!(stack, vec![MAIN]),
+            _ => panic!("expected an invalid free"),
+        }
+        match heap.call(HeapFunction::Realloc, [0x2000, 8], SP, vec![MAIN]) {
+            Err(HeapError::InvalidFree(0x2000, _)) => {}
+            _ => panic!("expected realloc of a bad pointer to be an invalid free"),
+        }
+    }
+
+    #[test]
+    fn realloc_frees_the_old_block() {
+        let mut heap = HeapTracker::new();
+        allocate(&mut heap, HeapFunction::Malloc, [16, 0], 0x1000);
+        allocate(&mut heap, HeapFunction::Realloc, [0x1000, 64], 0x3000);
+        let live: Vec<(usize, usize)> = heap.live().map(|a| (a.addr, a.size)).collect();
+        assert_eq!(live, vec![(0x3000, 64)]);
+        match free(&mut heap, 0x1000, MAIN) {
+            Err(HeapError::DoubleFree(0x1000, _, _)) => {}
+            _ => panic!("the old block was freed by realloc"),
+        }
+
+        // A failed realloc leaves the block alone
+        allocate(&mut heap, HeapFunction::Realloc, [0x3000, 1 << 40], 0);
+        assert_eq!(heap.live().count(), 1);
+    }
+
+    #[test]
+    fn allocations_libraries_make_for_themselves_are_still_reachable() {
+        let mut heap = HeapTracker::new();
+        let in_main = vec![MAIN + 0x10, LIBC];
+        allocate_from(
+            &mut heap,
+            HeapFunction::Malloc,
+            [8, 0],
+            0x1000,
+            in_main.clone(),
+        );
+        allocate_from(
+            &mut heap,
+            HeapFunction::Malloc,
+            [8, 0],
+            0x1100,
+            in_main.clone(),
+        );
+        allocate_from(
+            &mut heap,
+            HeapFunction::Malloc,
+            [100, 0],
+            0x1200,
+            vec![MAIN + 0x40],
+        );
+        // e.g. printf allocating stdout's buffer
+        let in_libc = vec![LIBC + 0x100, LIBC + 0x200, MAIN + 0x60];
+        allocate_from(&mut heap, HeapFunction::Malloc, [4096, 0], 0x5000, in_libc);
+
+        let leaks = heap.leaks(|addr| (MAIN..MAIN + 0x1000).contains(&addr));
+        assert_eq!(
+            leaks.leaked,
+            vec![
+                Leak {
+                    stack: vec![MAIN + 0x40],
+                    blocks: 1,
+                    bytes: 100
+                },
+                Leak {
+                    stack: in_main,
+                    blocks: 2,
+                    bytes: 16
+                },
+            ]
+        );
+        assert_eq!((leaks.reachable.blocks, leaks.reachable.bytes), (1, 4096));
+    }
+}
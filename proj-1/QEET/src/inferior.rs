@@ -1,13 +1,33 @@
-use crate::dwarf_data::{DwarfData, Error as DwarfError};
+use crate::dwarf_data::{DwarfData, Line, Type};
+use crate::error::Error;
+use crate::heap::{self, HeapBreakpoint, HeapError, HeapFunction, HeapTracker, Stop};
+use crate::record::{Recorder, ReplayStatus};
+use crate::syscalls;
+use crate::target::Target;
+use nix::errno::Errno;
 use nix::sys::ptrace;
 use nix::sys::signal;
 use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::unistd::Pid;
+use object::Object;
 use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt;
+use std::fs;
+use std::io;
 use std::mem::size_of;
 use std::os::unix::process::CommandExt;
 use std::process::Child;
-use std::process::Command;
+use std::process::{ChildStderr, ChildStdout, Command};
+
+/// The longest an x86-64 instruction can be, used to recognize return addresses pushed by `call`.
+const MAX_INSTRUCTION_LEN: usize = 15;
+/// Where the debug registers are in `struct user` on x86-64, for PTRACE_PEEKUSER/POKEUSER.
+const DEBUGREG_OFFSET: usize = 848;
+/// DR0-DR3 hold the addresses of up to four hardware watchpoints.
+const NUM_DEBUG_ADDRESS_REGISTERS: usize = 4;
+const DR_STATUS: usize = 6;
+const DR_CONTROL: usize = 7;
 
 fn align_addr_to_word(addr: usize) -> usize {
     addr & (-(size_of::<usize>() as isize) as usize)
@@ -22,160 +42,1685 @@ pub enum Status {
     /// Indicates the inferior exited due to a signal. Contains the signal that killed the
     /// process.
     Signaled(signal::Signal),
+    /// Indicates the inferior stopped at an event it was asked to catch. Contains the event and
+    /// the current instruction pointer.
+    Caught(Event, usize),
+}
+
+/// Events reported through ptrace other than signals.
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// Entry to a system call. Contains the syscall number and its arguments.
+    SyscallEntry(u64, [u64; 6]),
+    /// Return from a system call. Contains the syscall number and its return value.
+    SyscallExit(u64, i64),
+    /// The inferior forked. Contains the new child's pid.
+    Fork(Pid),
+    /// The inferior called exec. Contains the path of the new program.
+    Exec(String),
+    /// Watched memory changed. Contains the watchpoint's number and its old and new contents.
+    Watchpoint(usize, Vec<u8>, Vec<u8>),
+    /// With --check-memory, the inferior is about to free memory it shouldn't.
+    HeapError(HeapError),
+}
+
+/// Signals that are delivered to the inferior without stopping it, unless a catchpoint asks for
+/// them. Programs get these all the time in the normal course of things.
+const QUIET_SIGNALS: [signal::Signal; 7] = [
+    signal::Signal::SIGCHLD,
+    signal::Signal::SIGWINCH,
+    signal::Signal::SIGURG,
+    signal::Signal::SIGALRM,
+    signal::Signal::SIGPROF,
+    signal::Signal::SIGVTALRM,
+    signal::Signal::SIGIO,
+];
+
+/// Something that should stop the inferior besides a breakpoint.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Catchpoint {
+    /// A system call, by number. None catches every system call.
+    Syscall(Option<u64>),
+    /// Delivery of a signal. None catches every signal. Most signals stop the inferior anyway;
+    /// this is what makes it stop for the quiet ones like SIGCHLD.
+    Signal(Option<signal::Signal>),
+    Fork,
+    Exec,
+}
+
+impl Catchpoint {
+    /// Returns true if this catchpoint should stop the inferior for the given event.
+    pub fn matches(&self, event: &Event) -> bool {
+        match (self, event) {
+            (Catchpoint::Syscall(None), Event::SyscallEntry(_, _))
+            | (Catchpoint::Syscall(None), Event::SyscallExit(_, _)) => true,
+            (Catchpoint::Syscall(Some(num)), Event::SyscallEntry(number, _))
+            | (Catchpoint::Syscall(Some(num)), Event::SyscallExit(number, _)) => num == number,
+            (Catchpoint::Fork, Event::Fork(_)) => true,
+            (Catchpoint::Exec, Event::Exec(_)) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns true if this catchpoint should stop the inferior when it receives the signal.
+    pub fn catches_signal(&self, signal: signal::Signal) -> bool {
+        match self {
+            Catchpoint::Signal(None) => true,
+            Catchpoint::Signal(Some(caught)) => *caught == signal,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Catchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Catchpoint::Syscall(None) => write!(f, "any syscall"),
+            Catchpoint::Syscall(Some(number)) => {
+                write!(
+                    f,
+                    "syscall '{}' [{}]",
+                    syscalls::display_name(*number),
+                    number
+                )
+            }
+            Catchpoint::Signal(None) => write!(f, "any signal"),
+            Catchpoint::Signal(Some(signal)) => write!(f, "signal {}", signal),
+            Catchpoint::Fork => write!(f, "fork"),
+            Catchpoint::Exec => write!(f, "exec"),
+        }
+    }
+}
+
+/// The ptrace request used to resume the inferior.
+#[derive(Clone, Copy)]
+enum Resume {
+    Continue,
+    /// Continue, but also stop at system call entry and exit.
+    Syscall,
+    Step,
+}
+
+/// A breakpoint planted in the inferior's code, keyed by address.
+#[derive(Debug, Clone, Copy)]
+pub struct Breakpoint {
+    /// The instruction byte that 0xcc replaced.
+    pub orig_byte: u8,
+    /// Temporary breakpoints are removed the first time they are hit.
+    pub temporary: bool,
+}
+
+impl Breakpoint {
+    /// A breakpoint that hasn't been written into the inferior yet.
+    pub fn pending(temporary: bool) -> Breakpoint {
+        Breakpoint {
+            orig_byte: 0,
+            temporary,
+        }
+    }
+}
+
+/// State saved when the debugger calls a function in the inferior, restored once it returns.
+pub struct CallFrame {
+    saved_regs: libc::user_regs_struct,
+    /// The called function returns here, where a breakpoint is planted.
+    return_addr: usize,
+    /// The stack pointer once the function has returned.
+    sp: usize,
+    /// Whether the breakpoint at `return_addr` was planted for this call.
+    planted: bool,
+}
+
+/// How a watchpoint notices that its memory has changed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchKind {
+    /// A debug register (DR0-DR3) traps writes to up to 8 aligned bytes.
+    Hardware(usize),
+    /// The inferior is single-stepped and the memory compared after every instruction. This
+    /// works for any size of value, but is very slow.
+    Software,
+}
+
+/// Memory that the inferior stops when it changes. The caller keeps these, like breakpoints, and
+/// sets them in each process it starts with `add_watchpoint`.
+#[derive(Clone)]
+pub struct Watchpoint {
+    pub number: usize,
+    /// What was watched, as typed.
+    pub expr: String,
+    pub addr: usize,
+    pub ty: Type,
+}
+
+/// A watchpoint as set in this process.
+struct ArmedWatchpoint {
+    number: usize,
+    addr: usize,
+    len: usize,
+    kind: WatchKind,
+    /// The watched memory when it was last checked.
+    old_value: Vec<u8>,
+}
+
+/// A copy of the inferior made by `checkpoint`, kept stopped so that `restart` can go back to it.
+pub struct Checkpoint {
+    pub pid: Pid,
+    /// Where the inferior was when the checkpoint was made.
+    pub rip: usize,
+    /// The heap as it was, with the tracker's breakpoints left in the copy.
+    heap: Option<HeapTracker>,
+}
+
+impl Checkpoint {
+    pub fn kill(&self) {
+        let _ = signal::kill(self.pid, signal::Signal::SIGKILL);
+        let _ = waitpid(self.pid, None);
+    }
+}
+
+/// One region of the inferior's address space, as listed in /proc/<pid>/maps.
+#[derive(Debug, Clone)]
+pub struct Mapping {
+    pub start: usize,
+    pub end: usize,
+    /// e.g. "r-xp"
+    pub perms: String,
+    /// Offset into the mapped file.
+    pub offset: usize,
+    /// The mapped file, or a pseudo-path like "[stack]". None for anonymous memory.
+    pub path: Option<String>,
+}
+
+impl Mapping {
+    pub fn readable(&self) -> bool {
+        self.perms.starts_with('r')
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.path
+            .as_ref()
+            .map_or(false, |path| path.starts_with('/'))
+    }
 }
 
 /// This function calls ptrace with PTRACE_TRACEME to enable debugging on a process. You should use
 /// pre_exec with Command to call this in the child process.
 fn child_traceme() -> Result<(), std::io::Error> {
-    ptrace::traceme().or(Err(std::io::Error::new(
-        std::io::ErrorKind::Other,
-        "ptrace TRACEME failed",
-    )))
+    ptrace::traceme()
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "ptrace TRACEME failed"))
+}
+
+/// Has syscall stops marked so they can't be confused with a SIGTRAP, and forks and execs reported
+/// as events.
+fn set_trace_options(pid: Pid) -> Result<(), nix::Error> {
+    ptrace::setoptions(
+        pid,
+        ptrace::Options::PTRACE_O_TRACESYSGOOD
+            | ptrace::Options::PTRACE_O_TRACEFORK
+            | ptrace::Options::PTRACE_O_TRACEEXEC,
+    )
+}
+
+/// Turns off address space randomization for the program about to be exec'd, so that its stack,
+/// heap and libraries are at the same addresses in every run. Like child_traceme, this is called
+/// with pre_exec. Where changing the personality isn't allowed (e.g. in some containers) the
+/// program just runs randomized.
+pub fn child_disable_randomization() -> Result<(), std::io::Error> {
+    unsafe {
+        // 0xffffffff queries the current personality without changing it
+        let persona = libc::personality(0xffff_ffff);
+        if persona != -1 {
+            libc::personality((persona | libc::ADDR_NO_RANDOMIZE) as libc::c_ulong);
+        }
+    }
+    Ok(())
+}
+
+/// Replaces the byte at `addr` in the given process's memory, returning the old byte.
+fn write_byte_to(pid: Pid, addr: usize, val: u8) -> Result<u8, nix::Error> {
+    let aligned_addr = align_addr_to_word(addr);
+    let byte_offset = addr - aligned_addr;
+    let word = ptrace::read(pid, aligned_addr as ptrace::AddressType)? as u64;
+    let orig_byte = (word >> (8 * byte_offset)) & 0xff;
+    let masked_word = word & !(0xff << (8 * byte_offset));
+    let updated_word = masked_word | ((val as u64) << (8 * byte_offset));
+    ptrace::write(
+        pid,
+        aligned_addr as ptrace::AddressType,
+        updated_word as *mut std::ffi::c_void,
+    )?;
+    Ok(orig_byte as u8)
+}
+
+/// Makes the stopped process `pid` fork by running a clone system call in it, in place of the
+/// instruction at %rip, and returns the new process. The copy is left stopped and traced by us,
+/// with the given breakpoints taken out of it; the original carries on as if nothing happened.
+fn fork_process(pid: Pid, breakpoints: &HashMap<usize, Breakpoint>) -> Result<Pid, nix::Error> {
+    let unexpected = nix::Error::Sys(nix::errno::Errno::EINVAL);
+    let saved_regs = ptrace::getregs(pid)?;
+    let rip = saved_regs.rip as ptrace::AddressType;
+    let saved_code = ptrace::read(pid, rip)?;
+    // `syscall` is 0f 05
+    let code = (saved_code as u64 & !0xffff) | 0x050f;
+    ptrace::write(pid, rip, code as *mut std::ffi::c_void)?;
+    let mut regs = saved_regs;
+    regs.rax = libc::SYS_clone as u64;
+    // Make the copy a child of the debugger rather than of the program, so that the program
+    // doesn't get a SIGCHLD when the copy goes away
+    regs.rdi = (libc::CLONE_PARENT | libc::SIGCHLD) as u64;
+    regs.rsi = 0;
+    regs.rdx = 0;
+    regs.r10 = 0;
+    regs.r8 = 0;
+    // Don't let the kernel restart a system call we were stopped in
+    regs.orig_rax = u64::MAX;
+    ptrace::setregs(pid, regs)?;
+
+    // Since we trace forks, the clone stops with an event before it returns
+    ptrace::step(pid, None)?;
+    let child = match waitpid(pid, None)? {
+        WaitStatus::PtraceEvent(_, _, libc::PTRACE_EVENT_FORK) => {
+            Pid::from_raw(ptrace::getevent(pid)? as i32)
+        }
+        _ => return Err(unexpected),
+    };
+    ptrace::step(pid, None)?;
+    match waitpid(pid, None)? {
+        WaitStatus::Stopped(_, signal::Signal::SIGTRAP) => {}
+        _ => return Err(unexpected),
+    }
+    ptrace::write(pid, rip, saved_code as *mut std::ffi::c_void)?;
+    ptrace::setregs(pid, saved_regs)?;
+
+    // The child is reported once it has stopped with SIGSTOP, and has a copy of our patch
+    waitpid(child, None)?;
+    ptrace::write(child, rip, saved_code as *mut std::ffi::c_void)?;
+    ptrace::setregs(child, saved_regs)?;
+    for (addr, bp) in breakpoints {
+        write_byte_to(child, *addr, bp.orig_byte)?;
+    }
+    Ok(child)
+}
+
+fn read_debugreg(pid: Pid, n: usize) -> Result<u64, nix::Error> {
+    // PEEKUSER returns the value, so errors can only be told apart from -1 by errno
+    let value = unsafe {
+        Errno::clear();
+        libc::ptrace(
+            libc::PTRACE_PEEKUSER,
+            pid.as_raw(),
+            (DEBUGREG_OFFSET + n * size_of::<u64>()) as *mut std::ffi::c_void,
+            std::ptr::null_mut::<std::ffi::c_void>(),
+        )
+    };
+    if value == -1 && Errno::last() != Errno::UnknownErrno {
+        return Err(nix::Error::Sys(Errno::last()));
+    }
+    Ok(value as u64)
+}
+
+fn write_debugreg(pid: Pid, n: usize, value: u64) -> Result<(), nix::Error> {
+    let result = unsafe {
+        libc::ptrace(
+            libc::PTRACE_POKEUSER,
+            pid.as_raw(),
+            (DEBUGREG_OFFSET + n * size_of::<u64>()) as *mut std::ffi::c_void,
+            value as *mut std::ffi::c_void,
+        )
+    };
+    if result == -1 {
+        return Err(nix::Error::Sys(Errno::last()));
+    }
+    Ok(())
+}
+
+/// Reads a process's memory map from /proc/<pid>/maps.
+pub fn read_mappings(pid: Pid) -> io::Result<Vec<Mapping>> {
+    let maps = fs::read_to_string(format!("/proc/{}/maps", pid))?;
+    let mut mappings = Vec::new();
+    for line in maps.lines() {
+        // e.g. "00400000-00401000 r--p 00000000 08:01 1234    /path/to/file"
+        let fields: Vec<&str> = line.splitn(6, char::is_whitespace).collect();
+        if fields.len() < 5 {
+            continue;
+        }
+        let mut range = fields[0].splitn(2, '-');
+        let start = usize::from_str_radix(range.next().unwrap(), 16).unwrap_or(0);
+        let end = usize::from_str_radix(range.next().unwrap_or(""), 16).unwrap_or(0);
+        let path = fields.get(5).map(|p| p.trim()).filter(|p| !p.is_empty());
+        mappings.push(Mapping {
+            start,
+            end,
+            perms: fields[1].to_string(),
+            offset: usize::from_str_radix(fields[2], 16).unwrap_or(0),
+            path: path.map(|p| p.to_string()),
+        });
+    }
+    Ok(mappings)
+}
+
+/// Names what each mapping holds, for `info proc mappings`: its file or pseudo-path, or for the
+/// anonymous memory straight after a file's last mapping, that file's .bss.
+pub fn objfile_names(mappings: &[Mapping]) -> Vec<String> {
+    let mut last_file: Option<(&str, usize)> = None;
+    mappings
+        .iter()
+        .map(|mapping| match mapping.path.as_deref() {
+            Some(path) if !path.starts_with('[') => {
+                last_file = Some((path, mapping.end));
+                path.to_string()
+            }
+            Some(path) => path.to_string(),
+            None => match last_file {
+                Some((path, end)) if end == mapping.start => format!("{} (.bss)", path),
+                _ => String::new(),
+            },
+        })
+        .collect()
+}
+
+/// True if both lines are known and refer to the same line of the same file.
+fn same_line(a: &Option<Line>, b: &Option<Line>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.file == b.file && a.number == b.number,
+        _ => false,
+    }
 }
 
 pub struct Inferior {
-    child: Child,
+    pid: Pid,
+    /// The process we started, unless this is a copy of a checkpoint.
+    child: Option<Child>,
+    /// Set while execution is being recorded for reverse execution.
+    recorder: Option<Recorder>,
+    catchpoints: Vec<Catchpoint>,
+    /// The watchpoints set with `add_watchpoint`, hardware and software alike.
+    watchpoints: Vec<ArmedWatchpoint>,
+    /// Set with --check-memory.
+    heap: Option<HeapTracker>,
+    /// Whether the last syscall stop was an entry and the inferior has only been resumed with
+    /// PTRACE_SYSCALL since, so the next syscall stop is the matching exit.
+    in_syscall: bool,
+    /// Problems that didn't stop the inferior, for the caller to pass on to the user.
+    warnings: Vec<String>,
 }
 
 impl Inferior {
-    /// Attempts to start a new inferior process. Returns Some(Inferior) if successful, or None if
-    /// an error is encountered.
-    pub fn new(
-        target: &str,
-        args: &Vec<String>,
-        breakpoints: &mut HashMap<usize, u8>,
+    fn traced(pid: Pid, child: Option<Child>) -> Inferior {
+        Inferior {
+            pid,
+            child,
+            recorder: None,
+            catchpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            heap: None,
+            in_syscall: false,
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Attempts to start a new inferior process from a caller-configured Command, e.g. one whose
+    /// stdio has been redirected. Returns Some(Inferior) if successful, or None if an error is
+    /// encountered.
+    pub fn spawn(
+        mut cmd: Command,
+        breakpoints: &mut HashMap<usize, Breakpoint>,
     ) -> Option<Inferior> {
         // #begin: trace the inferior.
-        let mut cmd = Command::new(target);
-        cmd.args(args);
         unsafe {
             cmd.pre_exec(child_traceme);
         }
         let child = cmd.spawn().ok()?;
-        let mut inferior = Inferior { child: child };
+        let mut inferior = Inferior::traced(Pid::from_raw(child.id() as i32), Some(child));
         // #end
-        // When a process that has PTRACE_TRACEME enabled
-        // calls exec（exec corresponds to ptrace::cont in continue_run),
-        // the operating system will load the specified program into the process,
-        // and then (before the new program starts running) it will pause the process using SIGTRAP.
+        // When a process that has PTRACE_TRACEME enabled calls exec, the operating system will
+        // load the specified program into the process, and then (before the new program starts
+        // running) it will pause the process using SIGTRAP. Wait for that pause so that the
+        // breakpoints below are written into the new program's text.
+        match inferior.wait(None).ok()? {
+            Status::Stopped(signal::Signal::SIGTRAP, _) => {}
+            _ => return None,
+        }
+        set_trace_options(inferior.pid()).ok()?;
+
+        inferior.install_breakpoints(breakpoints);
+        Some(inferior)
+    }
+
+    /// Starts debugging a process that is already running, which stops it.
+    pub fn attach(
+        pid: Pid,
+        breakpoints: &mut HashMap<usize, Breakpoint>,
+    ) -> Result<Inferior, Error> {
+        // Usually refused because of /proc/sys/kernel/yama/ptrace_scope
+        ptrace::attach(pid).map_err(|err| match err {
+            nix::Error::Sys(Errno::EPERM) => Error::PermissionDenied,
+            err => Error::from(err),
+        })?;
+        // PTRACE_ATTACH stops the process with SIGSTOP
+        waitpid(pid, None)?;
+        set_trace_options(pid)?;
+        let mut inferior = Inferior::traced(pid, None);
+        inferior.install_breakpoints(breakpoints);
+        Ok(inferior)
+    }
+
+    /// Takes the breakpoints and watchpoints back out of the process and lets it carry on without
+    /// the debugger.
+    pub fn detach(mut self, breakpoints: &HashMap<usize, Breakpoint>) -> Result<(), Error> {
+        let heap_breakpoints = self.heap.take().map(|heap| heap.breakpoints);
+        for (addr, bp) in breakpoints {
+            self.write_byte(*addr, bp.orig_byte)?;
+        }
+        for (addr, bp) in heap_breakpoints.iter().flatten() {
+            self.write_byte(*addr, bp.orig_byte)?;
+        }
+        if !self.watchpoints.is_empty() {
+            write_debugreg(self.pid(), DR_CONTROL, 0)?;
+        }
+        Ok(ptrace::detach(self.pid(), None)?)
+    }
+
+    /// Takes the warnings gathered since the last call, e.g. breakpoints that couldn't be written.
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Starts debugging a new copy of a checkpoint, leaving the checkpoint itself as it was so
+    /// that it can be restarted again.
+    pub fn restart(
+        checkpoint: &Checkpoint,
+        breakpoints: &mut HashMap<usize, Breakpoint>,
+    ) -> Result<Inferior, Error> {
+        // Checkpoints are kept without breakpoints, since they may have changed since
+        let pid = fork_process(checkpoint.pid, &HashMap::new())?;
+        let mut inferior = Inferior::traced(pid, None);
+        inferior.heap = checkpoint.heap.clone();
+        inferior.install_breakpoints(breakpoints);
+        Ok(inferior)
+    }
+
+    /// Forks a copy of the inferior as it is now, and keeps it stopped for `restart`.
+    pub fn checkpoint(
+        &self,
+        breakpoints: &HashMap<usize, Breakpoint>,
+    ) -> Result<Checkpoint, Error> {
+        // The heap tracker's breakpoints stay, to go with its copy of the heap
+        let mut breakpoints = breakpoints.clone();
+        breakpoints.retain(|addr, _| self.heap_original_byte(*addr).is_none());
+        // Read before forking, which runs code in the inferior and puts its registers back after
+        let rip = self.rip()?;
+        Ok(Checkpoint {
+            pid: fork_process(self.pid(), &breakpoints)?,
+            rip,
+            heap: self.heap.clone(),
+        })
+    }
 
+    /// Writes the breakpoints into the inferior's code, recording the bytes they replace.
+    fn install_breakpoints(&mut self, breakpoints: &mut HashMap<usize, Breakpoint>) {
         let bps = breakpoints.clone();
         for bp in bps.keys() {
-            match inferior.write_byte(*bp, 0xcc) {
+            // The heap tracker may already have a breakpoint here
+            let written = match self.heap_original_byte(*bp) {
+                Some(orig_byte) => Ok(orig_byte),
+                None => self.write_byte(*bp, 0xcc),
+            };
+            match written {
                 Ok(ori_instr) => {
-                    breakpoints.insert(*bp, ori_instr);
+                    breakpoints.get_mut(bp).unwrap().orig_byte = ori_instr;
                 }
-                Err(_) => println!("Invalid breakpoint address {:#x}", bp),
+                Err(_) => self
+                    .warnings
+                    .push(format!("Invalid breakpoint address {:#x}", bp)),
             }
         }
-        Some(inferior)
+    }
+
+    /// Takes the read ends of the inferior's stdout/stderr, if they were piped.
+    pub fn take_output(&mut self) -> (Option<ChildStdout>, Option<ChildStderr>) {
+        match self.child.as_mut() {
+            Some(child) => (child.stdout.take(), child.stderr.take()),
+            None => (None, None),
+        }
     }
 
     /// Returns the pid of this inferior.
     pub fn pid(&self) -> Pid {
-        nix::unistd::Pid::from_raw(self.child.id() as i32)
+        self.pid
+    }
+
+    /// Sets the events that `continue_run` stops for, in addition to breakpoints and signals.
+    pub fn set_catchpoints(&mut self, catchpoints: &[Catchpoint]) {
+        self.catchpoints = catchpoints.to_vec();
     }
 
     /// Calls waitpid on this inferior and returns a Status to indicate the state of the process
     /// after the waitpid call.
-    pub fn wait(&self, options: Option<WaitPidFlag>) -> Result<Status, nix::Error> {
-        Ok(match waitpid(self.pid(), options)? {
+    pub fn wait(&mut self, options: Option<WaitPidFlag>) -> Result<Status, Error> {
+        let status = waitpid(self.pid(), options)?;
+        Ok(match status {
             WaitStatus::Exited(_pid, exit_code) => Status::Exited(exit_code),
             WaitStatus::Signaled(_pid, signal, _core_dumped) => Status::Signaled(signal),
             WaitStatus::Stopped(_pid, signal) => {
                 let regs = ptrace::getregs(self.pid())?;
                 Status::Stopped(signal, regs.rip as usize)
             }
-            other => panic!("waitpid returned unexpected status: {:?}", other),
+            WaitStatus::PtraceSyscall(_pid) => {
+                // Entry and exit stops look the same, so we have to keep track of which is next.
+                // Other stops (e.g. PTRACE_EVENT_FORK in the middle of fork) don't change it.
+                let regs = ptrace::getregs(self.pid())?;
+                let event = if self.in_syscall {
+                    self.in_syscall = false;
+                    Event::SyscallExit(regs.orig_rax, regs.rax as i64)
+                } else {
+                    self.in_syscall = true;
+                    let args = [regs.rdi, regs.rsi, regs.rdx, regs.r10, regs.r8, regs.r9];
+                    Event::SyscallEntry(regs.orig_rax, args)
+                };
+                Status::Caught(event, regs.rip as usize)
+            }
+            WaitStatus::PtraceEvent(_pid, signal, event) => {
+                let rip = ptrace::getregs(self.pid())?.rip as usize;
+                match event {
+                    libc::PTRACE_EVENT_FORK => {
+                        let child = ptrace::getevent(self.pid())? as i32;
+                        Status::Caught(Event::Fork(Pid::from_raw(child)), rip)
+                    }
+                    libc::PTRACE_EVENT_EXEC => {
+                        let path = fs::read_link(format!("/proc/{}/exe", self.pid()))
+                            .map(|path| path.to_string_lossy().into_owned())
+                            .unwrap_or_default();
+                        Status::Caught(Event::Exec(path), rip)
+                    }
+                    _ => Status::Stopped(signal, rip),
+                }
+            }
+            other => return Err(Error::UnsupportedStop(other)),
         })
     }
 
+    /// Resumes the inferior and waits for it to stop. Events that aren't being caught are
+    /// handled here and the inferior is resumed again, so callers only see the ones they asked
+    /// for.
+    fn resume(
+        &mut self,
+        how: Resume,
+        signal: Option<signal::Signal>,
+        breakpoints: &HashMap<usize, Breakpoint>,
+    ) -> Result<Status, Error> {
+        let mut signal = signal;
+        loop {
+            if let Some(heap) = self.heap.as_mut() {
+                heap.handled = None;
+            }
+            match how {
+                Resume::Syscall => ptrace::syscall(self.pid(), signal)?,
+                // A system call we're stopped in the middle of finishes without an exit stop
+                Resume::Continue => {
+                    self.in_syscall = false;
+                    ptrace::cont(self.pid(), signal)?
+                }
+                Resume::Step => {
+                    self.in_syscall = false;
+                    ptrace::step(self.pid(), signal)?
+                }
+            }
+            signal = None;
+            let status = self.wait(None)?;
+            if let Status::Caught(event, _) = &status {
+                if let Event::Fork(child) = event {
+                    self.detach_fork_child(*child, breakpoints)?;
+                }
+                if !self.catchpoints.iter().any(|catch| catch.matches(event)) {
+                    continue;
+                }
+            }
+            if let Status::Stopped(sig, _) = status {
+                if QUIET_SIGNALS.contains(&sig)
+                    && !self
+                        .catchpoints
+                        .iter()
+                        .any(|catch| catch.catches_signal(sig))
+                {
+                    signal = Some(sig);
+                    continue;
+                }
+            }
+            return Ok(status);
+        }
+    }
+
+    /// Lets a forked child run on its own. It starts out traced by us and with a copy of our
+    /// breakpoints, which would kill it with SIGTRAP once we stop tracing it.
+    fn detach_fork_child(
+        &self,
+        child: Pid,
+        breakpoints: &HashMap<usize, Breakpoint>,
+    ) -> Result<(), Error> {
+        // The child is reported once it has stopped with SIGSTOP
+        waitpid(child, None)?;
+        for (addr, bp) in breakpoints {
+            write_byte_to(child, *addr, bp.orig_byte)?;
+        }
+        Ok(ptrace::detach(child, None)?)
+    }
+
+    /// Sets a watchpoint, with a debug register if one is free and the value fits in it, and
+    /// otherwise by single-stepping. Returns which it is.
+    pub fn add_watchpoint(&mut self, watch: &Watchpoint) -> Result<WatchKind, Error> {
+        let (addr, len) = (watch.addr, watch.ty.size);
+        let old_value = self
+            .read_memory(addr, len)
+            .map_err(|err| Error::memory(err, addr))?;
+        let free_slot = (0..NUM_DEBUG_ADDRESS_REGISTERS).find(|slot| {
+            !self
+                .watchpoints
+                .iter()
+                .any(|watch| watch.kind == WatchKind::Hardware(*slot))
+        });
+        let kind = match free_slot {
+            Some(slot) if [1, 2, 4, 8].contains(&len) && addr % len == 0 => {
+                // R/W = 01 traps on writes. LEN is 00, 01, 11 or 10 for 1, 2, 4 or 8 bytes.
+                let len_bits: u64 = match len {
+                    1 => 0b00,
+                    2 => 0b01,
+                    4 => 0b11,
+                    _ => 0b10,
+                };
+                let control = read_debugreg(self.pid(), DR_CONTROL)?;
+                let control = (control & !(0b1111 << (16 + 4 * slot)))
+                    | (1 << (2 * slot))
+                    | ((0b01 | (len_bits << 2)) << (16 + 4 * slot));
+                write_debugreg(self.pid(), slot, addr as u64)?;
+                write_debugreg(self.pid(), DR_CONTROL, control)?;
+                WatchKind::Hardware(slot)
+            }
+            _ => WatchKind::Software,
+        };
+        self.watchpoints.push(ArmedWatchpoint {
+            number: watch.number,
+            addr,
+            len,
+            kind,
+            old_value,
+        });
+        Ok(kind)
+    }
+
+    /// Removes a watchpoint, returning false if there was none with that number.
+    pub fn remove_watchpoint(&mut self, number: usize) -> Result<bool, Error> {
+        let index = match self.watchpoints.iter().position(|w| w.number == number) {
+            Some(index) => index,
+            None => return Ok(false),
+        };
+        if let WatchKind::Hardware(slot) = self.watchpoints[index].kind {
+            let control = read_debugreg(self.pid(), DR_CONTROL)?;
+            write_debugreg(self.pid(), DR_CONTROL, control & !(0b11 << (2 * slot)))?;
+        }
+        self.watchpoints.remove(index);
+        Ok(true)
+    }
+
+    /// How the watchpoint with this number is set, if it is.
+    pub fn watch_kind(&self, number: usize) -> Option<WatchKind> {
+        self.watchpoints
+            .iter()
+            .find(|watch| watch.number == number)
+            .map(|watch| watch.kind)
+    }
+
+    /// Finds a watchpoint whose memory has changed since it was last checked, updating what it
+    /// remembers. Only the hardware watchpoint that trapped, if any, and the software ones need
+    /// to be compared.
+    fn changed_watchpoint(&mut self) -> Result<Option<Event>, Error> {
+        let status = read_debugreg(self.pid(), DR_STATUS)?;
+        if status & 0b1111 != 0 {
+            write_debugreg(self.pid(), DR_STATUS, 0)?;
+        }
+        for i in 0..self.watchpoints.len() {
+            let check = match self.watchpoints[i].kind {
+                WatchKind::Hardware(slot) => status & (1 << slot) != 0,
+                WatchKind::Software => true,
+            };
+            if !check {
+                continue;
+            }
+            let (number, addr, size) = {
+                let watch = &self.watchpoints[i];
+                (watch.number, watch.addr, watch.len)
+            };
+            let value = self
+                .read_memory(addr, size)
+                .map_err(|err| Error::memory(err, addr))?;
+            if value != self.watchpoints[i].old_value {
+                let old_value =
+                    std::mem::replace(&mut self.watchpoints[i].old_value, value.clone());
+                return Ok(Some(Event::Watchpoint(number, old_value, value)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Resumes the inferior until it hits a breakpoint, receives a signal, or terminates. When it
+    /// stops at a breakpoint, %rip is rewound to the breakpoint's address. It also stops when
+    /// watched memory changes.
     pub fn continue_run(
         &mut self,
         signal: Option<signal::Signal>,
-        breakpoints: &mut HashMap<usize, u8>,
-    ) -> Result<Status, nix::Error> {
-        // #begin: check if inferior stopped at a breakpoint
-        let mut regs = ptrace::getregs(self.pid())?;
-        let rip = regs.rip as usize;
-
-        if let Some(ori_instr) = breakpoints.get(&(rip - 1)) {
-            println!("stopped at a breakpoint");
+        breakpoints: &HashMap<usize, Breakpoint>,
+    ) -> Result<Status, Error> {
+        let mut signal = signal;
+        loop {
+            let status = self.continue_once(signal, breakpoints)?;
+            // The heap tracker's breakpoints are handled as the inferior is resumed from them
+            if let Status::Stopped(signal::Signal::SIGTRAP, rip) = status {
+                if self.heap_original_byte(rip).is_some() && !breakpoints.contains_key(&rip) {
+                    signal = None;
+                    continue;
+                }
+            }
+            return Ok(status);
+        }
+    }
+
+    fn continue_once(
+        &mut self,
+        signal: Option<signal::Signal>,
+        breakpoints: &HashMap<usize, Breakpoint>,
+    ) -> Result<Status, Error> {
+        let software_watch = self
+            .watchpoints
+            .iter()
+            .any(|watch| watch.kind == WatchKind::Software);
+        if self.recorder.is_some() || software_watch {
+            // Every instruction has to be recorded or have the watched memory compared after it,
+            // so we can only single-step
+            let catch_syscalls = self.catches_syscalls();
+            loop {
+                // A single step runs a whole system call without stopping in it
+                let status = if catch_syscalls && (self.in_syscall || self.at_syscall()?) {
+                    self.step_syscall(breakpoints)?
+                } else {
+                    self.step_instruction(breakpoints)?
+                };
+                match status {
+                    Status::Stopped(signal::Signal::SIGTRAP, rip)
+                        if !breakpoints.contains_key(&rip) =>
+                    {
+                        if let Some(event) = self.changed_watchpoint()? {
+                            return Ok(Status::Caught(event, rip));
+                        }
+                    }
+                    status => return Ok(status),
+                }
+            }
+        }
+        if let Some(status) = self.step_over_breakpoint(breakpoints)? {
+            if let Status::Exited(_)
+            | Status::Signaled(_)
+            | Status::Caught(Event::HeapError(_), _) = status
+            {
+                return Ok(status);
+            }
+        }
+        // resume normal execution, stopping at system calls only if some are being caught
+        let how = if self.catches_syscalls() {
+            Resume::Syscall
+        } else {
+            Resume::Continue
+        };
+        let status = self.resume(how, signal, breakpoints)?;
+        if let Status::Stopped(signal::Signal::SIGTRAP, rip) = status {
+            let watch_hit =
+                !self.watchpoints.is_empty() && read_debugreg(self.pid(), DR_STATUS)? & 0b1111 != 0;
+            if watch_hit {
+                // A hardware watchpoint traps after the write. Writing the same value again
+                // doesn't count as a change.
+                return match self.changed_watchpoint()? {
+                    Some(event) => Ok(Status::Caught(event, rip)),
+                    None => self.continue_run(None, breakpoints),
+                };
+            }
+            let at_breakpoint =
+                breakpoints.contains_key(&(rip - 1)) || self.heap_original_byte(rip - 1).is_some();
+            if at_breakpoint {
+                // set %rip = %rip - 1 to rewind the instruction pointer onto the breakpoint
+                let mut regs = ptrace::getregs(self.pid())?;
+                regs.rip = (rip - 1) as u64;
+                ptrace::setregs(self.pid(), regs)?;
+                return Ok(Status::Stopped(signal::Signal::SIGTRAP, rip - 1));
+            }
+        }
+        Ok(status)
+    }
+
+    fn catches_syscalls(&self) -> bool {
+        self.catchpoints
+            .iter()
+            .any(|catch| matches!(catch, Catchpoint::Syscall(_)))
+    }
+
+    /// Whether the next instruction is `syscall` (0f 05).
+    fn at_syscall(&self) -> Result<bool, Error> {
+        let rip = self.rip()?;
+        let code = self
+            .read_memory(rip, 2)
+            .map_err(|err| Error::memory(err, rip))?;
+        Ok(code == [0x0f, 0x05])
+    }
+
+    /// Takes the inferior to the next stop of the system call it is making or in the middle of,
+    /// for single-stepping while syscalls are caught. A stop that isn't caught is reported like
+    /// the end of a step.
+    fn step_syscall(&mut self, breakpoints: &HashMap<usize, Breakpoint>) -> Result<Status, Error> {
+        let regs = ptrace::getregs(self.pid())?;
+        ptrace::syscall(self.pid(), None)?;
+        let status = match self.wait(None)? {
+            Status::Caught(event, rip) => {
+                if let Event::Fork(child) = event {
+                    self.detach_fork_child(child, breakpoints)?;
+                }
+                if self.catchpoints.iter().any(|catch| catch.matches(&event)) {
+                    Status::Caught(event, rip)
+                } else {
+                    Status::Stopped(signal::Signal::SIGTRAP, rip)
+                }
+            }
+            status => status,
+        };
+        if let Status::Stopped(_, _) | Status::Caught(_, _) = status {
+            self.record_step(regs);
+        }
+        Ok(status)
+    }
+
+    /// If the inferior is sitting on a breakpoint, executes the original instruction underneath
+    /// it and then reinstalls the breakpoint. Returns None if there was no breakpoint to step over.
+    /// A breakpoint of the heap tracker's is handled first, and the inferior doesn't move if that
+    /// finds a bad free.
+    fn step_over_breakpoint(
+        &mut self,
+        breakpoints: &HashMap<usize, Breakpoint>,
+    ) -> Result<Option<Status>, Error> {
+        let rip = ptrace::getregs(self.pid())?.rip as usize;
+        if let Some(event) = self.heap_hit(rip, breakpoints)? {
+            return Ok(Some(Status::Caught(event, rip)));
+        }
+        let orig_byte = match breakpoints.get(&rip) {
+            Some(bp) => Some(bp.orig_byte),
+            None => self.heap_original_byte(rip),
+        };
+        if let Some(orig_byte) = orig_byte {
             // restore the first byte of the instruction we replaced
-            self.write_byte(rip - 1, *ori_instr).unwrap();
-            // set %rip = %rip - 1 to rewind the instruction pointer
-            regs.rip = (rip - 1) as u64;
-            ptrace::setregs(self.pid(), regs).unwrap(); // go to the next instruction
-            ptrace::step(self.pid(), None).unwrap();
-            //? same as the way dealing with stack frame
+            self.write_byte(rip, orig_byte)?;
             // wait for inferior to stop due to SIGTRAP, just return if the inferior terminates here
-            match self.wait(None).unwrap() {
-                Status::Exited(exit_code) => return Ok(Status::Exited(exit_code)),
-                Status::Signaled(signal) => return Ok(Status::Signaled(signal)), //? Signaled 和 Stopped的差别
-                Status::Stopped(_, _) => {
+            let status = self.resume(Resume::Step, None, breakpoints)?;
+            // the heap tracker may have removed its breakpoint
+            let still_set =
+                breakpoints.contains_key(&rip) || self.heap_original_byte(rip).is_some();
+            if let Status::Stopped(_, _) | Status::Caught(_, _) = status {
+                if still_set {
                     // restore 0xcc in the breakpoint location
-                    self.write_byte(rip - 1, 0xcc).unwrap();
+                    self.write_byte(rip, 0xcc)?;
                 }
             }
+            return Ok(Some(status));
         }
-        // #end
-        // resume normal execution
-        ptrace::cont(self.pid(), signal)?;
-        // wait for inferior to stop or terminate
-        self.wait(None)
+        Ok(None)
     }
 
-    /// kill the inferior, assume that the inferior is still alive
-    pub fn kill(&mut self) {
-        self.child.kill().unwrap();
-        self.wait(None).unwrap(); // to ensure child's process has been killed.
-        println!("Killing running inferior (pid {})", self.pid());
-        //? for now I don't check why .id is work after invoke kill()
-        //? One assumption is the child's ownership still exist, therefore the propeties can be accessed
+    /// Starts tracking the heap for --check-memory. The allocation functions are found and
+    /// broken on once the inferior reaches its entry point.
+    pub fn track_heap(&mut self, breakpoints: &HashMap<usize, Breakpoint>) -> Result<(), Error> {
+        let entry = self.entry_point().ok_or(Error::NoEntryPoint)?;
+        self.heap = Some(HeapTracker::new());
+        self.plant_heap_breakpoint(entry, Stop::Entry, breakpoints)
+    }
+
+    pub fn heap(&self) -> Option<&HeapTracker> {
+        self.heap.as_ref()
+    }
+
+    /// Returns the byte under the heap tracker's breakpoint at `addr`, if it has one there.
+    fn heap_original_byte(&self, addr: usize) -> Option<u8> {
+        self.heap
+            .as_ref()?
+            .breakpoints
+            .get(&addr)
+            .map(|bp| bp.orig_byte)
     }
 
-    pub fn print_backtrace(&mut self, debug_data: &DwarfData) -> Result<(), nix::Error> {
-        // #begin: backtrace the inferior which get trapped by SIGNAL, and print the father call function simultaniously.
+    fn plant_heap_breakpoint(
+        &mut self,
+        addr: usize,
+        stop: Stop,
+        breakpoints: &HashMap<usize, Breakpoint>,
+    ) -> Result<(), Error> {
+        if self.heap_original_byte(addr).is_some() {
+            return Ok(());
+        }
+        // Share the user's breakpoint if there is one here already
+        let orig_byte = match breakpoints.get(&addr) {
+            Some(bp) => bp.orig_byte,
+            None => self.write_byte(addr, 0xcc)?,
+        };
+        if let Some(heap) = self.heap.as_mut() {
+            heap.breakpoints
+                .insert(addr, HeapBreakpoint { orig_byte, stop });
+        }
+        Ok(())
+    }
+
+    fn remove_heap_breakpoint(
+        &mut self,
+        addr: usize,
+        breakpoints: &HashMap<usize, Breakpoint>,
+    ) -> Result<(), Error> {
+        let removed = self
+            .heap
+            .as_mut()
+            .and_then(|heap| heap.breakpoints.remove(&addr));
+        if let Some(bp) = removed {
+            if !breakpoints.contains_key(&addr) {
+                self.write_byte(addr, bp.orig_byte)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles the heap tracker's breakpoint at `rip`, which the inferior is about to execute.
+    /// Returns the error to stop for if the inferior is about to free memory it shouldn't.
+    fn heap_hit(
+        &mut self,
+        rip: usize,
+        breakpoints: &HashMap<usize, Breakpoint>,
+    ) -> Result<Option<Event>, Error> {
+        let stop = match &self.heap {
+            Some(heap) if heap.handled != Some(rip) => match heap.breakpoints.get(&rip) {
+                Some(bp) => bp.stop,
+                None => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+        if let Some(heap) = self.heap.as_mut() {
+            heap.handled = Some(rip);
+        }
         let regs = ptrace::getregs(self.pid())?;
-        let mut rip = regs.rip as usize;
+        match stop {
+            Stop::Entry => {
+                self.remove_heap_breakpoint(rip, breakpoints)?;
+                let mut missing = Vec::new();
+                for function in HeapFunction::ALL.iter() {
+                    match self.lookup_library_function(function.name()) {
+                        Some((addr, false)) => {
+                            self.plant_heap_breakpoint(addr, Stop::Call(*function), breakpoints)?
+                        }
+                        _ => missing.push(function.name()),
+                    }
+                }
+                if !missing.is_empty() {
+                    self.warnings.push(format!(
+                        "Warning: could not find {} in a shared library, so the heap won't be \
+                         fully checked.",
+                        missing.join(", ")
+                    ));
+                }
+            }
+            Stop::Call(function) => {
+                let stack = self.heap_stack(&regs);
+                let args = [regs.rdi as usize, regs.rsi as usize];
+                let heap = self.heap.as_mut().unwrap();
+                match heap.call(function, args, regs.rsp as usize, stack) {
+                    Ok(Some(return_addr)) => {
+                        self.plant_heap_breakpoint(return_addr, Stop::Return, breakpoints)?
+                    }
+                    Ok(None) => {}
+                    Err(err) => return Ok(Some(Event::HeapError(err))),
+                }
+            }
+            Stop::Return => {
+                let heap = self.heap.as_mut().unwrap();
+                heap.returned(regs.rsp as usize, regs.rax as usize);
+                if !heap.returns_to(rip) {
+                    self.remove_heap_breakpoint(rip, breakpoints)?;
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the return addresses on the stack on entry to a heap function: the one its caller
+    /// pushed, then the ones found by following saved frame pointers, as `backtrace` does.
+    fn heap_stack(&self, regs: &libc::user_regs_struct) -> Vec<usize> {
+        let mut stack = Vec::new();
+        match self.read_word(regs.rsp as usize) {
+            Ok(return_addr) => stack.push(return_addr as usize),
+            Err(_) => return stack,
+        }
         let mut rbp = regs.rbp as usize;
+        while stack.len() < heap::MAX_STACK_DEPTH && rbp != 0 {
+            match (self.read_word(rbp + 8), self.read_word(rbp)) {
+                (Ok(rip), Ok(next)) if rip != 0 => {
+                    stack.push(rip as usize);
+                    // the frames of callers are further up the stack
+                    if next as usize <= rbp {
+                        break;
+                    }
+                    rbp = next as usize;
+                }
+                _ => break,
+            }
+        }
+        stack
+    }
+
+    /// Executes a single machine instruction.
+    pub fn step_instruction(
+        &mut self,
+        breakpoints: &HashMap<usize, Breakpoint>,
+    ) -> Result<Status, Error> {
+        let regs = ptrace::getregs(self.pid())?;
+        let status = match self.step_over_breakpoint(breakpoints)? {
+            Some(status) => status,
+            None => self.resume(Resume::Step, None, breakpoints)?,
+        };
+        if let Status::Stopped(_, _) = status {
+            self.record_step(regs);
+        }
+        Ok(status)
+    }
+
+    /// Logs a step for reverse execution if recording, given the registers from before it.
+    fn record_step(&mut self, regs: libc::user_regs_struct) {
+        let pid = self.pid();
+        let recorded = self
+            .recorder
+            .as_mut()
+            .map(|recorder| recorder.record(pid, regs));
+        if let Some(Err(err)) = recorded {
+            self.warnings
+                .push(format!("Recording stopped, could not read memory: {}", err));
+            self.recorder = None;
+        }
+    }
+
+    /// Steps until the inferior reaches the start of a different source line. Function calls are
+    /// stepped over, unless `step_into` is set and the callee has line information.
+    pub fn step_line(
+        &mut self,
+        debug_data: &DwarfData,
+        breakpoints: &mut HashMap<usize, Breakpoint>,
+        step_into: bool,
+    ) -> Result<Status, Error> {
+        let start_rip = self.rip()?;
+        let start_line = match debug_data.get_line_from_addr(start_rip) {
+            Some(line) => line,
+            // Without line information there is no line to step off of
+            None => return self.step_instruction(breakpoints),
+        };
+        let start_depth = debug_data.get_inlined_calls(start_rip).len();
         loop {
-            let _line = debug_data.get_line_from_addr(rip);
-            let _func = debug_data.get_function_from_addr(rip);
-            match (&_line, &_func) {
-                (None, None) => println!("unknown func (source file not found)"),
-                (Some(line), None) => println!("unknown func ({})", line),
-                (None, Some(func)) => println!("{} (source file not found)", func),
-                (Some(line), Some(func)) => println!("{} ({})", func, line),
-            }
-            if let Some(func) = _func {
-                if func == "main" {
-                    break;
+            let regs = ptrace::getregs(self.pid())?;
+            let mut rip = match self.step_instruction(breakpoints)? {
+                Status::Stopped(signal::Signal::SIGTRAP, rip) => rip,
+                other => return Ok(other),
+            };
+
+            // `call` pushes the address of the instruction following it
+            let new_rsp = ptrace::getregs(self.pid())?.rsp;
+            if new_rsp == regs.rsp - 8 {
+                let ret_addr = self
+                    .read_word(new_rsp as usize)
+                    .map_err(|err| Error::memory(err, new_rsp as usize))?
+                    as usize;
+                let call_addr = regs.rip as usize;
+                let is_call = ret_addr > call_addr && ret_addr <= call_addr + MAX_INSTRUCTION_LEN;
+                if is_call && (!step_into || debug_data.get_line_from_addr(rip).is_none()) {
+                    // The call may have been the last instruction of the line, so where it
+                    // returns to is checked like any other instruction
+                    match self.run_to_return(ret_addr, regs.rsp as usize, breakpoints)? {
+                        Status::Stopped(signal::Signal::SIGTRAP, addr) if addr == ret_addr => {
+                            rip = addr
+                        }
+                        other => return Ok(other),
+                    }
                 }
+            }
+
+            // Inlined calls made by this line are stepped into like real ones: `step` stops at
+            // the start of the inlined function, and `next` runs through it
+            let inlined = debug_data.get_inlined_calls(rip);
+            if inlined.len() > start_depth {
+                if step_into && inlined[start_depth..].iter().any(|call| call.entry == rip) {
+                    return Ok(Status::Stopped(signal::Signal::SIGTRAP, rip));
+                }
+                if !step_into {
+                    continue;
+                }
+            }
+
+            match debug_data.get_line_from_addr(rip) {
+                // We've left the code we know about (e.g. returned from main), so just let it run
+                None => return self.continue_run(None, breakpoints),
+                Some(line) => {
+                    // Don't stop on the first instruction of a function we stepped into: like
+                    // a breakpoint, we want to land after the prologue has set up the frame.
+                    let at_function_entry = debug_data
+                        .get_function_containing(rip)
+                        .map_or(false, |func| func.address == rip);
+                    if (line.file != start_line.file || line.number != start_line.number)
+                        && debug_data.is_line_boundary(rip)
+                        && !at_function_entry
+                    {
+                        return Ok(Status::Stopped(signal::Signal::SIGTRAP, rip));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Runs until the current call returns to `ret_addr` in the frame whose stack pointer was
+    /// `frame_sp` before the call. Recursive calls passing through the same address are skipped.
+    fn run_to_return(
+        &mut self,
+        ret_addr: usize,
+        frame_sp: usize,
+        breakpoints: &mut HashMap<usize, Breakpoint>,
+    ) -> Result<Status, Error> {
+        let planted = !breakpoints.contains_key(&ret_addr);
+        if planted {
+            self.insert_breakpoint(ret_addr, true, breakpoints)?;
+        }
+        let status = loop {
+            let status = self.continue_run(None, breakpoints)?;
+            if let Status::Stopped(signal::Signal::SIGTRAP, rip) = status {
+                if rip == ret_addr && (ptrace::getregs(self.pid())?.rsp as usize) < frame_sp {
+                    continue;
+                }
+            }
+            break status;
+        };
+        if planted {
+            if let Status::Stopped(_, _) | Status::Caught(_, _) = status {
+                self.remove_breakpoint(ret_addr, breakpoints)?;
             } else {
-                break;
+                breakpoints.remove(&ret_addr);
             }
-            rip = ptrace::read(self.pid(), (rbp + 8) as ptrace::AddressType)? as usize;
-            rbp = ptrace::read(self.pid(), rbp as ptrace::AddressType)? as usize;
-            // function's return address in running is stored 8 bytes above the saved %rbp value.
-            // This return address is effectively %rip (the instruction pointer) for the previous stack frame.
         }
-        // #end
+        Ok(status)
+    }
+
+    /// Starts logging each instruction executed so that it can later be undone.
+    pub fn start_recording(&mut self) -> io::Result<()> {
+        self.recorder = Some(Recorder::new(self.pid())?);
         Ok(())
     }
 
-    pub fn write_byte(&mut self, addr: usize, val: u8) -> Result<u8, nix::Error> {
-        let aligned_addr = align_addr_to_word(addr);
-        let byte_offset = addr - aligned_addr;
-        let word = ptrace::read(self.pid(), aligned_addr as ptrace::AddressType)? as u64;
-        let orig_byte = (word >> 8 * byte_offset) & 0xff;
-        let masked_word = word & !(0xff << 8 * byte_offset);
-        let updated_word = masked_word | ((val as u64) << 8 * byte_offset);
+    pub fn stop_recording(&mut self) {
+        self.recorder = None;
+    }
+
+    /// Returns the number of recorded instructions, or None if not recording.
+    pub fn recorded_steps(&self) -> Option<usize> {
+        self.recorder.as_ref().map(|recorder| recorder.num_steps())
+    }
+
+    /// Undoes the most recently executed instruction. Returns Ok(None) if there is nothing left
+    /// to undo.
+    pub fn reverse_step_instruction(&mut self) -> Result<Option<usize>, Error> {
+        let step = match self.recorder.as_mut().and_then(|recorder| recorder.pop()) {
+            Some(step) => step,
+            None => return Ok(None),
+        };
+        for (addr, old_word) in step.memory {
+            ptrace::write(
+                self.pid(),
+                addr as ptrace::AddressType,
+                old_word as *mut std::ffi::c_void,
+            )
+            .map_err(|err| Error::memory(err, addr))?;
+        }
+        ptrace::setregs(self.pid(), step.regs)?;
+        Ok(Some(step.regs.rip as usize))
+    }
+
+    /// Runs backwards until reaching a breakpoint or the start of the recording.
+    pub fn reverse_continue(
+        &mut self,
+        breakpoints: &HashMap<usize, Breakpoint>,
+    ) -> Result<ReplayStatus, Error> {
+        let mut rip = self.rip()?;
+        loop {
+            match self.reverse_step_instruction()? {
+                Some(addr) if breakpoints.contains_key(&addr) => {
+                    return Ok(ReplayStatus::Stopped(addr))
+                }
+                Some(addr) => rip = addr,
+                None => return Ok(ReplayStatus::NoMoreHistory(rip)),
+            }
+        }
+    }
+
+    /// Runs backwards to the start of the previous source line in this function (or its caller,
+    /// if we're at the top of the function), passing over any calls made in between.
+    pub fn reverse_next(&mut self, debug_data: &DwarfData) -> Result<ReplayStatus, Error> {
+        let start = ptrace::getregs(self.pid())?;
+        let start_line = debug_data.get_line_from_addr(start.rip as usize);
+
+        // Go back until we're on a different line, ignoring instructions in functions we called
+        let mut rip = start.rip as usize;
+        loop {
+            rip = match self.reverse_step_instruction()? {
+                Some(addr) => addr,
+                None => return Ok(ReplayStatus::NoMoreHistory(rip)),
+            };
+            let rsp = ptrace::getregs(self.pid())?.rsp;
+            if rsp >= start.rsp && !same_line(&debug_data.get_line_from_addr(rip), &start_line) {
+                break;
+            }
+        }
+
+        // Then keep going to the first instruction of that line
+        let frame_rsp = ptrace::getregs(self.pid())?.rsp;
+        let line = debug_data.get_line_from_addr(rip);
+        while let Some(prev) = self.recorder.as_ref().and_then(|recorder| recorder.peek()) {
+            let in_callee = prev.rsp < frame_rsp;
+            if !in_callee && !same_line(&debug_data.get_line_from_addr(prev.rip as usize), &line) {
+                break;
+            }
+            rip = self.reverse_step_instruction()?.unwrap();
+        }
+        Ok(ReplayStatus::Stopped(rip))
+    }
+
+    /// Sets up a call to the function at `addr` following the System V ABI, with `args` passed in
+    /// integer registers. Once the inferior is continued and the function returns, it stops at
+    /// `CallFrame::return_addr`; `finish_call` then restores the state from before the call.
+    pub fn start_call(
+        &mut self,
+        addr: usize,
+        args: &[u64],
+        breakpoints: &mut HashMap<usize, Breakpoint>,
+    ) -> Result<CallFrame, Error> {
+        let saved_regs = ptrace::getregs(self.pid())?;
+        // Like gdb, return to the program's entry point, which won't run again
+        let return_addr = self.entry_point().ok_or(Error::NoEntryPoint)?;
+        let planted = !breakpoints.contains_key(&return_addr);
+        if planted {
+            self.insert_breakpoint(return_addr, false, breakpoints)?;
+        }
+
+        let mut regs = saved_regs;
+        // Skip the red zone below the stack pointer, and align the stack as if `call` pushed the
+        // return address.
+        let sp = (saved_regs.rsp as usize - 256) & !0xf;
         ptrace::write(
             self.pid(),
-            aligned_addr as ptrace::AddressType,
-            updated_word as *mut std::ffi::c_void,
-        )?;
-        Ok(orig_byte as u8)
+            (sp - 8) as ptrace::AddressType,
+            return_addr as *mut std::ffi::c_void,
+        )
+        .map_err(|err| Error::memory(err, sp - 8))?;
+        regs.rsp = (sp - 8) as u64;
+        regs.rip = addr as u64;
+        // Variadic functions read the number of vector registers used from %al
+        regs.rax = 0;
+        // Don't let the kernel restart a system call we were stopped in
+        regs.orig_rax = u64::MAX;
+        let mut arg_regs = [
+            &mut regs.rdi,
+            &mut regs.rsi,
+            &mut regs.rdx,
+            &mut regs.rcx,
+            &mut regs.r8,
+            &mut regs.r9,
+        ];
+        for (reg, arg) in arg_regs.iter_mut().zip(args) {
+            **reg = *arg;
+        }
+        ptrace::setregs(self.pid(), regs)?;
+        Ok(CallFrame {
+            saved_regs,
+            return_addr,
+            sp,
+            planted,
+        })
+    }
+
+    /// Returns true if the inferior is stopped because the function called by `frame` returned.
+    pub fn call_returned(&self, frame: &CallFrame) -> Result<bool, Error> {
+        let regs = ptrace::getregs(self.pid())?;
+        Ok(regs.rip as usize == frame.return_addr && regs.rsp as usize == frame.sp)
+    }
+
+    /// Restores the registers from before the call, returning the called function's %rax.
+    pub fn finish_call(
+        &mut self,
+        frame: CallFrame,
+        breakpoints: &mut HashMap<usize, Breakpoint>,
+    ) -> Result<u64, Error> {
+        let rax = ptrace::getregs(self.pid())?.rax;
+        if frame.planted {
+            self.remove_breakpoint(frame.return_addr, breakpoints)?;
+        }
+        ptrace::setregs(self.pid(), frame.saved_regs)?;
+        Ok(rax)
+    }
+
+    /// Reads the program's entry point from the auxiliary vector.
+    fn entry_point(&self) -> Option<usize> {
+        let auxv = fs::read(format!("/proc/{}/auxv", self.pid())).ok()?;
+        auxv.chunks_exact(2 * size_of::<u64>()).find_map(|pair| {
+            let (key, value) = pair.split_at(size_of::<u64>());
+            if u64::from_le_bytes(key.try_into().unwrap()) == libc::AT_ENTRY {
+                Some(u64::from_le_bytes(value.try_into().unwrap()) as usize)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Looks up a function exported by one of the shared libraries loaded into the inferior, e.g.
+    /// `strlen` in libc. Returns its address, and whether it is an indirect function (whose
+    /// address must be obtained by calling it first).
+    pub fn lookup_library_function(&self, name: &str) -> Option<(usize, bool)> {
+        for mapping in self.mappings().ok()? {
+            let path = match &mapping.path {
+                Some(path) if mapping.is_file() && mapping.offset == 0 && path.contains(".so") => {
+                    path
+                }
+                _ => continue,
+            };
+            let data = match fs::read(path) {
+                Ok(data) => data,
+                Err(_) => continue,
+            };
+            let object = match object::File::parse(&data) {
+                Ok(object) => object,
+                Err(_) => continue,
+            };
+            let found = object.dynamic_symbols().find(|(_, symbol)| {
+                symbol.name() == Some(name) && !symbol.is_undefined() && symbol.address() != 0
+            });
+            if let Some((_, symbol)) = found {
+                // Shared libraries are linked at 0, so addresses are relative to the mapping
+                let indirect = symbol.kind() == object::SymbolKind::Unknown;
+                return Some((mapping.start + symbol.address() as usize, indirect));
+            }
+        }
+        None
+    }
+
+    /// Reads the inferior's memory map from /proc/<pid>/maps.
+    pub fn mappings(&self) -> io::Result<Vec<Mapping>> {
+        read_mappings(self.pid())
+    }
+
+    /// Returns the current instruction pointer.
+    pub fn rip(&self) -> Result<usize, Error> {
+        Ok(ptrace::getregs(self.pid())?.rip as usize)
+    }
+
+    /// Kills the inferior. There is nothing to do if it has already gone.
+    pub fn kill(&mut self) {
+        if signal::kill(self.pid(), signal::Signal::SIGKILL).is_ok() {
+            // to ensure child's process has been killed
+            let _ = self.wait(None);
+        }
+    }
+
+    /// Writes a breakpoint into the inferior's code at `addr` and records it in `breakpoints`.
+    pub fn insert_breakpoint(
+        &mut self,
+        addr: usize,
+        temporary: bool,
+        breakpoints: &mut HashMap<usize, Breakpoint>,
+    ) -> Result<(), Error> {
+        // Writing 0xcc over 0xcc would lose the original instruction
+        if let Some(bp) = breakpoints.get_mut(&addr) {
+            bp.temporary &= temporary;
+            return Ok(());
+        }
+        let orig_byte = match self.heap_original_byte(addr) {
+            Some(orig_byte) => orig_byte,
+            None => self.write_byte(addr, 0xcc)?,
+        };
+        breakpoints.insert(
+            addr,
+            Breakpoint {
+                orig_byte,
+                temporary,
+            },
+        );
+        Ok(())
+    }
+
+    /// Removes the breakpoint at `addr`, putting the original instruction back.
+    pub fn remove_breakpoint(
+        &mut self,
+        addr: usize,
+        breakpoints: &mut HashMap<usize, Breakpoint>,
+    ) -> Result<(), Error> {
+        match breakpoints.remove(&addr) {
+            // leave the heap tracker's breakpoint in place
+            Some(_) if self.heap_original_byte(addr).is_some() => Ok(()),
+            Some(bp) => self.write_byte(addr, bp.orig_byte).map(|_| ()),
+            None => Ok(()),
+        }
+    }
+
+    pub fn write_byte(&mut self, addr: usize, val: u8) -> Result<u8, Error> {
+        write_byte_to(self.pid(), addr, val).map_err(|err| Error::memory(err, addr))
+    }
+}
+
+impl Target for Inferior {
+    fn registers(&self) -> Result<libc::user_regs_struct, nix::Error> {
+        ptrace::getregs(self.pid())
+    }
+
+    fn read_word(&self, addr: usize) -> Result<u64, nix::Error> {
+        Ok(ptrace::read(self.pid(), addr as ptrace::AddressType)? as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn mapping(start: usize, end: usize, path: Option<&str>) -> Mapping {
+        Mapping {
+            start,
+            end,
+            perms: "rw-p".to_string(),
+            offset: 0,
+            path: path.map(|path| path.to_string()),
+        }
+    }
+
+    #[test]
+    fn anonymous_memory_after_a_file_is_its_bss() {
+        let mappings = [
+            mapping(0x0040_0000, 0x0040_1000, Some("/bin/prog")),
+            mapping(0x0040_3000, 0x0040_4000, Some("/bin/prog")),
+            mapping(0x0040_4000, 0x0040_5000, None),
+            mapping(0x0040_5000, 0x0042_6000, Some("[heap]")),
+            // Not next to a file
+            mapping(0x7f00_0000_0000, 0x7f00_0000_2000, None),
+            mapping(0x7f00_0000_2000, 0x7f00_0000_3000, Some("/lib/libc.so.6")),
+            mapping(0x7f00_0000_3000, 0x7f00_0000_4000, None),
+        ];
+        assert_eq!(
+            objfile_names(&mappings),
+            [
+                "/bin/prog",
+                "/bin/prog",
+                "/bin/prog (.bss)",
+                "[heap]",
+                "",
+                "/lib/libc.so.6",
+                "/lib/libc.so.6 (.bss)",
+            ]
+        );
+    }
+
+    /// Continues to the next stop, which should be a caught event.
+    fn next_event(inferior: &mut Inferior, breakpoints: &HashMap<usize, Breakpoint>) -> Event {
+        match inferior.continue_run(None, breakpoints).unwrap() {
+            Status::Caught(event, _) => event,
+            _ => panic!("expected a catchpoint to stop the inferior"),
+        }
+    }
+
+    /// Continues, passing on any signals (e.g. SIGCHLD), until the inferior exits. Returns the
+    /// exit status.
+    fn run_to_exit(inferior: &mut Inferior, breakpoints: &HashMap<usize, Breakpoint>) -> i32 {
+        let mut signal = None;
+        loop {
+            match inferior.continue_run(signal, breakpoints).unwrap() {
+                Status::Exited(code) => return code,
+                Status::Stopped(stop_signal, _) => signal = Some(stop_signal),
+                _ => panic!("expected the program to run to the end"),
+            }
+        }
+    }
+
+    #[test]
+    fn syscall_entry_and_exit_are_paired() {
+        let mut breakpoints = HashMap::new();
+        let mut inferior = testing::spawn("hello", &mut breakpoints);
+        inferior.set_catchpoints(&[Catchpoint::Syscall(Some(libc::SYS_write as u64))]);
+
+        match next_event(&mut inferior, &breakpoints) {
+            Event::SyscallEntry(number, args) => {
+                assert_eq!(number, libc::SYS_write as u64);
+                assert_eq!(args[0], 1);
+                assert_eq!(args[2], "Hello world!\n".len() as u64);
+            }
+            _ => panic!("expected the entry to write"),
+        }
+        match next_event(&mut inferior, &breakpoints) {
+            Event::SyscallExit(number, ret) => {
+                assert_eq!(number, libc::SYS_write as u64);
+                assert_eq!(ret, "Hello world!\n".len() as i64);
+            }
+            _ => panic!("expected the exit from write"),
+        }
+        assert_eq!(run_to_exit(&mut inferior, &breakpoints), 0);
+    }
+
+    #[test]
+    fn fork_event_does_not_unpair_syscall_stops() {
+        let mut breakpoints = HashMap::new();
+        let mut inferior = testing::spawn("fork", &mut breakpoints);
+        inferior.set_catchpoints(&[
+            Catchpoint::Syscall(Some(libc::SYS_clone as u64)),
+            Catchpoint::Syscall(Some(libc::SYS_clone3 as u64)),
+            Catchpoint::Fork,
+        ]);
+
+        let number = match next_event(&mut inferior, &breakpoints) {
+            Event::SyscallEntry(number, _) => number,
+            _ => panic!("expected the entry to clone"),
+        };
+        let child = match next_event(&mut inferior, &breakpoints) {
+            Event::Fork(child) => child,
+            _ => panic!("expected the fork event"),
+        };
+        match next_event(&mut inferior, &breakpoints) {
+            Event::SyscallExit(exit_number, ret) => {
+                assert_eq!(exit_number, number);
+                assert_eq!(ret, child.as_raw() as i64);
+            }
+            _ => panic!("expected the exit from clone"),
+        }
+        assert_eq!(run_to_exit(&mut inferior, &breakpoints), 0);
+    }
+
+    fn watchpoint(number: usize, addr: usize, size: usize) -> Watchpoint {
+        Watchpoint {
+            number,
+            expr: format!("*{:#x}", addr),
+            addr,
+            ty: Type {
+                size,
+                ..Type::default()
+            },
+        }
+    }
+
+    #[test]
+    fn watchpoint_set_before_the_program_runs_fires() {
+        let mut breakpoints = HashMap::new();
+        let mut inferior = testing::spawn("hello", &mut breakpoints);
+        // The first push or call of the program writes just below the initial stack pointer
+        let sp = ptrace::getregs(inferior.pid()).unwrap().rsp as usize;
+        let kind = inferior.add_watchpoint(&watchpoint(3, sp - 8, 8)).unwrap();
+        assert_eq!(kind, WatchKind::Hardware(0));
+        assert_eq!(inferior.watch_kind(3), Some(WatchKind::Hardware(0)));
+
+        match next_event(&mut inferior, &breakpoints) {
+            Event::Watchpoint(number, old, new) => {
+                assert_eq!(number, 3);
+                assert_ne!(old, new);
+            }
+            _ => panic!("expected the watchpoint to fire"),
+        }
+        assert!(inferior.remove_watchpoint(3).unwrap());
+        assert!(!inferior.remove_watchpoint(3).unwrap());
+        assert_eq!(inferior.watch_kind(3), None);
+        assert_eq!(run_to_exit(&mut inferior, &breakpoints), 0);
+    }
+
+    #[test]
+    fn software_watchpoint_still_stops_for_syscalls() {
+        let debug_data = testing::debug_data("hello");
+        let mut breakpoints = testing::breakpoints_at(&debug_data, &["main"]);
+        let mut inferior = testing::spawn("hello", &mut breakpoints);
+        match inferior.continue_run(None, &breakpoints).unwrap() {
+            Status::Stopped(signal::Signal::SIGTRAP, _) => {}
+            _ => panic!("expected to stop in main"),
+        }
+        // Code doesn't change, so this only makes the inferior single-step
+        let main = debug_data.get_addr_for_function(None, "main").unwrap();
+        let kind = inferior.add_watchpoint(&watchpoint(1, main + 16, 16));
+        assert_eq!(kind.unwrap(), WatchKind::Software);
+        inferior.set_catchpoints(&[Catchpoint::Syscall(Some(libc::SYS_write as u64))]);
+
+        match next_event(&mut inferior, &breakpoints) {
+            Event::SyscallEntry(number, _) => assert_eq!(number, libc::SYS_write as u64),
+            _ => panic!("expected the entry to write"),
+        }
+        match next_event(&mut inferior, &breakpoints) {
+            Event::SyscallExit(number, ret) => {
+                assert_eq!(number, libc::SYS_write as u64);
+                assert_eq!(ret, "Hello world!\n".len() as i64);
+            }
+            _ => panic!("expected the exit from write"),
+        }
+        assert_eq!(run_to_exit(&mut inferior, &breakpoints), 0);
+    }
+
+    #[test]
+    fn uncaught_syscall_exit_is_forgotten() {
+        let mut breakpoints = HashMap::new();
+        let mut inferior = testing::spawn("hello", &mut breakpoints);
+        inferior.set_catchpoints(&[Catchpoint::Syscall(None)]);
+        match next_event(&mut inferior, &breakpoints) {
+            Event::SyscallEntry(_, _) => {}
+            _ => panic!("expected a syscall entry"),
+        }
+
+        // Continuing without syscall catchpoints skips the exit stop, so the next stop once they
+        // are back is an entry again
+        inferior.set_catchpoints(&[]);
+        inferior.step_instruction(&breakpoints).unwrap();
+        inferior.set_catchpoints(&[Catchpoint::Syscall(None)]);
+        match next_event(&mut inferior, &breakpoints) {
+            Event::SyscallEntry(_, _) => {}
+            _ => panic!("expected a syscall entry"),
+        }
+        inferior.kill();
     }
 }
@@ -0,0 +1,332 @@
+//! How `run` starts the program. Its arguments can redirect the program's standard streams the
+//! way a shell would (`run args < in.txt > out.txt 2>&1`), and its environment, working directory
+//! and terminal are set with `set environment`, `set cwd` and `tty`. All of these last between
+//! runs. Address space randomization is off unless `set disable-randomization off` is used, so
+//! that addresses are the same from one run to the next.
+
+use crate::inferior::child_disable_randomization;
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::io::FromRawFd;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Redirection operators, longest first so that `2>>` isn't taken for `2>`.
+const OPERATORS: &[&str] = &["2>&1", "2>>", "1>>", "&>", "2>", "1>", ">>", ">", "<"];
+
+/// A word of a command line, such as an argument of `run`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Word {
+    pub text: String,
+    /// How many bytes at the start of `text` were neither quoted nor escaped. Only those can make
+    /// up a redirection operator, so `run ">"` passes ">" to the program.
+    pub unquoted: usize,
+}
+
+impl Word {
+    /// A word with nothing quoted in it.
+    pub fn plain(text: &str) -> Word {
+        Word {
+            text: text.to_string(),
+            unquoted: text.len(),
+        }
+    }
+
+    /// A word that is taken as it is, never as a redirection.
+    pub fn quoted(text: &str) -> Word {
+        Word {
+            text: text.to_string(),
+            unquoted: 0,
+        }
+    }
+}
+
+/// Where the program's stderr goes.
+enum Stderr {
+    /// A file, and whether to append to it.
+    File(String, bool),
+    /// Wherever stdout goes (`2>&1`).
+    Stdout,
+    /// Where stdout would go without a redirection, for `2>&1 > out.txt`, which like a shell
+    /// leaves stderr where stdout was when `2>&1` came.
+    DefaultStdout,
+}
+
+#[derive(Default)]
+struct Redirections {
+    stdin: Option<String>,
+    /// A file for stdout, and whether to append to it.
+    stdout: Option<(String, bool)>,
+    stderr: Option<Stderr>,
+}
+
+pub struct Launch {
+    /// The arguments of the last `run` or `set args`, redirections included.
+    pub args: Vec<Word>,
+    /// The environment the program starts with: the debugger's own, unless changed.
+    pub environment: BTreeMap<String, String>,
+    pub cwd: Option<String>,
+    /// A terminal for the program's stdin, stdout and stderr, instead of the debugger's.
+    pub tty: Option<String>,
+    pub disable_randomization: bool,
+    /// Pipes the program's stdout and stderr back to the debugger, to be read with
+    /// `Session::take_output`, and gives it an empty stdin. Redirections still apply.
+    pub capture_output: bool,
+}
+
+impl Launch {
+    pub fn new() -> Launch {
+        Launch {
+            args: Vec::new(),
+            environment: std::env::vars().collect(),
+            cwd: None,
+            tty: None,
+            disable_randomization: true,
+            capture_output: false,
+        }
+    }
+
+    /// Builds the command that starts `target`, opening the files its streams are redirected to.
+    pub fn command(&self, target: &str) -> Result<Command, String> {
+        let (args, redirections) = parse_args(&self.args)?;
+        // Relative paths are relative to the program's working directory, as they would be for
+        // a shell started there
+        let dir = self.cwd.as_ref().map(Path::new);
+        let resolve = |file: &str| match dir {
+            Some(dir) => dir.join(file),
+            None => PathBuf::from(file),
+        };
+        let program = match dir {
+            Some(_) => fs::canonicalize(target).map_err(|err| format!("{}: {}", target, err))?,
+            None => PathBuf::from(target),
+        };
+
+        let mut cmd = Command::new(program);
+        cmd.args(args).env_clear().envs(&self.environment);
+        if let Some(dir) = dir {
+            cmd.current_dir(dir);
+        }
+
+        let tty = match &self.tty {
+            Some(path) => Some(open(path, OpenOptions::new().read(true).write(true))?),
+            None => None,
+        };
+        let tty_stream = || match &tty {
+            Some(tty) => tty.try_clone().map(Some).map_err(|err| err.to_string()),
+            None => Ok(None),
+        };
+        let stdin = match &redirections.stdin {
+            Some(file) => Some(open(&resolve(file), OpenOptions::new().read(true))?),
+            None => tty_stream()?,
+        };
+        let stdout = match &redirections.stdout {
+            Some((file, append)) => Some(create(&resolve(file), *append)?),
+            None => tty_stream()?,
+        };
+        let stderr = match &redirections.stderr {
+            Some(Stderr::File(file, append)) => Some(create(&resolve(file), *append)?),
+            Some(Stderr::Stdout) => match &stdout {
+                Some(stdout) => Some(stdout.try_clone().map_err(|err| err.to_string())?),
+                None => None,
+            },
+            Some(Stderr::DefaultStdout) => match tty_stream()? {
+                Some(tty) => Some(tty),
+                None => Some(debugger_stdout()?),
+            },
+            None => tty_stream()?,
+        };
+        if let Some(stdin) = stdin {
+            cmd.stdin(stdin);
+        } else if self.capture_output {
+            cmd.stdin(Stdio::null());
+        }
+        if let Some(stdout) = stdout {
+            cmd.stdout(stdout);
+        } else if self.capture_output {
+            cmd.stdout(Stdio::piped());
+        }
+        if let Some(stderr) = stderr {
+            cmd.stderr(stderr);
+        } else if self.capture_output {
+            cmd.stderr(Stdio::piped());
+        }
+        if tty.is_some() {
+            unsafe {
+                cmd.pre_exec(set_controlling_terminal);
+            }
+        }
+        if self.disable_randomization {
+            unsafe {
+                cmd.pre_exec(child_disable_randomization);
+            }
+        }
+        Ok(cmd)
+    }
+}
+
+impl Default for Launch {
+    fn default() -> Self {
+        Launch::new()
+    }
+}
+
+/// Separates the redirections from the program's arguments. An operator can be followed by its
+/// file name (`>out.txt`) or have it as the next argument (`> out.txt`). Redirections apply in
+/// order, as in a shell.
+fn parse_args(words: &[Word]) -> Result<(Vec<String>, Redirections), String> {
+    let mut args = Vec::new();
+    let mut redirections = Redirections::default();
+    let mut words = words.iter();
+    while let Some(word) = words.next() {
+        let token = &word.text;
+        let operator = OPERATORS
+            .iter()
+            .find(|op| op.len() <= word.unquoted && token.starts_with(*op));
+        let operator = match operator {
+            Some(operator) => *operator,
+            None => {
+                args.push(token.clone());
+                continue;
+            }
+        };
+        if operator == "2>&1" {
+            redirections.stderr = Some(Stderr::Stdout);
+            continue;
+        }
+        let file = match &token[operator.len()..] {
+            "" => words
+                .next()
+                .ok_or_else(|| format!("Missing file name after \"{}\".", operator))?
+                .text
+                .clone(),
+            file => file.to_string(),
+        };
+        let append = operator.ends_with(">>");
+        match operator {
+            "<" => redirections.stdin = Some(file),
+            "2>" | "2>>" => redirections.stderr = Some(Stderr::File(file, append)),
+            "&>" => {
+                redirections.stdout = Some((file, false));
+                redirections.stderr = Some(Stderr::Stdout);
+            }
+            _ => {
+                // An earlier 2>&1 keeps stderr where stdout was until now
+                if let Some(Stderr::Stdout) = redirections.stderr {
+                    redirections.stderr = Some(match redirections.stdout.take() {
+                        Some((file, append)) => Stderr::File(file, append),
+                        None => Stderr::DefaultStdout,
+                    });
+                }
+                redirections.stdout = Some((file, append));
+            }
+        }
+    }
+    Ok((args, redirections))
+}
+
+fn open<P: AsRef<Path>>(path: P, options: &OpenOptions) -> Result<File, String> {
+    options
+        .open(path.as_ref())
+        .map_err(|err| format!("{}: {}", path.as_ref().display(), err))
+}
+
+fn create(path: &Path, append: bool) -> Result<File, String> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true);
+    if append {
+        options.append(true);
+    } else {
+        options.truncate(true);
+    }
+    open(path, &options)
+}
+
+/// A copy of the debugger's own stdout, which is where the program's goes by default.
+fn debugger_stdout() -> Result<File, String> {
+    let fd = nix::unistd::dup(libc::STDOUT_FILENO).map_err(|err| err.to_string())?;
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+/// Runs in the child before it execs. Starts a new session with the terminal on stdin as its
+/// controlling terminal, so that ^C typed there goes to the program. Taking the terminal fails if
+/// a shell owns it, in which case the program still uses it for its streams.
+fn set_controlling_terminal() -> io::Result<()> {
+    unsafe {
+        libc::setsid();
+        libc::ioctl(libc::STDIN_FILENO, libc::TIOCSCTTY, 0);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plain(words: &[&str]) -> Vec<Word> {
+        words.iter().map(|word| Word::plain(word)).collect()
+    }
+
+    #[test]
+    fn redirections_are_taken_out_of_the_arguments() {
+        let (args, redirections) =
+            parse_args(&plain(&["a", "<in", ">", "out", "b", "2>>log"])).unwrap();
+        assert_eq!(args, vec!["a", "b"]);
+        assert_eq!(redirections.stdin.as_deref(), Some("in"));
+        assert_eq!(redirections.stdout, Some(("out".to_string(), false)));
+        match redirections.stderr {
+            Some(Stderr::File(file, true)) => assert_eq!(file, "log"),
+            _ => panic!("stderr should be appended to log"),
+        }
+        assert_eq!(
+            parse_args(&plain(&["a", ">>"])).err().unwrap(),
+            "Missing file name after \">>\"."
+        );
+    }
+
+    #[test]
+    fn quoted_operators_are_arguments() {
+        let words = vec![
+            Word::quoted(">"),
+            Word::quoted("<x"),
+            Word {
+                text: "2>x".to_string(),
+                unquoted: 1,
+            },
+            Word {
+                text: ">out".to_string(),
+                unquoted: 1,
+            },
+        ];
+        let (args, redirections) = parse_args(&words).unwrap();
+        assert_eq!(args, vec![">", "<x", "2>x"]);
+        assert_eq!(redirections.stdout, Some(("out".to_string(), false)));
+        assert!(redirections.stdin.is_none() && redirections.stderr.is_none());
+    }
+
+    #[test]
+    fn stderr_goes_where_stdout_was_at_2_and_1() {
+        let (_, redirections) = parse_args(&plain(&["2>&1", ">out"])).unwrap();
+        match redirections.stderr {
+            Some(Stderr::DefaultStdout) => {}
+            _ => panic!("stderr should stay on the default stdout"),
+        }
+
+        let dir = std::env::temp_dir().join(format!("qeet-test-launch.{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut launch = Launch::new();
+        launch.cwd = Some(dir.to_string_lossy().into_owned());
+        launch.args = vec![Word::plain("-c"), Word::quoted("echo out; echo err >&2")];
+        launch
+            .args
+            .extend(plain(&[">", "first", "2>&1", ">", "second"]));
+        let status = launch.command("/bin/sh").unwrap().status().unwrap();
+        assert!(status.success());
+        let first = fs::read_to_string(dir.join("first")).unwrap();
+        let second = fs::read_to_string(dir.join("second")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(first, "err\n");
+        assert_eq!(second, "out\n");
+    }
+}
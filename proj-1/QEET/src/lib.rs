@@ -0,0 +1,26 @@
+//! qeet, the engine behind the deet debugger: reading debugging information, running and
+//! inspecting programs under ptrace, and core files. `Session` puts it together for front ends;
+//! the modules are public for ones that need more than it offers.
+
+pub mod core_file;
+mod debug_files;
+mod demangle;
+pub mod disassemble;
+pub mod dwarf_data;
+pub mod dwarf_expr;
+mod dwarf_index;
+pub mod error;
+mod gimli_wrapper;
+pub mod heap;
+pub mod inferior;
+pub mod launch;
+pub mod pretty;
+pub mod record;
+pub mod session;
+pub mod syscalls;
+pub mod target;
+#[cfg(test)]
+mod testing;
+
+pub use error::Error;
+pub use session::{Session, Step, Stop};
@@ -1,28 +1,103 @@
+mod completion;
+mod dap;
 mod debugger;
 mod debugger_command;
-mod dwarf_data;
-mod gimli_wrapper;
-mod inferior;
+mod tui;
 
+use crate::dap::DapServer;
 use crate::debugger::Debugger;
 use nix::sys::signal::{signal, SigHandler, Signal};
+use qeet::dwarf_data::{DwarfData, Error as DwarfError};
 use std::env;
 
 pub struct Main {}
 
 impl Main {
     pub fn run() {
-        let args: Vec<String> = env::args().collect();
-        if args.len() != 2 {
-            println!("Usage: {} <target program>", args[0]);
+        let mut args: Vec<String> = env::args().collect();
+        if args.len() >= 2 && args[1] == "dump" {
+            Main::dump(&args);
+            return;
+        }
+        let (mut tui, mut check_memory) = (false, false);
+        while args.len() >= 2 && (args[1] == "--tui" || args[1] == "--check-memory") {
+            match args.remove(1).as_str() {
+                "--tui" => tui = true,
+                _ => check_memory = true,
+            }
+        }
+        let dap = args.len() >= 2 && args[1] == "--dap";
+        let with_core = args.len() == 4 && args[2] == "--core";
+        if (dap && args.len() > 3) || (!dap && !with_core && args.len() != 2) {
+            println!(
+                "Usage: {} [--tui] [--check-memory] <target program> [--core <core file>]",
+                args[0]
+            );
+            println!("       {} --dap [target program]", args[0]);
+            println!("       {} dump <target program> [--format text|json]", args[0]);
             std::process::exit(1);
         }
-        let target = &args[1];
 
         // Disable handling of ctrl+c in this process (so that ctrl+c only gets delivered to child processes)
         unsafe { signal(Signal::SIGINT, SigHandler::SigIgn) }
             .expect("Error disabling SIGINT handling");
-        Debugger::new(target).run();
+        if dap && tui {
+            println!("--tui can't be used with --dap");
+            std::process::exit(1);
+        }
+        if dap && check_memory {
+            println!("--check-memory can't be used with --dap");
+            std::process::exit(1);
+        }
+        if dap {
+            DapServer::new(args.get(2).map(|s| s.as_str())).run();
+        } else {
+            let mut debugger = Debugger::new(&args[1]);
+            if tui {
+                debugger.enable_tui();
+            }
+            if check_memory {
+                debugger.enable_memory_checking();
+            }
+            if with_core {
+                debugger.load_core(&args[3]);
+            }
+            debugger.run();
+        }
+    }
+
+    /// Implements `dump <target program> [--format text|json]`, printing the debugging information
+    /// we parsed from the program.
+    fn dump(args: &[String]) {
+        let option = |i: usize| args.get(i).map(|s| s.as_str());
+        let json = match (args.len(), option(3), option(4)) {
+            (3, _, _) => false,
+            (5, Some("--format"), Some("text")) => false,
+            (5, Some("--format"), Some("json")) => true,
+            _ => {
+                println!("Usage: {} dump <target program> [--format text|json]", args[0]);
+                std::process::exit(1);
+            }
+        };
+        let mut debug_data = match DwarfData::from_file(&args[2]) {
+            Ok(val) => val,
+            Err(DwarfError::ErrorOpeningFile) => {
+                println!("Could not open file {}", args[2]);
+                std::process::exit(1);
+            }
+            Err(DwarfError::DwarfFormatError(err)) => {
+                println!("Could not load debugging symbols from {}: {:?}", args[2], err);
+                std::process::exit(1);
+            }
+        };
+        for warning in debug_data.take_warnings() {
+            eprintln!("{}", warning);
+        }
+        if json {
+            println!("{:#}", debug_data.to_json());
+        } else {
+            print!("{}", debug_data.to_text());
+        }
     }
 }
 
@@ -0,0 +1,589 @@
+//! Showing values the way their language would. C structures print the way gdb prints them, and
+//! Rust values print the way `{:?}` would, which means looking inside the core types: a `Vec` is
+//! shown by its elements rather than by the fields of its buffer.
+//!
+//! Types that need more than their fields to make sense get a `PrettyPrinter`, picked by the
+//! DWARF name of the type. The standard library's are built in, and users can add their own to
+//! ~/.qeet_printers, one per line:
+//!
+//! ```text
+//! # <type name> = <template>
+//! struct list_node = {value} -> {*next}
+//! geometry::Point = ({x}, {y})
+//! ```
+//!
+//! `{a.b}` is replaced with the value of the field `b` of the field `a`, and `{*a}` with the value
+//! field `a` points to. Rust type names can leave out the module path and generic arguments.
+
+use crate::demangle;
+use crate::dwarf_data::{Member, Type, TypeKind, Variants};
+use std::fs;
+
+/// How many elements of a `Vec` or slice to show, unless changed with `set print elements`.
+const MAX_ELEMENTS: usize = 200;
+/// How many bytes of a string to show.
+const MAX_STRING_LENGTH: usize = 4096;
+
+/// Reads `len` bytes of the program's memory at an address, or returns None if they can't be
+/// read.
+pub type ReadMemory<'a> = &'a dyn Fn(usize, usize) -> Option<Vec<u8>>;
+
+pub trait PrettyPrinter {
+    /// Describes the printer for `info pretty-printer`.
+    fn describe(&self) -> String;
+    /// Returns true if this printer is for values of the type with this DWARF name.
+    fn matches(&self, type_name: &str) -> bool;
+    /// Renders a value, or returns None to leave it to the next printer (or to the raw
+    /// rendering of its fields). `printers` formats the values the printer is made of.
+    fn format(
+        &self,
+        ty: &Type,
+        bytes: &[u8],
+        printers: &Printers,
+        read: ReadMemory,
+    ) -> Option<String>;
+}
+
+/// The pretty-printers in use.
+pub struct Printers {
+    /// Later printers take precedence, so users' printers override the built-in ones.
+    printers: Vec<Box<dyn PrettyPrinter>>,
+    /// How many elements of a `Vec` or slice to show, or None for all of them.
+    max_elements: Option<usize>,
+}
+
+impl Printers {
+    /// Creates a registry holding the built-in printers.
+    pub fn new() -> Printers {
+        Printers {
+            printers: vec![
+                Box::new(RustStr),
+                Box::new(RustSlice),
+                Box::new(RustString),
+                Box::new(RustVec),
+            ],
+            max_elements: Some(MAX_ELEMENTS),
+        }
+    }
+
+    pub fn max_elements(&self) -> Option<usize> {
+        self.max_elements
+    }
+
+    pub fn set_max_elements(&mut self, max_elements: Option<usize>) {
+        self.max_elements = max_elements;
+    }
+
+    /// Creates a registry holding the built-in printers and the ones in ~/.qeet_printers, if it
+    /// exists. Errors in the file are returned alongside the printers that could be loaded.
+    pub fn with_config() -> (Printers, Result<(), String>) {
+        let mut printers = Printers::new();
+        let path = match std::env::var("HOME") {
+            Ok(home) => format!("{}/.qeet_printers", home),
+            Err(_) => return (printers, Ok(())),
+        };
+        let result = match fs::read_to_string(&path) {
+            Ok(config) => printers
+                .load(&config)
+                .map_err(|err| format!("{}: {}", path, err)),
+            Err(_) => Ok(()),
+        };
+        (printers, result)
+    }
+
+    pub fn register(&mut self, printer: Box<dyn PrettyPrinter>) {
+        self.printers.push(printer);
+    }
+
+    /// Registers the printers in a config file (see the module documentation).
+    pub fn load(&mut self, config: &str) -> Result<(), String> {
+        for (i, line) in config.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.find(" = ") {
+                Some(index) if index > 0 => self.register(Box::new(TemplatePrinter {
+                    type_name: line[..index].trim().to_string(),
+                    template: line[index + 3..].to_string(),
+                })),
+                _ => return Err(format!("line {}: expected <type name> = <template>", i + 1)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Describes each printer, in the order they are consulted.
+    pub fn describe(&self) -> Vec<String> {
+        self.printers
+            .iter()
+            .rev()
+            .map(|printer| printer.describe())
+            .collect()
+    }
+
+    /// Renders a value of the given type from its bytes. Values that point elsewhere (like the
+    /// contents of a `Vec`) are read with `read`.
+    pub fn format_value(&self, ty: &Type, bytes: &[u8], read: ReadMemory) -> String {
+        for printer in self.printers.iter().rev() {
+            if printer.matches(&ty.name) {
+                if let Some(value) = printer.format(ty, bytes, self, read) {
+                    return value;
+                }
+            }
+        }
+        if ty.rust {
+            if let Some(variants) = &ty.variants {
+                return self.format_rust_enum(variants, bytes, read);
+            }
+        }
+        match ty.kind {
+            TypeKind::Struct | TypeKind::Union if ty.rust => {
+                if ty.name.starts_with('(') {
+                    // Tuples
+                    let fields: Vec<String> = ty
+                        .members
+                        .iter()
+                        .map(|member| self.format_member(member, bytes, read))
+                        .collect();
+                    format!("({})", fields.join(", "))
+                } else {
+                    self.format_rust_fields(&demangle::base_name(&ty.name), ty, bytes, read)
+                }
+            }
+            TypeKind::Struct | TypeKind::Union => {
+                let fields: Vec<String> = ty
+                    .members
+                    .iter()
+                    .map(|member| {
+                        let value = self.format_member(member, bytes, read);
+                        format!("{} = {}", member.name, value)
+                    })
+                    .collect();
+                format!("{{{}}}", fields.join(", "))
+            }
+            TypeKind::Array => match &ty.pointee {
+                // C strings are kept in char arrays, and end at the first NUL
+                Some(element)
+                    if !ty.rust && element.size == 1 && element.name.ends_with("char") =>
+                {
+                    let len = bytes
+                        .iter()
+                        .position(|&b| b == 0)
+                        .unwrap_or_else(|| bytes.len());
+                    format!("{:?}", String::from_utf8_lossy(&bytes[..len]))
+                }
+                Some(element) if element.size > 0 => {
+                    let max = self.max_elements.unwrap_or(usize::MAX);
+                    let mut values: Vec<String> = bytes
+                        .chunks_exact(element.size)
+                        .take(max)
+                        .map(|bytes| self.format_value(element, bytes, read))
+                        .collect();
+                    if bytes.len() / element.size > max {
+                        values.push("...".to_string());
+                    }
+                    if ty.rust {
+                        format!("[{}]", values.join(", "))
+                    } else {
+                        format!("{{{}}}", values.join(", "))
+                    }
+                }
+                _ => ty.format_value(bytes),
+            },
+            _ => ty.format_value(bytes),
+        }
+    }
+
+    fn format_member(&self, member: &Member, bytes: &[u8], read: ReadMemory) -> String {
+        match member_bytes(member, bytes) {
+            Some(bytes) => self.format_value(&member.entity_type, bytes, read),
+            None => "<invalid>".to_string(),
+        }
+    }
+
+    /// Shows a structure or enum variant: `Point { x: 1, y: 2 }`, `Some(5)` or `None`.
+    fn format_rust_fields(&self, name: &str, ty: &Type, bytes: &[u8], read: ReadMemory) -> String {
+        if ty.members.is_empty() {
+            return name.to_string();
+        }
+        // Tuple structs and variants number their fields
+        let tuple = ty
+            .members
+            .iter()
+            .all(|member| member.name.starts_with("__"));
+        let fields: Vec<String> = ty
+            .members
+            .iter()
+            .map(|member| {
+                let value = self.format_member(member, bytes, read);
+                if tuple {
+                    value
+                } else {
+                    format!("{}: {}", member.name, value)
+                }
+            })
+            .collect();
+        if tuple {
+            format!("{}({})", name, fields.join(", "))
+        } else {
+            format!("{} {{ {} }}", name, fields.join(", "))
+        }
+    }
+
+    /// Shows the variant of a Rust enum that the discriminant selects. Niche-optimized enums
+    /// (like `Option<&T>`) keep the discriminant inside the data of one variant, which is the one
+    /// without a discriminant value of its own.
+    fn format_rust_enum(&self, variants: &Variants, bytes: &[u8], read: ReadMemory) -> String {
+        let variant = match &variants.discriminant {
+            Some(discriminant) => {
+                let value = member_bytes(discriminant, bytes).map(to_uint);
+                variants
+                    .variants
+                    .iter()
+                    .find(|variant| variant.discr_value.is_some() && variant.discr_value == value)
+                    .or_else(|| {
+                        variants
+                            .variants
+                            .iter()
+                            .find(|variant| variant.discr_value.is_none())
+                    })
+            }
+            None => variants.variants.first(),
+        };
+        match variant {
+            Some(variant) => match member_bytes(&variant.member, bytes) {
+                Some(variant_bytes) => self.format_rust_fields(
+                    &variant.member.name,
+                    &variant.member.entity_type,
+                    variant_bytes,
+                    read,
+                ),
+                None => "<invalid>".to_string(),
+            },
+            None => "<invalid discriminant>".to_string(),
+        }
+    }
+
+    /// Shows `len` values of type `element` stored at `addr`: `[1, 2, 3]`.
+    fn format_elements(&self, element: &Type, addr: usize, len: usize, read: ReadMemory) -> String {
+        let count = len.min(self.max_elements.unwrap_or(usize::MAX));
+        let data = match read(addr, count * element.size) {
+            Some(data) => data,
+            None => return format!("<cannot access memory at {:#x}>", addr),
+        };
+        let mut values: Vec<String> = (0..count)
+            .map(|i| {
+                let bytes = &data[i * element.size..(i + 1) * element.size];
+                self.format_value(element, bytes, read)
+            })
+            .collect();
+        if len > count {
+            values.push("...".to_string());
+        }
+        format!("[{}]", values.join(", "))
+    }
+}
+
+fn member_bytes<'a>(member: &Member, bytes: &'a [u8]) -> Option<&'a [u8]> {
+    bytes.get(member.offset..member.offset + member.entity_type.size)
+}
+
+fn to_uint(bytes: &[u8]) -> u64 {
+    let mut word = [0; 8];
+    let len = bytes.len().min(8);
+    word[..len].copy_from_slice(&bytes[..len]);
+    u64::from_le_bytes(word)
+}
+
+/// Reads an integer or pointer field of a value.
+fn field_uint(ty: &Type, bytes: &[u8], name: &str) -> Option<usize> {
+    Some(to_uint(member_bytes(ty.member(name)?, bytes)?) as usize)
+}
+
+fn format_string(addr: usize, len: usize, read: ReadMemory) -> String {
+    let count = len.min(MAX_STRING_LENGTH);
+    match read(addr, count) {
+        Some(data) => {
+            let string = format!("{:?}", String::from_utf8_lossy(&data));
+            if len > count {
+                format!("{}...", string)
+            } else {
+                string
+            }
+        }
+        None => format!("<cannot access memory at {:#x}>", addr),
+    }
+}
+
+impl Default for Printers {
+    fn default() -> Self {
+        Printers::new()
+    }
+}
+
+/// Finds where a `Vec`'s buffer is, what it holds, and how many elements it has. The buffer
+/// pointer is buried a few structures deep (`buf.ptr.pointer`, depending on the Rust version), so
+/// take the first pointer in `buf`.
+fn vec_parts<'a>(ty: &'a Type, bytes: &[u8]) -> Option<(usize, &'a Type, usize)> {
+    fn find_pointer(ty: &Type, offset: usize) -> Option<(&Type, usize)> {
+        if ty.kind == TypeKind::Pointer {
+            return Some((ty, offset));
+        }
+        ty.members
+            .iter()
+            .find_map(|member| find_pointer(&member.entity_type, offset + member.offset))
+    }
+    let buf = ty.member("buf")?;
+    let (pointer, offset) = find_pointer(&buf.entity_type, buf.offset)?;
+    let addr = to_uint(bytes.get(offset..offset + pointer.size)?) as usize;
+    let element: &Type = pointer.pointee.as_ref()?;
+    Some((addr, element, field_uint(ty, bytes, "len")?))
+}
+
+/// `&str`, shown as a quoted string.
+struct RustStr;
+
+impl PrettyPrinter for RustStr {
+    fn describe(&self) -> String {
+        "&str (built in)".to_string()
+    }
+
+    fn matches(&self, type_name: &str) -> bool {
+        type_name == "&str" || type_name == "&mut str"
+    }
+
+    fn format(&self, ty: &Type, bytes: &[u8], _: &Printers, read: ReadMemory) -> Option<String> {
+        let addr = field_uint(ty, bytes, "data_ptr")?;
+        Some(format_string(addr, field_uint(ty, bytes, "length")?, read))
+    }
+}
+
+/// `&[T]`, shown by its elements.
+struct RustSlice;
+
+impl PrettyPrinter for RustSlice {
+    fn describe(&self) -> String {
+        "&[T] (built in)".to_string()
+    }
+
+    fn matches(&self, type_name: &str) -> bool {
+        type_name.starts_with("&[") || type_name.starts_with("&mut [")
+    }
+
+    fn format(
+        &self,
+        ty: &Type,
+        bytes: &[u8],
+        printers: &Printers,
+        read: ReadMemory,
+    ) -> Option<String> {
+        let element = ty.member("data_ptr")?.entity_type.pointee.as_ref()?;
+        let addr = field_uint(ty, bytes, "data_ptr")?;
+        let len = field_uint(ty, bytes, "length")?;
+        Some(printers.format_elements(element, addr, len, read))
+    }
+}
+
+/// `String`, shown as a quoted string.
+struct RustString;
+
+impl PrettyPrinter for RustString {
+    fn describe(&self) -> String {
+        "alloc::string::String (built in)".to_string()
+    }
+
+    fn matches(&self, type_name: &str) -> bool {
+        type_name == "alloc::string::String"
+    }
+
+    fn format(&self, ty: &Type, bytes: &[u8], _: &Printers, read: ReadMemory) -> Option<String> {
+        let vec = ty.member("vec")?;
+        let (addr, _, len) = vec_parts(&vec.entity_type, member_bytes(vec, bytes)?)?;
+        Some(format_string(addr, len, read))
+    }
+}
+
+/// `Vec<T>`, shown by its elements.
+struct RustVec;
+
+impl PrettyPrinter for RustVec {
+    fn describe(&self) -> String {
+        "alloc::vec::Vec<T> (built in)".to_string()
+    }
+
+    fn matches(&self, type_name: &str) -> bool {
+        type_name.starts_with("alloc::vec::Vec<")
+    }
+
+    fn format(
+        &self,
+        ty: &Type,
+        bytes: &[u8],
+        printers: &Printers,
+        read: ReadMemory,
+    ) -> Option<String> {
+        let (addr, element, len) = vec_parts(ty, bytes)?;
+        Some(printers.format_elements(element, addr, len, read))
+    }
+}
+
+/// A printer from the config file, which fills fields into a template.
+struct TemplatePrinter {
+    type_name: String,
+    template: String,
+}
+
+impl TemplatePrinter {
+    /// Renders a `{path}` or `{*path}` placeholder.
+    fn field(
+        &self,
+        placeholder: &str,
+        ty: &Type,
+        bytes: &[u8],
+        printers: &Printers,
+        read: ReadMemory,
+    ) -> Option<String> {
+        let (deref, path) = if placeholder.starts_with('*') {
+            (true, &placeholder[1..])
+        } else {
+            (false, placeholder)
+        };
+        let mut field_type = ty;
+        let mut offset = 0;
+        for name in path.split('.') {
+            let member = field_type.member(name.trim())?;
+            offset += member.offset;
+            field_type = &member.entity_type;
+        }
+        let field_bytes = bytes.get(offset..offset + field_type.size)?;
+        if !deref {
+            return Some(printers.format_value(field_type, field_bytes, read));
+        }
+        let addr = to_uint(field_bytes) as usize;
+        if addr == 0 {
+            return Some("0x0".to_string());
+        }
+        // Linked structures end once pointers stop being followed (see `gimli_wrapper`)
+        Some(match &field_type.pointee {
+            Some(pointee) => match read(addr, pointee.size) {
+                Some(pointee_bytes) => printers.format_value(pointee, &pointee_bytes, read),
+                None => format!("<cannot access memory at {:#x}>", addr),
+            },
+            None => format!("{:#x}", addr),
+        })
+    }
+}
+
+impl PrettyPrinter for TemplatePrinter {
+    fn describe(&self) -> String {
+        format!("{} = {}", self.type_name, self.template)
+    }
+
+    fn matches(&self, type_name: &str) -> bool {
+        demangle::name_matches(type_name, &self.type_name)
+    }
+
+    fn format(
+        &self,
+        ty: &Type,
+        bytes: &[u8],
+        printers: &Printers,
+        read: ReadMemory,
+    ) -> Option<String> {
+        let mut value = String::new();
+        let mut rest = self.template.as_str();
+        while let Some(start) = rest.find('{') {
+            let end = start + rest[start..].find('}')?;
+            value.push_str(&rest[..start]);
+            value.push_str(&self.field(&rest[start + 1..end], ty, bytes, printers, read)?);
+            rest = &rest[end + 1..];
+        }
+        value.push_str(rest);
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    /// `struct list_node { int value; struct list_node *next; }`, with `next` followed `depth`
+    /// times.
+    fn list_node(depth: usize) -> Type {
+        let mut next = Type::new("struct list_node *".to_string(), 8);
+        next.kind = TypeKind::Pointer;
+        if depth > 0 {
+            next.pointee = Some(Rc::new(list_node(depth - 1)));
+        }
+        let mut node = Type::new("struct list_node".to_string(), 16);
+        node.kind = TypeKind::Struct;
+        node.members = vec![
+            Member {
+                name: "value".to_string(),
+                offset: 0,
+                entity_type: Rc::new(Type::new("int".to_string(), 4)),
+            },
+            Member {
+                name: "next".to_string(),
+                offset: 8,
+                entity_type: Rc::new(next),
+            },
+        ];
+        node
+    }
+
+    fn node_bytes(value: i32, next: usize) -> Vec<u8> {
+        let mut bytes = value.to_le_bytes().to_vec();
+        bytes.resize(8, 0);
+        bytes.extend_from_slice(&(next as u64).to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn templates_follow_pointers() {
+        let mut printers = Printers::new();
+        printers
+            .load("# comment\n\nstruct list_node = {value} -> {*next}\n")
+            .unwrap();
+        let read = |addr, len| match addr {
+            0x1000 => Some(node_bytes(2, 0)[..len].to_vec()),
+            _ => None,
+        };
+        let ty = list_node(2);
+        assert_eq!(
+            printers.format_value(&ty, &node_bytes(1, 0x1000), &read),
+            "1 -> 2 -> 0x0"
+        );
+        assert_eq!(
+            printers.format_value(&ty, &node_bytes(1, 0x2000), &read),
+            "1 -> <cannot access memory at 0x2000>"
+        );
+        // Without the pointee's type, the pointer is all there is to show
+        assert_eq!(
+            printers.format_value(&list_node(0), &node_bytes(1, 0x1000), &read),
+            "1 -> 0x1000"
+        );
+    }
+
+    #[test]
+    fn structures_without_a_printer_show_their_fields() {
+        let printers = Printers::new();
+        let read = |_, _| None;
+        assert_eq!(
+            printers.format_value(&list_node(0), &node_bytes(-1, 0), &read),
+            "{value = -1, next = 0x0}"
+        );
+    }
+
+    #[test]
+    fn bad_config_lines_are_reported() {
+        let mut printers = Printers::new();
+        assert_eq!(
+            printers.load("struct a = {x}\nstruct b {y}\n"),
+            Err("line 2: expected <type name> = <template>".to_string())
+        );
+        assert_eq!(printers.describe()[0], "struct a = {x}");
+    }
+}
@@ -0,0 +1,202 @@
+//! Execution recording, for reverse execution. While recording, the inferior only ever moves
+//! forward one instruction at a time, and each step logs the registers from before it plus the old
+//! contents of every memory word it changed. Undoing a step restores both.
+//!
+//! Memory changes are found by diffing the writable mappings against a copy taken after the
+//! previous step, which is slow but catches everything, including writes made by system calls.
+
+use crate::inferior::read_mappings;
+use libc::user_regs_struct;
+use nix::unistd::Pid;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fs;
+use std::io;
+use std::mem::size_of;
+use std::os::unix::fs::FileExt;
+
+/// How the inferior's state was changed by one instruction.
+pub struct RecordedStep {
+    /// Registers from before the instruction executed.
+    pub regs: user_regs_struct,
+    /// (address, old value) of each word the instruction overwrote.
+    pub memory: Vec<(usize, u64)>,
+}
+
+/// The outcome of moving backwards through the recording.
+pub enum ReplayStatus {
+    /// Stopped at the given address.
+    Stopped(usize),
+    /// Stopped at the given address because that's where the recording began.
+    NoMoreHistory(usize),
+}
+
+pub struct Recorder {
+    /// Contents of each writable mapping as of the last recorded step, keyed by start address.
+    shadow: BTreeMap<usize, Vec<u8>>,
+    log: Vec<RecordedStep>,
+}
+
+/// Reads every writable mapping of the process.
+fn snapshot(pid: Pid) -> io::Result<BTreeMap<usize, Vec<u8>>> {
+    let mem = fs::File::open(format!("/proc/{}/mem", pid))?;
+    let mut regions = BTreeMap::new();
+    for mapping in read_mappings(pid)? {
+        if !mapping.readable() || !mapping.perms.contains('w') {
+            continue;
+        }
+        let mut data = vec![0u8; mapping.end - mapping.start];
+        if mem.read_exact_at(&mut data, mapping.start as u64).is_ok() {
+            regions.insert(mapping.start, data);
+        }
+    }
+    Ok(regions)
+}
+
+fn word_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + size_of::<u64>()].try_into().unwrap())
+}
+
+impl Recorder {
+    pub fn new(pid: Pid) -> io::Result<Recorder> {
+        Ok(Recorder {
+            shadow: snapshot(pid)?,
+            log: Vec::new(),
+        })
+    }
+
+    /// Number of instructions that can be undone.
+    pub fn num_steps(&self) -> usize {
+        self.log.len()
+    }
+
+    /// Logs the instruction that just executed, given the registers from before it.
+    pub fn record(&mut self, pid: Pid, regs: user_regs_struct) -> io::Result<()> {
+        let current = snapshot(pid)?;
+        let mut memory = Vec::new();
+        for (start, data) in &current {
+            // Memory that didn't exist before (e.g. the heap growing) has no old value to restore
+            let old = match self.shadow.get(start) {
+                Some(old) => old,
+                None => continue,
+            };
+            let len = old.len().min(data.len());
+            for offset in (0..len).step_by(size_of::<u64>()) {
+                let old_word = word_at(old, offset);
+                if old_word != word_at(data, offset) {
+                    memory.push((start + offset, old_word));
+                }
+            }
+        }
+        self.shadow = current;
+        self.log.push(RecordedStep { regs, memory });
+        Ok(())
+    }
+
+    /// Registers from before the most recently recorded instruction.
+    pub fn peek(&self) -> Option<&user_regs_struct> {
+        self.log.last().map(|step| &step.regs)
+    }
+
+    /// Removes the most recent step from the log. The caller must write its old state back into
+    /// the inferior.
+    pub fn pop(&mut self) -> Option<RecordedStep> {
+        let step = self.log.pop()?;
+        // Keep the shadow in sync with what the inferior's memory is about to become
+        for (addr, old_word) in &step.memory {
+            if let Some((start, data)) = self.shadow.range_mut(..=*addr).next_back() {
+                let offset = addr - start;
+                if offset + size_of::<u64>() <= data.len() {
+                    data[offset..offset + size_of::<u64>()]
+                        .copy_from_slice(&old_word.to_le_bytes());
+                }
+            }
+        }
+        Some(step)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inferior::{Breakpoint, Inferior, Status};
+    use crate::target::{regs_to_words, Target};
+    use crate::testing;
+    use std::collections::HashMap;
+
+    /// Stops function_calls at `sum = a + b` in func2 and starts recording there. Returns the
+    /// breakpoint's address.
+    fn record_from_sum(
+        debug_data: &crate::dwarf_data::DwarfData,
+        breakpoints: &mut HashMap<usize, Breakpoint>,
+    ) -> (Inferior, usize) {
+        let addr = debug_data.get_addr_for_line(None, 16).unwrap();
+        breakpoints.insert(addr, Breakpoint::pending(false));
+        let mut inferior = testing::spawn("function_calls", breakpoints);
+        match inferior.continue_run(None, breakpoints).unwrap() {
+            Status::Stopped(_, rip) => assert_eq!(rip, addr),
+            _ => panic!("expected to stop in func2"),
+        }
+        inferior.start_recording().unwrap();
+        (inferior, addr)
+    }
+
+    fn stack(inferior: &Inferior) -> Vec<u64> {
+        let rsp = inferior.registers().unwrap().rsp as usize;
+        (rsp - 64..rsp + 64)
+            .step_by(8)
+            .map(|addr| inferior.read_word(addr).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn reverse_continue_restores_registers_and_memory() {
+        let debug_data = testing::debug_data("function_calls");
+        let mut breakpoints = HashMap::new();
+        let (mut inferior, addr) = record_from_sum(&debug_data, &mut breakpoints);
+        let regs = inferior.registers().unwrap();
+        let stack_before = stack(&inferior);
+
+        inferior
+            .step_line(&debug_data, &mut breakpoints, false)
+            .unwrap();
+        let line = debug_data
+            .get_line_from_addr(inferior.rip().unwrap())
+            .unwrap();
+        assert_eq!(line.number, 17);
+        assert!(inferior.recorded_steps().unwrap() > 0);
+
+        match inferior.reverse_continue(&breakpoints).unwrap() {
+            ReplayStatus::Stopped(rip) => assert_eq!(rip, addr),
+            ReplayStatus::NoMoreHistory(_) => panic!("expected to stop at the breakpoint"),
+        }
+        assert_eq!(
+            regs_to_words(&inferior.registers().unwrap()),
+            regs_to_words(&regs)
+        );
+        assert_eq!(stack(&inferior), stack_before);
+        assert_eq!(inferior.recorded_steps(), Some(0));
+        assert_eq!(inferior.reverse_step_instruction().unwrap(), None);
+        inferior.kill();
+    }
+
+    #[test]
+    fn reverse_next_goes_back_a_line() {
+        let debug_data = testing::debug_data("function_calls");
+        let mut breakpoints = HashMap::new();
+        let (mut inferior, addr) = record_from_sum(&debug_data, &mut breakpoints);
+
+        inferior
+            .step_line(&debug_data, &mut breakpoints, false)
+            .unwrap();
+        match inferior.reverse_next(&debug_data).unwrap() {
+            ReplayStatus::Stopped(rip) => assert_eq!(rip, addr),
+            ReplayStatus::NoMoreHistory(_) => panic!("expected to stop at the start of line 16"),
+        }
+        match inferior.reverse_next(&debug_data).unwrap() {
+            ReplayStatus::NoMoreHistory(rip) => assert_eq!(rip, addr),
+            ReplayStatus::Stopped(_) => panic!("expected to run out of history"),
+        }
+        inferior.kill();
+    }
+}
@@ -0,0 +1,1450 @@
+//! The debugger without a user interface. A `Session` starts or attaches to a program, moves it
+//! along and inspects it, and returns what happened instead of printing it, so that the deet
+//! command line and test harnesses alike can sit on top of it.
+//!
+//! ```no_run
+//! use qeet::{Session, Step, Stop};
+//!
+//! let mut session = Session::new("samples/function_calls")?;
+//! session.set_breakpoint("func2")?;
+//! session.spawn()?;
+//! while let Stop::Breakpoint(_) = session.resume()? {
+//!     println!("{:?}", session.evaluate("a"));
+//!     session.step(Step::Over)?;
+//! }
+//! # Ok::<(), qeet::Error>(())
+//! ```
+
+use crate::core_file::{self, CoreFile};
+use crate::dwarf_data::{DwarfData, Error as DwarfError, Type, TypeKind, Variable};
+use crate::dwarf_expr;
+use crate::error::Error;
+use crate::heap::HeapTracker;
+use crate::inferior::{
+    Breakpoint, CallFrame, Catchpoint, Checkpoint, Event, Inferior, Mapping, Status, WatchKind,
+    Watchpoint,
+};
+use crate::launch::Launch;
+use crate::pretty::Printers;
+use crate::record::ReplayStatus;
+use crate::target::{regs_to_words, Frame, Target, REGISTER_NAMES};
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
+use std::collections::HashMap;
+use std::process::{ChildStderr, ChildStdout};
+
+/// Where the program is after being resumed or stepped, or how it ended.
+#[derive(Debug, Clone)]
+pub enum Stop {
+    /// The program exited with this status.
+    Exited(i32),
+    /// The program was killed by a signal.
+    Signaled(Signal),
+    /// The program is at the breakpoint at this address.
+    Breakpoint(usize),
+    /// A step finished at this address.
+    Stepped(usize),
+    /// The program received a signal, which it gets when it is resumed. Contains the signal and
+    /// where the program is.
+    Signal(Signal, usize),
+    /// A catchpoint or watchpoint triggered, or the heap checker found a bad free.
+    Event(Event, usize),
+    /// A function called with `call` returned, and the program is back where it was before the
+    /// call. Contains the call as written, its return value (None if the function returns
+    /// void), and where the program is.
+    Returned(String, Option<String>, usize),
+}
+
+impl Stop {
+    /// Classifies how the inferior stopped. A SIGTRAP is a breakpoint if there is one where the
+    /// program is, and otherwise the end of a step if it was `stepping`.
+    pub fn from_status(
+        status: Status,
+        breakpoints: &HashMap<usize, Breakpoint>,
+        stepping: bool,
+    ) -> Stop {
+        match status {
+            Status::Exited(exit_code) => Stop::Exited(exit_code),
+            Status::Signaled(signal) => Stop::Signaled(signal),
+            Status::Stopped(Signal::SIGTRAP, rip) if breakpoints.contains_key(&rip) => {
+                Stop::Breakpoint(rip)
+            }
+            Status::Stopped(Signal::SIGTRAP, rip) if stepping => Stop::Stepped(rip),
+            Status::Stopped(signal, rip) => Stop::Signal(signal, rip),
+            Status::Caught(event, rip) => Stop::Event(event, rip),
+        }
+    }
+
+    /// The signal the program should get when it is resumed. The debugger's own SIGTRAPs are
+    /// not passed on.
+    pub fn pending_signal(&self) -> Option<Signal> {
+        match self {
+            Stop::Signal(signal, _) if *signal != Signal::SIGTRAP => Some(*signal),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Step {
+    /// To the next source line, stepping over calls.
+    Over,
+    /// To the next source line, into calls that have line information.
+    Into,
+    Instruction,
+}
+
+/// A breakpoint, watchpoint or catchpoint in the session's numbered table, which `delete`
+/// removes from.
+pub enum BreakpointEntry {
+    /// A breakpoint or temporary breakpoint, by its address.
+    Code(usize),
+    Watch(Watchpoint),
+    Catch(Catchpoint),
+}
+
+/// A function called with `call` that stopped before returning.
+struct PendingCall {
+    expr: String,
+    return_type: Option<Type>,
+    frame: CallFrame,
+}
+
+pub struct Session {
+    target: String,
+    debug_data: DwarfData,
+    inferior: Option<Inferior>,
+    /// Whether the process was attached to rather than spawned, so that it is detached from
+    /// instead of killed when the session is done with it.
+    attached: bool,
+    /// A core file being examined in place of a live process.
+    core: Option<CoreFile>,
+    breakpoints: HashMap<usize, Breakpoint>,
+    /// The breakpoints, watchpoints and catchpoints that were set, by number. Breakpoints the
+    /// session plants for itself (e.g. for `run_until`) are only in `breakpoints`.
+    table: Vec<(usize, BreakpointEntry)>,
+    next_breakpoint: usize,
+    /// Expressions shown each time the program stops, by number.
+    displays: Vec<(usize, String)>,
+    next_display: usize,
+    /// Copies of the program made by `checkpoint`, by checkpoint number.
+    checkpoints: Vec<(usize, Checkpoint)>,
+    next_checkpoint: usize,
+    /// Calls made with `call` that are still running, innermost last.
+    calls: Vec<PendingCall>,
+    /// The heap as the program left it when it exited, for reporting leaks.
+    exited_heap: Option<HeapTracker>,
+    /// A signal the program stopped with, to be delivered when it is resumed.
+    pending_signal: Option<Signal>,
+    warnings: Vec<String>,
+    /// How `evaluate` and `read_variable` format values.
+    pub printers: Printers,
+    /// The arguments, environment and so on that `spawn` starts the program with.
+    pub launch: Launch,
+    /// Whether `spawn` tracks the program's allocations, stopping with `Event::HeapError` at
+    /// bad frees. `heap` has what it hasn't freed.
+    pub check_memory: bool,
+}
+
+impl Session {
+    /// Loads the program's debugging information. Nothing runs until `spawn` or `attach`.
+    pub fn new(target: &str) -> Result<Session, Error> {
+        let mut debug_data = DwarfData::from_file(target).map_err(|err| match err {
+            DwarfError::ErrorOpeningFile => {
+                Error::DebugInfo(format!("Could not open file {}", target))
+            }
+            DwarfError::DwarfFormatError(err) => Error::DebugInfo(format!(
+                "Could not load debugging symbols from {}: {:?}",
+                target, err
+            )),
+        })?;
+        // e.g. split DWARF files that are missing
+        let warnings = debug_data.take_warnings();
+        Ok(Session {
+            target: target.to_string(),
+            debug_data,
+            inferior: None,
+            attached: false,
+            core: None,
+            breakpoints: HashMap::new(),
+            table: Vec::new(),
+            next_breakpoint: 0,
+            displays: Vec::new(),
+            next_display: 1,
+            checkpoints: Vec::new(),
+            next_checkpoint: 1,
+            calls: Vec::new(),
+            exited_heap: None,
+            pending_signal: None,
+            warnings,
+            printers: Printers::new(),
+            launch: Launch::new(),
+            check_memory: false,
+        })
+    }
+
+    pub fn debug_data(&self) -> &DwarfData {
+        &self.debug_data
+    }
+
+    /// The process being debugged, if there is one.
+    pub fn pid(&self) -> Option<Pid> {
+        self.inferior.as_ref().map(|inferior| inferior.pid())
+    }
+
+    /// What inspection reads from: the process if there is one, otherwise the core file loaded
+    /// with `load_core`.
+    pub fn target(&self) -> Option<&dyn Target> {
+        match (&self.inferior, &self.core) {
+            (Some(inferior), _) => Some(inferior as &dyn Target),
+            (None, Some(core)) => Some(core as &dyn Target),
+            (None, None) => None,
+        }
+    }
+
+    /// Every breakpoint written into the program, including the session's own, by address.
+    pub fn breakpoints(&self) -> &HashMap<usize, Breakpoint> {
+        &self.breakpoints
+    }
+
+    /// Loads a core file of the program to examine until a process is started.
+    pub fn load_core(&mut self, path: &str) -> Result<&CoreFile, core_file::Error> {
+        let core = CoreFile::open(path, &self.target)?;
+        self.core = None;
+        Ok(self.core.get_or_insert(core))
+    }
+
+    /// Starts the program as set up in `launch`, stopped before its first instruction. A process
+    /// the session already has is killed (or detached from) first, and the checkpoints of the
+    /// last run are dropped.
+    pub fn spawn(&mut self) -> Result<(), Error> {
+        self.kill();
+        self.kill_checkpoints();
+        self.core = None;
+        self.exited_heap = None;
+        let cmd = self.launch.command(&self.target).map_err(Error::Spawn)?;
+        let mut inferior = Inferior::spawn(cmd, &mut self.breakpoints)
+            .ok_or_else(|| Error::Spawn(format!("Error starting subprocess {}", self.target)))?;
+        if self.check_memory {
+            if let Err(err) = inferior.track_heap(&self.breakpoints) {
+                self.warnings
+                    .push(format!("Could not check memory: {}", err));
+            }
+        }
+        self.adopt(inferior);
+        self.attached = false;
+        Ok(())
+    }
+
+    /// Starts debugging a running process, which stops it.
+    pub fn attach(&mut self, pid: Pid) -> Result<(), Error> {
+        self.kill();
+        let inferior = Inferior::attach(pid, &mut self.breakpoints)?;
+        self.adopt(inferior);
+        self.attached = true;
+        Ok(())
+    }
+
+    /// Takes on a process that has just been started, attached to or restarted, setting the
+    /// catchpoints and watchpoints in it.
+    fn adopt(&mut self, mut inferior: Inferior) {
+        self.warnings.extend(inferior.take_warnings());
+        inferior.set_catchpoints(&catchpoint_list(&self.table));
+        // Watched at the same addresses, which stay put between runs while address space
+        // randomization is off
+        for (number, entry) in &self.table {
+            if let BreakpointEntry::Watch(watch) = entry {
+                if let Err(err) = inferior.add_watchpoint(watch) {
+                    self.warnings
+                        .push(format!("Could not set watchpoint {}: {}", number, err));
+                }
+            }
+        }
+        self.inferior = Some(inferior);
+        self.pending_signal = None;
+        self.calls.clear();
+    }
+
+    /// Lets an attached process carry on without the debugger. A spawned one is killed instead.
+    pub fn detach(&mut self) -> Result<(), Error> {
+        if !self.attached {
+            self.kill();
+            return Ok(());
+        }
+        self.attached = false;
+        self.pending_signal = None;
+        self.calls.clear();
+        match self.inferior.take() {
+            Some(inferior) => Ok(inferior.detach(&self.breakpoints)?),
+            None => Ok(()),
+        }
+    }
+
+    /// Kills a spawned program. An attached process is detached from instead.
+    pub fn kill(&mut self) {
+        if self.attached {
+            let _ = self.detach();
+            return;
+        }
+        if let Some(mut inferior) = self.inferior.take() {
+            inferior.kill();
+        }
+        self.pending_signal = None;
+        self.calls.clear();
+    }
+
+    fn kill_checkpoints(&mut self) {
+        for (_, checkpoint) in self.checkpoints.drain(..) {
+            checkpoint.kill();
+        }
+    }
+
+    /// Resolves a location as accepted by `set_breakpoint` to a single address.
+    pub fn resolve_location(&self, location: &str) -> Result<usize, Error> {
+        resolve_location(&self.debug_data, &self.target, location).map_err(Error::Location)
+    }
+
+    /// Resolves a location as accepted by `set_breakpoint` to every address it stands for.
+    pub fn breakpoint_addresses(&self, location: &str) -> Result<Vec<usize>, Error> {
+        breakpoint_addresses(&self.debug_data, &self.target, location).map_err(Error::Location)
+    }
+
+    /// Sets a breakpoint at `*address`, a line number in the program's main source file or
+    /// `file:line`, or a function. Returns the addresses it was set at: a function gets one on every copy of it
+    /// that was inlined, too.
+    pub fn set_breakpoint(&mut self, location: &str) -> Result<Vec<usize>, Error> {
+        let addrs = self.breakpoint_addresses(location)?;
+        for &addr in &addrs {
+            self.add_breakpoint(addr, false)?;
+        }
+        Ok(addrs)
+    }
+
+    /// Sets a breakpoint at `addr` and returns its number, or the number of the one already
+    /// there. A temporary breakpoint is deleted the first time the program stops at it.
+    pub fn add_breakpoint(&mut self, addr: usize, temporary: bool) -> Result<usize, Error> {
+        if let Some(number) = self.breakpoint_number(addr) {
+            return Ok(number);
+        }
+        match self.inferior.as_mut() {
+            Some(inferior) => inferior.insert_breakpoint(addr, temporary, &mut self.breakpoints)?,
+            // installed when the program is started
+            None => {
+                self.breakpoints
+                    .entry(addr)
+                    .and_modify(|bp| bp.temporary &= temporary)
+                    .or_insert_with(|| Breakpoint::pending(temporary));
+            }
+        }
+        let number = self.next_breakpoint;
+        self.next_breakpoint += 1;
+        self.table.push((number, BreakpointEntry::Code(addr)));
+        Ok(number)
+    }
+
+    /// Returns the number of the breakpoint at `addr`, if one was set there.
+    pub fn breakpoint_number(&self, addr: usize) -> Option<usize> {
+        self.table.iter().find_map(|(number, entry)| match entry {
+            BreakpointEntry::Code(at) if *at == addr => Some(*number),
+            _ => None,
+        })
+    }
+
+    /// The breakpoints and watchpoints that were set, in the order they were set.
+    pub fn breakpoint_table(&self) -> &[(usize, BreakpointEntry)] {
+        &self.table
+    }
+
+    /// Removes the breakpoint at `addr`.
+    pub fn remove_breakpoint(&mut self, addr: usize) -> Result<(), Error> {
+        self.unplant(addr)?;
+        self.table.retain(|(_, entry)| match entry {
+            BreakpointEntry::Code(at) => *at != addr,
+            _ => true,
+        });
+        Ok(())
+    }
+
+    /// Deletes the breakpoint, watchpoint or catchpoint with this number.
+    pub fn delete(&mut self, number: usize) -> Result<(), Error> {
+        let index = self
+            .table
+            .iter()
+            .position(|(n, _)| *n == number)
+            .ok_or(Error::NoBreakpoint(number))?;
+        match self.table[index].1 {
+            BreakpointEntry::Code(addr) => self.unplant(addr)?,
+            BreakpointEntry::Watch(_) => {
+                if let Some(inferior) = self.inferior.as_mut() {
+                    inferior.remove_watchpoint(number)?;
+                }
+            }
+            BreakpointEntry::Catch(_) => {}
+        }
+        self.table.remove(index);
+        if let Some(inferior) = self.inferior.as_mut() {
+            inferior.set_catchpoints(&catchpoint_list(&self.table));
+        }
+        Ok(())
+    }
+
+    /// Takes the breakpoint at `addr` out of the program, leaving the table alone.
+    fn unplant(&mut self, addr: usize) -> Result<(), Error> {
+        match self.inferior.as_mut() {
+            Some(inferior) => inferior.remove_breakpoint(addr, &mut self.breakpoints)?,
+            None => {
+                self.breakpoints.remove(&addr);
+            }
+        }
+        Ok(())
+    }
+
+    /// Watches a variable visible where the program is stopped, so that it stops when the
+    /// variable changes. Returns the watchpoint's number, and whether it uses a debug register
+    /// or has the program single-stepped.
+    pub fn watch(&mut self, expr: &str) -> Result<(usize, WatchKind), Error> {
+        let inferior = self.inferior.as_mut().ok_or(Error::NotRunning)?;
+        let frame = match inferior.backtrace(&self.debug_data) {
+            Ok(mut frames) if !frames.is_empty() => frames.remove(0),
+            _ => return Err(Error::Evaluate("No frame selected.".to_string())),
+        };
+        let var = lookup_variable(&self.debug_data, &frame, expr).ok_or_else(|| {
+            Error::Evaluate(format!("No symbol \"{}\" in current context.", expr))
+        })?;
+        let addr = match inferior.variable_address(var, &frame) {
+            Some(addr) if var.entity_type.size > 0 => addr,
+            _ => {
+                return Err(Error::Evaluate(format!(
+                    "Cannot watch \"{}\", which isn't in memory.",
+                    expr
+                )))
+            }
+        };
+        let watch = Watchpoint {
+            number: self.next_breakpoint,
+            expr: expr.to_string(),
+            addr,
+            ty: var.entity_type.clone(),
+        };
+        let kind = inferior.add_watchpoint(&watch)?;
+        let number = watch.number;
+        self.next_breakpoint += 1;
+        self.table.push((number, BreakpointEntry::Watch(watch)));
+        Ok((number, kind))
+    }
+
+    /// How watchpoint `number` is watched in the process, if it is set there.
+    pub fn watch_kind(&self, number: usize) -> Option<WatchKind> {
+        self.inferior.as_ref()?.watch_kind(number)
+    }
+
+    /// Makes the program stop for an event, as well as at breakpoints. Returns the catchpoint's
+    /// number, which is numbered along with the breakpoints.
+    pub fn add_catchpoint(&mut self, catchpoint: Catchpoint) -> usize {
+        let number = self.next_breakpoint;
+        self.next_breakpoint += 1;
+        self.table
+            .push((number, BreakpointEntry::Catch(catchpoint)));
+        if let Some(inferior) = self.inferior.as_mut() {
+            inferior.set_catchpoints(&catchpoint_list(&self.table));
+        }
+        number
+    }
+
+    /// The catchpoints that were set, with their numbers.
+    pub fn catchpoints(&self) -> impl Iterator<Item = (usize, &Catchpoint)> {
+        self.table.iter().filter_map(|(number, entry)| match entry {
+            BreakpointEntry::Catch(catch) => Some((*number, catch)),
+            _ => None,
+        })
+    }
+
+    /// Adds an expression to show each time the program stops. Returns its number.
+    pub fn add_display(&mut self, expr: &str) -> usize {
+        let number = self.next_display;
+        self.next_display += 1;
+        self.displays.push((number, expr.to_string()));
+        number
+    }
+
+    /// Stops showing the display with this number.
+    pub fn undisplay(&mut self, number: usize) -> Result<(), Error> {
+        let index = self
+            .displays
+            .iter()
+            .position(|(n, _)| *n == number)
+            .ok_or(Error::NoDisplay(number))?;
+        self.displays.remove(index);
+        Ok(())
+    }
+
+    /// The display expressions, in the order they were added.
+    pub fn displays(&self) -> &[(usize, String)] {
+        &self.displays
+    }
+
+    /// Evaluates the display expressions where the program is stopped, or just display `only`.
+    /// Expressions that can't be evaluated here, such as another function's locals, are left out.
+    pub fn display_values(&self, only: Option<usize>) -> Vec<(usize, &str, String)> {
+        self.displays
+            .iter()
+            .filter(|(number, _)| only.map_or(true, |only| only == *number))
+            .filter_map(|(number, expr)| Some((*number, expr.as_str(), self.evaluate(expr).ok()?)))
+            .collect()
+    }
+
+    /// Continues the program until it stops or ends.
+    pub fn resume(&mut self) -> Result<Stop, Error> {
+        let signal = self.pending_signal.take();
+        let inferior = self.inferior.as_mut().ok_or(Error::NotRunning)?;
+        match inferior.continue_run(signal, &self.breakpoints) {
+            Ok(status) => self.stopped(status, false),
+            Err(err) => Err(self.failed(err)),
+        }
+    }
+
+    pub fn step(&mut self, step: Step) -> Result<Stop, Error> {
+        let inferior = self.inferior.as_mut().ok_or(Error::NotRunning)?;
+        let status = match step {
+            Step::Over => inferior.step_line(&self.debug_data, &mut self.breakpoints, false),
+            Step::Into => inferior.step_line(&self.debug_data, &mut self.breakpoints, true),
+            Step::Instruction => inferior.step_instruction(&self.breakpoints),
+        };
+        match status {
+            Ok(status) => self.stopped(status, true),
+            Err(err) => Err(self.failed(err)),
+        }
+    }
+
+    /// Continues until the program reaches `location` or the current function returns,
+    /// whichever comes first.
+    pub fn run_until(&mut self, location: &str) -> Result<Stop, Error> {
+        if self.inferior.is_none() {
+            return Err(Error::NotRunning);
+        }
+        let addr = self.resolve_location(location)?;
+        let inferior = self.inferior.as_mut().ok_or(Error::NotRunning)?;
+        // The caller's frame is where the current function returns to
+        let ret_addr = inferior
+            .backtrace(&self.debug_data)
+            .ok()
+            .and_then(|frames| frames.into_iter().filter(|frame| !frame.inlined).nth(1))
+            .map(|frame| frame.rip);
+        let mut planted = Vec::new();
+        for addr in std::iter::once(addr).chain(ret_addr) {
+            if !self.breakpoints.contains_key(&addr)
+                && inferior
+                    .insert_breakpoint(addr, true, &mut self.breakpoints)
+                    .is_ok()
+            {
+                planted.push(addr);
+            }
+        }
+        let stop = self.resume();
+        for addr in planted {
+            let _ = self.unplant(addr);
+        }
+        stop
+    }
+
+    /// Starts recording the program's execution, so that it can be run backwards. The program is
+    /// single-stepped while it is recorded.
+    pub fn start_recording(&mut self) -> Result<(), Error> {
+        let inferior = self.inferior.as_mut().ok_or(Error::NotRunning)?;
+        Ok(inferior.start_recording()?)
+    }
+
+    /// Stops recording and throws the recording away. Returns false if there wasn't one.
+    pub fn stop_recording(&mut self) -> bool {
+        match self.inferior.as_mut() {
+            Some(inferior) if inferior.recorded_steps().is_some() => {
+                inferior.stop_recording();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The number of instructions that can be run backwards, or None if not recording.
+    pub fn recorded_steps(&self) -> Option<usize> {
+        self.inferior.as_ref()?.recorded_steps()
+    }
+
+    /// Undoes the last instruction the program executed.
+    pub fn reverse_step_instruction(&mut self) -> Result<ReplayStatus, Error> {
+        let inferior = recording(&mut self.inferior)?;
+        let status = inferior.rip().and_then(|rip| {
+            Ok(match inferior.reverse_step_instruction()? {
+                Some(rip) => ReplayStatus::Stopped(rip),
+                None => ReplayStatus::NoMoreHistory(rip),
+            })
+        });
+        status.map_err(|err| self.failed(err))
+    }
+
+    /// Runs backwards until a breakpoint or the start of the recording.
+    pub fn reverse_continue(&mut self) -> Result<ReplayStatus, Error> {
+        let inferior = recording(&mut self.inferior)?;
+        let status = inferior.reverse_continue(&self.breakpoints);
+        status.map_err(|err| self.failed(err))
+    }
+
+    /// Runs backwards to the start of the previous line, passing over calls.
+    pub fn reverse_next(&mut self) -> Result<ReplayStatus, Error> {
+        let inferior = recording(&mut self.inferior)?;
+        let status = inferior.reverse_next(&self.debug_data);
+        status.map_err(|err| self.failed(err))
+    }
+
+    /// Forks a copy of the program as it is now, for `restart`. Returns the checkpoint's number
+    /// and the copy's pid.
+    pub fn checkpoint(&mut self) -> Result<(usize, Pid), Error> {
+        let inferior = self.inferior.as_ref().ok_or(Error::NotRunning)?;
+        let checkpoint = inferior.checkpoint(&self.breakpoints)?;
+        let (number, pid) = (self.next_checkpoint, checkpoint.pid);
+        self.next_checkpoint += 1;
+        self.checkpoints.push((number, checkpoint));
+        Ok((number, pid))
+    }
+
+    /// The checkpoints of this run, by number.
+    pub fn checkpoints(&self) -> &[(usize, Checkpoint)] {
+        &self.checkpoints
+    }
+
+    /// Carries on from a copy of checkpoint `number` in place of the process, leaving the
+    /// checkpoint as it was so that it can be restarted any number of times. Returns where the
+    /// program is.
+    pub fn restart(&mut self, number: usize) -> Result<usize, Error> {
+        let checkpoint = self
+            .checkpoints
+            .iter()
+            .find(|(n, _)| *n == number)
+            .map(|(_, checkpoint)| checkpoint)
+            .ok_or(Error::NoCheckpoint(number))?;
+        let inferior = Inferior::restart(checkpoint, &mut self.breakpoints)?;
+        self.kill();
+        self.adopt(inferior);
+        self.attached = false;
+        let inferior = self.inferior.as_ref().ok_or(Error::NotRunning)?;
+        inferior.rip()
+    }
+
+    /// Calls a function in the program, e.g. `strlen(s)`, with arguments as accepted by
+    /// `evaluate_argument`. Gives `Stop::Returned` once it has returned. If the program stops
+    /// before then (e.g. at a breakpoint), that stop is given instead, and the call is finished
+    /// when the program is resumed and the function returns.
+    pub fn call(&mut self, expr: &str) -> Result<Stop, Error> {
+        if self.inferior.is_none() {
+            return Err(Error::NotRunning);
+        }
+        let (name, args) = match (expr.find('('), expr.rfind(')')) {
+            (Some(open), Some(close)) if open < close => {
+                (expr[..open].trim(), split_arguments(&expr[open + 1..close]))
+            }
+            _ => return Err(Error::Evaluate(format!("Invalid function call {}", expr))),
+        };
+        let values = args
+            .iter()
+            .map(|arg| self.evaluate_argument(arg))
+            .collect::<Result<Vec<u64>, Error>>()?;
+        if values.len() > 6 {
+            return Err(Error::Evaluate(
+                "Calls with more than 6 arguments are not supported.".to_string(),
+            ));
+        }
+
+        let (addr, return_type) = if let Some(func) = self.debug_data.get_function(name) {
+            if func.parameters.len() != values.len() {
+                return Err(Error::Evaluate(format!(
+                    "Too {} arguments in function call.",
+                    if values.len() < func.parameters.len() {
+                        "few"
+                    } else {
+                        "many"
+                    }
+                )));
+            }
+            let is_float = |ty: &Type| ty.name == "float" || ty.name == "double";
+            if func
+                .parameters
+                .iter()
+                .any(|param| is_float(&param.entity_type))
+                || func.return_type.as_ref().map_or(false, is_float)
+            {
+                return Err(Error::Evaluate(
+                    "Calling functions with floating-point arguments or results is not supported."
+                        .to_string(),
+                ));
+            }
+            (func.address, func.return_type.clone())
+        } else {
+            let found = self
+                .inferior
+                .as_ref()
+                .and_then(|inferior| inferior.lookup_library_function(name));
+            match found {
+                // An indirect function returns the address of the implementation to use
+                Some((resolver, true)) => match self.run_call(name, resolver, &[], None)? {
+                    Ok(addr) => (addr as usize, Some(Type::new("long".to_string(), 8))),
+                    Err(stop) => return Ok(stop),
+                },
+                // Without debugging information, assume the function returns a long
+                Some((addr, false)) => (addr, Some(Type::new("long".to_string(), 8))),
+                None => {
+                    return Err(Error::Evaluate(format!(
+                        "No symbol \"{}\" in current context.",
+                        name
+                    )))
+                }
+            }
+        };
+
+        match self.run_call(expr, addr, &values, return_type.clone())? {
+            Ok(rax) => {
+                let inferior = self.inferior.as_ref().ok_or(Error::NotRunning)?;
+                let value = return_type.map(|ty| format_return_value(&ty, rax));
+                Ok(Stop::Returned(expr.to_string(), value, inferior.rip()?))
+            }
+            Err(stop) => Ok(stop),
+        }
+    }
+
+    /// Runs the function at `addr` until it returns, giving its %rax. If the program stops
+    /// before then, the call is left pending and the stop is given instead.
+    fn run_call(
+        &mut self,
+        expr: &str,
+        addr: usize,
+        args: &[u64],
+        return_type: Option<Type>,
+    ) -> Result<Result<u64, Stop>, Error> {
+        let inferior = self.inferior.as_mut().ok_or(Error::NotRunning)?;
+        let frame = inferior.start_call(addr, args, &mut self.breakpoints)?;
+        let status = match inferior.continue_run(None, &self.breakpoints) {
+            Ok(status) => status,
+            // The registers from before the call can't be put back, so the program is left
+            // where it is
+            Err(err) => return Err(self.failed(err)),
+        };
+        if let Status::Stopped(Signal::SIGTRAP, _) = status {
+            if inferior.call_returned(&frame).unwrap_or(false) {
+                return Ok(Ok(inferior.finish_call(frame, &mut self.breakpoints)?));
+            }
+        }
+        self.calls.push(PendingCall {
+            expr: expr.to_string(),
+            return_type,
+            frame,
+        });
+        Ok(Err(self.stopped(status, false)?))
+    }
+
+    /// If the program has just returned from the innermost pending call, restores the state
+    /// from before it.
+    fn finish_pending_call(&mut self) -> Result<Option<Stop>, Error> {
+        let inferior = match self.inferior.as_mut() {
+            Some(inferior) => inferior,
+            None => return Ok(None),
+        };
+        let call = match self.calls.pop() {
+            Some(call) if inferior.call_returned(&call.frame).unwrap_or(false) => call,
+            Some(call) => {
+                self.calls.push(call);
+                return Ok(None);
+            }
+            None => return Ok(None),
+        };
+        let rax = inferior.finish_call(call.frame, &mut self.breakpoints)?;
+        let value = call.return_type.map(|ty| format_return_value(&ty, rax));
+        Ok(Some(Stop::Returned(call.expr, value, inferior.rip()?)))
+    }
+
+    /// The heap as tracked with `check_memory`: the process's, or once it has exited, as it was
+    /// when it exited.
+    pub fn heap(&self) -> Option<&HeapTracker> {
+        match &self.inferior {
+            Some(inferior) => inferior.heap(),
+            None => self.exited_heap.as_ref(),
+        }
+    }
+
+    /// The process's memory map.
+    pub fn mappings(&self) -> Result<Vec<Mapping>, Error> {
+        let inferior = self.inferior.as_ref().ok_or(Error::NotRunning)?;
+        Ok(inferior.mappings()?)
+    }
+
+    /// Writes a core file of the process to `path`, returning the number of memory regions
+    /// saved.
+    pub fn generate_core(&self, path: &str) -> Result<usize, Error> {
+        let inferior = self.inferior.as_ref().ok_or(Error::NotRunning)?;
+        Ok(core_file::generate(inferior, &self.breakpoints, path)?)
+    }
+
+    /// Reads the program's memory. Breakpoints are looked through, so code reads as it was.
+    pub fn read_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, Error> {
+        let inferior = self.inferior.as_ref().ok_or(Error::NotRunning)?;
+        let mut bytes = inferior
+            .read_memory(addr, len)
+            .map_err(|err| Error::memory(err, addr))?;
+        for (&bp_addr, bp) in &self.breakpoints {
+            if bp_addr >= addr && bp_addr < addr + bytes.len() {
+                bytes[bp_addr - addr] = bp.orig_byte;
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// The stack where the program (or the core file) is stopped, innermost frame first.
+    pub fn backtrace(&self) -> Result<Vec<Frame>, Error> {
+        let target = self.target().ok_or(Error::NotRunning)?;
+        Ok(target.backtrace(&self.debug_data)?)
+    }
+
+    /// The innermost frame of the stack.
+    fn frame(&self) -> Result<Frame, Error> {
+        match self.backtrace()?.into_iter().next() {
+            Some(frame) => Ok(frame),
+            None => Err(Error::Evaluate("No stack.".to_string())),
+        }
+    }
+
+    /// Formats the value of a variable, as seen from the innermost frame.
+    pub fn evaluate(&self, name: &str) -> Result<String, Error> {
+        let frame = self.frame()?;
+        let var = lookup_variable(&self.debug_data, &frame, name).ok_or_else(|| {
+            Error::Evaluate(format!("No symbol \"{}\" in current context.", name))
+        })?;
+        self.read_variable(var, &frame)
+    }
+
+    /// Formats the value of one of `frame`'s variables, or a global.
+    pub fn read_variable(&self, var: &Variable, frame: &Frame) -> Result<String, Error> {
+        let target = self.target().ok_or(Error::NotRunning)?;
+        target
+            .read_variable(var, frame, &self.printers)
+            .map_err(|err| {
+                Error::Evaluate(match err {
+                    dwarf_expr::Error::Memory(err) => {
+                        format!("Cannot access memory for {}: {}", var.name, err)
+                    }
+                    err => err.to_string(),
+                })
+            })
+    }
+
+    /// Evaluates a function argument or an address: an integer or character literal, a
+    /// variable, &variable, or $register. Arrays are passed by their address, as C does.
+    pub fn evaluate_argument(&self, arg: &str) -> Result<u64, Error> {
+        if let Ok(value) = arg.parse::<i64>() {
+            return Ok(value as u64);
+        }
+        if arg.to_lowercase().starts_with("0x") {
+            if let Some(value) = parse_address(arg) {
+                return Ok(value as u64);
+            }
+        }
+        if arg.len() == 3 && arg.starts_with('\'') && arg.ends_with('\'') {
+            return Ok(arg.as_bytes()[1] as u64);
+        }
+        let target = self.target().ok_or(Error::NotRunning)?;
+        if arg.starts_with('$') {
+            let register = &arg[1..];
+            let index = REGISTER_NAMES
+                .iter()
+                .position(|name| *name == register)
+                .ok_or_else(|| Error::Evaluate(format!("Invalid register ${}", register)))?;
+            return Ok(regs_to_words(&target.registers()?)[index]);
+        }
+        let frame = self.frame()?;
+        let (name, address_of) = if arg.starts_with('&') {
+            (&arg[1..], true)
+        } else {
+            (arg, false)
+        };
+        let var = lookup_variable(&self.debug_data, &frame, name).ok_or_else(|| {
+            Error::Evaluate(format!("No symbol \"{}\" in current context.", name))
+        })?;
+        if address_of || var.entity_type.kind == TypeKind::Array {
+            return target
+                .variable_address(var, &frame)
+                .map(|addr| addr as u64)
+                .ok_or_else(|| {
+                    Error::Evaluate(format!(
+                        "Can't take address of \"{}\" which isn't an lvalue.",
+                        name
+                    ))
+                });
+        }
+        let size = var.entity_type.size.min(8);
+        let bytes = target
+            .read_variable_bytes(var, &frame)
+            .map_err(|err| Error::Evaluate(format!("Cannot access memory for {}: {}", name, err)))?
+            .ok_or_else(|| Error::Evaluate(format!("{} has been optimized out", name)))?;
+        let mut word = [0u8; 8];
+        word[..size].copy_from_slice(&bytes[..size]);
+        let raw = u64::from_le_bytes(word);
+        // Smaller signed integers are sign-extended when passed in a register
+        let type_name = &var.entity_type.name;
+        if size > 0 && size < 8 && !type_name.contains("unsigned") && type_name != "_Bool" {
+            let shift = 64 - 8 * size as u32;
+            Ok((((raw << shift) as i64) >> shift) as u64)
+        } else {
+            Ok(raw)
+        }
+    }
+
+    /// Takes the read ends of the program's stdout and stderr, if `launch.capture_output` was
+    /// set when it was spawned.
+    pub fn take_output(&mut self) -> (Option<ChildStdout>, Option<ChildStderr>) {
+        match self.inferior.as_mut() {
+            Some(inferior) => inferior.take_output(),
+            None => (None, None),
+        }
+    }
+
+    /// Takes the warnings gathered since the last call, about problems that didn't stop the
+    /// program (e.g. a breakpoint address that couldn't be written).
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        self.take_inferior_warnings();
+        std::mem::take(&mut self.warnings)
+    }
+
+    fn take_inferior_warnings(&mut self) {
+        if let Some(inferior) = self.inferior.as_mut() {
+            self.warnings.extend(inferior.take_warnings());
+        }
+    }
+
+    /// Lets go of the inferior if `err` means it has gone, so that the session can start or
+    /// attach to another. Otherwise it stays stopped where it was.
+    fn failed(&mut self, err: Error) -> Error {
+        self.take_inferior_warnings();
+        if let Error::ProcessGone = err {
+            self.kill();
+        }
+        err
+    }
+
+    /// Turns the inferior's status into a `Stop`, letting go of the inferior if it has ended,
+    /// finishing a pending call that has returned, and deleting a temporary breakpoint that
+    /// was hit.
+    fn stopped(&mut self, status: Status, stepping: bool) -> Result<Stop, Error> {
+        self.take_inferior_warnings();
+        let stop = Stop::from_status(status, &self.breakpoints, stepping);
+        self.pending_signal = stop.pending_signal();
+        match stop {
+            Stop::Exited(_) | Stop::Signaled(_) => {
+                if let Some(inferior) = self.inferior.take() {
+                    self.exited_heap = inferior.heap().cloned();
+                }
+                self.calls.clear();
+            }
+            Stop::Breakpoint(_) | Stop::Signal(Signal::SIGTRAP, _) => {
+                match self.finish_pending_call() {
+                    Ok(Some(returned)) => return Ok(returned),
+                    Ok(None) => {}
+                    Err(err) => return Err(self.failed(err)),
+                }
+                if let Stop::Breakpoint(rip) = stop {
+                    if self.breakpoints[&rip].temporary {
+                        let _ = self.remove_breakpoint(rip);
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(stop)
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.kill();
+        self.kill_checkpoints();
+    }
+}
+
+/// Takes the process for running backwards, if its execution is being recorded.
+fn recording(inferior: &mut Option<Inferior>) -> Result<&mut Inferior, Error> {
+    match inferior.as_mut() {
+        Some(inferior) if inferior.recorded_steps().is_some() => Ok(inferior),
+        Some(_) => Err(Error::NotRecording),
+        None => Err(Error::NotRunning),
+    }
+}
+
+/// The catchpoints in the breakpoint table, for setting in the process.
+fn catchpoint_list(table: &[(usize, BreakpointEntry)]) -> Vec<Catchpoint> {
+    table
+        .iter()
+        .filter_map(|(_, entry)| match entry {
+            BreakpointEntry::Catch(catch) => Some(catch.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Splits the arguments of a function call at the commas between them, leaving alone commas in
+/// string and character literals and in nested calls: `f(a, g(b, c), ',')` has three arguments.
+fn split_arguments(args: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in args.char_indices() {
+        if let Some(q) = quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == q {
+                quote = None;
+            }
+            continue;
+        }
+        match c {
+            '"' | '\'' => quote = Some(c),
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(args[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(args[start..].trim());
+    parts.into_iter().filter(|arg| !arg.is_empty()).collect()
+}
+
+/// Formats a function's return value from %rax.
+fn format_return_value(return_type: &Type, rax: u64) -> String {
+    return_type.format_value(&rax.to_le_bytes()[..return_type.size.min(8).max(1)])
+}
+
+/// Parses a hexadecimal address, with or without a leading 0x.
+pub fn parse_address(addr: &str) -> Option<usize> {
+    let addr_without_0x = if addr.to_lowercase().starts_with("0x") {
+        &addr[2..]
+    } else {
+        &addr
+    };
+    usize::from_str_radix(addr_without_0x, 16).ok()
+}
+
+/// Resolves a location as accepted by `break`: `*address`, a line number, `file:line`, or a
+/// function name. Bare line numbers are in `<target>.c`, and a function is looked for there first, in case other
+/// files define a static function with the same name.
+pub fn resolve_location(
+    debug_data: &DwarfData,
+    target: &str,
+    location: &str,
+) -> Result<usize, String> {
+    let main_file = format!("{}.c", target);
+    if location.starts_with('*') {
+        parse_address(&location[1..]).ok_or_else(|| "Invalid address".to_string())
+    } else if let Ok(line) = location.parse::<usize>() {
+        debug_data
+            .get_addr_for_line(Some(&main_file), line)
+            .ok_or_else(|| "Invalid line number".to_string())
+    } else if let Some((file, line)) = parse_file_line(location) {
+        debug_data
+            .get_addr_for_line(Some(file), line)
+            .ok_or_else(|| format!("No line {} in file \"{}\".", line, file))
+    } else {
+        debug_data
+            .get_addr_for_function(Some(&main_file), &location)
+            .or_else(|| debug_data.get_addr_for_function(None, &location))
+            .ok_or_else(|| format!("Function \"{}\" not defined.", location))
+    }
+}
+
+/// Splits a `file:line` location. C++ names like `ns::func` aren't one.
+fn parse_file_line(location: &str) -> Option<(&str, usize)> {
+    let colon = location.rfind(':')?;
+    let (file, line) = (&location[..colon], &location[colon + 1..]);
+    if file.is_empty() || file.ends_with(':') {
+        return None;
+    }
+    Some((file, line.parse().ok()?))
+}
+
+/// Resolves a location for a breakpoint, sorted. A function has a breakpoint on its out-of-line
+/// copy and on every copy of it inlined elsewhere.
+pub fn breakpoint_addresses(
+    debug_data: &DwarfData,
+    target: &str,
+    location: &str,
+) -> Result<Vec<usize>, String> {
+    let mut addrs = Vec::new();
+    let resolved = resolve_location(debug_data, target, location);
+    if let Ok(addr) = resolved {
+        addrs.push(addr);
+    }
+    if !location.starts_with('*')
+        && location.parse::<usize>().is_err()
+        && parse_file_line(location).is_none()
+    {
+        addrs.extend(debug_data.get_inlined_entries(location));
+    }
+    if addrs.is_empty() {
+        return Err(resolved.unwrap_err());
+    }
+    addrs.sort_unstable();
+    addrs.dedup();
+    Ok(addrs)
+}
+
+/// Finds a variable by name, looking in the frame's function before globals.
+pub fn lookup_variable<'a>(
+    debug_data: &'a DwarfData,
+    frame: &Frame,
+    name: &str,
+) -> Option<&'a Variable> {
+    frame
+        .variables(debug_data)
+        .into_iter()
+        .find(|var| var.name == name)
+        .or_else(|| debug_data.get_global_variable(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    /// A session on a sample, with the sample's output piped away from the test's.
+    fn session(name: &str) -> Session {
+        let mut session = Session::new(&testing::sample(name)).unwrap();
+        session.launch.capture_output = true;
+        session
+    }
+
+    fn line_number(session: &Session, rip: usize) -> usize {
+        session.debug_data().get_line_from_addr(rip).unwrap().number
+    }
+
+    #[test]
+    fn call_arguments_split_at_top_level_commas() {
+        assert_eq!(split_arguments(""), Vec::<&str>::new());
+        assert_eq!(
+            split_arguments(" a , 0x10,$rdi "),
+            vec!["a", "0x10", "$rdi"]
+        );
+        assert_eq!(
+            split_arguments("f(a, b), ',', \"x, y\", '\\''"),
+            vec!["f(a, b)", "','", "\"x, y\"", "'\\''"]
+        );
+        assert_eq!(
+            split_arguments("grid[0][1], s.a"),
+            vec!["grid[0][1]", "s.a"]
+        );
+    }
+
+    #[test]
+    fn breakpoint_set_before_spawning_stops_the_program() {
+        let mut session = session("function_calls");
+        // printf("sum = %d\n", sum) in func2
+        let addrs = session.set_breakpoint("17").unwrap();
+        session.spawn().unwrap();
+        match session.resume().unwrap() {
+            Stop::Breakpoint(rip) => assert_eq!(addrs, [rip]),
+            stop => panic!("expected the breakpoint, not {:?}", stop),
+        }
+        assert_eq!(session.evaluate("a").unwrap(), "42");
+        assert_eq!(session.evaluate("sum").unwrap(), "47");
+        let functions: Vec<_> = session
+            .backtrace()
+            .unwrap()
+            .into_iter()
+            .map(|frame| frame.function.unwrap_or_default())
+            .collect();
+        assert_eq!(functions[..3], ["func2", "func1", "main"]);
+    }
+
+    #[test]
+    fn locations_can_name_a_file_and_line() {
+        let mut session = session("function_calls");
+        let line_17 = session.resolve_location("17").unwrap();
+        assert_eq!(
+            session.resolve_location("function_calls.c:17").unwrap(),
+            line_17
+        );
+        assert_eq!(
+            session
+                .resolve_location("samples/function_calls.c:17")
+                .unwrap(),
+            line_17
+        );
+        match session.resolve_location("function_calls.c:1000") {
+            Err(Error::Location(message)) => {
+                assert_eq!(message, "No line 1000 in file \"function_calls.c\".")
+            }
+            result => panic!("expected a location error, not {:?}", result),
+        }
+        assert!(session.resolve_location("nonexistent.c:17").is_err());
+        // Not a file and line
+        assert!(parse_file_line("std::vector::size").is_none());
+        assert!(parse_file_line(":17").is_none());
+
+        assert_eq!(
+            session.set_breakpoint("function_calls.c:17").unwrap(),
+            [line_17]
+        );
+        session.spawn().unwrap();
+        match session.resume().unwrap() {
+            Stop::Breakpoint(rip) => assert_eq!(rip, line_17),
+            stop => panic!("expected the breakpoint, not {:?}", stop),
+        }
+    }
+
+    #[test]
+    fn stack_and_heap_stay_put_between_runs_without_randomization() {
+        let mut session = session("function_calls");
+        assert!(session.launch.disable_randomization);
+        session.set_breakpoint("func2").unwrap();
+        let regions = |session: &mut Session| -> Vec<(usize, usize)> {
+            session.spawn().unwrap();
+            session.resume().unwrap();
+            session
+                .mappings()
+                .unwrap()
+                .into_iter()
+                .filter(|mapping| {
+                    matches!(mapping.path.as_deref(), Some("[stack]") | Some("[heap]"))
+                })
+                .map(|mapping| (mapping.start, mapping.end))
+                .collect()
+        };
+        let first = regions(&mut session);
+        // printf has allocated its buffer by now
+        assert_eq!(first.len(), 2);
+        assert_eq!(regions(&mut session), first);
+    }
+
+    #[test]
+    fn restarting_a_checkpoint_brings_back_its_variables() {
+        let mut session = session("squares");
+        // total += square(i)
+        let addrs = session.set_breakpoint("11").unwrap();
+        session.spawn().unwrap();
+        session.resume().unwrap();
+        assert_eq!(session.evaluate("total").unwrap(), "0");
+        let (number, _) = session.checkpoint().unwrap();
+
+        session.resume().unwrap();
+        assert_eq!(session.evaluate("i").unwrap(), "2");
+        assert_eq!(session.evaluate("total").unwrap(), "1");
+
+        assert_eq!(session.restart(number).unwrap(), addrs[0]);
+        assert_eq!(session.evaluate("i").unwrap(), "1");
+        assert_eq!(session.evaluate("total").unwrap(), "0");
+        // and it carries on from there as before
+        session.resume().unwrap();
+        assert_eq!(session.evaluate("total").unwrap(), "1");
+    }
+
+    #[test]
+    fn memory_reads_look_through_breakpoints() {
+        let mut session = session("function_calls");
+        session.spawn().unwrap();
+        let addr = session
+            .debug_data()
+            .get_addr_for_function(None, "func2")
+            .unwrap();
+        let code = session.read_memory(addr, 4).unwrap();
+        session.set_breakpoint("func2").unwrap();
+        assert_eq!(session.read_memory(addr, 4).unwrap(), code);
+        assert_ne!(code[0], 0xcc);
+    }
+
+    #[test]
+    fn stepping_over_a_line_stops_at_the_next_one() {
+        let mut session = session("function_calls");
+        session.set_breakpoint("func2").unwrap();
+        session.spawn().unwrap();
+        let line = match session.resume().unwrap() {
+            Stop::Breakpoint(rip) => line_number(&session, rip),
+            stop => panic!("expected the breakpoint, not {:?}", stop),
+        };
+        match session.step(Step::Over).unwrap() {
+            Stop::Stepped(rip) => assert_eq!(line_number(&session, rip), line + 1),
+            stop => panic!("expected the step to finish, not {:?}", stop),
+        }
+    }
+
+    #[test]
+    fn temporary_breakpoint_is_hit_once_and_removed() {
+        let mut session = session("squares");
+        let addr = session
+            .debug_data()
+            .get_addr_for_function(None, "square")
+            .unwrap();
+        session.add_breakpoint(addr, true).unwrap();
+        session.spawn().unwrap();
+        match session.resume().unwrap() {
+            Stop::Breakpoint(rip) => assert_eq!(rip, addr),
+            stop => panic!("expected the breakpoint, not {:?}", stop),
+        }
+        assert!(session.breakpoint_number(addr).is_none());
+        assert!(session.breakpoints().is_empty());
+        // square is called twice more
+        match session.resume().unwrap() {
+            Stop::Exited(code) => assert_eq!(code, 0),
+            stop => panic!("expected the program to exit, not {:?}", stop),
+        }
+    }
+
+    #[test]
+    fn until_stops_in_the_caller_when_the_function_returns_first() {
+        let mut session = session("squares");
+        session.set_breakpoint("square").unwrap();
+        session.spawn().unwrap();
+        session.resume().unwrap();
+        session.delete(0).unwrap();
+        // printf("total = %d\n", total), after the loop
+        match session.run_until("13").unwrap() {
+            Stop::Breakpoint(rip) => {
+                let function = session.debug_data().get_function_from_addr(rip);
+                assert_eq!(function.as_deref(), Some("main"));
+                assert_eq!(line_number(&session, rip), 11);
+            }
+            stop => panic!("expected to stop in main, not {:?}", stop),
+        }
+        assert!(session.breakpoints().is_empty());
+        match session.resume().unwrap() {
+            Stop::Exited(code) => assert_eq!(code, 0),
+            stop => panic!("expected the program to exit, not {:?}", stop),
+        }
+    }
+
+    #[test]
+    fn program_that_exits_is_let_go_of() {
+        let mut session = session("count");
+        session.spawn().unwrap();
+        match session.resume().unwrap() {
+            Stop::Exited(code) => assert_eq!(code, 0),
+            stop => panic!("expected the program to exit, not {:?}", stop),
+        }
+        assert!(session.pid().is_none());
+        match session.resume() {
+            Err(Error::NotRunning) => {}
+            result => panic!("expected NotRunning, not {:?}", result),
+        }
+    }
+
+    #[test]
+    fn program_killed_while_stopped_is_gone_and_can_be_started_again() {
+        let mut session = session("function_calls");
+        session.set_breakpoint("func2").unwrap();
+        session.spawn().unwrap();
+        session.resume().unwrap();
+
+        let pid = session.pid().unwrap();
+        nix::sys::signal::kill(pid, Signal::SIGKILL).unwrap();
+        // Until it has died, ptrace would still resume it
+        let stat = format!("/proc/{}/stat", pid);
+        while !std::fs::read_to_string(&stat).unwrap().contains(") Z ") {
+            std::thread::yield_now();
+        }
+        match session.resume() {
+            Err(Error::ProcessGone) => {}
+            result => panic!("expected ProcessGone, not {:?}", result),
+        }
+        assert!(session.pid().is_none());
+
+        session.spawn().unwrap();
+        match session.resume().unwrap() {
+            Stop::Breakpoint(_) => {}
+            stop => panic!("expected the breakpoint, not {:?}", stop),
+        }
+    }
+
+    #[test]
+    fn displays_leave_out_what_cannot_be_evaluated_and_can_be_removed() {
+        let mut session = session("function_calls");
+        assert_eq!(session.add_display("a"), 1);
+        assert_eq!(session.add_display("nonexistent"), 2);
+        assert_eq!(session.add_display("sum"), 3);
+        // Nothing to evaluate them in yet
+        assert!(session.display_values(None).is_empty());
+
+        session.set_breakpoint("17").unwrap();
+        session.spawn().unwrap();
+        session.resume().unwrap();
+        assert_eq!(
+            session.display_values(None),
+            [(1, "a", "42".to_string()), (3, "sum", "47".to_string())]
+        );
+        assert_eq!(
+            session.display_values(Some(3)),
+            [(3, "sum", "47".to_string())]
+        );
+
+        session.undisplay(1).unwrap();
+        assert_eq!(session.display_values(None), [(3, "sum", "47".to_string())]);
+        match session.undisplay(1) {
+            Err(Error::NoDisplay(1)) => {}
+            result => panic!("expected NoDisplay, not {:?}", result),
+        }
+        assert_eq!(session.displays().len(), 2);
+    }
+
+    #[test]
+    fn sigchld_only_stops_the_program_when_caught() {
+        let mut session = session("fork");
+        session.spawn().unwrap();
+        match session.resume().unwrap() {
+            Stop::Exited(code) => assert_eq!(code, 0),
+            stop => panic!("expected the program to exit, not {:?}", stop),
+        }
+
+        session.add_catchpoint(Catchpoint::Signal(Some(Signal::SIGCHLD)));
+        session.spawn().unwrap();
+        match session.resume().unwrap() {
+            Stop::Signal(Signal::SIGCHLD, _) => {}
+            stop => panic!("expected SIGCHLD, not {:?}", stop),
+        }
+        match session.resume().unwrap() {
+            Stop::Exited(code) => assert_eq!(code, 0),
+            stop => panic!("expected the program to exit, not {:?}", stop),
+        }
+    }
+
+    #[test]
+    fn catchpoints_are_numbered_with_breakpoints_and_can_be_deleted() {
+        let mut session = session("fork");
+        session.set_breakpoint("main").unwrap();
+        let number = session.add_catchpoint(Catchpoint::Fork);
+        assert_eq!(number, 1);
+        session.delete(number).unwrap();
+        assert_eq!(session.catchpoints().count(), 0);
+        assert_eq!(session.breakpoint_table().len(), 1);
+
+        session.spawn().unwrap();
+        session.resume().unwrap();
+        session.add_catchpoint(Catchpoint::Fork);
+        session.delete(2).unwrap();
+        match session.resume().unwrap() {
+            Stop::Exited(code) => assert_eq!(code, 0),
+            stop => panic!("expected the program to exit, not {:?}", stop),
+        }
+    }
+
+    #[test]
+    fn unknown_variable_is_an_evaluation_error() {
+        let mut session = session("function_calls");
+        session.set_breakpoint("func1").unwrap();
+        session.spawn().unwrap();
+        session.resume().unwrap();
+        match session.evaluate("nonexistent") {
+            Err(Error::Evaluate(message)) => {
+                assert_eq!(message, "No symbol \"nonexistent\" in current context.")
+            }
+            result => panic!("expected an evaluation error, not {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! Names and argument formats of x86-64 Linux system calls, for `catch syscall`. Only the calls
+//! that commonly show up in small C programs are described; others are printed by number with
+//! raw arguments.
+
+use crate::target::Target;
+use nix::errno::Errno;
+
+/// How to display a system call argument.
+#[derive(Clone, Copy)]
+enum Arg {
+    Int,
+    Hex,
+    /// A NUL-terminated string.
+    Str,
+    /// A buffer whose length is given by the argument at this index.
+    Buf(usize),
+}
+
+use Arg::*;
+
+const SYSCALLS: &[(u64, &str, &[Arg])] = &[
+    (0, "read", &[Int, Hex, Int]),
+    (1, "write", &[Int, Buf(2), Int]),
+    (2, "open", &[Str, Hex, Hex]),
+    (3, "close", &[Int]),
+    (4, "stat", &[Str, Hex]),
+    (5, "fstat", &[Int, Hex]),
+    (6, "lstat", &[Str, Hex]),
+    (7, "poll", &[Hex, Int, Int]),
+    (8, "lseek", &[Int, Int, Int]),
+    (9, "mmap", &[Hex, Int, Hex, Hex, Int, Hex]),
+    (10, "mprotect", &[Hex, Int, Hex]),
+    (11, "munmap", &[Hex, Int]),
+    (12, "brk", &[Hex]),
+    (13, "rt_sigaction", &[Int, Hex, Hex, Int]),
+    (14, "rt_sigprocmask", &[Int, Hex, Hex, Int]),
+    (16, "ioctl", &[Int, Hex, Hex]),
+    (17, "pread64", &[Int, Hex, Int, Int]),
+    (18, "pwrite64", &[Int, Buf(2), Int, Int]),
+    (21, "access", &[Str, Int]),
+    (22, "pipe", &[Hex]),
+    (32, "dup", &[Int]),
+    (33, "dup2", &[Int, Int]),
+    (35, "nanosleep", &[Hex, Hex]),
+    (39, "getpid", &[]),
+    (56, "clone", &[Hex, Hex, Hex, Hex, Hex]),
+    (57, "fork", &[]),
+    (58, "vfork", &[]),
+    (59, "execve", &[Str, Hex, Hex]),
+    (60, "exit", &[Int]),
+    (61, "wait4", &[Int, Hex, Hex, Hex]),
+    (62, "kill", &[Int, Int]),
+    (63, "uname", &[Hex]),
+    (72, "fcntl", &[Int, Int, Hex]),
+    (79, "getcwd", &[Hex, Int]),
+    (80, "chdir", &[Str]),
+    (82, "rename", &[Str, Str]),
+    (83, "mkdir", &[Str, Hex]),
+    (87, "unlink", &[Str]),
+    (89, "readlink", &[Str, Hex, Int]),
+    (96, "gettimeofday", &[Hex, Hex]),
+    (102, "getuid", &[]),
+    (110, "getppid", &[]),
+    (158, "arch_prctl", &[Hex, Hex]),
+    (186, "gettid", &[]),
+    (201, "time", &[Hex]),
+    (202, "futex", &[Hex, Int, Int, Hex, Hex, Int]),
+    (218, "set_tid_address", &[Hex]),
+    (228, "clock_gettime", &[Int, Hex]),
+    (230, "clock_nanosleep", &[Int, Int, Hex, Hex]),
+    (231, "exit_group", &[Int]),
+    (257, "openat", &[Int, Str, Hex, Hex]),
+    (262, "newfstatat", &[Int, Str, Hex, Hex]),
+    (273, "set_robust_list", &[Hex, Int]),
+    (302, "prlimit64", &[Int, Int, Hex, Hex]),
+    (318, "getrandom", &[Hex, Int, Hex]),
+    (334, "rseq", &[Hex, Int, Hex, Hex]),
+    (435, "clone3", &[Hex, Int]),
+];
+
+/// How much of a string or buffer argument to show.
+const MAX_STRING_LEN: usize = 32;
+
+pub fn name(number: u64) -> Option<&'static str> {
+    SYSCALLS
+        .iter()
+        .find(|(num, _, _)| *num == number)
+        .map(|(_, name, _)| *name)
+}
+
+pub fn number(name: &str) -> Option<u64> {
+    SYSCALLS
+        .iter()
+        .find(|(_, n, _)| *n == name)
+        .map(|(num, _, _)| *num)
+}
+
+/// Returns the syscall's name, or "syscall_<n>" for ones we don't know.
+pub fn display_name(number: u64) -> String {
+    name(number).map_or_else(|| format!("syscall_{}", number), |name| name.to_string())
+}
+
+/// Reads up to `max_len` bytes at `addr`, stopping early at a NUL if `nul_terminated`.
+fn read_bytes(
+    target: &dyn Target,
+    addr: usize,
+    max_len: usize,
+    nul_terminated: bool,
+) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    while bytes.len() < max_len {
+        let byte = target.read_memory(addr + bytes.len(), 1).ok()?[0];
+        if nul_terminated && byte == 0 {
+            break;
+        }
+        bytes.push(byte);
+    }
+    Some(bytes)
+}
+
+fn quote(bytes: &[u8], truncated: bool) -> String {
+    let escaped: String = bytes
+        .iter()
+        .flat_map(|&b| std::ascii::escape_default(b))
+        .map(|b| b as char)
+        .collect();
+    format!("\"{}\"{}", escaped, if truncated { "..." } else { "" })
+}
+
+/// Formats a call like strace does, e.g. `write(1, "Hello world!\n", 13)`.
+pub fn format_call(number: u64, args: &[u64; 6], target: &dyn Target) -> String {
+    let kinds: &[Arg] = SYSCALLS
+        .iter()
+        .find(|(num, _, _)| *num == number)
+        .map_or(&[Hex, Hex, Hex, Hex, Hex, Hex], |(_, _, kinds)| *kinds);
+    let formatted: Vec<String> = kinds
+        .iter()
+        .zip(args.iter())
+        .map(|(kind, &value)| match *kind {
+            Int => (value as i64).to_string(),
+            Hex => format!("{:#x}", value),
+            Str => match read_bytes(target, value as usize, MAX_STRING_LEN + 1, true) {
+                Some(bytes) if value != 0 => {
+                    let truncated = bytes.len() > MAX_STRING_LEN;
+                    quote(&bytes[..bytes.len().min(MAX_STRING_LEN)], truncated)
+                }
+                _ => format!("{:#x}", value),
+            },
+            Buf(len_index) => {
+                let len = args[len_index] as usize;
+                match read_bytes(target, value as usize, len.min(MAX_STRING_LEN), false) {
+                    Some(bytes) if value != 0 => quote(&bytes, len > MAX_STRING_LEN),
+                    _ => format!("{:#x}", value),
+                }
+            }
+        })
+        .collect();
+    format!("{}({})", display_name(number), formatted.join(", "))
+}
+
+/// Formats a return value, decoding negative values as errno.
+pub fn format_return(value: i64) -> String {
+    if value < 0 && value > -4096 {
+        format!(
+            "-1 {:?} ({})",
+            Errno::from_i32(-value as i32),
+            Errno::from_i32(-value as i32).desc()
+        )
+    } else {
+        value.to_string()
+    }
+}
@@ -0,0 +1,411 @@
+//! Inspecting a program's state doesn't depend on whether it is a live process being traced or a
+//! core file left behind by one. `Target` is the common interface, so that backtraces, variables,
+//! and memory dumps work the same way on both.
+
+use crate::dwarf_data::{DwarfData, Line, Variable};
+use crate::dwarf_expr::{self, Place};
+use crate::pretty::Printers;
+use libc::user_regs_struct;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt;
+use std::mem::size_of;
+
+/// Number of general-purpose registers in `user_regs_struct` (and in an NT_PRSTATUS note).
+pub const NUM_REGISTERS: usize = 27;
+
+/// Register names, in `user_regs_struct` order.
+pub const REGISTER_NAMES: [&str; NUM_REGISTERS] = [
+    "r15", "r14", "r13", "r12", "rbp", "rbx", "r11", "r10", "r9", "r8", "rax", "rcx", "rdx", "rsi",
+    "rdi", "orig_rax", "rip", "cs", "eflags", "rsp", "ss", "fs_base", "gs_base", "ds", "es", "fs",
+    "gs",
+];
+
+fn align_addr_to_word(addr: usize) -> usize {
+    addr & (-(size_of::<usize>() as isize) as usize)
+}
+
+/// Flattens registers into `user_regs_struct` order.
+pub fn regs_to_words(regs: &user_regs_struct) -> [u64; NUM_REGISTERS] {
+    [
+        regs.r15,
+        regs.r14,
+        regs.r13,
+        regs.r12,
+        regs.rbp,
+        regs.rbx,
+        regs.r11,
+        regs.r10,
+        regs.r9,
+        regs.r8,
+        regs.rax,
+        regs.rcx,
+        regs.rdx,
+        regs.rsi,
+        regs.rdi,
+        regs.orig_rax,
+        regs.rip,
+        regs.cs,
+        regs.eflags,
+        regs.rsp,
+        regs.ss,
+        regs.fs_base,
+        regs.gs_base,
+        regs.ds,
+        regs.es,
+        regs.fs,
+        regs.gs,
+    ]
+}
+
+/// The inverse of `regs_to_words`.
+pub fn words_to_regs(words: &[u64; NUM_REGISTERS]) -> user_regs_struct {
+    user_regs_struct {
+        r15: words[0],
+        r14: words[1],
+        r13: words[2],
+        r12: words[3],
+        rbp: words[4],
+        rbx: words[5],
+        r11: words[6],
+        r10: words[7],
+        r9: words[8],
+        r8: words[9],
+        rax: words[10],
+        rcx: words[11],
+        rdx: words[12],
+        rsi: words[13],
+        rdi: words[14],
+        orig_rax: words[15],
+        rip: words[16],
+        cs: words[17],
+        eflags: words[18],
+        rsp: words[19],
+        ss: words[20],
+        fs_base: words[21],
+        gs_base: words[22],
+        ds: words[23],
+        es: words[24],
+        fs: words[25],
+        gs: words[26],
+    }
+}
+
+/// One entry of the target's call stack, innermost first.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub function: Option<String>,
+    pub line: Option<Line>,
+    pub rip: usize,
+    /// Saved frame pointer of this frame. Locals are addressed relative to this.
+    pub rbp: usize,
+    /// All of the registers, for the innermost frame only. Outer frames just know rip and rbp.
+    pub registers: Option<[u64; NUM_REGISTERS]>,
+    /// Arguments the caller passed in registers, keyed by DWARF register number, for
+    /// DW_OP_entry_value.
+    pub entry_values: HashMap<u16, u64>,
+    /// This frame is a function that was inlined into the next one, so they share their rip and
+    /// registers.
+    pub inlined: bool,
+}
+
+impl Frame {
+    /// The address to look up what the frame is doing at. Outer frames are stopped at a return
+    /// address, which may be past the end of the inlined call or location list range that made
+    /// the call.
+    pub fn pc(&self) -> usize {
+        if self.registers.is_some() {
+            self.rip
+        } else {
+            self.rip.saturating_sub(1)
+        }
+    }
+
+    /// Returns the local variables and parameters of the frame's function.
+    pub fn variables<'a>(&self, debug_data: &'a DwarfData) -> Vec<&'a Variable> {
+        if self.inlined {
+            return debug_data
+                .get_inlined_calls(self.pc())
+                .into_iter()
+                .rev()
+                .find(|call| Some(&call.name) == self.function.as_ref())
+                .map(|call| call.variables.iter().collect())
+                .unwrap_or_default();
+        }
+        debug_data
+            .get_function_containing(self.pc())
+            .map(|func| func.variables.iter().collect())
+            .unwrap_or_default()
+    }
+}
+
+/// How `backtrace` lists the frame: its function and where in the source it is.
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.line, &self.function) {
+            (None, None) => write!(f, "unknown func (source file not found)"),
+            (Some(line), None) => write!(f, "unknown func ({})", line),
+            (None, Some(func)) => write!(f, "{} (source file not found)", func),
+            (Some(line), Some(func)) => write!(f, "{} ({})", func, line),
+        }
+    }
+}
+
+pub trait Target {
+    /// Returns the general-purpose registers of the (only) thread.
+    fn registers(&self) -> Result<user_regs_struct, nix::Error>;
+
+    /// Reads the word at a word-aligned address.
+    fn read_word(&self, addr: usize) -> Result<u64, nix::Error>;
+
+    /// Reads `len` bytes of memory starting at `addr`.
+    fn read_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        let start = align_addr_to_word(addr);
+        let mut bytes = Vec::with_capacity(len + size_of::<usize>());
+        let mut word_addr = start;
+        while word_addr < addr + len {
+            bytes.extend_from_slice(&self.read_word(word_addr)?.to_le_bytes());
+            word_addr += size_of::<usize>();
+        }
+        Ok(bytes[addr - start..addr - start + len].to_vec())
+    }
+
+    /// Walks the stack using the saved frame pointers, stopping at main or at the first frame we
+    /// have no debugging information for. Functions inlined at a frame's location get frames of
+    /// their own, like in gdb.
+    fn backtrace(&self, debug_data: &DwarfData) -> Result<Vec<Frame>, nix::Error> {
+        let regs = self.registers()?;
+        let mut rip = regs.rip as usize;
+        let mut rbp = regs.rbp as usize;
+        let mut frames = Vec::new();
+        loop {
+            let registers = if frames.is_empty() {
+                Some(regs_to_words(&regs))
+            } else {
+                None
+            };
+            let mut frame = Frame {
+                function: None,
+                line: None,
+                rip,
+                rbp,
+                registers,
+                entry_values: HashMap::new(),
+                inlined: false,
+            };
+            // The function whose code this is, even if it's in the middle of an inlined call.
+            // A caller's return address can already be on the line after the call, or past the
+            // end of the function if the call never returns.
+            let pc = frame.pc();
+            frame.function = debug_data
+                .get_function_containing(pc)
+                .map(|func| func.name.clone())
+                .filter(|name| !name.is_empty())
+                .or_else(|| debug_data.get_function_from_addr(pc));
+            frame.line = debug_data.get_line_from_addr(pc);
+            let reached_end = frame
+                .function
+                .as_deref()
+                .map_or(true, |func| func == "main");
+            frames.push(frame);
+            if reached_end {
+                break;
+            }
+            // Stopped on a function's first instruction (e.g. at a breakpoint on it), the function
+            // hasn't pushed %rbp yet: the return address is on top of the stack and %rbp is still
+            // the caller's
+            let at_entry = frames.len() == 1
+                && debug_data
+                    .get_function_containing(rip)
+                    .map_or(false, |func| func.address == rip);
+            // function's return address in running is stored 8 bytes above the saved %rbp value.
+            // This return address is effectively %rip (the instruction pointer) for the previous stack frame.
+            let return_address = if at_entry {
+                self.read_word(regs.rsp as usize)? as usize
+            } else {
+                match rbp.checked_add(8) {
+                    Some(addr) => self.read_word(addr)? as usize,
+                    None => break,
+                }
+            };
+            // The outermost frame (e.g. _start) has a return address of 0
+            if return_address == 0 {
+                break;
+            }
+            rip = return_address;
+            if !at_entry {
+                rbp = self.read_word(rbp)? as usize;
+            }
+        }
+        // Optimized code often loses a function's arguments once it's done with them. The caller
+        // may still be able to say what it passed, in terms of its own frame (which may in turn
+        // need its own arguments, so work outwards in).
+        for i in (1..frames.len()).rev() {
+            let (callees, callers) = frames.split_at_mut(i);
+            let caller = &callers[0];
+            let call_site = debug_data
+                .get_function_containing(caller.pc())
+                .and_then(|func| {
+                    func.call_sites
+                        .iter()
+                        .find(|site| site.return_address == caller.rip)
+                });
+            for (register, value) in call_site.iter().flat_map(|site| &site.parameters) {
+                let value = dwarf_expr::evaluate_expression(value, caller, self)
+                    .and_then(|place| dwarf_expr::read(&place, 8, caller, self));
+                if let Ok(Some(bytes)) = value {
+                    let value = u64::from_le_bytes(bytes[..].try_into().unwrap());
+                    callees[i - 1].entry_values.insert(*register, value);
+                }
+            }
+        }
+        let mut expanded = Vec::with_capacity(frames.len());
+        for frame in frames {
+            let mut line = frame.line.clone();
+            for call in debug_data.get_inlined_calls(frame.pc()).into_iter().rev() {
+                expanded.push(Frame {
+                    function: Some(call.name.clone()),
+                    line,
+                    entry_values: HashMap::new(),
+                    inlined: true,
+                    ..frame.clone()
+                });
+                line = Some(Line {
+                    file: call.call_file.clone(),
+                    number: call.call_line,
+                    address: frame.rip,
+                });
+            }
+            expanded.push(Frame { line, ..frame });
+        }
+        Ok(expanded)
+    }
+
+    /// Returns the address of a variable's storage in the given frame, or None if it isn't in
+    /// memory (it lives in a register, is computed, or was optimized out).
+    fn variable_address(&self, var: &Variable, frame: &Frame) -> Option<usize> {
+        match dwarf_expr::evaluate(&var.location, frame, self) {
+            Ok(Place::Memory(addr)) => Some(addr),
+            _ => None,
+        }
+    }
+
+    /// Reads the bytes of a variable's value in the given frame. Returns None if it has been
+    /// optimized out at this point in the program.
+    fn read_variable_bytes(
+        &self,
+        var: &Variable,
+        frame: &Frame,
+    ) -> Result<Option<Vec<u8>>, dwarf_expr::Error> {
+        let place = dwarf_expr::evaluate(&var.location, frame, self)?;
+        dwarf_expr::read(&place, var.entity_type.size, frame, self)
+    }
+
+    /// Reads a variable's value out of the given frame and formats it according to its type.
+    fn read_variable(
+        &self,
+        var: &Variable,
+        frame: &Frame,
+        printers: &Printers,
+    ) -> Result<String, dwarf_expr::Error> {
+        Ok(match self.read_variable_bytes(var, frame)? {
+            Some(bytes) => printers.format_value(&var.entity_type, &bytes, &|addr, len| {
+                self.read_memory(addr, len).ok()
+            }),
+            None => "<optimized out>".to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use nix::errno::Errno;
+
+    const RBP: usize = 0x7ff0_0000;
+    const RSP: usize = 0x7fe0_0000;
+    /// The length of `push %rbp; mov %rsp,%rbp`, which sets up a function's frame.
+    const PROLOGUE_LEN: u64 = 4;
+
+    /// A stack of saved frame pointers and return addresses.
+    struct Stack {
+        regs: user_regs_struct,
+        memory: HashMap<usize, u64>,
+    }
+
+    impl Target for Stack {
+        fn registers(&self) -> Result<user_regs_struct, nix::Error> {
+            Ok(self.regs)
+        }
+
+        fn read_word(&self, addr: usize) -> Result<u64, nix::Error> {
+            self.memory
+                .get(&addr)
+                .copied()
+                .ok_or(nix::Error::Sys(Errno::EIO))
+        }
+    }
+
+    /// A stack stopped in `function`, past the prologue that pointed %rbp at its frame.
+    fn stopped_in(debug_data: &DwarfData, function: &str, memory: Vec<(usize, u64)>) -> Stack {
+        let mut regs = words_to_regs(&[0; NUM_REGISTERS]);
+        regs.rip = debug_data.get_addr_for_function(None, function).unwrap() as u64 + PROLOGUE_LEN;
+        regs.rbp = RBP as u64;
+        regs.rsp = RSP as u64;
+        Stack {
+            regs,
+            memory: memory.into_iter().collect(),
+        }
+    }
+
+    fn function_names(frames: &[Frame]) -> Vec<&str> {
+        frames
+            .iter()
+            .map(|frame| frame.function.as_deref().unwrap_or("??"))
+            .collect()
+    }
+
+    #[test]
+    fn unwinding_stops_at_main() {
+        let debug_data = testing::debug_data("function_calls");
+        // A return address just after the start of main, from a call to func2
+        let main = debug_data.get_function("main").unwrap().address;
+        let stack = stopped_in(
+            &debug_data,
+            "func2",
+            vec![(RBP, RBP as u64 + 0x20), (RBP + 8, main as u64 + 5)],
+        );
+        let frames = stack.backtrace(&debug_data).unwrap();
+        assert_eq!(function_names(&frames), vec!["func2", "main"]);
+        assert_eq!(frames[1].pc(), main + 4);
+    }
+
+    #[test]
+    fn unwinding_from_a_function_entry_takes_the_return_address_off_the_stack() {
+        let debug_data = testing::debug_data("function_calls");
+        let main = debug_data.get_function("main").unwrap().address;
+        // %rbp is still main's
+        let mut stack = stopped_in(&debug_data, "func2", vec![(RSP, main as u64 + 5)]);
+        stack.regs.rip -= PROLOGUE_LEN;
+        let frames = stack.backtrace(&debug_data).unwrap();
+        assert_eq!(function_names(&frames), vec!["func2", "main"]);
+        assert_eq!(frames[1].pc(), main + 4);
+        assert_eq!(frames[1].rbp, RBP);
+    }
+
+    #[test]
+    fn unwinding_stops_at_a_zero_return_address() {
+        let debug_data = testing::debug_data("function_calls");
+        let stack = stopped_in(&debug_data, "func2", vec![(RBP, 0), (RBP + 8, 0)]);
+        let frames = stack.backtrace(&debug_data).unwrap();
+        assert_eq!(function_names(&frames), vec!["func2"]);
+
+        let outermost = Frame {
+            registers: None,
+            rip: 0,
+            ..frames[0].clone()
+        };
+        assert_eq!(outermost.pc(), 0);
+    }
+}
@@ -0,0 +1,35 @@
+//! Helpers for the unit tests, which debug the sample programs in samples/.
+
+use crate::dwarf_data::DwarfData;
+use crate::inferior::{Breakpoint, Inferior};
+use std::collections::HashMap;
+use std::process::{Command, Stdio};
+
+pub fn sample(name: &str) -> String {
+    format!("{}/samples/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+pub fn debug_data(name: &str) -> DwarfData {
+    DwarfData::from_file(&sample(name)).expect("could not load the sample's debugging information")
+}
+
+/// Starts a sample, with its output discarded, stopped before its first instruction. The
+/// breakpoints are written into it.
+pub fn spawn(name: &str, breakpoints: &mut HashMap<usize, Breakpoint>) -> Inferior {
+    let mut cmd = Command::new(sample(name));
+    cmd.stdout(Stdio::null());
+    Inferior::spawn(cmd, breakpoints).expect("could not spawn the sample")
+}
+
+/// Breakpoints at the start of each of the named functions.
+pub fn breakpoints_at(debug_data: &DwarfData, functions: &[&str]) -> HashMap<usize, Breakpoint> {
+    functions
+        .iter()
+        .map(|name| {
+            let addr = debug_data
+                .get_addr_for_function(None, name)
+                .expect("no such function in the sample");
+            (addr, Breakpoint::pending(false))
+        })
+        .collect()
+}
@@ -0,0 +1,443 @@
+//! `qeet --tui`: keeps the source around the current line on screen, like gdb's TUI. The top of
+//! the terminal holds a source pane, optionally followed by a disassembly or register pane, and
+//! commands scroll in the lines left below them. The panes are drawn with plain ANSI escape codes
+//! and the command area is a scrolling region, so rustyline works in it as usual.
+
+use qeet::disassemble::disassemble;
+use qeet::dwarf_data::DwarfData;
+use qeet::inferior::Breakpoint;
+use qeet::target::{regs_to_words, Target, REGISTER_NAMES};
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Write};
+
+/// Lines kept for commands and their output.
+const MIN_COMMAND_LINES: usize = 6;
+/// How much code to disassemble when we don't know where a function starts.
+const MAX_DISASSEMBLY_BYTES: usize = 4096;
+
+/// What is shown below the source pane.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Layout {
+    Source,
+    Assembly,
+    Registers,
+}
+
+impl Layout {
+    /// Parses the argument to `layout`.
+    pub fn from_name(name: &str) -> Option<Layout> {
+        match name {
+            "src" => Some(Layout::Source),
+            "asm" | "split" => Some(Layout::Assembly),
+            "regs" => Some(Layout::Registers),
+            _ => None,
+        }
+    }
+}
+
+pub struct Tui {
+    layout: Cell<Layout>,
+    /// Where the program is stopped, or None if it isn't running.
+    rip: Cell<Option<usize>>,
+    /// Whether the scrolling region has been set up yet.
+    started: Cell<bool>,
+}
+
+impl Tui {
+    pub fn new() -> Tui {
+        Tui {
+            layout: Cell::new(Layout::Source),
+            rip: Cell::new(None),
+            started: Cell::new(false),
+        }
+    }
+
+    pub fn set_layout(&self, layout: Layout) {
+        self.layout.set(layout);
+    }
+
+    /// Records where the program stopped (None once it has exited), for the next `refresh`.
+    pub fn stopped_at(&self, rip: Option<usize>) {
+        self.rip.set(rip);
+    }
+
+    /// Redraws the panes.
+    pub fn refresh(
+        &self,
+        debug_data: &DwarfData,
+        target: Option<&dyn Target>,
+        breakpoints: &HashMap<usize, Breakpoint>,
+    ) {
+        let (rows, columns) = terminal_size();
+        let pane_lines = rows.saturating_sub(MIN_COMMAND_LINES.max(rows / 3));
+        if pane_lines < 4 {
+            // Too small to be useful; just leave the command line alone
+            return;
+        }
+        let mut lines = Vec::with_capacity(pane_lines);
+        match self.layout.get() {
+            Layout::Source => {
+                lines.extend(self.source_pane(debug_data, breakpoints, pane_lines, columns))
+            }
+            layout => {
+                let source_lines = pane_lines / 2;
+                lines.extend(self.source_pane(debug_data, breakpoints, source_lines, columns));
+                let other_lines = pane_lines - source_lines;
+                if layout == Layout::Assembly {
+                    lines.extend(self.assembly_pane(
+                        debug_data,
+                        target,
+                        breakpoints,
+                        other_lines,
+                        columns,
+                    ));
+                } else {
+                    lines.extend(self.register_pane(target, other_lines, columns));
+                }
+            }
+        }
+
+        let mut out = String::new();
+        if self.started.get() {
+            // Save the cursor, which is somewhere in the command area
+            out.push_str("\x1b7");
+        } else {
+            out.push_str("\x1b[2J");
+        }
+        for (i, line) in lines.iter().enumerate() {
+            out.push_str(&format!("\x1b[{};1H\x1b[2K{}", i + 1, line));
+        }
+        // Commands scroll below the panes. Setting the region moves the cursor, so put it back.
+        out.push_str(&format!("\x1b[{};{}r", pane_lines + 1, rows));
+        if self.started.get() {
+            out.push_str("\x1b8");
+        } else {
+            out.push_str(&format!("\x1b[{};1H", rows));
+            self.started.set(true);
+        }
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(out.as_bytes());
+        let _ = stdout.flush();
+    }
+
+    /// Gives the whole terminal back to the shell.
+    pub fn leave(&self) {
+        if self.started.get() {
+            let (rows, _) = terminal_size();
+            print!("\x1b[r\x1b[{};1H", rows);
+            let _ = io::stdout().flush();
+            self.started.set(false);
+        }
+    }
+
+    /// Shows the source around the current line (or around main, before the program runs),
+    /// marking the current line with ">" and lines with breakpoints with "b".
+    fn source_pane(
+        &self,
+        debug_data: &DwarfData,
+        breakpoints: &HashMap<usize, Breakpoint>,
+        height: usize,
+        width: usize,
+    ) -> Vec<String> {
+        let addr = self
+            .rip
+            .get()
+            .or_else(|| debug_data.get_addr_for_function(None, "main"));
+        let line = match addr.and_then(|addr| debug_data.get_line_from_addr(addr)) {
+            Some(line) => line,
+            None => return empty_pane("src", "[ No Source Available ]", height, width),
+        };
+        let source = match fs::read_to_string(&line.file) {
+            Ok(source) => source,
+            Err(err) => {
+                let message = format!("[ Could not read {}: {} ]", line.file, err);
+                return empty_pane(&line.file, &message, height, width);
+            }
+        };
+        let source: Vec<&str> = source.lines().collect();
+        let marks = SourceMarks {
+            line: line.number,
+            stopped: self.rip.get().is_some(),
+            breakpoints: breakpoint_lines(debug_data, breakpoints, &line.file),
+        };
+        source_lines(&line.file, &source, &marks, height, width)
+    }
+
+    /// Disassembles the function the program is stopped in, around the current instruction.
+    fn assembly_pane(
+        &self,
+        debug_data: &DwarfData,
+        target: Option<&dyn Target>,
+        breakpoints: &HashMap<usize, Breakpoint>,
+        height: usize,
+        width: usize,
+    ) -> Vec<String> {
+        let (rip, target) = match (self.rip.get(), target) {
+            (Some(rip), Some(target)) => (rip, target),
+            _ => return empty_pane("asm", "[ No Assembly Available ]", height, width),
+        };
+        let function = debug_data.get_function_containing(rip);
+        let (start, len) = match function {
+            Some(func) => (func.address, func.text_length.min(MAX_DISASSEMBLY_BYTES)),
+            None => (rip, MAX_DISASSEMBLY_BYTES.min(height * 16)),
+        };
+        let instructions = match disassemble(target, start, len, breakpoints) {
+            Ok(instructions) => instructions,
+            Err(err) => {
+                let message = format!("[ Cannot access memory at {:#x}: {} ]", start, err);
+                return empty_pane("asm", &message, height, width);
+            }
+        };
+        let current = instructions
+            .iter()
+            .position(|&(addr, _)| addr == rip)
+            .unwrap_or(0);
+
+        let name = function.map_or("??", |func| func.name.as_str());
+        let mut lines = vec![title(name, width)];
+        let (first, last) = window(current, instructions.len(), height - 1);
+        for (addr, text) in &instructions[first..last] {
+            let text = format!(
+                "{}{} {:#x} <+{}> {}",
+                if breakpoints.contains_key(addr) {
+                    "b"
+                } else {
+                    " "
+                },
+                if *addr == rip { ">" } else { " " },
+                addr,
+                addr - start,
+                text
+            );
+            lines.push(highlight(&text, *addr == rip, width));
+        }
+        pad(lines, height)
+    }
+
+    fn register_pane(
+        &self,
+        target: Option<&dyn Target>,
+        height: usize,
+        width: usize,
+    ) -> Vec<String> {
+        let regs = match target.map(|target| target.registers()) {
+            Some(Ok(regs)) => regs_to_words(&regs),
+            _ => return empty_pane("regs", "[ Register Values Unavailable ]", height, width),
+        };
+        let cells: Vec<String> = REGISTER_NAMES
+            .iter()
+            .zip(regs.iter())
+            .map(|(name, value)| format!("{:<8} {:#018x}", name, value))
+            .collect();
+        let columns = (width / 30).max(1);
+        let mut lines = vec![title("registers", width)];
+        for row in cells.chunks(columns) {
+            let text: Vec<String> = row.iter().map(|cell| format!("{:<30}", cell)).collect();
+            lines.push(truncate(text.concat().trim_end(), width));
+        }
+        pad(lines, height)
+    }
+}
+
+impl Default for Tui {
+    fn default() -> Self {
+        Tui::new()
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        self.leave();
+    }
+}
+
+/// Where the source pane is centered, and which lines it marks.
+struct SourceMarks {
+    /// The line to show in the middle.
+    line: usize,
+    /// Whether the program is stopped at `line`, which is then marked with ">".
+    stopped: bool,
+    /// Lines marked with "b".
+    breakpoints: HashSet<usize>,
+}
+
+/// Returns the lines of `file` that have breakpoints.
+fn breakpoint_lines(
+    debug_data: &DwarfData,
+    breakpoints: &HashMap<usize, Breakpoint>,
+    file: &str,
+) -> HashSet<usize> {
+    breakpoints
+        .keys()
+        .filter_map(|&addr| debug_data.get_line_from_addr(addr))
+        .filter(|bp_line| bp_line.file == file)
+        .map(|bp_line| bp_line.number)
+        .collect()
+}
+
+/// Lays out the source pane for a file's lines.
+fn source_lines(
+    file: &str,
+    source: &[&str],
+    marks: &SourceMarks,
+    height: usize,
+    width: usize,
+) -> Vec<String> {
+    let mut lines = vec![title(file, width)];
+    let (first, last) = window(marks.line.max(1) - 1, source.len(), height - 1);
+    for (i, text) in source.iter().enumerate().take(last).skip(first) {
+        let number = i + 1;
+        let current = marks.stopped && number == marks.line;
+        let text = format!(
+            "{}{} {:>4} {}",
+            if marks.breakpoints.contains(&number) {
+                "b"
+            } else {
+                " "
+            },
+            if current { ">" } else { " " },
+            number,
+            text.replace('\t', "    ")
+        );
+        lines.push(highlight(&text, current, width));
+    }
+    pad(lines, height)
+}
+
+/// Returns the number of rows and columns of the terminal.
+fn terminal_size() -> (usize, usize) {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let ok = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0;
+    if ok && size.ws_row > 0 && size.ws_col > 0 {
+        (size.ws_row as usize, size.ws_col as usize)
+    } else {
+        (24, 80)
+    }
+}
+
+/// Chooses which of `len` items to show in `height` lines so that `current` is in the middle.
+fn window(current: usize, len: usize, height: usize) -> (usize, usize) {
+    let first = current
+        .saturating_sub(height / 2)
+        .min(len.saturating_sub(height));
+    (first, len.min(first + height))
+}
+
+fn truncate(text: &str, width: usize) -> String {
+    text.chars().take(width).collect()
+}
+
+/// Shows a line in reverse video if `on`, filling the width of the terminal.
+fn highlight(text: &str, on: bool, width: usize) -> String {
+    if on {
+        format!(
+            "\x1b[7m{:<width$}\x1b[0m",
+            truncate(text, width),
+            width = width
+        )
+    } else {
+        truncate(text, width)
+    }
+}
+
+fn title(text: &str, width: usize) -> String {
+    highlight(&format!(" {} ", text), true, width)
+}
+
+fn pad(mut lines: Vec<String>, height: usize) -> Vec<String> {
+    lines.resize(height, String::new());
+    lines
+}
+
+fn empty_pane(name: &str, message: &str, height: usize, width: usize) -> Vec<String> {
+    let mut lines = vec![title(name, width)];
+    lines.resize(height / 2, String::new());
+    lines.push(truncate(
+        &format!("{:^width$}", message, width = width),
+        width,
+    ));
+    pad(lines, height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_debug_data(name: &str) -> DwarfData {
+        let path = format!("{}/samples/{}", env!("CARGO_MANIFEST_DIR"), name);
+        DwarfData::from_file(&path).unwrap()
+    }
+
+    #[test]
+    fn window_keeps_the_current_item_in_the_middle() {
+        assert_eq!(window(0, 100, 10), (0, 10));
+        assert_eq!(window(50, 100, 10), (45, 55));
+        // Near the end, the window stays full rather than centered
+        assert_eq!(window(98, 100, 10), (90, 100));
+        // Everything fits
+        assert_eq!(window(3, 5, 10), (0, 5));
+        assert_eq!(window(0, 0, 10), (0, 0));
+    }
+
+    #[test]
+    fn panes_are_padded_to_their_height() {
+        assert_eq!(pad(vec!["a".to_string()], 3), ["a", "", ""]);
+        assert_eq!(pad(vec!["a".to_string(), "b".to_string()], 1), ["a"]);
+
+        let lines = empty_pane("src", "[ No Source Available ]", 6, 31);
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[0], title("src", 31));
+        assert_eq!(lines[3], "    [ No Source Available ]    ");
+        for (i, line) in lines.iter().enumerate() {
+            if i != 0 && i != 3 {
+                assert_eq!(line, "");
+            }
+        }
+    }
+
+    #[test]
+    fn source_pane_marks_breakpoints_and_the_current_line() {
+        let debug_data = sample_debug_data("function_calls");
+        let func2 = debug_data.get_addr_for_function(None, "func2").unwrap();
+        let file = debug_data.get_line_from_addr(func2).unwrap().file;
+        let mut breakpoints = HashMap::new();
+        breakpoints.insert(func2, Breakpoint::pending(false));
+        let marks = SourceMarks {
+            // printf("sum = %d\n", sum)
+            line: 17,
+            stopped: true,
+            breakpoints: breakpoint_lines(&debug_data, &breakpoints, &file),
+        };
+        assert_eq!(marks.breakpoints, [14].iter().copied().collect());
+        assert!(breakpoint_lines(&debug_data, &breakpoints, "other.c").is_empty());
+
+        // The sample was built elsewhere, so its source is read from here
+        let path = format!("{}/samples/function_calls.c", env!("CARGO_MANIFEST_DIR"));
+        let source = fs::read_to_string(path).unwrap();
+        let source: Vec<&str> = source.lines().collect();
+        let lines = source_lines(&file, &source, &marks, 12, 80);
+        assert_eq!(lines.len(), 12);
+        assert_eq!(lines[0], title(&file, 80));
+        let numbered = |number: usize| {
+            lines
+                .iter()
+                .find(|line| line.contains(&format!(" {:>4} ", number)))
+                .unwrap()
+        };
+        assert!(numbered(14).starts_with("b    14 void func2(int a, int b) {"));
+        assert!(numbered(15).starts_with("     15 "));
+        // In reverse video
+        assert!(numbered(17).starts_with("\x1b[7m >   17     printf(\"sum = %d\\n\", sum);"));
+        assert!(numbered(17).ends_with("\x1b[0m"));
+
+        // Before the program runs, nothing is current
+        let marks = SourceMarks {
+            stopped: false,
+            ..marks
+        };
+        let lines = source_lines(&file, &source, &marks, 12, 80);
+        assert!(lines[1..].iter().all(|line| !line.starts_with("\x1b[7m")));
+    }
+}
@@ -0,0 +1,30 @@
+mod common;
+
+use common::replay;
+
+/// Without breakpoints, the program runs to completion with its output relayed to us.
+#[test]
+fn test_run_to_exit() {
+    replay("run_to_exit");
+}
+
+/// Stop at a breakpoint in func2, once it has set `sum`, and inspect the stack, locals and
+/// globals there.
+#[test]
+fn test_breakpoint_stack_trace_and_variables() {
+    replay("breakpoint_stack_trace_and_variables");
+}
+
+/// `next` steps over calls within func2, stepping into printf (which has no line information)
+/// behaves like `next`, and `stepIn` enters func3.
+#[test]
+fn test_next_and_step_in() {
+    replay("next_and_step_in");
+}
+
+/// Breakpoints on lines that don't exist are reported as unverified, and unknown requests fail
+/// without bringing the adapter down.
+#[test]
+fn test_bad_requests() {
+    replay("bad_requests");
+}
@@ -0,0 +1,122 @@
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const CONTENT_LENGTH: &str = "Content-Length:";
+
+/// Plays the part of an editor: spawns `deet --dap` and exchanges DAP messages with it.
+pub struct DapClient {
+    child: Child,
+    stdin: ChildStdin,
+    messages: Receiver<Value>,
+}
+
+impl DapClient {
+    fn target_bin_path() -> std::path::PathBuf {
+        let mut path = std::env::current_exe().expect("Could not get current test executable path");
+        path.pop();
+        path.pop();
+        path.push("deet");
+        path
+    }
+
+    pub fn new() -> DapClient {
+        let mut child = Command::new(DapClient::target_bin_path())
+            .arg("--dap")
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Could not execute deet binary");
+        let stdin = child.stdin.take().unwrap();
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+
+        // Read messages on another thread so that a hung adapter fails the test instead of
+        // blocking it forever
+        let (sender, messages) = mpsc::channel();
+        thread::spawn(move || loop {
+            let mut content_length = 0;
+            loop {
+                let mut header = String::new();
+                if stdout.read_line(&mut header).unwrap_or(0) == 0 {
+                    return;
+                }
+                let header = header.trim_end();
+                if header.is_empty() {
+                    break;
+                }
+                if header.starts_with(CONTENT_LENGTH) {
+                    let len = &header[CONTENT_LENGTH.len()..];
+                    content_length = len.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; content_length];
+            stdout.read_exact(&mut body).unwrap();
+            if sender.send(serde_json::from_slice(&body).unwrap()).is_err() {
+                return;
+            }
+        });
+
+        DapClient {
+            child,
+            stdin,
+            messages,
+        }
+    }
+
+    pub fn send(&mut self, message: &Value) {
+        let body = message.to_string();
+        write!(self.stdin, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        self.stdin.flush().unwrap();
+    }
+
+    /// Returns the next message from the adapter, failing the test if none comes.
+    pub fn next_message(&mut self) -> Value {
+        self.messages
+            .recv_timeout(TIMEOUT)
+            .expect("Timed out waiting for a message from the adapter")
+    }
+
+    /// Returns the next message if one arrives within `wait`, or None once the adapter has been
+    /// quiet that long (or has exited).
+    pub fn try_next_message(&mut self, wait: Duration) -> Option<Value> {
+        match self.messages.recv_timeout(wait) {
+            Ok(message) => Some(message),
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => None,
+        }
+    }
+}
+
+impl Drop for DapClient {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Removes what changes from one run to the next, for comparing messages: the sequence numbers
+/// the adapter assigns, and the directories of source paths, which depend on where the samples
+/// were compiled.
+pub fn normalize(message: &Value) -> Value {
+    match message {
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .filter(|(key, _)| key.as_str() != "seq")
+                .map(|(key, value)| match (key.as_str(), value) {
+                    ("path", Value::String(path)) => {
+                        let name = path.rsplit('/').next().unwrap_or(path);
+                        (key.clone(), json!(name))
+                    }
+                    _ => (key.clone(), normalize(value)),
+                })
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(values.iter().map(normalize).collect()),
+        value => value.clone(),
+    }
+}
@@ -0,0 +1,4 @@
+mod dap_client;
+mod transcript;
+
+pub use transcript::replay;
@@ -0,0 +1,122 @@
+//! Recorded DAP sessions, kept in tests/dap_sessions: the requests an editor sent, one entry per
+//! line as `{"send": ...}`, and each message the adapter sent back as `{"recv": ...}`. Replaying
+//! a session sends each request once the messages recorded before it have come back, and checks
+//! that the same messages come back again.
+//!
+//! To record a session, write the requests it should make as `send` entries (`${SAMPLES}` stands
+//! for the samples directory) and run the tests with QEET_RECORD_DAP=1, which fills in what the
+//! adapter sends back.
+
+use super::dap_client::{normalize, DapClient};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::time::Duration;
+
+/// When recording, how long the adapter has to be quiet for everything a request led to to have
+/// been sent.
+const QUIET: Duration = Duration::from_secs(1);
+
+pub fn replay(name: &str) {
+    let path = format!(
+        "{}/tests/dap_sessions/{}.json",
+        env!("CARGO_MANIFEST_DIR"),
+        name
+    );
+    let transcript = fs::read_to_string(&path).expect("Could not read the recorded session");
+    let transcript: Vec<Value> =
+        serde_json::from_str(&transcript).expect("Could not parse the recorded session");
+    if std::env::var_os("QEET_RECORD_DAP").is_some() {
+        record(&path, &transcript);
+        return;
+    }
+
+    let mut client = DapClient::new();
+    // The program's output is relayed as it is read, so it can come between any two messages;
+    // only what it adds up to is compared
+    let mut expected_output = BTreeMap::new();
+    let mut output = BTreeMap::new();
+    for (i, entry) in transcript.iter().enumerate() {
+        if let Some(request) = entry.get("send") {
+            client.send(&with_samples_dir(request));
+            continue;
+        }
+        let expected = &entry["recv"];
+        if add_output(&mut expected_output, expected) {
+            continue;
+        }
+        let message = loop {
+            let message = client.next_message();
+            if !add_output(&mut output, &message) {
+                break message;
+            }
+        };
+        assert_eq!(
+            normalize(&message),
+            *expected,
+            "{}: message {} differs from the recording",
+            name,
+            i + 1
+        );
+    }
+    while let Some(message) = client.try_next_message(Duration::from_millis(100)) {
+        assert!(
+            add_output(&mut output, &message),
+            "{}: unexpected message after the recording ends: {}",
+            name,
+            message
+        );
+    }
+    assert_eq!(
+        output, expected_output,
+        "{}: the program's output differs",
+        name
+    );
+}
+
+/// Sends the requests of a transcript and saves them with what the adapter sends back.
+fn record(path: &str, transcript: &[Value]) {
+    let mut client = DapClient::new();
+    let mut recorded = Vec::new();
+    for request in transcript.iter().filter_map(|entry| entry.get("send")) {
+        client.send(&with_samples_dir(request));
+        recorded.push(json!({ "send": request }));
+        while let Some(message) = client.try_next_message(QUIET) {
+            recorded.push(json!({ "recv": normalize(&message) }));
+        }
+    }
+    let lines: Vec<String> = recorded.iter().map(|entry| entry.to_string()).collect();
+    fs::write(path, format!("[\n{}\n]\n", lines.join(",\n")))
+        .expect("Could not write the recorded session");
+}
+
+/// Adds an output event with the program's stdout or stderr to `output`, returning false for any
+/// other message.
+fn add_output(output: &mut BTreeMap<String, String>, message: &Value) -> bool {
+    let category = message["body"]["category"].as_str().unwrap_or("");
+    if message["event"] != "output" || (category != "stdout" && category != "stderr") {
+        return false;
+    }
+    output
+        .entry(category.to_string())
+        .or_default()
+        .push_str(message["body"]["output"].as_str().unwrap_or(""));
+    true
+}
+
+fn with_samples_dir(value: &Value) -> Value {
+    match value {
+        Value::String(s) => json!(s.replace(
+            "${SAMPLES}",
+            concat!(env!("CARGO_MANIFEST_DIR"), "/samples")
+        )),
+        Value::Array(values) => Value::Array(values.iter().map(with_samples_dir).collect()),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(key, value)| (key.clone(), with_samples_dir(value)))
+                .collect(),
+        ),
+        value => value.clone(),
+    }
+}